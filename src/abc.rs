@@ -0,0 +1,2427 @@
+use crate::score::{LoadError, ScoreEvent, ScoreNote};
+use crate::signature::{KeySignature, TimeSignature, DEFAULT_TIME_SIGNATURE};
+use itertools::Itertools;
+use midly::num::{u4, u7};
+use midly::MidiMessage::{NoteOff, NoteOn};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+
+/// The tempo of a tune without a Q: field, in microseconds per whole note (♩=120)
+const DEFAULT_WHOLE_NOTE_LENGTH: f64 = 2_000_000.0;
+/// How long each grace note is played before the note it ornaments, in microseconds
+const GRACE_NOTE_LENGTH: u64 = 50_000;
+/// The velocity of notes before any dynamics marking
+const DEFAULT_VELOCITY: u7 = u7::new(80);
+/// How much an accent raises the velocity of a note
+const ACCENT_VELOCITY: u8 = 20;
+/// The fraction of its written length a staccato note sounds
+const STACCATO_LENGTH: f64 = 0.5;
+/// Letters in the order sharps are added to key signatures
+const SHARPS: &str = "FCGDAEB";
+/// Letters in the order flats are added to key signatures
+const FLATS: &str = "BEADGCF";
+
+/// Tells whether a file is in ABC notation judging by its extension
+pub fn is_abc_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("abc"))
+}
+
+pub fn load_abc_file(
+    path: &Path,
+    voices: &[String],
+    grace_notes: GraceNotes,
+) -> Result<Vec<ScoreNote>, LoadError> {
+    let text = std::fs::read_to_string(path)?;
+    abc_voices_into_score_with_grace_notes(&text, voices)
+        .map(|score| grace_notes.select(score))
+        .map_err(LoadError::Abc)
+}
+
+/// What to do with the grace notes of an ABC tune
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GraceNotes {
+    /// Leave grace notes out of the score
+    Skip,
+    /// Play grace notes as short notes just before the notes they ornament
+    Play,
+}
+
+impl std::str::FromStr for GraceNotes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(GraceNotes::Skip),
+            "play" => Ok(GraceNotes::Play),
+            _ => Err(format!("unknown grace note handling '{}'", s)),
+        }
+    }
+}
+
+impl GraceNotes {
+    /// Keeps or leaves out the grace notes of a score
+    ///
+    /// # Arguments
+    ///
+    /// * score - The notes of the score and whether each of them is a grace note, see
+    ///   [`abc_voices_into_score_with_grace_notes`]
+    fn select(&self, (score, grace): (Vec<ScoreNote>, Vec<bool>)) -> Vec<ScoreNote> {
+        score
+            .into_iter()
+            .zip(grace)
+            .filter(|(_, grace)| *self == GraceNotes::Play || !grace)
+            .map(|(note, _)| note)
+            .collect()
+    }
+}
+
+/// A problem found in an ABC notation file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AbcError {
+    /// The line of the problem counting from 1, or `None` if it concerns the whole tune
+    pub line: Option<usize>,
+    /// The column of the problem on the line counting from 1, if known
+    pub column: Option<usize>,
+    /// The offending part of the line, if known
+    pub token: Option<String>,
+    /// What is wrong
+    pub message: String,
+}
+
+impl AbcError {
+    fn new(message: impl Into<String>) -> Self {
+        Self {
+            line: None,
+            column: None,
+            token: None,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for AbcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => {
+                write!(f, "line {}, column {}: {}", line, column, self.message)
+            }
+            (Some(line), None) => write!(f, "line {}: {}", line, self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl Error for AbcError {}
+
+/// Converts the first tune of an ABC notation file into a score, merging all of its
+/// voices, see [`abc_voices_into_score`]
+pub fn abc_into_score(text: &str) -> Result<Vec<ScoreNote>, AbcError> {
+    abc_voices_into_score(text, &[])
+}
+
+/// Converts the first tune of an ABC notation file into a score without its grace
+/// notes, see [`abc_voices_into_score_with_grace_notes`]
+pub fn abc_voices_into_score(text: &str, voices: &[String]) -> Result<Vec<ScoreNote>, AbcError> {
+    abc_voices_into_score_with_grace_notes(text, voices).map(|score| GraceNotes::Skip.select(score))
+}
+
+/// Converts the first tune of an ABC notation file into a score, flagging grace notes
+/// as ornaments, see [`convert_tune`]
+///
+/// # Return value
+///
+/// The score and whether each of its notes is a grace note, or the first problem
+/// found in the tune
+pub fn abc_voices_into_score_with_grace_notes(
+    text: &str,
+    voices: &[String],
+) -> Result<(Vec<ScoreNote>, Vec<bool>), AbcError> {
+    Ok(convert_tune(text, voices)?
+        .into_iter()
+        .map(|played| (played.note, played.grace))
+        .unzip())
+}
+
+/// Converts the first tune of an ABC notation file into note-on and note-off events
+/// for playback, see [`convert_tune`]
+///
+/// Velocities follow the dynamics markings like `!p!` and `!ff!` and accents like `!>!`
+/// or `L`, and staccato notes like `.c` sound for half of their written length. All
+/// events are on the first MIDI channel.
+///
+/// # Arguments
+///
+/// * text - The contents of the ABC notation file
+/// * voices - The IDs of the voices to convert, or an empty slice for all voices
+/// * grace_notes - Whether to play or skip grace notes
+pub fn abc_voices_into_events(
+    text: &str,
+    voices: &[String],
+    grace_notes: GraceNotes,
+) -> Result<Vec<ScoreEvent>, AbcError> {
+    let channel = u4::new(0);
+    Ok(convert_tune(text, voices)?
+        .into_iter()
+        .filter(|played| grace_notes == GraceNotes::Play || !played.grace)
+        .flat_map(|played| {
+            let key = played.note.pitch;
+            [
+                ScoreEvent {
+                    time: played.note.time,
+                    channel,
+                    message: NoteOn {
+                        key,
+                        vel: played.velocity,
+                    },
+                },
+                ScoreEvent {
+                    time: played.note.time + played.duration,
+                    channel,
+                    message: NoteOff {
+                        key,
+                        vel: u7::new(0),
+                    },
+                },
+            ]
+        })
+        // a note ending when the next one of the same pitch starts is released first
+        .sorted_by_key(|event| (event.time, matches!(event.message, NoteOn { .. })))
+        .collect())
+}
+
+pub fn load_abc_file_events(
+    path: &Path,
+    voices: &[String],
+    grace_notes: GraceNotes,
+) -> Result<Vec<ScoreEvent>, LoadError> {
+    let text = std::fs::read_to_string(path)?;
+    abc_voices_into_events(&text, voices, grace_notes).map_err(LoadError::Abc)
+}
+
+/// A note of a converted tune as it is played
+struct PlayedNote {
+    note: ScoreNote,
+    /// How long the note sounds in microseconds
+    duration: u64,
+    velocity: u7,
+    /// Whether the note is a grace note ornamenting the next note
+    grace: bool,
+}
+
+/// Converts the first tune of an ABC notation file into the notes it plays
+///
+/// Supports voices with key signatures, accidentals, note lengths, chords, rests, ties,
+/// broken rhythms, tuplets, repeats, numbered endings, grace notes, dynamics, accents
+/// and staccato. Other decorations, annotations and chord symbols are skipped. Notes
+/// are transposed to concert pitch by `%%transpose` and `%%MIDI transpose` directives
+/// and `transpose=` settings of K: and V: fields.
+///
+/// # Arguments
+///
+/// * text - The contents of the ABC notation file
+/// * voices - The IDs of the voices to merge into the score from their V: fields, or
+///   an empty slice for all voices
+///
+/// # Return value
+///
+/// The notes in time order, or the first problem found in the tune
+fn convert_tune(text: &str, voices: &[String]) -> Result<Vec<PlayedNote>, AbcError> {
+    let mut tune = Tune::default();
+    // whether the header of the tune has started with its X: field
+    let mut in_header = false;
+    for (line_index, line) in text.lines().enumerate() {
+        if let Some(directive) = line.trim_start().strip_prefix("%%") {
+            tune.apply_directive(directive)
+                .map_err(|message| AbcError {
+                    line: Some(line_index + 1),
+                    ..AbcError::new(message)
+                })?;
+            continue;
+        }
+        let code = line.split('%').next().unwrap();
+        let line = code.trim();
+        let indent = code.chars().count() - code.trim_start().chars().count();
+        let locate = |mut err: AbcError| {
+            err.line = Some(line_index + 1);
+            err.column = err.column.map(|column| indent + column);
+            err
+        };
+        if let Some((field, value)) = field(line) {
+            if tune.in_body && field == 'X' {
+                // the next tune starts
+                break;
+            }
+            tune.apply_field(field, value).map_err(|message| {
+                locate(AbcError {
+                    column: Some(line.find(value).unwrap_or(2) + 1),
+                    token: Some(value.to_string()),
+                    ..AbcError::new(message)
+                })
+            })?;
+            match field {
+                'X' => in_header = true,
+                'K' if !tune.in_body => tune.start_body(),
+                _ => {}
+            }
+        } else if tune.in_body {
+            tune.parse_music(line).map_err(locate)?;
+        } else if in_header && !line.is_empty() {
+            // free text is only allowed before the header of the tune
+            return Err(locate(AbcError {
+                column: Some(1),
+                token: Some(line.to_string()),
+                ..AbcError::new("expected a header field before the K: field")
+            }));
+        }
+    }
+    if !tune.in_body {
+        return Err(AbcError::new("missing K: field"));
+    }
+    // written tempo changes apply to repeated sections on every pass, so they are
+    // applied before unfolding the repeats
+    let mut tempo = std::mem::take(&mut tune.tempo);
+    tempo.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+    let tune_voices = tune.into_voices();
+    if let Some(missing) = voices.iter().find(|id| {
+        !tune_voices
+            .iter()
+            .any(|voice| voice.id.as_ref() == Some(id))
+    }) {
+        return Err(AbcError::new(format!("no voice '{}' in the tune", missing)));
+    }
+    Ok(tune_voices
+        .iter()
+        .filter(|voice| {
+            voices.is_empty() || voice.id.as_ref().is_some_and(|id| voices.contains(id))
+        })
+        .flat_map(|voice| voice.played_notes(&tempo))
+        // a stable sort keeps simultaneous notes in the order of the voices
+        .sorted_by_key(|played| played.note.time)
+        .collect())
+}
+
+/// Converts a tune written inline, e.g. on the command line, into a score
+///
+/// The tune may be just music like `CDEF GABc`, which gets the default unit note
+/// length and tempo, or start with header fields. A `K:C` field is added if the tune
+/// has no key field.
+///
+/// # Arguments
+///
+/// * text - The tune, with fields on lines of their own
+/// * voices - The IDs of the voices to merge into the score, or an empty slice for all
+///   voices
+/// * grace_notes - Whether to play or skip grace notes
+pub fn inline_abc_into_score(
+    text: &str,
+    voices: &[String],
+    grace_notes: GraceNotes,
+) -> Result<Vec<ScoreNote>, AbcError> {
+    let (tune, header) = complete_inline_tune(text);
+    abc_voices_into_score_with_grace_notes(&tune, voices)
+        .map(|score| grace_notes.select(score))
+        .map_err(|err| locate_in_inline_tune(err, header))
+}
+
+/// Converts a tune written inline into events for playback, see
+/// [`inline_abc_into_score`] and [`abc_voices_into_events`]
+pub fn inline_abc_into_events(
+    text: &str,
+    voices: &[String],
+    grace_notes: GraceNotes,
+) -> Result<Vec<ScoreEvent>, AbcError> {
+    let (tune, header) = complete_inline_tune(text);
+    abc_voices_into_events(&tune, voices, grace_notes)
+        .map_err(|err| locate_in_inline_tune(err, header))
+}
+
+/// Adds a `K:C` field after the header fields of an inline tune without a key field
+///
+/// # Return value
+///
+/// The complete tune, and the number of header lines if the key field was added
+fn complete_inline_tune(text: &str) -> (String, Option<usize>) {
+    let lines = text.lines().collect::<Vec<_>>();
+    if lines
+        .iter()
+        .any(|line| field(line.trim()).is_some_and(|(field, _)| field == 'K'))
+    {
+        return (text.to_string(), None);
+    }
+    // the key field ends the header
+    let header = lines
+        .iter()
+        .take_while(|line| field(line.trim()).is_some())
+        .count();
+    let mut tune = lines[..header].to_vec();
+    tune.push("K:C");
+    tune.extend(&lines[header..]);
+    (tune.join("\n"), Some(header))
+}
+
+/// Numbers the line of an error in a tune completed by [`complete_inline_tune`] as in
+/// the tune written inline
+fn locate_in_inline_tune(err: AbcError, header: Option<usize>) -> AbcError {
+    let header = match header {
+        Some(header) => header,
+        None => return err,
+    };
+    AbcError {
+        line: err
+            .line
+            .map(|line| if line > header { line - 1 } else { line }),
+        ..err
+    }
+}
+
+/// Splits an information field line like `K:G` into the field letter and its value
+fn field(line: &str) -> Option<(char, &str)> {
+    let mut chars = line.chars();
+    match (chars.next(), chars.next()) {
+        (Some(letter), Some(':')) if letter.is_ascii_alphabetic() => {
+            Some((letter, line[2..].trim()))
+        }
+        _ => None,
+    }
+}
+
+/// Parses a fraction like `1/8`, or a whole number like `3`
+///
+/// # Return value
+///
+/// The value of the fraction, or `None` if it isn't a finite number, e.g. with a zero
+/// denominator
+fn fraction(value: &str) -> Option<f64> {
+    let value = match value.split_once('/') {
+        Some((numerator, denominator)) => {
+            numerator.trim().parse::<f64>().ok()? / denominator.trim().parse::<f64>().ok()?
+        }
+        None => value.trim().parse().ok()?,
+    };
+    value.is_finite().then_some(value)
+}
+
+/// A repeat sign or the start of a numbered ending
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Repeat {
+    /// A start repeat sign `|:`
+    Start,
+    /// An end repeat sign `:|`
+    End,
+    /// The start of an ending played on the given passes through the repeat, like `[1`
+    /// or `[2,3`
+    Ending(Vec<u8>),
+}
+
+/// Converts a time in whole notes into microseconds
+///
+/// # Arguments
+///
+/// * tempo - The tempo changes sorted by time, as the times in whole notes when they
+///   take effect and the new lengths of a whole note in microseconds
+/// * time - The time in whole notes
+fn to_micros(tempo: &[(f64, f64)], time: f64) -> u64 {
+    let mut micros = 0.0;
+    for (index, &(start, whole_note_length)) in tempo.iter().enumerate() {
+        let end = tempo
+            .get(index + 1)
+            .map_or(time, |&(next, _)| next.min(time));
+        if end > start {
+            micros += (end - start) * whole_note_length;
+        }
+    }
+    micros.round() as u64
+}
+
+/// Parses the passes of an ending like `1`, `1,3` or `1-3`
+///
+/// # Return value
+///
+/// A 2-tuple of
+/// * the numbers of the passes
+/// * the index of the first character after the ending
+fn ending_passes(chars: &[char], mut i: usize) -> (Vec<u8>, usize) {
+    let number = |i: &mut usize| {
+        let start = *i;
+        while chars.get(*i).is_some_and(char::is_ascii_digit) {
+            *i += 1;
+        }
+        chars[start..*i]
+            .iter()
+            .collect::<String>()
+            .parse::<u8>()
+            .ok()
+    };
+    let mut passes = vec![];
+    while let Some(first) = number(&mut i) {
+        let last = match chars.get(i) {
+            Some('-') if chars.get(i + 1).is_some_and(char::is_ascii_digit) => {
+                i += 1;
+                number(&mut i).unwrap_or(first)
+            }
+            _ => first,
+        };
+        passes.extend(first..=last);
+        match chars.get(i) {
+            Some(',') if chars.get(i + 1).is_some_and(char::is_ascii_digit) => i += 1,
+            _ => break,
+        }
+    }
+    (passes, i)
+}
+
+/// Puts the notes of a tune in the order they are played
+///
+/// An end repeat sign goes back to the previous start repeat sign, or to the previous
+/// end repeat sign or the start of the tune if there is none. The repeat is played
+/// twice, or more times if it has endings for more passes. An ending is skipped on the
+/// passes it isn't numbered for.
+///
+/// # Arguments
+///
+/// * notes - The notes or other items with their times in written order
+/// * repeats - The repeat signs and endings in written order with their times
+/// * end - The time of the end of the tune
+fn unfold_repeats<T: Copy>(
+    notes: &[(u64, T)],
+    repeats: &[(u64, Repeat)],
+    end: u64,
+) -> Vec<(u64, T)> {
+    let is_ending_for = |repeat: &Repeat, pass: u8| match repeat {
+        Repeat::Ending(passes) => passes.contains(&pass),
+        _ => false,
+    };
+    // the sections of the written tune in the order they are played
+    let mut sections = vec![];
+    let mut section_start = 0;
+    // the index of the repeat sign a repeat jumps back to, and the pass through it
+    let mut repeat_start: Option<usize> = None;
+    let mut pass = 1;
+    let mut i = 0;
+    while i < repeats.len() {
+        let (time, repeat) = &repeats[i];
+        let rest_of_repeat = repeats[i + 1..]
+            .iter()
+            .enumerate()
+            .map(|(offset, (time, repeat))| (i + 1 + offset, *time, repeat))
+            .take_while(|(_, _, repeat)| **repeat != Repeat::Start);
+        match repeat {
+            Repeat::Start => {
+                repeat_start = Some(i);
+                pass = 1;
+            }
+            Repeat::Ending(passes) if !passes.contains(&pass) => {
+                sections.push(section_start..*time);
+                // go to the ending for this pass, or to the end of the repeat if there
+                // is none
+                let rest_of_repeat = rest_of_repeat.collect::<Vec<_>>();
+                let next = rest_of_repeat
+                    .iter()
+                    .find(|(_, _, repeat)| is_ending_for(repeat, pass))
+                    .or_else(|| {
+                        rest_of_repeat
+                            .iter()
+                            .find(|(_, _, repeat)| **repeat == Repeat::End)
+                    });
+                match next {
+                    Some(&(index, time, _)) => {
+                        section_start = time;
+                        i = index;
+                        continue;
+                    }
+                    None => {
+                        section_start = end;
+                        break;
+                    }
+                }
+            }
+            Repeat::End => {
+                let more_endings = rest_of_repeat
+                    .clone()
+                    .any(|(_, _, repeat)| is_ending_for(repeat, pass + 1));
+                if pass == 1 || more_endings {
+                    sections.push(section_start..*time);
+                    section_start = repeat_start.map_or(0, |index| repeats[index].0);
+                    pass += 1;
+                    i = repeat_start.map_or(0, |index| index + 1);
+                    continue;
+                }
+                // a later end repeat sign without a start repeat sign goes back here
+                repeat_start = Some(i);
+                pass = 1;
+            }
+            Repeat::Ending(_) => {}
+        }
+        i += 1;
+    }
+    sections.push(section_start..end);
+    let mut played = vec![];
+    let mut played_time = 0;
+    for section in sections {
+        played.extend(
+            notes
+                .iter()
+                .filter(|(time, _)| section.contains(time))
+                .map(|&(time, note)| (played_time + time - section.start, note)),
+        );
+        played_time += section.end.saturating_sub(section.start);
+    }
+    played
+}
+
+/// A meter from an M: field
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Meter {
+    /// The length of a measure in whole notes
+    length: f64,
+    /// Whether the meter is compound like 6/8, which makes tuplets of 5, 7 or 9 notes
+    /// take the time of three notes instead of two
+    compound: bool,
+}
+
+impl Default for Meter {
+    fn default() -> Self {
+        Self {
+            length: 1.0,
+            compound: false,
+        }
+    }
+}
+
+impl Meter {
+    /// Parses a meter like `6/8`, `C`, `C|`, `none`, or an additive meter like
+    /// `2+3+2/8` or `(3+2)/4`
+    fn parse(value: &str) -> Option<Self> {
+        // common time C and cut time C| both last a whole note, and free meter has no
+        // measures, so it gets the default
+        if matches!(value, "C" | "C|" | "none" | "") {
+            return Some(Self::default());
+        }
+        let (beats, beat) = value.split_once('/')?;
+        let beats = beats
+            .trim()
+            .trim_start_matches('(')
+            .trim_end_matches(')')
+            .split('+')
+            .map(|beats| beats.trim().parse::<u32>().ok())
+            .sum::<Option<u32>>()?;
+        let beat = beat.trim().parse::<u32>().ok().filter(|&beat| beat > 0)?;
+        (beats > 0).then_some(Self {
+            length: beats as f64 / beat as f64,
+            compound: beats > 3 && beats % 3 == 0,
+        })
+    }
+
+    /// The default unit note length in whole notes, a sixteenth note for meters shorter
+    /// than 3/4 and an eighth note otherwise
+    fn default_unit(&self) -> f64 {
+        match self.length < 0.75 {
+            true => 1.0 / 16.0,
+            false => 1.0 / 8.0,
+        }
+    }
+}
+
+/// A note as written in a voice
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct WrittenNote {
+    /// The time in whole notes
+    time: f64,
+    pitch: u7,
+    /// How long the note sounds in whole notes, which is shorter than written for
+    /// staccato notes
+    length: f64,
+    velocity: u7,
+}
+
+/// The parsing state of one voice of a tune
+struct Voice {
+    /// The ID of the voice from its V: field, or `None` for music before any V: field
+    id: Option<String>,
+    /// The notes in written order
+    notes: Vec<WrittenNote>,
+    /// The grace notes with the times of the notes they ornament, and how many grace
+    /// notes before those notes they are played
+    grace_notes: Vec<(WrittenNote, u64)>,
+    /// The velocity from the latest dynamics marking
+    dynamic: u7,
+    /// Whether the next note or chord is accented
+    accent: bool,
+    /// Whether the next note or chord is played staccato
+    staccato: bool,
+    /// Grace notes waiting for the note they ornament
+    pending_grace_notes: Vec<u7>,
+    /// The current time in whole notes
+    time: f64,
+    /// The meter of the voice, if the meter of the header has been changed in it
+    meter: Option<Meter>,
+    /// The unit note length of the voice in whole notes, if the unit note length of
+    /// the header has been changed in it
+    unit: Option<f64>,
+    /// Semitones added by a key signature changed in the voice to each note letter, if
+    /// the key of the header has been changed
+    key: Option<HashMap<char, i8>>,
+    /// Semitones added to the written pitches by a transposition set in the voice, if
+    /// any
+    transpose: Option<i32>,
+    /// Semitones added by accidentals earlier in the measure, by letter and octave
+    measure_accidentals: HashMap<(char, i8), i8>,
+    /// Accidentals of the previous measure for notes tied over the bar line, by letter
+    /// and octave
+    tied_accidentals: HashMap<(char, i8), i8>,
+    /// Pitches tied from the previous note or chord into the next one
+    ties: Vec<u7>,
+    /// Pitches of the previous note or chord
+    last_pitches: Vec<u7>,
+    /// The length of the previous note, rest or chord in whole notes
+    last_length: f64,
+    /// The factor for the length of the next note from a broken rhythm
+    broken_rhythm: f64,
+    /// The factor for note lengths in a tuplet and the number of notes left in it
+    tuplet: Option<(f64, usize)>,
+    /// Repeat signs and the starts of endings with their times in whole notes
+    repeats: Vec<(f64, Repeat)>,
+}
+
+impl Voice {
+    fn new(id: Option<String>) -> Self {
+        Self {
+            id,
+            notes: vec![],
+            grace_notes: vec![],
+            pending_grace_notes: vec![],
+            dynamic: DEFAULT_VELOCITY,
+            accent: false,
+            staccato: false,
+            time: 0.0,
+            meter: None,
+            unit: None,
+            key: None,
+            transpose: None,
+            measure_accidentals: HashMap::new(),
+            tied_accidentals: HashMap::new(),
+            ties: vec![],
+            last_pitches: vec![],
+            last_length: 0.0,
+            broken_rhythm: 1.0,
+            tuplet: None,
+            repeats: vec![],
+        }
+    }
+
+    /// Returns the notes in the order they are played, going through repeats and
+    /// endings
+    ///
+    /// # Arguments
+    ///
+    /// * tempo - The tempo changes of the tune sorted by time, see [`to_micros`]
+    ///
+    /// # Return value
+    ///
+    /// The notes in time order
+    fn played_notes(&self, tempo: &[(f64, f64)]) -> Vec<PlayedNote> {
+        // grace notes go through repeats with the notes they ornament, and stay before
+        // them if there is no time before them at the start of the tune
+        let notes = self
+            .grace_notes
+            .iter()
+            .copied()
+            .chain(self.notes.iter().map(|&note| (note, 0)))
+            .map(|(note, before)| {
+                let start = to_micros(tempo, note.time);
+                let duration = match before {
+                    0 => to_micros(tempo, note.time + note.length).saturating_sub(start),
+                    _ => GRACE_NOTE_LENGTH,
+                };
+                (start, (note.pitch, note.velocity, duration, before))
+            })
+            .collect::<Vec<_>>();
+        let repeats = self
+            .repeats
+            .iter()
+            .map(|(time, repeat)| (to_micros(tempo, *time), repeat.clone()))
+            .collect::<Vec<_>>();
+        unfold_repeats(&notes, &repeats, to_micros(tempo, self.time))
+            .into_iter()
+            .map(|(time, (pitch, velocity, duration, before))| PlayedNote {
+                note: ScoreNote {
+                    time: time.saturating_sub(before * GRACE_NOTE_LENGTH),
+                    pitch,
+                },
+                duration,
+                velocity,
+                grace: before > 0,
+            })
+            .sorted_by_key(|played| played.note.time)
+            .collect()
+    }
+}
+
+/// The parsing state of one tune
+struct Tune {
+    /// The meter of the header
+    meter: Meter,
+    /// The unit note length of the header in whole notes, or `None` until set by an L:
+    /// field or by the default for the meter at the end of the header
+    unit: Option<f64>,
+    /// The tempo changes in the order they are written, as the times in whole notes
+    /// when they take effect and the new lengths of a whole note in microseconds
+    tempo: Vec<(f64, f64)>,
+    /// The values of the M:, L: and Q: fields of the header, for rejecting conflicting
+    /// duplicates
+    header_fields: HashMap<char, String>,
+    /// Semitones added by the key signature of the header to each note letter
+    key: HashMap<char, i8>,
+    /// Semitones added to the written pitches of all voices by a transposition in the
+    /// header, e.g. for a tune for a B♭ clarinet
+    transpose: i32,
+    /// Whether the header has ended with its K: field
+    in_body: bool,
+    /// The voice being parsed
+    voice: Voice,
+    /// All voices in the order they first appear, with the one being parsed moved out
+    /// to `voice`
+    voices: Vec<Voice>,
+    /// The index of the voice being parsed in `voices`
+    current_voice: usize,
+}
+
+impl Default for Tune {
+    fn default() -> Self {
+        Self {
+            meter: Meter::default(),
+            unit: None,
+            tempo: vec![(0.0, DEFAULT_WHOLE_NOTE_LENGTH)],
+            header_fields: HashMap::new(),
+            key: HashMap::new(),
+            transpose: 0,
+            in_body: false,
+            voice: Voice::new(None),
+            voices: vec![Voice::new(None)],
+            current_voice: 0,
+        }
+    }
+}
+
+impl Tune {
+    /// The unit note length of the voice being parsed, by default an eighth or a
+    /// sixteenth note depending on the meter of the header
+    fn unit(&self) -> f64 {
+        self.voice
+            .unit
+            .or(self.unit)
+            .unwrap_or_else(|| self.meter.default_unit())
+    }
+
+    /// The meter of the voice being parsed
+    fn meter(&self) -> Meter {
+        self.voice.meter.unwrap_or(self.meter)
+    }
+
+    fn apply_field(&mut self, field: char, value: &str) -> Result<(), String> {
+        let invalid = || format!("invalid {}: field '{}'", field, value);
+        if !self.in_body && "MLQ".contains(field) {
+            // repeating the same value is harmless, but a different value would make the
+            // meter, unit note length or tempo of the tune ambiguous
+            match self.header_fields.get(&field) {
+                Some(previous) if previous != value => {
+                    return Err(format!(
+                        "conflicting {0}: fields '{1}' and '{2}' in the header",
+                        field, previous, value
+                    ));
+                }
+                Some(_) => return Ok(()),
+                None => {
+                    self.header_fields.insert(field, value.to_string());
+                }
+            }
+        }
+        match field {
+            // meter and unit note length changes in the music apply to the voice being
+            // parsed only
+            'M' => {
+                let meter = Meter::parse(value).ok_or_else(invalid)?;
+                match self.in_body {
+                    true => self.voice.meter = Some(meter),
+                    false => self.meter = meter,
+                }
+            }
+            'L' => {
+                let unit = fraction(value)
+                    .filter(|&unit| unit > 0.0)
+                    .ok_or_else(invalid)?;
+                match self.in_body {
+                    true => self.voice.unit = Some(unit),
+                    false => self.unit = Some(unit),
+                }
+            }
+            'Q' => {
+                // e.g. `Q:1/4=120`, `Q:"Allegro" 3/8=80` or `Q:1/4 3/8=40` with the beat
+                // as a sum of note lengths, or just the number of units per minute in old
+                // tunes
+                let value = value.split('"').step_by(2).collect::<String>();
+                if value.trim().is_empty() {
+                    // only a tempo name like `Q:"Allegro"`
+                    return Ok(());
+                }
+                let (beat, per_minute) = match value.split_once('=') {
+                    Some((beat, per_minute)) => {
+                        let beat = beat
+                            .split_whitespace()
+                            .map(fraction)
+                            .sum::<Option<f64>>()
+                            .filter(|&beat| beat > 0.0)
+                            .ok_or_else(invalid)?;
+                        (beat, per_minute)
+                    }
+                    None => (self.unit(), value.as_str()),
+                };
+                let per_minute = fraction(per_minute)
+                    .filter(|&per_minute| per_minute > 0.0)
+                    .ok_or_else(invalid)?;
+                // tempo changes take effect in all voices at the same point of the music
+                self.tempo
+                    .push((self.voice.time, 60_000_000.0 / (beat * per_minute)));
+            }
+            'K' => {
+                let key = key_signature(value).ok_or_else(invalid)?;
+                // a key change in the music applies to the voice being parsed only
+                match self.in_body {
+                    true => self.voice.key = Some(key),
+                    false => self.key = key,
+                }
+                self.voice.measure_accidentals.clear();
+                self.apply_transpose_setting(value)?;
+            }
+            'V' => {
+                let id = value.split_whitespace().next().ok_or_else(invalid)?;
+                self.switch_voice(id);
+                // a transposition in a V: field applies to the voice wherever it is
+                if let Some(semitones) = transpose_setting(value)? {
+                    self.voice.transpose = Some(semitones);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Applies a `%%transpose` or `%%MIDI transpose` directive, ignoring other
+    /// directives
+    fn apply_directive(&mut self, directive: &str) -> Result<(), String> {
+        let words = directive.split_whitespace().collect::<Vec<_>>();
+        let semitones = match words.as_slice() {
+            ["transpose", semitones] | ["MIDI", "transpose", semitones] => semitones,
+            _ => return Ok(()),
+        };
+        self.set_transpose(
+            semitones
+                .parse()
+                .map_err(|_| format!("invalid transposition '{}'", semitones))?,
+        );
+        Ok(())
+    }
+
+    /// Applies a `transpose=` setting in a K: field, if there is one
+    fn apply_transpose_setting(&mut self, value: &str) -> Result<(), String> {
+        if let Some(semitones) = transpose_setting(value)? {
+            self.set_transpose(semitones);
+        }
+        Ok(())
+    }
+
+    /// Sets the transposition of the whole tune in the header, or of the voice being
+    /// parsed in the music
+    fn set_transpose(&mut self, semitones: i32) {
+        match self.in_body {
+            true => self.voice.transpose = Some(semitones),
+            false => self.transpose = semitones,
+        }
+    }
+
+    /// The semitones added to the written pitches of the voice being parsed
+    fn transpose(&self) -> i32 {
+        self.voice.transpose.unwrap_or(self.transpose)
+    }
+
+    /// Parses one line of music
+    ///
+    /// # Return value
+    ///
+    /// Nothing, or the problem found with its column and token but without the line
+    fn parse_music(&mut self, line: &str) -> Result<(), AbcError> {
+        let chars = line.chars().collect::<Vec<_>>();
+        let mut token = 0..0;
+        self.parse_symbols(&chars, &mut token).map_err(|message| {
+            let end = token.end.clamp(token.start + 1, chars.len());
+            AbcError {
+                column: Some(token.start + 1),
+                token: chars.get(token.start..end).map(String::from_iter),
+                ..AbcError::new(message)
+            }
+        })
+    }
+
+    /// Parses the symbols of one line of music
+    ///
+    /// # Arguments
+    ///
+    /// * chars - The characters of the line
+    /// * token - Set to the character range of the symbol being parsed, for locating
+    ///   problems
+    fn parse_symbols(&mut self, chars: &[char], token: &mut Range<usize>) -> Result<(), String> {
+        let mut i = 0;
+        // the index of the opening bracket of the chord being parsed
+        let mut chord_bracket = 0;
+        // the start time and length of the chord being parsed, and the index of its
+        // first note in the score
+        let mut chord: Option<(f64, Option<f64>, usize)> = None;
+        // pitches tied from inside the chord being parsed
+        let mut chord_ties = vec![];
+        while i < chars.len() {
+            let c = chars[i];
+            *token = i..i + 1;
+            i += 1;
+            match c {
+                '|' | ':' => {
+                    self.end_measure();
+                    let start = i - 1;
+                    while chars.get(i).is_some_and(|c| matches!(c, '|' | ':')) {
+                        i += 1;
+                    }
+                    let bar = chars[start..i].iter().collect::<String>();
+                    if bar.len() > 1 && bar.starts_with(':') {
+                        self.mark(Repeat::End);
+                    }
+                    if bar.len() > 1 && bar.ends_with(':') {
+                        self.mark(Repeat::Start);
+                    }
+                    if bar.ends_with('|') && chars.get(i) == Some(&']') {
+                        // a final bar `|]`
+                        i += 1;
+                    }
+                    // endings like `|1`, `:|2` and `|[1`
+                    if chars.get(i) == Some(&'[')
+                        && chars.get(i + 1).is_some_and(char::is_ascii_digit)
+                    {
+                        i += 1;
+                    }
+                    if chars.get(i).is_some_and(char::is_ascii_digit) {
+                        let (passes, next) = ending_passes(chars, i);
+                        i = next;
+                        self.mark(Repeat::Ending(passes));
+                    }
+                }
+                '[' if chars.get(i + 1) == Some(&':') => {
+                    // an inline field like `[K:D]`
+                    let end = find(chars, i, ']')?;
+                    token.end = end + 1;
+                    let text = chars[i..end].iter().collect::<String>();
+                    let (field, value) = field(&text).ok_or("invalid inline field")?;
+                    self.apply_field(field, value)?;
+                    i = end + 1;
+                }
+                '[' if chars.get(i).is_some_and(char::is_ascii_digit) => {
+                    // endings like `[2`
+                    let (passes, next) = ending_passes(chars, i);
+                    i = next;
+                    self.mark(Repeat::Ending(passes));
+                }
+                '[' if chars.get(i) == Some(&'|') => {
+                    // the thick part of a thick-thin bar `[|`
+                }
+                '[' => {
+                    self.start_event();
+                    chord_bracket = i - 1;
+                    chord = Some((self.voice.time, None, self.voice.notes.len()));
+                }
+                ']' => {
+                    let (start, length, first_note) = chord.take().ok_or("unexpected ']'")?;
+                    // chords are written in any order, and a unison sounds one key
+                    let mut chord_notes = self.voice.notes.split_off(first_note);
+                    chord_notes.sort_by_key(|note| note.pitch);
+                    chord_notes.dedup_by_key(|note| note.pitch);
+                    self.voice.notes.extend(chord_notes);
+                    let (multiplier, next) = note_length(chars, i)?;
+                    i = next;
+                    for note in &mut self.voice.notes[first_note..] {
+                        note.length *= multiplier;
+                    }
+                    self.voice.time = start + length.unwrap_or(0.0) * multiplier;
+                    self.end_event(length.unwrap_or(0.0) * multiplier);
+                    self.voice.ties = std::mem::take(&mut chord_ties);
+                }
+                '"' => i = find(chars, i, '"')? + 1,
+                '!' | '+' => {
+                    let end = find(chars, i, c)?;
+                    self.apply_decoration(&chars[i..end].iter().collect::<String>());
+                    i = end + 1;
+                }
+                '.' => self.voice.staccato = true,
+                // an accent in old tunes
+                'L' => self.voice.accent = true,
+                '{' => {
+                    let end = find(chars, i, '}')?;
+                    while i < end {
+                        match chars[i] {
+                            // acciaccaturas are written with a slash like `{/g}`
+                            '/' | ' ' => i += 1,
+                            '^' | '_' | '=' | 'A'..='G' | 'a'..='g' => {
+                                let (pitch, _, next) = self.parse_note(&chars[..end], i)?;
+                                self.voice.pending_grace_notes.extend(pitch);
+                                i = next;
+                            }
+                            c => return Err(format!("unexpected '{}' in grace notes", c)),
+                        }
+                    }
+                    i = end + 1;
+                }
+                '(' if chars.get(i).is_some_and(char::is_ascii_digit) => {
+                    // `(p:q:r` puts p notes into the time of q for the next r notes, with
+                    // q and r optional
+                    let number = |i: &mut usize| {
+                        let start = *i;
+                        while chars.get(*i).is_some_and(char::is_ascii_digit) {
+                            *i += 1;
+                        }
+                        chars[start..*i]
+                            .iter()
+                            .collect::<String>()
+                            .parse::<usize>()
+                            .ok()
+                    };
+                    let optional_number = |i: &mut usize| match chars.get(*i) {
+                        Some(':') => {
+                            *i += 1;
+                            number(i)
+                        }
+                        _ => None,
+                    };
+                    let notes = number(&mut i).filter(|&notes| notes > 0);
+                    let in_time_of = optional_number(&mut i);
+                    let count = optional_number(&mut i);
+                    let notes = notes.ok_or_else(|| "invalid tuplet".to_string())?;
+                    let in_time_of = in_time_of.unwrap_or(match notes {
+                        2 | 4 | 8 => 3,
+                        5 | 7 | 9 if self.meter().compound => 3,
+                        _ => 2,
+                    });
+                    self.voice.tuplet =
+                        Some((in_time_of as f64 / notes as f64, count.unwrap_or(notes)));
+                }
+                '-' => match chord {
+                    Some(_) => chord_ties.extend(self.voice.last_pitches.last()),
+                    None => self.voice.ties = self.voice.last_pitches.clone(),
+                },
+                '>' | '<' => {
+                    let mut count = 1;
+                    while chars.get(i) == Some(&c) {
+                        count += 1;
+                        i += 1;
+                    }
+                    let shortened = 0.5f64.powi(count);
+                    let (previous, next) = match c {
+                        '>' => (2.0 - shortened, shortened),
+                        _ => (shortened, 2.0 - shortened),
+                    };
+                    self.voice.time += self.voice.last_length * (previous - 1.0);
+                    self.voice.broken_rhythm = next;
+                }
+                'Z' => {
+                    let (measures, next) = note_length(chars, i)?;
+                    i = next;
+                    self.start_event();
+                    let length = measures * self.meter().length;
+                    self.voice.time += length;
+                    self.end_event(length);
+                }
+                '^' | '_' | '=' | 'A'..='G' | 'a'..='g' | 'z' | 'x' => {
+                    i -= 1;
+                    if chord.is_none() {
+                        self.start_event();
+                    }
+                    let start = chord.map_or(self.voice.time, |(start, _, _)| start);
+                    let (pitch, length, next) = self.parse_note(chars, i)?;
+                    i = next;
+                    if let Some(pitch) = pitch {
+                        let sounding = match self.voice.staccato {
+                            true => length * STACCATO_LENGTH,
+                            false => length,
+                        };
+                        let tied = match self.voice.ties.contains(&pitch) {
+                            true => self
+                                .voice
+                                .notes
+                                .iter_mut()
+                                .rfind(|note| note.pitch == pitch),
+                            false => None,
+                        };
+                        match tied {
+                            // a tied note sounds until the end of the note it is tied to
+                            Some(tied) => tied.length = start + sounding - tied.time,
+                            None => {
+                                let velocity = match self.voice.accent {
+                                    true => self.voice.dynamic.as_int() + ACCENT_VELOCITY,
+                                    false => self.voice.dynamic.as_int(),
+                                };
+                                self.voice.notes.push(WrittenNote {
+                                    time: start,
+                                    pitch,
+                                    length: sounding,
+                                    velocity: u7::new(velocity.min(127)),
+                                });
+                            }
+                        }
+                        self.voice.last_pitches.push(pitch);
+                    }
+                    match &mut chord {
+                        Some((_, chord_length, _)) => {
+                            chord_length.get_or_insert(length);
+                        }
+                        None => {
+                            self.voice.time += length;
+                            self.end_event(length);
+                        }
+                    }
+                }
+                // slurs don't change the notes
+                ' ' | '\t' | '`' | '~' | '(' | ')' | 'y' | '$' | '\\' | 'H'..='Y' | 'u' | 'v' => {}
+                _ => return Err(format!("unexpected '{}'", c)),
+            }
+        }
+        match chord {
+            Some(_) => {
+                *token = chord_bracket..chars.len();
+                Err("unterminated chord".to_string())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Makes a voice the one being parsed, creating it on its first appearance
+    fn switch_voice(&mut self, id: &str) {
+        if self.voice.id.as_deref() == Some(id) {
+            return;
+        }
+        if self.voice.id.is_none() && self.voice.notes.is_empty() && self.voice.time == 0.0 {
+            // music before the first V: field belongs to the first voice
+            self.voice.id = Some(id.to_string());
+            return;
+        }
+        std::mem::swap(&mut self.voice, &mut self.voices[self.current_voice]);
+        self.current_voice = match self
+            .voices
+            .iter()
+            .position(|voice| voice.id.as_deref() == Some(id))
+        {
+            Some(index) => index,
+            None => {
+                self.voices.push(Voice::new(Some(id.to_string())));
+                self.voices.len() - 1
+            }
+        };
+        std::mem::swap(&mut self.voice, &mut self.voices[self.current_voice]);
+    }
+
+    /// Makes the first voice the one being parsed at the end of the header, since music
+    /// without a V: field belongs to it
+    fn start_body(&mut self) {
+        self.in_body = true;
+        // the default unit note length depends on the meter of the header only
+        self.unit.get_or_insert(self.meter.default_unit());
+        let first = match self.current_voice {
+            0 => &self.voice,
+            _ => &self.voices[0],
+        };
+        if let Some(id) = first.id.clone() {
+            self.switch_voice(&id);
+        }
+    }
+
+    /// Finishes parsing
+    ///
+    /// # Return value
+    ///
+    /// All voices in the order they first appear
+    fn into_voices(mut self) -> Vec<Voice> {
+        std::mem::swap(&mut self.voice, &mut self.voices[self.current_voice]);
+        self.voices
+    }
+
+    /// Records a repeat sign or the start of an ending at the current time
+    fn mark(&mut self, repeat: Repeat) {
+        self.voice.repeats.push((self.voice.time, repeat));
+    }
+
+    /// Forgets the accidentals of a measure at a bar line, except for notes tied over the
+    /// bar line, which keep their pitch
+    fn end_measure(&mut self) {
+        let transpose = self.transpose();
+        let voice = &mut self.voice;
+        voice.tied_accidentals = voice
+            .measure_accidentals
+            .drain()
+            .filter(|&((letter, octave), semitones)| {
+                letter_pitch(letter, octave, semitones as i32 + transpose)
+                    .is_some_and(|pitch| voice.ties.contains(&pitch))
+            })
+            .collect();
+    }
+
+    /// Applies a decoration like `!f!` or `!>!` to the voice being parsed, ignoring
+    /// decorations without an effect on the notes played
+    fn apply_decoration(&mut self, name: &str) {
+        let voice = &mut self.voice;
+        voice.dynamic = match name {
+            "pppp" | "ppp" => u7::new(30),
+            "pp" => u7::new(45),
+            "p" => u7::new(60),
+            "mp" => u7::new(75),
+            "mf" => u7::new(90),
+            "f" => u7::new(105),
+            "ff" => u7::new(120),
+            "fff" | "ffff" => u7::new(127),
+            ">" | "accent" | "emphasis" => {
+                voice.accent = true;
+                return;
+            }
+            "staccato" => {
+                voice.staccato = true;
+                return;
+            }
+            _ => return,
+        };
+    }
+
+    /// Prepares for a note, rest or chord
+    fn start_event(&mut self) {
+        let voice = &mut self.voice;
+        voice.last_pitches.clear();
+        let count = voice.pending_grace_notes.len();
+        voice
+            .grace_notes
+            .extend(
+                voice
+                    .pending_grace_notes
+                    .drain(..)
+                    .enumerate()
+                    .map(|(index, pitch)| {
+                        let note = WrittenNote {
+                            time: voice.time,
+                            pitch,
+                            length: 0.0,
+                            velocity: voice.dynamic,
+                        };
+                        (note, (count - index) as u64)
+                    }),
+            );
+    }
+
+    /// Finishes a note, rest or chord and prepares the tie, broken rhythm and tuplet
+    /// state for the next one
+    fn end_event(&mut self, length: f64) {
+        self.voice.ties.clear();
+        self.voice.tied_accidentals.clear();
+        self.voice.last_length = length;
+        self.voice.broken_rhythm = 1.0;
+        self.voice.accent = false;
+        self.voice.staccato = false;
+        self.voice.tuplet = match self.voice.tuplet {
+            Some((factor, notes)) if notes > 1 => Some((factor, notes - 1)),
+            _ => None,
+        };
+    }
+
+    /// Parses a note or a rest with its accidental, octave and length
+    ///
+    /// # Return value
+    ///
+    /// A 3-tuple of
+    /// * the pitch of the note, or `None` for a rest
+    /// * the length of the note in microseconds
+    /// * the index of the first character after the note
+    fn parse_note(
+        &mut self,
+        chars: &[char],
+        mut i: usize,
+    ) -> Result<(Option<u7>, f64, usize), String> {
+        let mut accidental = None;
+        while let Some(&c) = chars.get(i) {
+            let semitones = match c {
+                '^' => 1,
+                '_' => -1,
+                '=' => 0,
+                _ => break,
+            };
+            accidental = Some(accidental.unwrap_or(0) + semitones);
+            i += 1;
+        }
+        let letter = *chars.get(i).ok_or("missing note after accidental")?;
+        i += 1;
+        let mut octave = if letter.is_ascii_lowercase() { 1 } else { 0 };
+        while let Some(&c) = chars.get(i) {
+            match c {
+                ',' => octave -= 1,
+                '\'' => octave += 1,
+                _ => break,
+            }
+            i += 1;
+        }
+        let (multiplier, i) = note_length(chars, i)?;
+        let factor = self.voice.tuplet.map_or(1.0, |(factor, _)| factor) * self.voice.broken_rhythm;
+        let length = multiplier * self.unit() * factor;
+        let letter = letter.to_ascii_uppercase();
+        // rests
+        if !"CDEFGAB".contains(letter) {
+            return Ok((None, length, i));
+        }
+        let alteration = match accidental {
+            Some(semitones) => {
+                self.voice
+                    .measure_accidentals
+                    .insert((letter, octave), semitones);
+                semitones
+            }
+            None => match self
+                .voice
+                .measure_accidentals
+                .get(&(letter, octave))
+                .or_else(|| self.voice.tied_accidentals.get(&(letter, octave)))
+            {
+                Some(&semitones) => semitones,
+                None => {
+                    let key = self.voice.key.as_ref().unwrap_or(&self.key);
+                    *key.get(&letter).unwrap_or(&0)
+                }
+            },
+        };
+        let pitch = letter_pitch(letter, octave, alteration as i32 + self.transpose())
+            .ok_or_else(|| format!("note {} out of range", letter))?;
+        Ok((Some(pitch), length, i))
+    }
+}
+
+/// Calculates the pitch of a note
+///
+/// # Arguments
+///
+/// * letter - The uppercase note letter
+/// * octave - The octave relative to the one starting from middle C
+/// * alteration - The semitones added by accidentals or the key signature, and by
+///   transposition
+fn letter_pitch(letter: char, octave: i8, alteration: i32) -> Option<u7> {
+    let step = letter_step(letter)?;
+    let pitch = 60 + 12 * octave as i32 + step + alteration;
+    u8::try_from(pitch).ok().and_then(u7::try_from)
+}
+
+/// Returns the semitones from C to an uppercase note letter
+fn letter_step(letter: char) -> Option<i32> {
+    match letter {
+        'C' => Some(0),
+        'D' => Some(2),
+        'E' => Some(4),
+        'F' => Some(5),
+        'G' => Some(7),
+        'A' => Some(9),
+        'B' => Some(11),
+        _ => None,
+    }
+}
+
+/// Finds the index of a character in a line, starting from a given index
+fn find(chars: &[char], start: usize, c: char) -> Result<usize, String> {
+    chars[start..]
+        .iter()
+        .position(|&other| other == c)
+        .map(|position| start + position)
+        .ok_or_else(|| format!("missing closing '{}'", c))
+}
+
+/// Parses a note length multiplier like `3`, `/`, `3/2` or `//`
+///
+/// # Return value
+///
+/// A 2-tuple of
+/// * the multiplier, 1.0 if there is none
+/// * the index of the first character after the multiplier
+///
+/// or an error for a zero length or a division by zero, e.g. `C/0`
+fn note_length(chars: &[char], mut i: usize) -> Result<(f64, usize), String> {
+    let start = i;
+    let number = |i: &mut usize| {
+        let start = *i;
+        while chars.get(*i).is_some_and(char::is_ascii_digit) {
+            *i += 1;
+        }
+        chars[start..*i]
+            .iter()
+            .collect::<String>()
+            .parse::<f64>()
+            .ok()
+    };
+    let mut multiplier = number(&mut i).unwrap_or(1.0);
+    while chars.get(i) == Some(&'/') {
+        i += 1;
+        multiplier /= number(&mut i).unwrap_or(2.0);
+    }
+    if !multiplier.is_finite() || multiplier <= 0.0 {
+        let length = chars[start..i].iter().collect::<String>();
+        return Err(format!("invalid note length '{}'", length));
+    }
+    Ok((multiplier, i))
+}
+
+/// Finds the `transpose=` setting in a K: or V: field value like `Bb transpose=-2`
+///
+/// # Return value
+///
+/// The transposition in semitones, or `None` if the field doesn't have the setting
+fn transpose_setting(value: &str) -> Result<Option<i32>, String> {
+    value
+        .split_whitespace()
+        .find_map(|word| word.strip_prefix("transpose="))
+        .map(|semitones| {
+            semitones
+                .parse()
+                .map_err(|_| format!("invalid transposition '{}'", semitones))
+        })
+        .transpose()
+}
+
+/// Finds out the alterations of a key signature like `G`, `Bbm`, `D dorian` or
+/// `D exp _b ^f`
+///
+/// Explicit accidentals after the key and mode are added to the key signature, or
+/// replace it after `exp`.
+///
+/// # Return value
+///
+/// The semitones added to each altered note letter, or `None` for an unknown key
+fn key_signature(value: &str) -> Option<HashMap<char, i8>> {
+    // clef and other settings like `clef=bass` may follow the key
+    let words = value
+        .split_whitespace()
+        .take_while(|word| !word.trim_start_matches('=').contains('='))
+        .collect::<Vec<_>>();
+    let (accidentals, name): (Vec<&str>, Vec<&str>) = words
+        .iter()
+        .partition(|word| word.starts_with(['^', '_', '=']));
+    let explicit = name.iter().any(|word| word.eq_ignore_ascii_case("exp"));
+    let name = name
+        .iter()
+        .filter(|word| !word.eq_ignore_ascii_case("exp"))
+        .copied()
+        .collect::<String>();
+    let mut key = match name.as_str() {
+        _ if explicit => HashMap::new(),
+        // the Highland pipe scale, written with sharp Fs and Cs and natural Gs
+        "Hp" => HashMap::from([('F', 1), ('C', 1)]),
+        _ => named_key_signature(&name.to_lowercase())?,
+    };
+    for accidental in accidentals {
+        let letter = accidental.trim_start_matches(['^', '_', '=']);
+        let semitones = match &accidental[..accidental.len() - letter.len()] {
+            "^" => 1,
+            "^^" => 2,
+            "_" => -1,
+            "__" => -2,
+            "=" => 0,
+            _ => return None,
+        };
+        let mut letter = letter.chars();
+        match (letter.next(), letter.next()) {
+            (Some(letter), None) if "CDEFGAB".contains(letter.to_ascii_uppercase()) => {
+                key.insert(letter.to_ascii_uppercase(), semitones);
+            }
+            _ => return None,
+        }
+    }
+    key.retain(|_, semitones| *semitones != 0);
+    Some(key)
+}
+
+/// Finds out the alterations of a key signature from the lowercase name of the tonic
+/// and the mode, like `bbm` or `ddorian`
+fn named_key_signature(name: &str) -> Option<HashMap<char, i8>> {
+    if name.is_empty() || name == "none" || name == "hp" {
+        return Some(HashMap::new());
+    }
+    let mut chars = name.chars();
+    let mut fifths = match chars.next()? {
+        'f' => -1,
+        'c' => 0,
+        'g' => 1,
+        'd' => 2,
+        'a' => 3,
+        'e' => 4,
+        'b' => 5,
+        _ => return None,
+    };
+    let mut mode = chars.as_str();
+    if let Some(rest) = mode.strip_prefix('#') {
+        fifths += 7;
+        mode = rest;
+    } else if let Some(rest) = mode.strip_prefix('b') {
+        fifths -= 7;
+        mode = rest;
+    }
+    // modes may be written in full, and only their first three letters count
+    let mode = mode.chars().take(3).collect::<String>();
+    fifths += match mode.as_str() {
+        "" | "maj" | "ion" => 0,
+        "m" | "min" | "aeo" => -3,
+        "mix" => -1,
+        "dor" => -2,
+        "phr" => -4,
+        "loc" => -5,
+        "lyd" => 1,
+        _ => return None,
+    };
+    fifths_key_signature(fifths)
+}
+
+/// Finds out the alterations of a key signature with a number of sharps, or a negative
+/// number of flats
+fn fifths_key_signature(fifths: i32) -> Option<HashMap<char, i8>> {
+    let mut key = HashMap::new();
+    match fifths {
+        0 => {}
+        1..=7 => key.extend(SHARPS.chars().take(fifths as usize).map(|c| (c, 1))),
+        -7..=-1 => key.extend(FLATS.chars().take(-fifths as usize).map(|c| (c, -1))),
+        _ => return None,
+    }
+    Some(key)
+}
+
+/// Settings for writing a score in ABC notation with [`score_to_abc`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AbcExportConfig {
+    /// The unit note length as a fraction of a whole note, e.g. 8 for `L:1/8`, which is
+    /// also the grid note times are rounded to
+    pub unit: u32,
+    /// The key signature, used for spelling the notes
+    pub key: KeySignature,
+    /// The time signature, used for placing bar lines
+    pub meter: TimeSignature,
+    /// The tempo in quarter notes per minute
+    pub tempo: f64,
+}
+
+impl Default for AbcExportConfig {
+    fn default() -> Self {
+        Self {
+            unit: 8,
+            key: KeySignature {
+                sharps: 0,
+                minor: false,
+            },
+            meter: DEFAULT_TIME_SIGNATURE,
+            tempo: 120.0,
+        }
+    }
+}
+
+/// Writes a score in ABC notation, e.g. for proofreading a recorded performance or a
+/// converted MIDI file
+///
+/// Score notes have no durations, so each note or chord lasts until the next one starts,
+/// and the last one until the end of its measure. Notes and chords crossing a bar line
+/// are tied over it.
+///
+/// # Arguments
+///
+/// * score - The notes to write
+/// * config - The unit note length, key, meter and tempo of the tune
+///
+/// # Return value
+///
+/// The tune with its header fields
+pub fn score_to_abc(score: &[ScoreNote], config: &AbcExportConfig) -> String {
+    let key = config.key;
+    let key_alterations = fifths_key_signature(key.sharps as i32).unwrap_or_default();
+    let key_name = match key.minor {
+        true => MINOR_KEYS.get((key.sharps + 7) as usize),
+        false => MAJOR_KEYS.get((key.sharps + 7) as usize),
+    };
+    let mut abc = format!(
+        "X:1\nM:{}\nL:1/{}\nQ:1/4={}\nK:{}\n",
+        config.meter,
+        config.unit,
+        config.tempo,
+        key_name.unwrap_or(&"C")
+    );
+    let unit = config.unit.max(1) as u64;
+    let unit_micros = 60_000_000.0 / config.tempo * 4.0 / unit as f64;
+    let measure = (config.meter.numerator as u64 * unit / config.meter.denominator as u64).max(1);
+    // the pitches of each chord by its time in units, with a rest before the first one
+    let mut chords = vec![(0, vec![])];
+    for (time, notes) in &score
+        .iter()
+        .chunk_by(|note| (note.time as f64 / unit_micros).round() as u64)
+    {
+        let pitches = notes.map(|note| note.pitch).sorted().dedup().collect();
+        match chords.last_mut() {
+            Some((last_time, last_pitches)) if *last_time == time => {
+                last_pitches.extend::<Vec<u7>>(pitches);
+                last_pitches.sort();
+                last_pitches.dedup();
+            }
+            _ => chords.push((time, pitches)),
+        }
+    }
+    let mut measure_accidentals = HashMap::new();
+    let mut measures = 0;
+    for (index, (start, pitches)) in chords.iter().enumerate() {
+        let end = match chords.get(index + 1) {
+            Some((next, _)) => *next,
+            None => (start / measure + 1) * measure,
+        };
+        let mut time = *start;
+        while time < end {
+            let bar = (time / measure + 1) * measure;
+            let length = end.min(bar) - time;
+            let mut symbol = match pitches.len() {
+                0 => "z".to_string(),
+                1 => abc_note(
+                    pitches[0],
+                    &key_alterations,
+                    key.sharps < 0,
+                    &mut measure_accidentals,
+                ),
+                _ => format!(
+                    "[{}]",
+                    pitches
+                        .iter()
+                        .map(|&pitch| abc_note(
+                            pitch,
+                            &key_alterations,
+                            key.sharps < 0,
+                            &mut measure_accidentals
+                        ))
+                        .collect::<String>()
+                ),
+            };
+            if length > 1 {
+                symbol += &length.to_string();
+            }
+            time += length;
+            if time < end && !pitches.is_empty() {
+                symbol.push('-');
+            }
+            abc += &symbol;
+            if time == bar {
+                measure_accidentals.clear();
+                measures += 1;
+                // four measures on each line
+                abc += match measures % 4 {
+                    0 => " |\n",
+                    _ => " | ",
+                };
+            } else {
+                abc.push(' ');
+            }
+        }
+    }
+    if !abc.ends_with('\n') {
+        abc = abc.trim_end().to_string() + "\n";
+    }
+    abc
+}
+
+/// The ABC names of major keys from seven flats to seven sharps
+const MAJOR_KEYS: [&str; 15] = [
+    "Cb", "Gb", "Db", "Ab", "Eb", "Bb", "F", "C", "G", "D", "A", "E", "B", "F#", "C#",
+];
+/// The ABC names of minor keys from seven flats to seven sharps
+const MINOR_KEYS: [&str; 15] = [
+    "Abm", "Ebm", "Bbm", "Fm", "Cm", "Gm", "Dm", "Am", "Em", "Bm", "F#m", "C#m", "G#m", "D#m",
+    "A#m",
+];
+
+/// Writes a note in ABC notation with an accidental if the key signature and the
+/// earlier accidentals of the measure don't already give its pitch
+///
+/// # Arguments
+///
+/// * pitch - The pitch of the note
+/// * key - The semitones added by the key signature to each note letter
+/// * flats - Whether to spell pitches outside the key with flats instead of sharps
+/// * measure_accidentals - The accidentals written earlier in the measure by note letter
+///   and octave, updated with the accidental of this note
+fn abc_note(
+    pitch: u7,
+    key: &HashMap<char, i8>,
+    flats: bool,
+    measure_accidentals: &mut HashMap<(char, i8), i8>,
+) -> String {
+    let pitch = pitch.as_int() as i32;
+    let spells = |letter: char, alteration: i8| {
+        (letter_step(letter).unwrap() + alteration as i32 - pitch).rem_euclid(12) == 0
+    };
+    let outside_key = if flats { -1 } else { 1 };
+    // the key's own spelling, then a natural, then a sharp or a flat
+    let (letter, alteration) = "CDEFGAB"
+        .chars()
+        .map(|letter| (letter, *key.get(&letter).unwrap_or(&0)))
+        .chain("CDEFGAB".chars().map(|letter| (letter, 0)))
+        .chain("CDEFGAB".chars().map(|letter| (letter, outside_key)))
+        .find(|&(letter, alteration)| spells(letter, alteration))
+        .unwrap();
+    let octave = ((pitch - 60 - letter_step(letter).unwrap() - alteration as i32) / 12) as i8;
+    let current = measure_accidentals
+        .get(&(letter, octave))
+        .or_else(|| key.get(&letter))
+        .copied()
+        .unwrap_or(0);
+    let mut note = String::new();
+    if alteration != current {
+        note += match alteration {
+            1 => "^",
+            -1 => "_",
+            _ => "=",
+        };
+        measure_accidentals.insert((letter, octave), alteration);
+    }
+    match octave {
+        1.. => {
+            note.push(letter.to_ascii_lowercase());
+            note += &"'".repeat(octave as usize - 1);
+        }
+        _ => {
+            note.push(letter);
+            note += &",".repeat(-octave as usize);
+        }
+    }
+    note
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[test]
+    fn notes_and_lengths() {
+        let score = abc_into_score("X:1\nT:Scale\nL:1/4\nQ:1/4=60\nK:C\nCDE/E/|c2 G,|\n").unwrap();
+        assert_eq!(
+            score,
+            notes![
+                (0, 60),
+                (1000000, 62),
+                (2000000, 64),
+                (2500000, 64),
+                (3000000, 72),
+                (5000000, 55)
+            ]
+        );
+    }
+
+    #[test]
+    fn key_signature_and_accidentals() {
+        let score = abc_into_score("L:1/4\nK:G\nF =F F ^c|c _B B|[K:F]B\n").unwrap();
+        assert_eq!(
+            score
+                .iter()
+                .map(|note| note.pitch.as_int())
+                .collect::<Vec<_>>(),
+            [66, 65, 65, 73, 72, 70, 70, 70]
+        );
+    }
+
+    #[test]
+    fn chords_rests_and_ties() {
+        let score = abc_into_score("L:1/4\nQ:1/4=60\nK:C\n[CEG]2 z C-|C E\n").unwrap();
+        assert_eq!(
+            score,
+            notes![(0, 60), (0, 64), (0, 67), (3000000, 60), (5000000, 64)]
+        );
+    }
+
+    #[rstest(abc, expected,
+        case("[GEC] c", vec![(0, 60), (0, 64), (0, 67), (1000000, 72)]),
+        case("[CCE] c", vec![(0, 60), (0, 64), (1000000, 72)]),
+        case("[C2E]G", vec![(0, 60), (0, 64), (2000000, 67)]),
+        case("[CEG]3/2 c", vec![(0, 60), (0, 64), (0, 67), (1500000, 72)]),
+        case("[C E G] c", vec![(0, 60), (0, 64), (0, 67), (1000000, 72)]),
+        case("[!p!C\"Am\"E] c", vec![(0, 60), (0, 64), (1000000, 72)]),
+        case("[^FA] F|F", vec![(0, 66), (0, 69), (1000000, 66), (2000000, 65)]),
+        case("[CE]2- [CE] c", vec![(0, 60), (0, 64), (3000000, 72)]),
+        case("[C-E-][CF] c", vec![(0, 60), (0, 64), (1000000, 65), (2000000, 72)]),
+        case("[CE]/>[DF]/ G", vec![(0, 60), (0, 64), (750000, 62), (750000, 65), (1000000, 67)]),
+        case("(3[CE]/[DF]/[EG]/ A", vec![
+            (0, 60), (0, 64), (333333, 62), (333333, 65), (666667, 64), (666667, 67), (1000000, 69)
+        ]),
+    )]
+    fn chords(abc: &str, expected: Vec<(u64, u8)>) {
+        let score = abc_into_score(&format!("L:1/4\nQ:1/4=60\nK:C\n{}\n", abc)).unwrap();
+        assert_eq!(score, ScoreNote::from_tuples(&expected));
+    }
+
+    #[rstest(key, expected,
+        case("C", [60, 62, 64, 65, 67, 69, 71]),
+        case("G", [60, 62, 64, 66, 67, 69, 71]),
+        case("F", [60, 62, 64, 65, 67, 69, 70]),
+        case("Bb", [60, 62, 63, 65, 67, 69, 70]),
+        case("F#m", [61, 62, 64, 66, 68, 69, 71]),
+        case("C#", [61, 63, 65, 66, 68, 70, 72]),
+        case("Cb", [59, 61, 63, 64, 66, 68, 70]),
+        case("Am", [60, 62, 64, 65, 67, 69, 71]),
+        case("A minor", [60, 62, 64, 65, 67, 69, 71]),
+        case("Ador", [60, 62, 64, 66, 67, 69, 71]),
+        case("E Dorian", [61, 62, 64, 66, 67, 69, 71]),
+        case("DMix", [60, 62, 64, 66, 67, 69, 71]),
+        case("EPhr", [60, 62, 64, 65, 67, 69, 71]),
+        case("F Lydian", [60, 62, 64, 65, 67, 69, 71]),
+        case("BLoc", [60, 62, 64, 65, 67, 69, 71]),
+        case("Ebm", [59, 61, 63, 65, 66, 68, 70]),
+        case("G clef=bass", [60, 62, 64, 66, 67, 69, 71]),
+        case("D =c", [60, 62, 64, 66, 67, 69, 71]),
+        case("C ^f _b", [60, 62, 64, 66, 67, 69, 70]),
+        case("D exp _b", [60, 62, 64, 65, 67, 69, 70]),
+        case("Hp", [61, 62, 64, 66, 67, 69, 71]),
+        case("HP", [60, 62, 64, 65, 67, 69, 71]),
+        case("none", [60, 62, 64, 65, 67, 69, 71]),
+    )]
+    fn key_signatures(key: &str, expected: [u8; 7]) {
+        let score = abc_into_score(&format!("L:1/4\nK:{}\nCDEFGAB\n", key)).unwrap();
+        assert_eq!(
+            score
+                .iter()
+                .map(|note| note.pitch.as_int())
+                .collect::<Vec<_>>(),
+            expected
+        );
+    }
+
+    #[rstest(key, case("H"), case("C foo"), case("D ^x"), case("C ^^^f"))]
+    fn invalid_key_signature(key: &str) {
+        assert!(abc_into_score(&format!("K:{}\nC\n", key)).is_err());
+    }
+
+    #[test]
+    fn accidentals_in_key() {
+        // an accidental lasts until the end of the measure and only in its octave
+        let score = abc_into_score("L:1/4\nK:D\nF =F f F|F _B B b|B\n").unwrap();
+        assert_eq!(
+            score
+                .iter()
+                .map(|note| note.pitch.as_int())
+                .collect::<Vec<_>>(),
+            [66, 65, 78, 65, 66, 70, 70, 83, 71]
+        );
+    }
+
+    #[rstest(abc, expected,
+        case("(CD) E", vec![(0, 60), (1000000, 62), (2000000, 64)]),
+        case("(C-C) D", vec![(0, 60), (2000000, 62)]),
+        case("C-C-C D", vec![(0, 60), (3000000, 62)]),
+        case("C-\nC D", vec![(0, 60), (2000000, 62)]),
+        case("^C-|C D", vec![(0, 61), (2000000, 62)]),
+        case("^C-|C C", vec![(0, 61), (2000000, 60)]),
+        case("^C D|C", vec![(0, 61), (1000000, 62), (2000000, 60)]),
+        case("[CE]-[CE]", vec![(0, 60), (0, 64)]),
+    )]
+    fn ties_and_slurs(abc: &str, expected: Vec<(u64, u8)>) {
+        let score = abc_into_score(&format!("L:1/4\nQ:1/4=60\nK:C\n{}\n", abc)).unwrap();
+        assert_eq!(score, ScoreNote::from_tuples(&expected));
+    }
+
+    #[rstest(
+        abc,
+        expected,
+        case("|: C D :| E", "CDCDE"),
+        case("C D :| E", "CDCDE"),
+        case("|: C D |1 E :|2 F |]", "CDECDF"),
+        case("|: C [1 D :| [2 E :| [3 F |]", "CDCECF"),
+        case("|: C |[1,2 D :|[3 E |]", "CDCDCE"),
+        case("|: C :: D :|", "CCDD"),
+        case("|: C [1 D :| E", "CDCE"),
+        case("|: C D\nE F :|\n|: G :|]", "CDEFCDEFGG")
+    )]
+    fn repeats_and_endings(abc: &str, expected: &str) {
+        let score = abc_into_score(&format!("L:1/4\nK:C\n{}\n", abc)).unwrap();
+        let expected = abc_into_score(&format!("L:1/4\nK:C\n{}\n", expected)).unwrap();
+        assert_eq!(score, expected);
+    }
+
+    const VOICES: &str =
+        "X:1\nL:1/4\nQ:1/4=60\nV:S\nV:A\nK:C\nc d|\nV:A\nE [K:G]F|\nV:S\ne f|\n[V:A] G A|\n";
+
+    #[rstest(voices, expected,
+        case(vec![], vec![(0, 72), (0, 64), (1000000, 74), (1000000, 66), (2000000, 76), (2000000, 67), (3000000, 77), (3000000, 69)]),
+        case(vec!["S"], vec![(0, 72), (1000000, 74), (2000000, 76), (3000000, 77)]),
+        case(vec!["A"], vec![(0, 64), (1000000, 66), (2000000, 67), (3000000, 69)]),
+    )]
+    fn multiple_voices(voices: Vec<&str>, expected: Vec<(u64, u8)>) {
+        let voices = voices.into_iter().map(String::from).collect::<Vec<_>>();
+        assert_eq!(
+            abc_voices_into_score(VOICES, &voices).unwrap(),
+            ScoreNote::from_tuples(&expected)
+        );
+    }
+
+    #[rstest(
+        tempo,
+        expected,
+        case("1/4=120", 500000),
+        case("\"Allegro\" 1/4=120", 500000),
+        case("1/4=120 \"Allegro\"", 500000),
+        case("1/8 1/8=120", 500000),
+        case("3/8=40", 1000000),
+        case("\"Allegro\"", 500000),
+        case("60", 1000000)
+    )]
+    fn tempo_field(tempo: &str, expected: u64) {
+        let score = abc_into_score(&format!("L:1/4\nQ:{}\nK:C\nC D\n", tempo)).unwrap();
+        assert_eq!(score[1].time, expected);
+    }
+
+    #[test]
+    fn tempo_changes() {
+        let score = abc_into_score("L:1/4\nQ:1/4=60\nK:C\nC D [Q:1/4=120] E F|\n").unwrap();
+        assert_eq!(
+            score,
+            notes![(0, 60), (1000000, 62), (2000000, 64), (2500000, 65)]
+        );
+    }
+
+    #[test]
+    fn tempo_changes_in_all_voices() {
+        let score = abc_voices_into_score(
+            "L:1/4\nQ:1/4=60\nK:C\nV:1\nc d [Q:1/4=120] e f|\nV:2\nC D E F|\n",
+            &["2".to_string()],
+        )
+        .unwrap();
+        assert_eq!(
+            score,
+            notes![(0, 60), (1000000, 62), (2000000, 64), (2500000, 65)]
+        );
+    }
+
+    #[test]
+    fn tempo_changes_in_repeats() {
+        let score = abc_into_score("L:1/4\nQ:1/4=60\nK:C\n|: C [Q:1/4=120] D :|\n").unwrap();
+        assert_eq!(
+            score,
+            notes![(0, 60), (1000000, 62), (1500000, 60), (2500000, 62)]
+        );
+    }
+
+    #[rstest(
+        value,
+        length,
+        compound,
+        case("4/4", 1.0, false),
+        case("C", 1.0, false),
+        case("C|", 1.0, false),
+        case("none", 1.0, false),
+        case("3/4", 0.75, false),
+        case("6/8", 0.75, true),
+        case("12/8", 1.5, true),
+        case("5/4", 1.25, false),
+        case("2+3+2/8", 0.875, false),
+        case("(3+3)/8", 0.75, true),
+        case(" 7 / 8 ", 0.875, false)
+    )]
+    fn meters(value: &str, length: f64, compound: bool) {
+        assert_eq!(Meter::parse(value), Some(Meter { length, compound }));
+    }
+
+    #[rstest(value, case("x"), case("3/0"), case("0/4"), case("3+/4"))]
+    fn invalid_meters(value: &str) {
+        assert_eq!(Meter::parse(value), None);
+    }
+
+    #[rstest(abc, expected,
+        // the default unit note length comes from the meter of the header
+        case("M:2/4\nK:C\nC D", vec![(0, 60), (125000, 62)]),
+        case("M:3/4\nK:C\nC D", vec![(0, 60), (250000, 62)]),
+        case("M:2/4\nK:C\nC [M:4/4] D E", vec![(0, 60), (125000, 62), (250000, 64)]),
+        case("M:4/4\nK:C\nC\nM:2/4\nD E", vec![(0, 60), (250000, 62), (500000, 64)]),
+        case("K:C\nC [L:1/4] D E [L:1/16] F G", vec![(0, 60), (250000, 62), (750000, 64), (1250000, 65), (1375000, 67)]),
+        case("M:5/4\nL:1/4\nK:C\nZ C", vec![(2500000, 60)]),
+        case("M:2+3/8\nL:1/8\nK:C\nZ2 C", vec![(2500000, 60)]),
+        case("L:1/4\nK:C\nC [M:3/4] Z D", vec![(0, 60), (2000000, 62)]),
+        case("L:1\nK:C\nC D", vec![(0, 60), (2000000, 62)]),
+        case("L:1/4\nV:1\nV:2\nK:C\nV:1\n[L:1/8] C D\nV:2\nE F", vec![(0, 60), (0, 64), (250000, 62), (500000, 65)]),
+    )]
+    fn meter_and_unit_changes(abc: &str, expected: Vec<(u64, u8)>) {
+        let score = abc_into_score(&format!("Q:1/4=120\n{}\n", abc)).unwrap();
+        assert_eq!(score, ScoreNote::from_tuples(&expected));
+    }
+
+    #[rstest(meter, abc, expected,
+        case("4/4", "(3CDE F", vec![(0, 60), (333333, 62), (666667, 64), (1000000, 65)]),
+        case("4/4", "(2CD E", vec![(0, 60), (750000, 62), (1500000, 64)]),
+        case("4/4", "(5CDEFG A", vec![(0, 60), (200000, 62), (400000, 64), (600000, 65), (800000, 67), (1000000, 69)]),
+        case("6/8", "(5CDEFG A", vec![(0, 60), (300000, 62), (600000, 64), (900000, 65), (1200000, 67), (1500000, 69)]),
+        case("4/4", "(3:2:2C2D E", vec![(0, 60), (666667, 62), (1000000, 64)]),
+        case("4/4", "(3::2C2D E", vec![(0, 60), (666667, 62), (1000000, 64)]),
+        case("4/4", "(3:4CDE F", vec![(0, 60), (666667, 62), (1333333, 64), (2000000, 65)]),
+        case("4/4", "(3C z E F", vec![(0, 60), (666667, 64), (1000000, 65)]),
+        case("4/4", "(3[CE]DE F", vec![(0, 60), (0, 64), (333333, 62), (666667, 64), (1000000, 65)]),
+    )]
+    fn tuplets(meter: &str, abc: &str, expected: Vec<(u64, u8)>) {
+        let score =
+            abc_into_score(&format!("M:{}\nL:1/8\nQ:1/4=60\nK:C\n{}\n", meter, abc)).unwrap();
+        assert_eq!(score, ScoreNote::from_tuples(&expected));
+    }
+
+    #[test]
+    fn broken_rhythm_and_tuplet() {
+        let score = abc_into_score("L:1/8\nQ:1/4=60\nK:C\nC>D (3EFG A\n").unwrap();
+        assert_eq!(
+            score,
+            notes![
+                (0, 60),
+                (750000, 62),
+                (1000000, 64),
+                (1333333, 65),
+                (1666667, 67),
+                (2000000, 69)
+            ]
+        );
+    }
+
+    #[test]
+    fn first_tune_only() {
+        let score = abc_into_score("X:1\nK:C\nC\n\nX:2\nK:C\nD\n").unwrap();
+        assert_eq!(score.len(), 1);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            abc_into_score("X:1\nT:No key\n"),
+            Err(AbcError::new("missing K: field"))
+        );
+        assert_eq!(
+            abc_voices_into_score(VOICES, &["T".to_string()]).map_err(|err| err.to_string()),
+            Err("no voice 'T' in the tune".to_string())
+        );
+    }
+
+    #[rstest(
+        abc,
+        line,
+        column,
+        token,
+        message,
+        case("K:C\nC D\nE [F G\n", 3, 3, "[F G", "unterminated chord"),
+        case("K:H\n", 1, 3, "H", "invalid K: field 'H'"),
+        case("K:Céé\nC\n", 1, 3, "Céé", "invalid K: field 'Céé'"),
+        case("L:1/4\nM:x\nK:C\n", 2, 3, "x", "invalid M: field 'x'"),
+        case("L:1/0\nK:C\nC D\n", 1, 3, "1/0", "invalid L: field '1/0'"),
+        case(
+            "X:1\nM:3/4\nM:6/8\nK:C\n",
+            3,
+            3,
+            "6/8",
+            "conflicting M: fields '3/4' and '6/8' in the header"
+        ),
+        case(
+            "X:1\nL:1/8\nL: 1/4\nK:C\n",
+            3,
+            4,
+            "1/4",
+            "conflicting L: fields '1/8' and '1/4' in the header"
+        ),
+        case(
+            "X:1\nQ:1/4=120\nQ:1/4=90\nK:C\n",
+            3,
+            3,
+            "1/4=90",
+            "conflicting Q: fields '1/4=120' and '1/4=90' in the header"
+        ),
+        case(
+            "X:1\nT:Tune\nM 3/4\nK:C\n",
+            3,
+            1,
+            "M 3/4",
+            "expected a header field before the K: field"
+        ),
+        case("K:C\nC D & E\n", 2, 5, "&", "unexpected '&'"),
+        case("K:C\n  C D & E % comment\n", 2, 7, "&", "unexpected '&'"),
+        case("K:C\nC [K:H] D\n", 2, 3, "[K:H]", "invalid K: field 'H'"),
+        case("K:C\nC \"Am D\n", 2, 3, "\"", "missing closing '\"'"),
+        case("K:C\nC D ]\n", 2, 5, "]", "unexpected ']'"),
+        case("K:C\nC D ^\n", 2, 5, "^", "missing note after accidental"),
+        case("K:C\nC/0 D E\n", 2, 1, "C", "invalid note length '/0'"),
+        case("K:C\n[CE]/0 D\n", 2, 4, "]", "invalid note length '/0'")
+    )]
+    fn error_locations(abc: &str, line: usize, column: usize, token: &str, message: &str) {
+        assert_eq!(
+            abc_into_score(abc),
+            Err(AbcError {
+                line: Some(line),
+                column: Some(column),
+                token: Some(token.to_string()),
+                message: message.to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn repeated_header_fields() {
+        // free text before the header and the same meter written twice
+        let abc = "A tune book\n\nX:1\nM:2/4\nL:1/8\nM:2/4\nK:C\nC D\n";
+        assert_eq!(
+            abc_into_score(abc),
+            Ok(notes![(0, 60), (250000, 62)].to_vec())
+        );
+    }
+
+    #[test]
+    fn error_message() {
+        let err = abc_into_score("K:C\nC D & E\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 2, column 5: unexpected '&'");
+        let err = inline_abc_into_events("L:1/4\nCDE &", &[], GraceNotes::Skip).unwrap_err();
+        assert_eq!(err.to_string(), "line 2, column 5: unexpected '&'");
+    }
+
+    #[test]
+    fn inline_tune_events() {
+        use crate::score::note_on_key;
+        let events = inline_abc_into_events("L:1/4\nCD", &[], GraceNotes::Skip).unwrap();
+        let keys = events
+            .iter()
+            .map(|event| (event.time, note_on_key(event.message).map(u7::as_int)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            [
+                (0, Some(60)),
+                (500000, None),
+                (500000, Some(62)),
+                (1000000, None)
+            ]
+        );
+    }
+
+    #[test]
+    fn export_score() {
+        // ♩=120 with eighth note units of 250 ms
+        let score = notes![
+            (0, 60),
+            (0, 64),
+            (500000, 66),
+            (750000, 67),
+            (1000000, 66),
+            (1500000, 70),
+            (1750000, 48),
+            (2500000, 84)
+        ];
+        let abc = score_to_abc(&score, &AbcExportConfig::default());
+        assert_eq!(
+            abc,
+            "X:1\nM:4/4\nL:1/8\nQ:1/4=120\nK:C\n[CE]2 ^F G F2 ^A C,- | C,2 c'6 |\n"
+        );
+        assert_eq!(abc_into_score(&abc), Ok(score.to_vec()));
+    }
+
+    #[rstest(sharps, minor, expected,
+        case(2, false, "K:D\nF ^G =F ^F F4 |"),
+        case(-3, true, "K:Cm\n_G A F G G4 |"),
+    )]
+    fn export_in_key(sharps: i8, minor: bool, expected: &str) {
+        let score = notes![
+            (0, 66),
+            (250000, 68),
+            (500000, 65),
+            (750000, 66),
+            (1000000, 66)
+        ];
+        let config = AbcExportConfig {
+            key: KeySignature { sharps, minor },
+            ..AbcExportConfig::default()
+        };
+        let abc = score_to_abc(&score, &config);
+        assert!(abc.contains(expected), "{}", abc);
+    }
+
+    #[test]
+    fn export_rounds_to_units() {
+        let score = notes![(10000, 60), (240000, 62), (260000, 64), (990000, 65)];
+        let config = AbcExportConfig {
+            unit: 4,
+            meter: TimeSignature {
+                numerator: 3,
+                denominator: 4,
+            },
+            tempo: 60.0,
+            ..AbcExportConfig::default()
+        };
+        assert_eq!(
+            score_to_abc(&score, &config),
+            "X:1\nM:3/4\nL:1/4\nQ:1/4=60\nK:C\n[CDE] F2 |\n"
+        );
+    }
+
+    #[rstest(abc, expected,
+        case("CDE", vec![(0, 60), (250000, 62), (500000, 64)]),
+        case("L:1/4\nQ:1/4=60\nCD", vec![(0, 60), (1000000, 62)]),
+        case("L:1/4\nK:G\nF", vec![(0, 66)]),
+        case("X:1\nT:Scale\nL:1/4\nQ:1/4=60\nCD", vec![(0, 60), (1000000, 62)]),
+    )]
+    fn inline_tune(abc: &str, expected: Vec<(u64, u8)>) {
+        assert_eq!(
+            inline_abc_into_score(abc, &[], GraceNotes::Skip),
+            Ok(ScoreNote::from_tuples(&expected))
+        );
+    }
+
+    #[test]
+    fn inline_tune_error() {
+        let err = inline_abc_into_score("L:1/4\nCDE &", &[], GraceNotes::Skip).unwrap_err();
+        assert_eq!(err.to_string(), "line 2, column 5: unexpected '&'");
+    }
+
+    #[rstest(abc, expected,
+        case("K:C\nC D", vec![60, 62]),
+        case("%%transpose -2\nK:C\nC D", vec![58, 60]),
+        case("%%MIDI transpose 3\nK:C\nC D", vec![63, 65]),
+        case("K:D transpose=-2\nF", vec![64]),
+        case("K:C\nC %%transpose 12 is just a comment here", vec![60]),
+        case("K:C\nC\n%%transpose 12\nC", vec![60, 72]),
+        case("K:C\nC [K:C transpose=-12] C", vec![60, 48]),
+        case("%%transpose -2\nK:C\n^C-|C", vec![59]),
+        case("V:1 transpose=-2\nV:2\nK:C\nV:1\nC\nV:2\nC", vec![58, 60]),
+        case("%%transpose 7\nV:1 transpose=0\nV:2\nK:C\nV:1\nC\nV:2\nC", vec![60, 67]),
+    )]
+    fn transposition(abc: &str, expected: Vec<u8>) {
+        let score = abc_into_score(&format!("L:1/4\n{}\n", abc)).unwrap();
+        assert_eq!(
+            score
+                .iter()
+                .map(|note| note.pitch.as_int())
+                .collect::<Vec<_>>(),
+            expected
+        );
+    }
+
+    #[test]
+    fn invalid_transposition() {
+        assert_eq!(
+            abc_into_score("%%transpose up\nK:C\nC\n").map_err(|err| err.to_string()),
+            Err("line 1: invalid transposition 'up'".to_string())
+        );
+        assert!(abc_into_score("K:C\nc''''\n%%transpose 12\nc''''\n").is_err());
+    }
+
+    #[rstest(abc, expected, grace,
+        case("C {d}E", vec![(0, 60), (950000, 74), (1000000, 64)], vec![false, true, false]),
+        case("C {/dc}E", vec![(0, 60), (900000, 74), (950000, 72), (1000000, 64)], vec![false, true, true, false]),
+        case("{^f}C", vec![(0, 78), (0, 60)], vec![true, false]),
+        case("C {d}[EG]", vec![(0, 60), (950000, 74), (1000000, 64), (1000000, 67)], vec![false, true, false, false]),
+        case("C {d}|: E :|", vec![(0, 60), (950000, 74), (1000000, 64), (1950000, 74), (2000000, 64)], vec![false, true, false, true, false]),
+        case("C {d}\nE", vec![(0, 60), (950000, 74), (1000000, 64)], vec![false, true, false]),
+    )]
+    fn grace_notes(abc: &str, expected: Vec<(u64, u8)>, grace: Vec<bool>) {
+        let text = format!("L:1/4\nQ:1/4=60\nK:C\n{}\n", abc);
+        assert_eq!(
+            abc_voices_into_score_with_grace_notes(&text, &[]),
+            Ok((ScoreNote::from_tuples(&expected), grace.clone()))
+        );
+        let played = GraceNotes::Play.select((ScoreNote::from_tuples(&expected), grace));
+        assert_eq!(played, ScoreNote::from_tuples(&expected));
+        let skipped = expected
+            .iter()
+            .zip(
+                abc_voices_into_score_with_grace_notes(&text, &[])
+                    .unwrap()
+                    .1,
+            )
+            .filter(|(_, grace)| !grace)
+            .map(|(note, _)| *note)
+            .collect::<Vec<_>>();
+        assert_eq!(abc_into_score(&text), Ok(ScoreNote::from_tuples(&skipped)));
+    }
+
+    #[rstest(abc, case("{d"), case("{d&}E"))]
+    fn invalid_grace_notes(abc: &str) {
+        assert!(abc_into_score(&format!("K:C\n{}\n", abc)).is_err());
+    }
+
+    /// Converts a tune into `(time, pitch, velocity, duration)` tuples of its notes
+    fn played(abc: &str) -> Vec<(u64, u8, u8, u64)> {
+        let events = abc_voices_into_events(
+            &format!("L:1/4\nQ:1/4=60\nK:C\n{}\n", abc),
+            &[],
+            GraceNotes::Play,
+        )
+        .unwrap();
+        events
+            .iter()
+            .filter_map(|event| match event.message {
+                NoteOn { key, vel } => {
+                    let end = events.iter().find(|end| {
+                        end.time > event.time
+                            && matches!(end.message, NoteOff { key: off, .. } if off == key)
+                    })?;
+                    Some((
+                        event.time,
+                        key.as_int(),
+                        vel.as_int(),
+                        end.time - event.time,
+                    ))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[rstest(abc, expected,
+        case("C D", vec![(0, 60, 80, 1000000), (1000000, 62, 80, 1000000)]),
+        case("!p!C !ff!D E", vec![(0, 60, 60, 1000000), (1000000, 62, 120, 1000000), (2000000, 64, 120, 1000000)]),
+        case("!>!C D", vec![(0, 60, 100, 1000000), (1000000, 62, 80, 1000000)]),
+        case("LC !fff!!accent!D", vec![(0, 60, 100, 1000000), (1000000, 62, 127, 1000000)]),
+        case(".C D", vec![(0, 60, 80, 500000), (1000000, 62, 80, 1000000)]),
+        case("!staccato![CE]2 G", vec![(0, 60, 80, 1000000), (0, 64, 80, 1000000), (2000000, 67, 80, 1000000)]),
+        case("C2-C/ D", vec![(0, 60, 80, 2500000), (2500000, 62, 80, 1000000)]),
+        case("C-.C D", vec![(0, 60, 80, 1500000), (2000000, 62, 80, 1000000)]),
+        case("C C", vec![(0, 60, 80, 1000000), (1000000, 60, 80, 1000000)]),
+        case("!mf!{d}C", vec![(0, 74, 90, 50000), (0, 60, 90, 1000000)]),
+        case("!crescendo(!C +f+D", vec![(0, 60, 80, 1000000), (1000000, 62, 105, 1000000)]),
+    )]
+    fn decorations(abc: &str, expected: Vec<(u64, u8, u8, u64)>) {
+        assert_eq!(played(abc), expected);
+    }
+
+    #[test]
+    fn events_release_before_repeated_notes() {
+        let events =
+            abc_voices_into_events("L:1/4\nQ:1/4=60\nK:C\nC C\n", &[], GraceNotes::Skip).unwrap();
+        assert_eq!(
+            events
+                .iter()
+                .map(|event| (event.time, matches!(event.message, NoteOn { .. })))
+                .collect::<Vec<_>>(),
+            [
+                (0, true),
+                (1000000, false),
+                (1000000, true),
+                (2000000, false)
+            ]
+        );
+    }
+
+    #[rstest(spec, expected,
+        case("skip", Ok(GraceNotes::Skip)),
+        case("play", Ok(GraceNotes::Play)),
+        case("trill", Err("unknown grace note handling 'trill'".to_string())),
+    )]
+    fn parse_grace_notes(spec: &str, expected: Result<GraceNotes, String>) {
+        assert_eq!(spec.parse(), expected);
+    }
+
+    #[test]
+    fn abc_file_extension() {
+        assert!(is_abc_file(Path::new("tune.ABC")));
+        assert!(!is_abc_file(Path::new("tune.mid")));
+    }
+}
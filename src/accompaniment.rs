@@ -0,0 +1,245 @@
+use crate::harmony::{infer_chord, pitch_class_profile, Chord};
+use crate::score::{ScoreEvent, ScoreNote};
+use itertools::Itertools;
+use midly::num::{u4, u7};
+use midly::MidiMessage::{NoteOff, NoteOn};
+use std::str::FromStr;
+
+/// The pitch of the C below which accompaniment chords are voiced
+const VOICING_BASE: u8 = 48;
+/// The velocity of the notes of a generated accompaniment
+const ACCOMPANIMENT_VELOCITY: u7 = u7::new(64);
+
+/// A pattern for playing the chords of an accompaniment
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompingStyle {
+    /// All chord tones together at the start of each beat
+    Block,
+    /// Chord tones one after another from the lowest up, spread evenly over each beat
+    Arpeggio,
+    /// Root, fifth, third, fifth in four even subdivisions of each beat
+    Alberti,
+}
+
+impl FromStr for CompingStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(CompingStyle::Block),
+            "arpeggio" => Ok(CompingStyle::Arpeggio),
+            "alberti" => Ok(CompingStyle::Alberti),
+            _ => Err(format!("unknown comping style '{}'", s)),
+        }
+    }
+}
+
+/// Returns the pitches of a chord in close position starting from the root below middle C
+pub fn chord_voicing(chord: Chord) -> Vec<u7> {
+    chord
+        .quality
+        .intervals()
+        .iter()
+        .map(|interval| u7::from(VOICING_BASE + chord.root + interval))
+        .collect()
+}
+
+/// Generates an accompaniment for a melody by inferring a chord for each beat
+///
+/// A beat without melody notes keeps the chord of the previous beat. Beats before the
+/// first chord has been inferred are left silent.
+///
+/// # Arguments
+///
+/// * melody - The melody to accompany, with timestamps and pitches
+/// * beat - The length of one beat in microseconds
+/// * style - The pattern for playing each chord
+///
+/// # Return value
+///
+/// The notes of the accompaniment in time order
+pub fn generate_accompaniment(
+    melody: &[ScoreNote],
+    beat: u64,
+    style: CompingStyle,
+) -> Vec<ScoreNote> {
+    let end = match melody.last() {
+        Some(last) if beat > 0 => last.time + 1,
+        _ => return vec![],
+    };
+    let mut accompaniment = vec![];
+    let mut chord = None;
+    for beat_start in (0..end).step_by(beat as usize) {
+        let profile = pitch_class_profile(melody, beat_start, beat_start + beat);
+        chord = infer_chord(&profile).or(chord);
+        let voicing = match chord {
+            Some(chord) => chord_voicing(chord),
+            None => continue,
+        };
+        let notes: Vec<(u64, u7)> = match style {
+            CompingStyle::Block => voicing.iter().map(|&pitch| (0, pitch)).collect(),
+            CompingStyle::Arpeggio => voicing
+                .iter()
+                .enumerate()
+                .map(|(step, &pitch)| (step as u64 * beat / voicing.len() as u64, pitch))
+                .collect(),
+            CompingStyle::Alberti => [0, 2, 1, 2]
+                .iter()
+                .enumerate()
+                .map(|(step, &voice)| (step as u64 * beat / 4, voicing[voice]))
+                .collect(),
+        };
+        for (offset, pitch) in notes {
+            accompaniment.push(ScoreNote {
+                time: beat_start + offset,
+                pitch,
+            });
+        }
+    }
+    accompaniment
+}
+
+/// Converts a generated accompaniment into events for playback on the first MIDI
+/// channel
+///
+/// Each note is held for a beat, or until the next note of the same pitch starts.
+pub fn accompaniment_events(accompaniment: &[ScoreNote], beat: u64) -> Vec<ScoreEvent> {
+    let channel = u4::new(0);
+    accompaniment
+        .iter()
+        .enumerate()
+        .flat_map(|(index, note)| {
+            let key = note.pitch;
+            let end = accompaniment[index + 1..]
+                .iter()
+                .find(|next| next.pitch == key && next.time > note.time)
+                .map_or(u64::MAX, |next| next.time)
+                .min(note.time + beat);
+            [
+                ScoreEvent {
+                    time: note.time,
+                    channel,
+                    message: NoteOn {
+                        key,
+                        vel: ACCOMPANIMENT_VELOCITY,
+                    },
+                },
+                ScoreEvent {
+                    time: end,
+                    channel,
+                    message: NoteOff {
+                        key,
+                        vel: u7::new(0),
+                    },
+                },
+            ]
+        })
+        // a note ending when the next one of the same pitch starts is released first
+        .sorted_by_key(|event| (event.time, matches!(event.message, NoteOn { .. })))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harmony::ChordQuality;
+
+    fn pitches(notes: &[ScoreNote]) -> Vec<u8> {
+        notes.iter().map(|n| n.pitch.as_int()).collect()
+    }
+
+    #[test]
+    fn voicing() {
+        let chord = Chord {
+            root: 7,
+            quality: ChordQuality::Dominant7,
+        };
+        assert_eq!(
+            chord_voicing(chord),
+            [u7::from(55), u7::from(59), u7::from(62), u7::from(65)]
+        );
+    }
+
+    #[test]
+    fn block_chords() {
+        let melody = notes![(0, 72), (500, 76), (1000, 71), (1500, 74)];
+        let accompaniment = generate_accompaniment(&melody, 1000, CompingStyle::Block);
+        assert_eq!(
+            accompaniment,
+            notes![
+                (0, 48),
+                (0, 52),
+                (0, 55),
+                (1000, 55),
+                (1000, 59),
+                (1000, 62)
+            ]
+        );
+    }
+
+    #[test]
+    fn alberti_bass() {
+        let melody = notes![(0, 72), (0, 76)];
+        let accompaniment = generate_accompaniment(&melody, 1000, CompingStyle::Alberti);
+        assert_eq!(
+            accompaniment,
+            notes![(0, 48), (250, 55), (500, 52), (750, 55)]
+        );
+    }
+
+    #[test]
+    fn arpeggio_keeps_chord_through_rests() {
+        let melody = notes![
+            (0, 69),
+            (0, 72),
+            (0, 76),
+            (1000, 69),
+            (1000, 72),
+            (1000, 76)
+        ];
+        let accompaniment = generate_accompaniment(&melody, 500, CompingStyle::Arpeggio);
+        assert_eq!(
+            pitches(&accompaniment),
+            [57, 60, 64, 57, 60, 64, 57, 60, 64]
+        );
+        assert_eq!(accompaniment[3].time, 500);
+        assert_eq!(accompaniment[4].time, 666);
+    }
+
+    #[test]
+    fn hold_accompaniment_notes() {
+        let accompaniment = notes![(0, 48), (250, 55), (500, 52), (750, 55)];
+        let events = accompaniment_events(&accompaniment, 1000)
+            .iter()
+            .map(|event| match event.message {
+                NoteOn { key, .. } => (event.time, key.as_int(), true),
+                NoteOff { key, .. } => (event.time, key.as_int(), false),
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            [
+                (0, 48, true),
+                (250, 55, true),
+                (500, 52, true),
+                (750, 55, false),
+                (750, 55, true),
+                (1000, 48, false),
+                (1500, 52, false),
+                (1750, 55, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn no_accompaniment_for_empty_melody() {
+        assert!(generate_accompaniment(&[], 1000, CompingStyle::Block).is_empty());
+    }
+
+    #[test]
+    fn parse_comping_style() {
+        assert_eq!("alberti".parse(), Ok(CompingStyle::Alberti));
+        assert!("bossa".parse::<CompingStyle>().is_err());
+    }
+}
@@ -0,0 +1,112 @@
+use crate::beam::{BeamConfig, BeamSearch};
+use crate::ensemble::Ensemble;
+use crate::follower::{Config, HomophonoPedantic, ScoreFollower};
+use crate::score::ScoreNote;
+use crate::trace::StderrTrace;
+use std::str::FromStr;
+
+/// A score following algorithm which can be chosen at runtime
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    /// [`HomophonoPedantic`]
+    Pedantic,
+    /// [`BeamSearch`]
+    Beam,
+    /// An [`Ensemble`] of the other algorithms
+    Ensemble,
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pedantic" => Ok(Algorithm::Pedantic),
+            "beam" => Ok(Algorithm::Beam),
+            "ensemble" => Ok(Algorithm::Ensemble),
+            _ => Err(format!("unknown algorithm '{}'", s)),
+        }
+    }
+}
+
+/// Settings for creating a follower with any of the algorithms
+///
+/// Each algorithm uses the settings which apply to it and ignores the rest.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FollowerSettings<'a> {
+    pub config: Config,
+    pub beam: BeamConfig,
+    /// The duration of each score note, for algorithms which match note durations
+    pub durations: Option<&'a [Option<u64>]>,
+    /// Whether to trace matching decisions to the standard error stream
+    pub trace: bool,
+}
+
+impl Algorithm {
+    /// Creates a follower for a score
+    ///
+    /// All followers borrow the score, so followers of different algorithms can share
+    /// one loaded score.
+    ///
+    /// # Arguments
+    ///
+    /// * score - The complete expected musical score with timestamps and pitches
+    /// * settings - The settings for the follower
+    pub fn new_follower<'a>(
+        self,
+        score: &'a [ScoreNote],
+        settings: &FollowerSettings<'a>,
+    ) -> Box<dyn ScoreFollower + 'a> {
+        let pedantic = || {
+            let pedantic = HomophonoPedantic::with_config(score, settings.config);
+            match settings.trace {
+                true => pedantic.with_trace(Box::new(StderrTrace)),
+                false => pedantic,
+            }
+        };
+        let beam_search = || {
+            let mut beam_search = BeamSearch::with_config(score, settings.beam);
+            if let Some(durations) = settings.durations {
+                beam_search = beam_search.with_score_durations(durations);
+            }
+            if settings.trace {
+                beam_search = beam_search.with_trace(Box::new(StderrTrace));
+            }
+            beam_search
+        };
+        match self {
+            Algorithm::Pedantic => Box::new(pedantic()),
+            Algorithm::Beam => Box::new(beam_search()),
+            Algorithm::Ensemble => Box::new(Ensemble::new(vec![
+                Box::new(pedantic()),
+                Box::new(beam_search()),
+            ])),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_algorithm() {
+        assert_eq!("beam".parse(), Ok(Algorithm::Beam));
+        assert!("greedy".parse::<Algorithm>().is_err());
+    }
+
+    #[test]
+    fn share_one_score() {
+        let score = notes![(0, 60), (100, 62)];
+        let settings = FollowerSettings::default();
+        let mut followers = [Algorithm::Pedantic, Algorithm::Beam, Algorithm::Ensemble]
+            .map(|algorithm| algorithm.new_follower(&score, &settings));
+        for follower in followers.iter_mut() {
+            for note in &score {
+                follower.push_live(*note);
+                follower.follow_score();
+            }
+            assert!(follower.is_finished());
+        }
+    }
+}
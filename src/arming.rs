@@ -0,0 +1,178 @@
+use crate::follower::{FollowResult, ScoreFollower};
+use crate::score::ScoreNote;
+use crate::{IgnoreReason, Match};
+use std::time::Duration;
+
+/// A score follower which discards warm-up playing before the piece starts
+///
+/// Performers often play scales or tuning notes before starting. Live notes are held
+/// back until the latest ones play the opening notes of the score in order. Only then
+/// is the follower armed: the opening notes are passed on to another follower, and so
+/// are all later live notes. Everything played before the opening is discarded.
+pub struct Arming<'a> {
+    score: &'a [ScoreNote],
+    inner: Box<dyn ScoreFollower + 'a>,
+    /// The number of opening score notes which arm the follower
+    arm_length: usize,
+    /// The latest live notes received before arming
+    pending: Vec<ScoreNote>,
+    armed: bool,
+}
+
+impl<'a> Arming<'a> {
+    /// # Arguments
+    ///
+    /// * score - The complete expected musical score with timestamps and pitches
+    /// * inner - The follower to pass live notes to once armed
+    /// * arm_length - The number of opening score notes which need to be played in order
+    ///   to arm the follower
+    pub fn new(
+        score: &'a [ScoreNote],
+        inner: Box<dyn ScoreFollower + 'a>,
+        arm_length: usize,
+    ) -> Self {
+        let arm_length = arm_length.min(score.len());
+        Self {
+            score,
+            inner,
+            arm_length,
+            pending: vec![],
+            armed: arm_length == 0,
+        }
+    }
+
+    /// Tells whether the opening of the score has been played
+    pub fn is_armed(&self) -> bool {
+        self.armed
+    }
+}
+
+impl ScoreFollower for Arming<'_> {
+    fn push_live(&mut self, note: ScoreNote) {
+        if self.armed {
+            self.inner.push_live(note);
+            return;
+        }
+        self.pending.push(note);
+        if self.pending.len() > self.arm_length {
+            self.pending.remove(0);
+        }
+        let opening = self.score[..self.arm_length].iter().map(|n| n.pitch);
+        if opening.eq(self.pending.iter().map(|n| n.pitch)) {
+            self.armed = true;
+            for note in self.pending.drain(..) {
+                self.inner.push_live(note);
+            }
+        }
+    }
+
+    fn push_note_off(&mut self, note: ScoreNote) {
+        // releases of discarded warm-up notes have nothing to be matched against
+        if self.armed {
+            self.inner.push_note_off(note);
+        }
+    }
+
+    fn follow_score(&mut self) -> FollowResult {
+        if !self.armed {
+            return FollowResult {
+                score_time: 0,
+                stretch_factor: 1.0,
+                new_matches: vec![],
+                ignored: vec![],
+                retracted: vec![],
+            };
+        }
+        self.inner.follow_score()
+    }
+
+    fn live(&self) -> &[ScoreNote] {
+        self.inner.live()
+    }
+
+    fn matches(&self) -> &[Match] {
+        self.inner.matches()
+    }
+
+    fn ignored(&self) -> &[usize] {
+        self.inner.ignored()
+    }
+
+    fn ignore_reason(&self, live_index: usize) -> Option<IgnoreReason> {
+        self.inner.ignore_reason(live_index)
+    }
+
+    fn last_match(&self) -> Option<Match> {
+        self.inner.last_match()
+    }
+
+    fn tentative_count(&self) -> usize {
+        self.inner.tentative_count()
+    }
+
+    fn is_finished(&self) -> bool {
+        self.inner.is_finished()
+    }
+
+    fn estimated_score_time(&self, now: Duration) -> Option<u64> {
+        self.inner.estimated_score_time(now)
+    }
+
+    fn extend_score(&mut self, notes: &[ScoreNote]) {
+        self.inner.extend_score(notes);
+    }
+
+    fn reanchor(&mut self, anchor: Match) {
+        self.inner.reanchor(anchor);
+    }
+
+    fn confidence(&self) -> f32 {
+        self.inner.confidence()
+    }
+
+    fn transposition(&self) -> Option<i8> {
+        self.inner.transposition()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::follower::HomophonoPedantic;
+
+    #[test]
+    fn discard_warm_up() {
+        let score = notes![(0, 60), (100, 64), (200, 67), (300, 72)];
+        let live = notes![
+            (0, 60),
+            (50, 62),
+            (100, 64),
+            (150, 65),
+            (1000, 60),
+            (1100, 64),
+            (1200, 67)
+        ];
+        let mut follower = Arming::new(&score, Box::new(HomophonoPedantic::new(&score)), 2);
+        for note in live {
+            follower.push_live(note);
+            follower.follow_score();
+        }
+        assert!(follower.is_armed());
+        assert_eq!(follower.live(), &live[4..]);
+        assert_eq!(follower.matches().len(), 3);
+        assert!(follower.ignored().is_empty());
+    }
+
+    #[test]
+    fn not_armed_by_wrong_order() {
+        let score = notes![(0, 60), (100, 64), (200, 67)];
+        let mut follower = Arming::new(&score, Box::new(HomophonoPedantic::new(&score)), 2);
+        for note in notes![(0, 64), (100, 60)] {
+            follower.push_live(note);
+            let result = follower.follow_score();
+            assert!(result.new_matches.is_empty());
+        }
+        assert!(!follower.is_armed());
+        assert!(follower.live().is_empty());
+    }
+}
@@ -0,0 +1,767 @@
+use crate::follower::{
+    append_score_notes, diff_matches, matches_confidence, FollowResult, ScoreFollower,
+    StretchConfig,
+};
+use crate::score::ScoreNote;
+use crate::trace::{trace_result, TraceSink};
+use crate::{extrapolate_score_time, get_score_time, windowed_stretch_factor, IgnoreReason, Match};
+use std::borrow::Cow;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// Cost of leaving a live note unmatched
+const IGNORE_COST: u32 = 2;
+/// Cost of each score note skipped over when matching a live note
+const SKIP_COST: u32 = 1;
+/// Cost of matching a live note to a score note held for a very different time, the
+/// same as for ignoring a wrong note
+const DURATION_MISMATCH_COST: u32 = 2;
+/// The largest ratio between a live note duration (after undoing the time stretch) and
+/// the score note duration, either way, which still counts as the same articulation
+const DURATION_TOLERANCE: f32 = 2.0;
+
+/// Settings for a [`BeamSearch`] follower
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BeamConfig {
+    /// The number of alignment hypotheses to keep
+    pub beam_width: usize,
+    /// The maximum number of score notes to search ahead for a match for each live note
+    pub lookahead: usize,
+    /// How to estimate the time stretch factor
+    pub stretch: StretchConfig,
+    /// The stretch factor to assume until two live notes have been matched, see
+    /// [`Config::initial_stretch_factor`](crate::follower::Config::initial_stretch_factor)
+    pub initial_stretch_factor: f32,
+}
+
+impl Default for BeamConfig {
+    fn default() -> Self {
+        Self {
+            beam_width: 8,
+            lookahead: 8,
+            stretch: StretchConfig::default(),
+            initial_stretch_factor: 1.0,
+        }
+    }
+}
+
+/// How one live note was aligned by a [`Step`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Alignment {
+    Matched(Match),
+    Ignored(usize, IgnoreReason),
+}
+
+impl Alignment {
+    fn live_index(&self) -> usize {
+        match self {
+            Alignment::Matched(m) => m.live_index,
+            Alignment::Ignored(live_index, _) => *live_index,
+        }
+    }
+}
+
+/// One step of the alignment of a hypothesis, linked to the steps before it
+///
+/// Hypotheses branching off the same alignment share the steps before the branch, so
+/// extending a hypothesis costs the same however many notes have been played.
+#[derive(Debug)]
+struct Step {
+    alignment: Alignment,
+    /// The number of steps up to and including this one
+    depth: usize,
+    /// The number of matches up to and including this step
+    match_count: usize,
+    /// The number of ignored live notes up to and including this step
+    ignored_count: usize,
+    previous: Option<Rc<Step>>,
+}
+
+impl Drop for Step {
+    fn drop(&mut self) {
+        // unlinks the steps one by one, since dropping a long performance recursively
+        // would overflow the stack
+        let mut previous = self.previous.take();
+        while let Some(step) = previous {
+            previous = Rc::try_unwrap(step)
+                .ok()
+                .and_then(|mut step| step.previous.take());
+        }
+    }
+}
+
+/// Iterates over the steps of an alignment from the latest one backwards
+fn steps_back(latest: &Option<Rc<Step>>) -> impl Iterator<Item = &Rc<Step>> {
+    std::iter::successors(latest.as_ref(), |step| step.previous.as_ref())
+}
+
+/// One possible alignment between the live performance and the score
+#[derive(Clone, Debug)]
+struct Hypothesis {
+    /// The latest step of the alignment, or `None` before the first live note
+    steps: Option<Rc<Step>>,
+    last_match: Option<Match>,
+    /// Index of the first score note not yet matched or skipped over
+    next: usize,
+    /// Score notes skipped over at the time of the last match, which may still be
+    /// matched if they belong to the same chord
+    skipped: Vec<usize>,
+    cost: u32,
+}
+
+impl Hypothesis {
+    fn start() -> Self {
+        Self {
+            steps: None,
+            last_match: None,
+            next: 0,
+            skipped: vec![],
+            cost: 0,
+        }
+    }
+
+    /// Appends the alignment of one more live note
+    fn push(&mut self, alignment: Alignment) {
+        let (depth, match_count, ignored_count) = self.steps.as_ref().map_or((0, 0, 0), |step| {
+            (step.depth, step.match_count, step.ignored_count)
+        });
+        let (match_count, ignored_count) = match alignment {
+            Alignment::Matched(m) => {
+                self.last_match = Some(m);
+                (match_count + 1, ignored_count)
+            }
+            Alignment::Ignored(..) => (match_count, ignored_count + 1),
+        };
+        self.steps = Some(Rc::new(Step {
+            alignment,
+            depth: depth + 1,
+            match_count,
+            ignored_count,
+            previous: self.steps.take(),
+        }));
+    }
+
+    /// Returns the latest matches in live performance order, enough of them for
+    /// estimating the stretch factor over a window of match pairs, see
+    /// [`windowed_stretch_factor`]
+    fn recent_matches(&self, score: &[ScoreNote], window: usize) -> Vec<Match> {
+        let mut matches = steps_back(&self.steps).filter_map(|step| match step.alignment {
+            Alignment::Matched(m) => Some(m),
+            Alignment::Ignored(..) => None,
+        });
+        let mut recent = matches.by_ref().take(window + 1).collect::<Vec<_>>();
+        // the earliest match needs the whole chord it belongs to
+        if let Some(earliest) = recent.last().map(|m| score[m.score_index].time) {
+            recent.extend(matches.take_while(|m| score[m.score_index].time == earliest));
+        }
+        recent.reverse();
+        recent
+    }
+
+    /// Finds the match of a live note
+    fn match_of(&self, live_index: usize) -> Option<Match> {
+        steps_back(&self.steps)
+            .take_while(|step| step.alignment.live_index() >= live_index)
+            .find_map(|step| match step.alignment {
+                Alignment::Matched(m) if m.live_index == live_index => Some(m),
+                _ => None,
+            })
+    }
+
+    /// Returns all hypotheses resulting from aligning one more live note
+    fn extend(
+        &self,
+        score: &[ScoreNote],
+        live_index: usize,
+        live_note: ScoreNote,
+        lookahead: usize,
+    ) -> Vec<Hypothesis> {
+        let mut extended = vec![];
+        let chord_time = self.last_match.map(|m| score[m.score_index].time);
+        for (position, &score_index) in self.skipped.iter().enumerate() {
+            let note = score[score_index];
+            if note.pitch == live_note.pitch && Some(note.time) == chord_time {
+                let mut chord = self.clone();
+                chord.skipped.remove(position);
+                chord.push(Alignment::Matched(Match::new(score_index, live_index)));
+                chord.cost -= SKIP_COST;
+                extended.push(chord);
+            }
+        }
+        let end = self.next.saturating_add(lookahead).min(score.len());
+        for score_index in self.next..end {
+            if score[score_index].pitch == live_note.pitch {
+                let mut ahead = self.clone();
+                ahead.skipped = (self.next..score_index)
+                    .filter(|&i| score[i].time == score[score_index].time)
+                    .collect();
+                ahead.push(Alignment::Matched(Match::new(score_index, live_index)));
+                ahead.next = score_index + 1;
+                ahead.cost += (score_index - self.next) as u32 * SKIP_COST;
+                extended.push(ahead);
+            }
+        }
+        let reason = if !extended.is_empty() {
+            IgnoreReason::Unaligned
+        } else if score[end..]
+            .iter()
+            .any(|note| note.pitch == live_note.pitch)
+        {
+            IgnoreReason::OutsideSearchWindow
+        } else {
+            IgnoreReason::WrongPitch
+        };
+        let mut ignore = self.clone();
+        ignore.push(Alignment::Ignored(live_index, reason));
+        ignore.cost += IGNORE_COST;
+        // ignoring comes first, so it wins ties in the stable sort
+        extended.insert(0, ignore);
+        extended
+    }
+}
+
+/// A score follower which keeps the best few alignment hypotheses instead of committing
+/// greedily to a single match for each live note
+///
+/// Ambiguous notes, e.g. a pitch which occurs several times in the next few score notes
+/// or a chord played in a different order than written, are resolved once later notes
+/// show which alignment is the most plausible. Each hypothesis has a cost which grows
+/// for every ignored live note and every skipped score note, and only the
+/// [`BeamConfig::beam_width`] cheapest hypotheses are kept. The cheapest one is
+/// reported through the [`ScoreFollower`] methods.
+pub struct BeamSearch<'a> {
+    score: Cow<'a, [ScoreNote]>,
+    live: Vec<ScoreNote>,
+    hypotheses: Vec<Hypothesis>,
+    /// The duration of each score note, if note durations are used for matching
+    score_durations: Option<&'a [Option<u64>]>,
+    /// Whether each live note is still held down
+    held: Vec<bool>,
+    /// The steps of the best hypothesis, indexed by their depth minus one
+    steps: Vec<Rc<Step>>,
+    /// The matches of the best hypothesis
+    matches: Vec<Match>,
+    /// The live notes ignored by the best hypothesis
+    ignored: Vec<usize>,
+    /// The reason for ignoring each live note in `ignored`, in the same order
+    ignore_reasons: Vec<IgnoreReason>,
+    /// How many of the first `matches` have been reported in a [`FollowResult`]
+    reported_count: usize,
+    /// The reported matches which followed the first `reported_count` matches, but are
+    /// no longer matches of the best hypothesis
+    unmatched: Vec<Match>,
+    new_live_index: usize,
+    stretch_factor: f32,
+    config: BeamConfig,
+    trace: Option<Box<dyn TraceSink + 'a>>,
+}
+
+impl<'a> BeamSearch<'a> {
+    pub fn new(score: &'a [ScoreNote]) -> Self {
+        Self::with_config(score, BeamConfig::default())
+    }
+
+    pub fn with_config(score: &'a [ScoreNote], config: BeamConfig) -> Self {
+        Self {
+            score: Cow::Borrowed(score),
+            live: vec![],
+            hypotheses: vec![Hypothesis::start()],
+            score_durations: None,
+            held: vec![],
+            steps: vec![],
+            matches: vec![],
+            ignored: vec![],
+            ignore_reasons: vec![],
+            reported_count: 0,
+            unmatched: vec![],
+            new_live_index: 0,
+            stretch_factor: config.initial_stretch_factor,
+            config,
+            trace: None,
+        }
+    }
+
+    /// Uses note durations as an extra matching feature
+    ///
+    /// When a live note is released, hypotheses which matched it to a score note of a
+    /// very different duration, e.g. a staccato note to a legato one, become more
+    /// expensive.
+    ///
+    /// # Arguments
+    ///
+    /// * durations - The duration of each score note, see
+    ///   [`load_note_durations`](crate::score::load_note_durations)
+    pub fn with_score_durations(mut self, durations: &'a [Option<u64>]) -> Self {
+        self.score_durations = Some(durations);
+        self
+    }
+
+    /// Traces the matching decisions of the best hypothesis for each live note to a sink
+    pub fn with_trace(mut self, sink: Box<dyn TraceSink + 'a>) -> Self {
+        self.trace = Some(sink);
+        self
+    }
+
+    /// The cheapest hypothesis, which is always the first one
+    fn best(&self) -> &Hypothesis {
+        &self.hypotheses[0]
+    }
+
+    /// Finds the latest step of a hypothesis which the best hypothesis shares
+    fn common_step<'s>(&self, hypothesis: &'s Hypothesis) -> Option<&'s Rc<Step>> {
+        steps_back(&hypothesis.steps).find(|step| {
+            self.steps
+                .get(step.depth - 1)
+                .is_some_and(|best| Rc::ptr_eq(best, step))
+        })
+    }
+
+    /// Brings the matches and ignored notes up to date with the best hypothesis, only
+    /// walking back its steps since it branched off the previous best one
+    fn update_best(&mut self) {
+        let best = self.best().clone();
+        let (depth, match_count, ignored_count) =
+            self.common_step(&best).map_or((0, 0, 0), |step| {
+                (step.depth, step.match_count, step.ignored_count)
+            });
+        let branch = steps_back(&best.steps)
+            .take_while(|step| step.depth > depth)
+            .cloned()
+            .collect::<Vec<_>>();
+        let dropped = self.matches.split_off(match_count);
+        if match_count < self.reported_count {
+            let unmatched = &dropped[..self.reported_count - match_count];
+            self.unmatched.splice(0..0, unmatched.iter().copied());
+            self.reported_count = match_count;
+        }
+        self.steps.truncate(depth);
+        self.ignored.truncate(ignored_count);
+        self.ignore_reasons.truncate(ignored_count);
+        for step in branch.into_iter().rev() {
+            match step.alignment {
+                Alignment::Matched(m) => self.matches.push(m),
+                Alignment::Ignored(live_index, reason) => {
+                    self.ignored.push(live_index);
+                    self.ignore_reasons.push(reason);
+                }
+            }
+            self.steps.push(step);
+        }
+    }
+}
+
+impl ScoreFollower for BeamSearch<'_> {
+    fn push_live(&mut self, note: ScoreNote) {
+        self.live.push(note);
+        self.held.push(true);
+    }
+
+    fn push_note_off(&mut self, note: ScoreNote) {
+        let released = (0..self.live.len())
+            .rev()
+            .find(|&i| self.held[i] && self.live[i].pitch == note.pitch);
+        let (live_index, score_durations) = match (released, self.score_durations) {
+            (Some(live_index), Some(score_durations)) => (live_index, score_durations),
+            _ => return,
+        };
+        self.held[live_index] = false;
+        let live_duration = note.time.saturating_sub(self.live[live_index].time) as f32;
+        let config = self.config.stretch;
+        for hypothesis in self.hypotheses.iter_mut() {
+            // each hypothesis implies its own tempo
+            let estimate = windowed_stretch_factor(
+                &self.score,
+                &self.live,
+                &hypothesis.recent_matches(&self.score, config.window),
+                config.window,
+                config.decay,
+                &config.roll,
+            )
+            .unwrap_or(self.stretch_factor);
+            let stretch_factor = config.clamp(estimate, self.stretch_factor);
+            let score_duration = hypothesis
+                .match_of(live_index)
+                .and_then(|m| score_durations.get(m.score_index).copied().flatten());
+            if let Some(score_duration) = score_duration {
+                let ratio = live_duration / stretch_factor / score_duration.max(1) as f32;
+                if !(1.0 / DURATION_TOLERANCE..=DURATION_TOLERANCE).contains(&ratio) {
+                    hypothesis.cost += DURATION_MISMATCH_COST;
+                }
+            }
+        }
+        self.hypotheses.sort_by_key(|h| h.cost);
+        self.update_best();
+    }
+
+    fn follow_score(&mut self) -> FollowResult {
+        let prev_match = self.last_match();
+        for live_index in self.new_live_index..self.live.len() {
+            let live_note = self.live[live_index];
+            let mut extended = self
+                .hypotheses
+                .iter()
+                .flat_map(|h| h.extend(&self.score, live_index, live_note, self.config.lookahead))
+                .collect::<Vec<_>>();
+            // stable sort, so of equally cheap hypotheses the oldest and least
+            // adventurous one wins
+            extended.sort_by_key(|h| h.cost);
+            extended.truncate(self.config.beam_width.max(1));
+            self.hypotheses = extended;
+        }
+        self.update_best();
+        let (new_matches, retracted) = diff_matches(
+            &std::mem::take(&mut self.unmatched),
+            &self.matches[self.reported_count..],
+        );
+        self.reported_count = self.matches.len();
+        let ignored = self
+            .ignored
+            .iter()
+            .zip(&self.ignore_reasons)
+            .filter(|(&i, _)| {
+                i >= self.new_live_index || retracted.iter().any(|m| m.live_index == i)
+            })
+            .map(|(&i, &reason)| (i, reason))
+            .collect::<Vec<_>>();
+        let config = self.config.stretch;
+        let estimate = windowed_stretch_factor(
+            &self.score,
+            &self.live,
+            &self.matches,
+            config.window,
+            config.decay,
+            &config.roll,
+        )
+        .unwrap_or(self.stretch_factor);
+        let stretch_factor = config.clamp(estimate, self.stretch_factor);
+        let score_time = match self.matches.last() {
+            Some(last) if last.live_index == self.live.len() - 1 => {
+                self.score[last.score_index].time
+            }
+            _ => get_score_time(&self.score, &self.live, prev_match, stretch_factor),
+        };
+        self.new_live_index = self.live.len();
+        self.stretch_factor = stretch_factor;
+        let result = FollowResult {
+            score_time,
+            stretch_factor,
+            new_matches,
+            ignored,
+            retracted,
+        };
+        if let Some(sink) = &mut self.trace {
+            sink.trace(format_args!(
+                "{} hypotheses, best cost {}",
+                self.hypotheses.len(),
+                self.hypotheses[0].cost
+            ));
+        }
+        trace_result(&mut self.trace, &result);
+        result
+    }
+
+    fn live(&self) -> &[ScoreNote] {
+        &self.live
+    }
+
+    fn matches(&self) -> &[Match] {
+        &self.matches
+    }
+
+    fn ignored(&self) -> &[usize] {
+        &self.ignored
+    }
+
+    fn ignore_reason(&self, live_index: usize) -> Option<IgnoreReason> {
+        let position = self.ignored.binary_search(&live_index).ok()?;
+        Some(self.ignore_reasons[position])
+    }
+
+    fn last_match(&self) -> Option<Match> {
+        self.matches.last().copied()
+    }
+
+    fn is_finished(&self) -> bool {
+        self.last_match()
+            .is_some_and(|m| m.score_index + 1 == self.score.len())
+    }
+
+    fn extend_score(&mut self, notes: &[ScoreNote]) {
+        append_score_notes(&mut self.score, notes);
+    }
+
+    fn reanchor(&mut self, anchor: Match) {
+        let mut hypothesis = self.best().clone();
+        // the anchor note doesn't stay ignored, so the steps from it on are redone
+        let mut later = vec![];
+        while let Some(step) = hypothesis
+            .steps
+            .clone()
+            .filter(|step| step.alignment.live_index() >= anchor.live_index)
+        {
+            hypothesis.steps = step.previous.clone();
+            if !matches!(step.alignment, Alignment::Ignored(i, _) if i == anchor.live_index) {
+                later.push(step.alignment);
+            }
+        }
+        hypothesis.last_match =
+            steps_back(&hypothesis.steps).find_map(|step| match step.alignment {
+                Alignment::Matched(m) => Some(m),
+                Alignment::Ignored(..) => None,
+            });
+        for alignment in later.into_iter().rev() {
+            hypothesis.push(alignment);
+        }
+        hypothesis.push(Alignment::Matched(anchor));
+        hypothesis.next = anchor.score_index + 1;
+        hypothesis.skipped.clear();
+        self.hypotheses = vec![hypothesis];
+        self.update_best();
+        self.reported_count = self.matches.len();
+        self.unmatched.clear();
+    }
+
+    fn tentative_count(&self) -> usize {
+        let committed = self
+            .hypotheses
+            .iter()
+            .map(|h| self.common_step(h).map_or(0, |step| step.match_count))
+            .min()
+            .unwrap_or(self.matches.len());
+        self.matches.len() - committed
+    }
+
+    fn estimated_score_time(&self, now: Duration) -> Option<u64> {
+        extrapolate_score_time(
+            &self.score,
+            &self.live,
+            self.last_match(),
+            self.stretch_factor,
+            now,
+        )
+    }
+
+    fn confidence(&self) -> f32 {
+        matches_confidence(&self.score, &self.live, &self.matches, self.stretch_factor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use midly::num::u7;
+
+    fn follow<'a>(score: &'a [ScoreNote], live: &[ScoreNote]) -> BeamSearch<'a> {
+        let mut follower = BeamSearch::new(score);
+        for note in live {
+            follower.push_live(*note);
+            follower.follow_score();
+        }
+        follower
+    }
+
+    #[test]
+    fn follow_exact_performance() {
+        let score = notes![(0, 60), (100, 62), (200, 64)];
+        let follower = follow(&score, &score);
+        assert_eq!(
+            follower.matches(),
+            [Match::new(0, 0), Match::new(1, 1), Match::new(2, 2)]
+        );
+        assert!(follower.ignored().is_empty());
+    }
+
+    #[test]
+    fn hypotheses_share_steps() {
+        let score = (0..500)
+            .map(|i| ScoreNote {
+                time: 100 * i,
+                pitch: u7::from(60 + (i % 3) as u8),
+            })
+            .collect::<Vec<_>>();
+        let follower = follow(&score, &score);
+        assert_eq!(follower.matches().len(), 500);
+        assert!(follower.hypotheses.len() > 1);
+        // the hypotheses only branched off the best one at the latest notes
+        for hypothesis in &follower.hypotheses {
+            let common = follower
+                .common_step(hypothesis)
+                .map_or(0, |step| step.depth);
+            assert!(common + 10 > 500);
+        }
+    }
+
+    #[test]
+    fn drop_long_history() {
+        let mut hypothesis = Hypothesis::start();
+        for live_index in 0..1_000_000 {
+            hypothesis.push(Alignment::Ignored(live_index, IgnoreReason::WrongPitch));
+        }
+        drop(hypothesis);
+    }
+
+    #[test]
+    fn follow_extended_score() {
+        let score = notes![(0, 60), (100, 62)];
+        let mut follower = follow(&score, &score);
+        follower.extend_score(&notes![(200, 62), (300, 64)]);
+        for note in notes![(200, 62), (300, 64)] {
+            follower.push_live(note);
+            follower.follow_score();
+        }
+        assert_eq!(
+            follower.matches(),
+            [
+                Match::new(0, 0),
+                Match::new(1, 1),
+                Match::new(2, 2),
+                Match::new(3, 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn chord_in_different_order() {
+        let score = notes![(0, 60), (0, 64), (0, 67), (100, 72)];
+        let live = notes![(0, 67), (5, 60), (10, 64), (100, 72)];
+        let follower = follow(&score, &live);
+        assert_eq!(
+            follower.matches(),
+            [
+                Match::new(2, 0),
+                Match::new(0, 1),
+                Match::new(1, 2),
+                Match::new(3, 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn later_notes_resolve_ambiguity() {
+        // the early E could be the E two notes ahead, but the following notes show it
+        // was just a wrong note
+        let score = notes![(0, 60), (100, 62), (200, 64), (300, 65), (400, 67)];
+        let live = notes![
+            (0, 60),
+            (50, 64),
+            (100, 62),
+            (200, 64),
+            (300, 65),
+            (400, 67)
+        ];
+        let follower = follow(&score, &live);
+        assert_eq!(follower.ignored(), [1]);
+        assert_eq!(follower.ignore_reason(1), Some(IgnoreReason::Unaligned));
+        assert_eq!(follower.last_match(), Some(Match::new(4, 5)));
+    }
+
+    #[test]
+    fn retract_match_on_later_evidence() {
+        let score = notes![(0, 60), (100, 62), (200, 64), (300, 65), (400, 67)];
+        let live = notes![(0, 60), (50, 64), (100, 62), (200, 64)];
+        let mut follower = BeamSearch::new(&score);
+        let mut results = vec![];
+        for note in live {
+            follower.push_live(note);
+            results.push(follower.follow_score());
+        }
+        assert_eq!(results[1].new_matches, [Match::new(2, 1)]);
+        assert!(results[1].retracted.is_empty());
+        assert_eq!(results[2].retracted, [Match::new(2, 1)]);
+        assert_eq!(results[2].new_matches, [Match::new(1, 2)]);
+        assert_eq!(results[2].ignored, [(1, IgnoreReason::Unaligned)]);
+        assert_eq!(results[3].new_matches, [Match::new(2, 3)]);
+    }
+
+    #[test]
+    fn durations_resolve_ambiguity() {
+        // a long and a short G, with a live short G played after a wrong note
+        let score = notes![(0, 60), (100, 67), (500, 67), (600, 72)];
+        let durations = [Some(100), Some(400), Some(100), Some(100)];
+        let live = notes![(0, 60), (100, 66), (500, 67)];
+        let mut follower = BeamSearch::new(&score).with_score_durations(&durations);
+        for note in live {
+            follower.push_live(note);
+            follower.follow_score();
+        }
+        assert_eq!(follower.last_match(), Some(Match::new(1, 2)));
+        follower.push_note_off(ScoreNote {
+            time: 590,
+            pitch: u7::from(67),
+        });
+        assert_eq!(
+            follower.last_match(),
+            Some(Match::new(2, 2)),
+            "the live G was short like the second G in the score"
+        );
+    }
+
+    #[test]
+    fn ignore_wrong_note() {
+        let score = notes![(0, 60), (100, 62), (200, 64)];
+        let live = notes![(0, 60), (50, 61), (100, 62), (200, 64)];
+        let mut follower = BeamSearch::new(&score);
+        let mut results = vec![];
+        for note in live {
+            follower.push_live(note);
+            results.push(follower.follow_score());
+        }
+        assert_eq!(results[1].ignored, [(1, IgnoreReason::WrongPitch)]);
+        assert_eq!(follower.ignored(), [1]);
+        assert_eq!(follower.last_match(), Some(Match::new(2, 3)));
+        assert_eq!(
+            results[3].score_time, 200,
+            "the last live note was matched exactly"
+        );
+        follower.push_live(ScoreNote {
+            time: 300,
+            pitch: u7::from(70),
+        });
+        assert_eq!(
+            follower.follow_score().ignored,
+            [(4, IgnoreReason::WrongPitch)]
+        );
+    }
+
+    #[test]
+    fn initial_stretch_factor() {
+        let score = notes![(0, 60), (100, 62), (200, 64)];
+        let config = BeamConfig {
+            initial_stretch_factor: 2.0,
+            ..BeamConfig::default()
+        };
+        let mut follower = BeamSearch::with_config(&score, config);
+        follower.push_live(ScoreNote {
+            time: 1000,
+            pitch: u7::from(60),
+        });
+        assert_eq!(follower.follow_score().stretch_factor, 2.0);
+        assert_eq!(
+            follower.estimated_score_time(Duration::from_micros(1200)),
+            Some(100)
+        );
+    }
+
+    #[test]
+    fn reanchor() {
+        let score = notes![(0, 60), (100, 62), (200, 64), (300, 60), (400, 62)];
+        let mut follower = follow(&score, &notes![(0, 60)]);
+        follower.reanchor(Match::new(3, 0));
+        follower.push_live(ScoreNote {
+            time: 100,
+            pitch: u7::from(62),
+        });
+        let result = follower.follow_score();
+        assert_eq!(result.new_matches, [Match::new(4, 1)]);
+    }
+
+    #[test]
+    fn reanchor_unignores_anchor_note() {
+        let score = notes![(0, 60), (100, 62), (200, 64), (300, 60), (400, 62)];
+        let mut follower = follow(&score, &notes![(0, 60), (100, 70)]);
+        assert_eq!(follower.ignored(), [1]);
+        follower.reanchor(Match::new(3, 1));
+        assert!(follower.ignored().is_empty());
+        assert_eq!(follower.matches(), [Match::new(0, 0), Match::new(3, 1)]);
+    }
+}
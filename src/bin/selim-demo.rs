@@ -0,0 +1,56 @@
+use midly::num::u4;
+use selim::follower::{HomophonoPedantic, ScoreFollower};
+use selim::rng::SeededRng;
+use selim::score::{load_midi_data, pitch_to_name};
+use selim::simulate::{simulate_performance, Performance};
+use structopt::StructOpt;
+
+/// The piece used for the demo, bundled into the binary
+const DEMO_PIECE: &[u8] = include_bytes!("../../test-asset/Clementi.mid");
+
+/// Follows a simulated performance of a bundled piece, without any MIDI devices
+#[derive(StructOpt)]
+struct Cli {
+    /// Seed for the random deviations in the simulated performance
+    #[structopt(long = "seed", default_value = "0")]
+    seed: u64,
+    /// Live time elapsed per score time in the simulated performance
+    #[structopt(long = "stretch", default_value = "1.2")]
+    stretch_factor: f32,
+    /// Maximum timing deviation of simulated notes in milliseconds
+    #[structopt(long = "jitter", default_value = "0")]
+    jitter_ms: u64,
+    /// Probability of a wrong note in the simulated performance
+    #[structopt(long = "wrong-notes", default_value = "0.0")]
+    wrong_note_rate: f32,
+}
+
+fn main() {
+    let args = Cli::from_args();
+    let score = load_midi_data(DEMO_PIECE, &[(1, &[u4::from(0)])]).expect("invalid demo piece");
+    let performance = Performance {
+        stretch_factor: args.stretch_factor,
+        jitter: 1000 * args.jitter_ms,
+        wrong_note_rate: args.wrong_note_rate,
+    };
+    let live = simulate_performance(&score, performance, &mut SeededRng::new(args.seed));
+
+    let mut follower = HomophonoPedantic::new(&score);
+    for note in live {
+        follower.push_live(note);
+        let result = follower.follow_score();
+        println!(
+            "live {:>7.3} {:<4} -> score {:>7.3} {:>5.1}% confidence {:>3.0}%",
+            note.time as f64 / 1000000.0,
+            pitch_to_name(note.pitch),
+            result.score_time as f64 / 1000000.0,
+            100.0 * result.stretch_factor,
+            100.0 * follower.confidence(),
+        );
+    }
+    println!(
+        "matched {} of {} live notes",
+        follower.matches().len(),
+        follower.live().len()
+    );
+}
@@ -0,0 +1,68 @@
+use selim::score::{
+    diff_with_tolerance, load_score_file, pitch_to_name, NoteDiff, ScoreNote, TrackChannels,
+};
+use std::path::{Path, PathBuf};
+use std::process;
+use structopt::StructOpt;
+
+/// Compares two scores, e.g. two editions of the same piece, and lists the notes
+/// deleted, inserted and shifted in the second one
+#[derive(StructOpt)]
+struct Cli {
+    /// The first MIDI or ABC score file
+    #[structopt(parse(from_os_str))]
+    a: PathBuf,
+    /// The second MIDI or ABC score file
+    #[structopt(parse(from_os_str))]
+    b: PathBuf,
+    /// Tracks and channels to compare, e.g. 2:1, counting from 1. All by default.
+    #[structopt(long = "channels")]
+    channels: Vec<TrackChannels>,
+    /// Largest time difference of a note in milliseconds not reported as a shift
+    #[structopt(long = "tolerance-ms", default_value = "10")]
+    tolerance_ms: u64,
+}
+
+fn main() {
+    let args = Cli::from_args();
+    let channels = TrackChannels::as_slices(&args.channels);
+    let load = |path: &Path| {
+        load_score_file(path, &channels).unwrap_or_else(|err| {
+            eprintln!("Error: {}: {}", path.display(), err);
+            process::exit(2)
+        })
+    };
+    let (a, b) = (load(&args.a), load(&args.b));
+    let diffs = diff_with_tolerance(&a, &b, 1000 * args.tolerance_ms);
+    let describe = |note: ScoreNote| {
+        format!(
+            "{:>8.3} {:<4}",
+            note.time as f64 / 1000000.0,
+            pitch_to_name(note.pitch)
+        )
+    };
+    for note_diff in &diffs {
+        match *note_diff {
+            NoteDiff::Deleted { a_index } => println!("deleted  {}", describe(a[a_index])),
+            NoteDiff::Inserted { b_index } => println!("inserted {}", describe(b[b_index])),
+            NoteDiff::Shifted {
+                a_index, offset, ..
+            } => println!(
+                "shifted  {} by {:+.3}",
+                describe(a[a_index]),
+                offset as f64 / 1000000.0
+            ),
+        }
+    }
+    let count = |f: fn(&NoteDiff) -> bool| diffs.iter().filter(|d| f(d)).count();
+    println!(
+        "{} deleted, {} inserted, {} shifted",
+        count(|d| matches!(d, NoteDiff::Deleted { .. })),
+        count(|d| matches!(d, NoteDiff::Inserted { .. })),
+        count(|d| matches!(d, NoteDiff::Shifted { .. })),
+    );
+    // like diff(1), differences make the exit status 1
+    if !diffs.is_empty() {
+        process::exit(1);
+    }
+}
@@ -1,7 +1,7 @@
 use midir::{Ignore, MidiInput};
 use midly::live::{LiveEvent, LiveEvent::Midi};
-use midly::MidiMessage::NoteOn;
 use selim::device::{find_port, DeviceSelector};
+use selim::score::note_on_key;
 use std::boxed::Box;
 use std::error::Error;
 use std::io::stdin;
@@ -32,11 +32,11 @@ fn main() {
 
 fn callback<T>(microsecond: u64, message: &[u8], _: &mut T) {
     let event = LiveEvent::parse(message).unwrap();
-    if let Midi {
-        channel: _,
-        message: NoteOn { key, vel: _ },
-    } = event
-    {
+    let key = match event {
+        Midi { message, .. } => note_on_key(message),
+        _ => None,
+    };
+    if let Some(key) = key {
         println!("{};{}", microsecond, key);
     }
 }
@@ -1,4 +1,4 @@
-use midly::{MidiMessage::NoteOn, TrackEventKind::Midi};
+use selim::score::describe;
 use std::env;
 
 fn main() {
@@ -12,25 +12,10 @@ fn main() {
     let mut smf = midly::Smf::parse(&data).unwrap();
 
     // Use the information
-    println!("midi file has {} tracks!", smf.tracks.len());
-
-    for (track_num, track) in smf.tracks.iter().enumerate() {
-        println!("track {} has {} events", track_num + 1, track.len());
-        for c in 0..16 {
-            let musical_events = track.iter().filter(|event| match event.kind {
-                Midi {
-                    channel,
-                    message: NoteOn { key: _, vel: _ },
-                } => channel == c,
-                _ => false,
-            });
-            println!(
-                "track {} has {} 'note on' events on channel {}",
-                track_num + 1,
-                musical_events.count(),
-                c + 1
-            );
-        }
+    let tracks = describe(&smf).unwrap();
+    println!("midi file has {} tracks!", tracks.len());
+    for (track_num, track) in tracks.iter().enumerate() {
+        println!("track {}: {}", track_num + 1, track);
     }
 
     // Modify the file
@@ -1,15 +1,61 @@
-use std::{env, path::Path};
+use std::{
+    env, fs,
+    io::{self, Read},
+    process,
+    time::Duration,
+};
 
-use selim::score::load_midi_file;
+use selim::abc::{score_to_abc, AbcExportConfig};
+use selim::score::{export_csv, export_json, load_detailed_notes, load_midi_data, LoadError};
+use selim::signature::load_signatures;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let path = Path::new(&args[1]);
-    let score = load_midi_file(path, &[]);
+    let path = &args[1];
 
-    // Iterate over the events from all tracks:
-    println!("time;pitch");
-    for note in score.iter() {
-        println!("{};{}", note.time, note.pitch);
+    let exit_on_error = |err: LoadError| -> ! {
+        eprintln!("Error: {}: {}", path, err);
+        process::exit(1)
+    };
+    // `-` reads the MIDI file from standard input
+    let data = match path.as_str() {
+        "-" => {
+            let mut data = vec![];
+            io::stdin().read_to_end(&mut data).map(|_| data)
+        }
+        _ => fs::read(path),
+    }
+    .unwrap_or_else(|err| exit_on_error(err.into()));
+    // `csv` and `json` add the pitch name, velocity, channel and track of each note
+    match args.get(2).map(String::as_str) {
+        Some(format @ ("csv" | "json")) => {
+            let notes = load_detailed_notes(&data, &[]).unwrap_or_else(|err| exit_on_error(err));
+            match format {
+                "csv" => print!("{}", export_csv(&notes)),
+                _ => println!("{}", export_json(&notes)),
+            }
+        }
+        // `abc` writes the notes in ABC notation in the first time and key signature
+        Some("abc") => {
+            let score = load_midi_data(&data, &[]).unwrap_or_else(|err| exit_on_error(err));
+            let signatures = load_signatures(&data).unwrap_or_else(|err| exit_on_error(err));
+            let default = AbcExportConfig::default();
+            let config = AbcExportConfig {
+                meter: signatures.time_at(Duration::ZERO),
+                key: signatures.key_at(Duration::ZERO).unwrap_or(default.key),
+                ..default
+            };
+            print!("{}", score_to_abc(&score, &config));
+        }
+        Some(format) => panic!("unknown output format '{}'", format),
+        None => {
+            let score = load_midi_data(&data, &[]).unwrap_or_else(|err| exit_on_error(err));
+
+            // Iterate over the events from all tracks:
+            println!("time;pitch");
+            for note in score.iter() {
+                println!("{};{}", note.time, note.pitch);
+            }
+        }
     }
 }
@@ -0,0 +1,175 @@
+use itertools::Itertools;
+use selim::abc::{is_abc_file, load_abc_file_events, GraceNotes};
+use selim::algorithm::{Algorithm, FollowerSettings};
+use selim::click::{click_track, with_click_track, ClickConfig};
+use selim::playback::{
+    describe_event, events_to_midi_data, follow_performance, render_playback, retain_event_classes,
+    EventClass, FlushOrder, PlaybackAnchor, PlaybackScheduler, TempoRamp,
+};
+use selim::score::{
+    load_channel_events, load_midi_file, load_score_file, ScoreEvent, TrackChannels,
+};
+use selim::tempo::load_midi_file_tempo_map;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+use structopt::StructOpt;
+
+/// Follows a recorded performance offline and writes the accompaniment played along with
+/// it to a MIDI file, e.g. for a rehearsal recording or for checking playback without
+/// MIDI hardware
+#[derive(StructOpt)]
+struct Cli {
+    /// The MIDI file of the recorded performance
+    #[structopt(parse(from_os_str))]
+    performance: PathBuf,
+    /// The MIDI file to write the accompaniment to
+    #[structopt(parse(from_os_str), required_unless = "no-output")]
+    output: Option<PathBuf>,
+    /// Print the matches and the accompaniment events with their times instead of
+    /// writing a MIDI file, e.g. for checking the loading of the scores, the channel
+    /// selection and the follower
+    #[structopt(long = "no-output", conflicts_with = "output")]
+    no_output: bool,
+    /// MIDI or ABC notation file of the part performed
+    #[structopt(short = "i", long = "input-score-file", parse(from_os_str))]
+    input_score_file: PathBuf,
+    /// MIDI or ABC notation file to play back
+    #[structopt(short = "p", long = "playback-score-file", parse(from_os_str))]
+    playback_score_file: PathBuf,
+    /// Tracks and channels of the input score, like `2:1`, `2:1-8`, `2:*` or `2:!10` with
+    /// tracks and channels numbered from 1
+    #[structopt(long = "input-channels", default_value = "2:1")]
+    input_channels: Vec<TrackChannels>,
+    /// Tracks and channels of the playback score, in the same format as
+    /// --input-channels
+    #[structopt(long = "playback-channels", default_value = "3:2")]
+    playback_channels: Vec<TrackChannels>,
+    /// Classes of MIDI messages of the playback score to play, separated by commas:
+    /// notes, program, controller, pitch-bend and aftertouch. All of them by default, so
+    /// that instrument assignments and the sustain pedal are kept.
+    #[structopt(
+        long = "playback-events",
+        use_delimiter = true,
+        default_value = "notes,program,controller,pitch-bend,aftertouch"
+    )]
+    playback_events: Vec<EventClass>,
+    /// Add a metronome click on every beat of the input score to the accompaniment,
+    /// following the tempo of the performance like it. Needs a MIDI input score with
+    /// beat timing.
+    #[structopt(long = "click")]
+    click: bool,
+    /// Tracks and channels of the recorded performance, in the same format as
+    /// --input-channels
+    #[structopt(long = "performance-channels", default_value = "1:*")]
+    performance_channels: Vec<TrackChannels>,
+    /// Score following algorithm
+    #[structopt(long = "algorithm", default_value = "pedantic", possible_values = &["pedantic", "beam", "ensemble"])]
+    algorithm: Algorithm,
+    /// Time in milliseconds for the accompaniment to converge to each new tempo
+    /// estimate, or 0 for following it at once
+    #[structopt(long = "ramp-ms", default_value = "0")]
+    ramp_ms: u64,
+    /// How to order events sent at the same moment: offs-before-ons, or as-loaded to
+    /// keep the order of the playback score
+    #[structopt(long = "flush-order", default_value = "offs-before-ons")]
+    flush_order: FlushOrder,
+}
+
+/// Returns the value of a result, or reports the error with the path of the file it
+/// concerns and exits
+fn exit_on_error<T, E: Display>(path: &Path, result: Result<T, E>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("Error: {}: {}", path.display(), err);
+        process::exit(1)
+    })
+}
+
+/// Prints the anchors and the events of a rendered playback in live time order
+fn print_playback(anchors: &[PlaybackAnchor], events: &[ScoreEvent]) {
+    let seconds = |micros: u64| micros as f64 / 1000000.0;
+    let anchors = anchors.iter().map(|anchor| {
+        let line = format!(
+            "{:>11.6} match at score {:.6}, stretch factor {:.3}",
+            seconds(anchor.live_time),
+            seconds(anchor.score_time),
+            anchor.stretch_factor
+        );
+        (anchor.live_time, line)
+    });
+    let events = events.iter().map(|event| {
+        let line = format!("{:>11.6} {}", seconds(event.time), describe_event(event));
+        (event.time, line)
+    });
+    // matches go first, since they trigger the events at the same moment
+    for (_, line) in anchors.merge_by(events, |(a, _), (b, _)| a <= b) {
+        println!("{}", line);
+    }
+}
+
+fn main() {
+    let args = Cli::from_args();
+    let score = exit_on_error(
+        &args.input_score_file,
+        load_score_file(
+            &args.input_score_file,
+            &TrackChannels::as_slices(&args.input_channels),
+        ),
+    );
+    let live = exit_on_error(
+        &args.performance,
+        load_midi_file(
+            &args.performance,
+            &TrackChannels::as_slices(&args.performance_channels),
+        ),
+    );
+    let path = &args.playback_score_file;
+    let mut events = match is_abc_file(path) {
+        true => exit_on_error(path, load_abc_file_events(path, &[], GraceNotes::Skip)),
+        false => exit_on_error(
+            path,
+            std::fs::read(path)
+                .map_err(|err| err.to_string())
+                .and_then(|data| {
+                    let channels = TrackChannels::as_slices(&args.playback_channels);
+                    load_channel_events(&data, &channels).map_err(|err| err.to_string())
+                }),
+        ),
+    };
+    retain_event_classes(&mut events, &args.playback_events);
+    if args.click {
+        let path = &args.input_score_file;
+        let tempo_map = exit_on_error(path, load_midi_file_tempo_map(path))
+            .unwrap_or_else(|| exit_on_error(path, Err("no beat timing for the click track")));
+        let end = score.last().map_or(0, |note| note.time);
+        let clicks = click_track(
+            &tempo_map,
+            Duration::from_micros(end),
+            &ClickConfig::default(),
+        );
+        events = with_click_track(&events, &clicks);
+    }
+    let mut follower = args
+        .algorithm
+        .new_follower(&score, &FollowerSettings::default());
+    let anchors = follow_performance(follower.as_mut(), &live);
+    let rendered = render_playback(
+        PlaybackScheduler::new(&events).with_flush_order(args.flush_order),
+        TempoRamp::new(1000 * args.ramp_ms),
+        &anchors,
+    );
+    match &args.output {
+        Some(output) if !args.no_output => exit_on_error(
+            output,
+            std::fs::write(output, events_to_midi_data(&rendered)),
+        ),
+        _ => print_playback(&anchors, &rendered),
+    }
+    eprintln!(
+        "{} of {} live notes matched, {} events written",
+        anchors.len(),
+        live.len(),
+        rendered.len()
+    );
+}
@@ -0,0 +1,85 @@
+use midly::num::u4;
+use selim::algorithm::{Algorithm, FollowerSettings};
+use selim::rng::SeededRng;
+use selim::score::{load_midi_data, load_score_file};
+use selim::simulate::{stress_stream, StressPattern};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::time::Instant;
+use structopt::StructOpt;
+
+/// The score used when none is given, bundled into the binary
+const DEFAULT_SCORE: &[u8] = include_bytes!("../../test-asset/Clementi.mid");
+const PATTERNS: [StressPattern; 3] = [
+    StressPattern::PolyphonyBurst,
+    StressPattern::RepeatedPitch,
+    StressPattern::RandomNoise,
+];
+
+/// Feeds pathological live input to a score follower as fast as possible, reporting
+/// throughput and any panics
+#[derive(StructOpt)]
+struct Cli {
+    /// Score to follow, the bundled demo piece by default
+    #[structopt(short = "i", long = "input-score-file", parse(from_os_str))]
+    input_score_file: Option<PathBuf>,
+    /// Score following algorithm
+    #[structopt(long = "algorithm", default_value = "pedantic", possible_values = &["pedantic", "beam", "ensemble"])]
+    algorithm: Algorithm,
+    /// Pattern to feed: polyphony-burst, repeated-pitch or random-noise; all by default
+    #[structopt(long = "pattern")]
+    pattern: Option<StressPattern>,
+    /// Number of live notes to feed for each pattern
+    #[structopt(long = "notes", default_value = "2000")]
+    notes: usize,
+    /// Seed for the random pitches and times
+    #[structopt(long = "seed", default_value = "0")]
+    seed: u64,
+}
+
+fn main() {
+    let args = Cli::from_args();
+    let channels: &[(usize, &[u4])] = &[(1, &[u4::from(0)])];
+    let score = match &args.input_score_file {
+        Some(path) => load_score_file(path, channels).unwrap_or_else(|err| {
+            eprintln!("Error: {}: {}", path.display(), err);
+            std::process::exit(1)
+        }),
+        None => load_midi_data(DEFAULT_SCORE, channels).expect("invalid default score"),
+    };
+    let patterns = match args.pattern {
+        Some(pattern) => vec![pattern],
+        None => PATTERNS.to_vec(),
+    };
+    let mut panicked = false;
+    for pattern in patterns {
+        let live = stress_stream(pattern, args.notes, &mut SeededRng::new(args.seed));
+        let start = Instant::now();
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut follower = args
+                .algorithm
+                .new_follower(&score, &FollowerSettings::default());
+            for note in &live {
+                follower.push_live(*note);
+                follower.follow_score();
+            }
+        }));
+        let elapsed = start.elapsed().as_secs_f64();
+        match outcome {
+            Ok(()) => println!(
+                "{:?}: {} notes in {:.3} s, {:.0} notes/s",
+                pattern,
+                live.len(),
+                elapsed,
+                live.len() as f64 / elapsed
+            ),
+            Err(_) => {
+                println!("{:?}: PANICKED after {:.3} s", pattern, elapsed);
+                panicked = true;
+            }
+        }
+    }
+    if panicked {
+        std::process::exit(1);
+    }
+}
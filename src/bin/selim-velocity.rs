@@ -0,0 +1,39 @@
+use selim::velocity::{remap_smf_velocities, VelocityCurve};
+use std::path::PathBuf;
+use std::process;
+use structopt::StructOpt;
+
+/// Maps the note velocities of a MIDI file, e.g. to give a flat export reasonable
+/// dynamics before using it as a playback score
+#[derive(StructOpt)]
+struct Cli {
+    /// The MIDI file to read
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+    /// The MIDI file to write
+    #[structopt(parse(from_os_str))]
+    output: PathBuf,
+    /// normalize, range:<min>-<max>, compress:<ratio> or gamma:<exponent>
+    curve: VelocityCurve,
+}
+
+fn main() {
+    let args = Cli::from_args();
+    let data = std::fs::read(&args.input).unwrap_or_else(|err| {
+        eprintln!("Error: {}: {}", args.input.display(), err);
+        process::exit(1)
+    });
+    let mut smf = midly::Smf::parse(&data).unwrap_or_else(|err| {
+        eprintln!(
+            "Error: {}: invalid MIDI file: {}",
+            args.input.display(),
+            err
+        );
+        process::exit(1)
+    });
+    remap_smf_velocities(&mut smf, args.curve);
+    if let Err(err) = smf.save(&args.output) {
+        eprintln!("Error: {}: {}", args.output.display(), err);
+        process::exit(1)
+    }
+}
@@ -0,0 +1,395 @@
+use crate::score::{load_channel_events, load_midi_data, LoadError, ScoreEvent, ScoreNote};
+use crate::tempo::{load_tempo_map, TempoMap};
+use midly::live::LiveEvent;
+use midly::num::{u4, u7};
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+/// Calculates the cache key for MIDI file contents loaded with the given channels
+/// into the cache format identified by `magic`
+///
+/// Since the key covers the complete contents of the file, a changed MIDI file never
+/// hits a stale cache entry.
+fn cache_key(data: &[u8], magic: &[u8], channels: &[(usize, &[u4])]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    magic.hash(&mut hasher);
+    channels.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Identifies score cache files and the version of their format
+const CACHE_MAGIC: &[u8] = b"SELIM\x01";
+
+/// Identifies playback event cache files and the version of their format
+const EVENTS_MAGIC: &[u8] = b"SELEV\x01";
+
+/// Identifies tempo map cache files and the version of their format
+const TEMPO_MAGIC: &[u8] = b"SELTM\x01";
+
+/// Returns the path of the cache file for the given cache key
+fn cache_path(cache_dir: &Path, key: u64) -> PathBuf {
+    cache_dir.join(format!("{:016x}.bin", key))
+}
+
+/// Appends an unsigned LEB128 variable-length integer
+pub(crate) fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// Reads an unsigned LEB128 variable-length integer written by [`write_varint`]
+pub(crate) fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> Option<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = bytes.next()?;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Appends a floating point number as its eight little-endian bytes
+pub(crate) fn write_f64(bytes: &mut Vec<u8>, value: f64) {
+    bytes.extend(value.to_le_bytes());
+}
+
+/// Reads a floating point number written by [`write_f64`]
+pub(crate) fn read_f64(bytes: &mut impl Iterator<Item = u8>) -> Option<f64> {
+    let mut value = [0; 8];
+    for byte in &mut value {
+        *byte = bytes.next()?;
+    }
+    Some(f64::from_le_bytes(value))
+}
+
+/// Encodes a score compactly as the time difference to the previous note and the
+/// pitch of each note
+///
+/// Time differences are variable-length integers, so most notes take two to four
+/// bytes.
+fn encode_score(score: &[ScoreNote]) -> Vec<u8> {
+    let mut bytes = CACHE_MAGIC.to_vec();
+    let mut previous_time = 0;
+    for note in score {
+        // wrapping keeps the rare note earlier than the previous one decodable
+        write_varint(&mut bytes, note.time.wrapping_sub(previous_time));
+        bytes.push(note.pitch.as_int());
+        previous_time = note.time;
+    }
+    bytes
+}
+
+/// Decodes a score encoded by [`encode_score`]
+///
+/// # Return value
+///
+/// The decoded score, or `None` if the cache file is corrupt or from another version
+fn decode_score(bytes: &[u8]) -> Option<Vec<ScoreNote>> {
+    let mut bytes = bytes.strip_prefix(CACHE_MAGIC)?.iter().copied().peekable();
+    let mut score = vec![];
+    let mut time = 0u64;
+    while bytes.peek().is_some() {
+        time = time.wrapping_add(read_varint(&mut bytes)?);
+        let pitch = u7::try_from(bytes.next()?)?;
+        score.push(ScoreNote { time, pitch });
+    }
+    Some(score)
+}
+
+/// Encodes playback events as the time difference to the previous event and the raw
+/// bytes of each channel message
+fn encode_events(events: &[ScoreEvent]) -> Vec<u8> {
+    let mut bytes = EVENTS_MAGIC.to_vec();
+    let mut previous_time = 0;
+    for event in events {
+        write_varint(&mut bytes, event.time.wrapping_sub(previous_time));
+        LiveEvent::Midi {
+            channel: event.channel,
+            message: event.message,
+        }
+        .write_std(&mut bytes)
+        .unwrap();
+        previous_time = event.time;
+    }
+    bytes
+}
+
+/// Decodes playback events encoded by [`encode_events`]
+///
+/// # Return value
+///
+/// The decoded events, or `None` if the cache file is corrupt or from another version
+fn decode_events(bytes: &[u8]) -> Option<Vec<ScoreEvent>> {
+    let mut bytes = bytes.strip_prefix(EVENTS_MAGIC)?.iter().copied().peekable();
+    let mut events = vec![];
+    let mut time = 0u64;
+    while bytes.peek().is_some() {
+        time = time.wrapping_add(read_varint(&mut bytes)?);
+        let status = bytes.next()?;
+        // program changes and channel pressure have one data byte, the others two
+        let length = match status >> 4 {
+            0xc | 0xd => 1,
+            _ => 2,
+        };
+        let mut message = vec![status];
+        for _ in 0..length {
+            message.push(bytes.next()?);
+        }
+        match LiveEvent::parse(&message).ok()? {
+            LiveEvent::Midi { channel, message } => events.push(ScoreEvent {
+                time,
+                channel,
+                message,
+            }),
+            _ => return None,
+        }
+    }
+    Some(events)
+}
+
+/// Encodes the tempo map of a MIDI file, or its absence in files with SMPTE timecode
+fn encode_tempo_map(tempo_map: &Option<TempoMap>) -> Vec<u8> {
+    let mut bytes = TEMPO_MAGIC.to_vec();
+    if let Some(tempo_map) = tempo_map {
+        tempo_map.encode(&mut bytes);
+    }
+    bytes
+}
+
+/// Decodes a tempo map encoded by [`encode_tempo_map`]
+///
+/// # Return value
+///
+/// The decoded tempo map, or `None` if the cache file is corrupt or from another
+/// version
+fn decode_tempo_map(bytes: &[u8]) -> Option<Option<TempoMap>> {
+    let mut bytes = bytes.strip_prefix(TEMPO_MAGIC)?.iter().copied().peekable();
+    if bytes.peek().is_none() {
+        return Some(None);
+    }
+    let tempo_map = TempoMap::decode(&mut bytes)?;
+    match bytes.next() {
+        Some(_) => None,
+        None => Some(Some(tempo_map)),
+    }
+}
+
+/// Loads data converted from a MIDI file, using a previously stored copy if the file
+/// has been converted before into the same format with the same channels
+///
+/// Failing to read or write the cache is not an error, the file is just converted
+/// again.
+fn load_cached<T>(
+    path: &Path,
+    channels: &[(usize, &[u4])],
+    cache_dir: &Path,
+    magic: &[u8],
+    encode: fn(&T) -> Vec<u8>,
+    decode: fn(&[u8]) -> Option<T>,
+    convert: impl FnOnce(&[u8]) -> Result<T, LoadError>,
+) -> Result<T, LoadError> {
+    let data = fs::read(path)?;
+    let cache_file = cache_path(cache_dir, cache_key(&data, magic, channels));
+    if let Some(converted) = fs::read(&cache_file).ok().and_then(|bytes| decode(&bytes)) {
+        return Ok(converted);
+    }
+    let converted = convert(&data)?;
+    if fs::create_dir_all(cache_dir).is_ok() {
+        let _ = fs::write(&cache_file, encode(&converted));
+    }
+    Ok(converted)
+}
+
+/// Loads a score from a MIDI file, using a previously stored copy of the parsed score
+/// if the file has been loaded before with the same channels
+///
+/// Parsed scores are stored in `cache_dir`, which is created if it doesn't exist.
+/// Failing to read or write the cache is not an error, the file is just parsed again.
+pub fn load_midi_file_cached(
+    path: &Path,
+    channels: &[(usize, &[u4])],
+    cache_dir: &Path,
+) -> Result<Vec<ScoreNote>, LoadError> {
+    load_cached(
+        path,
+        channels,
+        cache_dir,
+        CACHE_MAGIC,
+        |score| encode_score(score),
+        decode_score,
+        |data| load_midi_data(data, channels),
+    )
+}
+
+/// Loads the channel messages of the given tracks and channels from a MIDI file for
+/// playback, caching them like [`load_midi_file_cached`]
+pub fn load_channel_events_cached(
+    path: &Path,
+    channels: &[(usize, &[u4])],
+    cache_dir: &Path,
+) -> Result<Vec<ScoreEvent>, LoadError> {
+    load_cached(
+        path,
+        channels,
+        cache_dir,
+        EVENTS_MAGIC,
+        |events| encode_events(events),
+        decode_events,
+        |data| load_channel_events(data, channels),
+    )
+}
+
+/// Loads the tempo map of a MIDI file, caching it like [`load_midi_file_cached`]
+///
+/// # Return value
+///
+/// The tempo map, or `None` if the file uses SMPTE timecode instead of beats
+pub fn load_midi_file_tempo_map_cached(
+    path: &Path,
+    cache_dir: &Path,
+) -> Result<Option<TempoMap>, LoadError> {
+    load_cached(
+        path,
+        &[],
+        cache_dir,
+        TEMPO_MAGIC,
+        encode_tempo_map,
+        decode_tempo_map,
+        load_tempo_map,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::score::load_midi_file;
+    use midly::num::u14;
+    use midly::{MidiMessage, PitchBend};
+    use rstest::rstest;
+
+    #[test]
+    fn encode_and_decode_score() {
+        let score = notes![(0, 48), (100, 72), (750000, 76), (500000, 60)];
+        let bytes = encode_score(&score);
+        assert_eq!(bytes[..6], *CACHE_MAGIC);
+        assert_eq!(bytes[6..14], [0, 48, 100, 72, 0xcc, 0xe2, 0x2d, 76]);
+        assert_eq!(decode_score(&bytes).unwrap(), score);
+    }
+
+    #[rstest(bytes,
+        case(b"SELIM\x01\x00".to_vec()),
+        case(b"SELIM\x01\x00\x80".to_vec()),
+        case(b"SELIM\x01\x80".to_vec()),
+        case(b"SELIM\x02\x00\x30".to_vec()),
+        case(b"0;48\n".to_vec()),
+    )]
+    fn decode_corrupt_score(bytes: Vec<u8>) {
+        assert_eq!(decode_score(&bytes), None);
+    }
+
+    #[test]
+    fn cache_key_depends_on_channels() {
+        let data = [1, 2, 3];
+        let channels = [u4::from(0)];
+        assert_ne!(
+            cache_key(&data, CACHE_MAGIC, &[]),
+            cache_key(&data, CACHE_MAGIC, &[(1, &channels)])
+        );
+    }
+
+    #[test]
+    fn load_midi_file_clementi_cached() {
+        let path = AsRef::<Path>::as_ref("test-asset").join("Clementi.mid");
+        let cache_dir = std::env::temp_dir().join(format!("selim-test-{}", std::process::id()));
+        let uncached = load_midi_file(&path, &[]).unwrap();
+        let first = load_midi_file_cached(&path, &[], &cache_dir).unwrap();
+        assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 1);
+        let second = load_midi_file_cached(&path, &[], &cache_dir).unwrap();
+        fs::remove_dir_all(&cache_dir).unwrap();
+        assert_eq!(first, uncached);
+        assert_eq!(second, uncached);
+    }
+
+    #[test]
+    fn cache_key_depends_on_format() {
+        let data = [1, 2, 3];
+        assert_ne!(
+            cache_key(&data, CACHE_MAGIC, &[]),
+            cache_key(&data, EVENTS_MAGIC, &[])
+        );
+    }
+
+    #[test]
+    fn encode_and_decode_events() {
+        let events = [
+            ScoreEvent {
+                time: 0,
+                channel: u4::from(1),
+                message: MidiMessage::ProgramChange {
+                    program: u7::from(5),
+                },
+            },
+            ScoreEvent {
+                time: 1000,
+                channel: u4::from(1),
+                message: MidiMessage::NoteOn {
+                    key: u7::from(60),
+                    vel: u7::from(90),
+                },
+            },
+            ScoreEvent {
+                time: 1000,
+                channel: u4::from(9),
+                message: MidiMessage::PitchBend {
+                    bend: PitchBend(u14::from(9000)),
+                },
+            },
+        ];
+        let bytes = encode_events(&events);
+        assert_eq!(
+            bytes[6..],
+            [0, 0xc1, 5, 0xe8, 0x07, 0x91, 60, 90, 0, 0xe9, 0x28, 0x46]
+        );
+        assert_eq!(decode_events(&bytes).unwrap(), events);
+    }
+
+    #[rstest(bytes,
+        case(b"SELEV\x01\x00\x90\x3c".to_vec()),
+        case(b"SELEV\x01\x00\xf0\x00\x00".to_vec()),
+        case(b"SELIM\x01\x00\x30".to_vec()),
+    )]
+    fn decode_corrupt_events(bytes: Vec<u8>) {
+        assert_eq!(decode_events(&bytes), None);
+    }
+
+    #[test]
+    fn load_midi_file_clementi_events_and_tempo_map_cached() {
+        let path = AsRef::<Path>::as_ref("test-asset").join("Clementi.mid");
+        let cache_dir =
+            std::env::temp_dir().join(format!("selim-test-events-{}", std::process::id()));
+        let data = fs::read(&path).unwrap();
+        let events = load_channel_events(&data, &[]).unwrap();
+        let tempo_map = load_tempo_map(&data).unwrap();
+        assert!(tempo_map.is_some());
+        for _ in 0..2 {
+            assert_eq!(
+                load_channel_events_cached(&path, &[], &cache_dir).unwrap(),
+                events
+            );
+            assert_eq!(
+                load_midi_file_tempo_map_cached(&path, &cache_dir).unwrap(),
+                tempo_map
+            );
+        }
+        assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 2);
+        fs::remove_dir_all(&cache_dir).unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+use crate::score::ScoreEvent;
+use crate::tempo::TempoMap;
+use itertools::Itertools;
+use midly::num::{u4, u7};
+use midly::MidiMessage::{self, NoteOff, NoteOn};
+use std::time::Duration;
+
+/// The sound of a metronome click track
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClickConfig {
+    /// The output channel, by default the General MIDI percussion channel
+    pub channel: u4,
+    /// The pitch of the clicks on other beats than downbeats, by default a low wood block
+    pub pitch: u7,
+    /// The pitch of the clicks on the first beat of each measure, by default a high wood
+    /// block
+    pub downbeat_pitch: u7,
+    pub velocity: u7,
+    pub downbeat_velocity: u7,
+    /// How long each click is held, in microseconds of score time
+    pub length: u64,
+}
+
+impl Default for ClickConfig {
+    fn default() -> Self {
+        Self {
+            channel: u4::new(9),
+            pitch: u7::new(77),
+            downbeat_pitch: u7::new(76),
+            velocity: u7::new(80),
+            downbeat_velocity: u7::new(110),
+            length: 50_000,
+        }
+    }
+}
+
+/// Generates a metronome click on every counted beat of a score, with accented
+/// downbeats, see [`TempoMap::counted_beats`]
+///
+/// The clicks are in score time, so merged into a playback score with
+/// [`with_click_track`] they follow the tempo of the performer like the accompaniment.
+///
+/// # Arguments
+///
+/// * tempo_map - The tempo changes and time signatures of the score
+/// * end - The score time to stop clicking at, e.g. the end of the last note
+/// * config - The sound of the clicks
+///
+/// # Return value
+///
+/// The note-on and note-off events of the clicks in time order
+pub fn click_track(tempo_map: &TempoMap, end: Duration, config: &ClickConfig) -> Vec<ScoreEvent> {
+    let event = |time, message: MidiMessage| ScoreEvent {
+        time,
+        channel: config.channel,
+        message,
+    };
+    tempo_map
+        .counted_beats(end)
+        .into_iter()
+        .flat_map(|(time, downbeat)| {
+            let time = time.as_micros() as u64;
+            let (key, vel) = match downbeat {
+                true => (config.downbeat_pitch, config.downbeat_velocity),
+                false => (config.pitch, config.velocity),
+            };
+            [
+                event(time, NoteOn { key, vel }),
+                event(
+                    time + config.length,
+                    NoteOff {
+                        key,
+                        vel: u7::new(0),
+                    },
+                ),
+            ]
+        })
+        // a click may be held past the next one on very fast beats
+        .sorted_by_key(|event| event.time)
+        .collect()
+}
+
+/// Merges a click track into a playback score, keeping the events of the score first
+/// among simultaneous events
+pub fn with_click_track(events: &[ScoreEvent], clicks: &[ScoreEvent]) -> Vec<ScoreEvent> {
+    events
+        .iter()
+        .merge_by(clicks, |event, click| event.time <= click.time)
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use midly::num::{u15, u24, u28};
+    use midly::{Format, Header, MetaMessage, Smf, Timing, TrackEvent, TrackEventKind};
+
+    /// 3/4 at 120 bpm
+    fn waltz() -> TempoMap {
+        let meta = |message| TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(message),
+        };
+        let smf = Smf {
+            header: Header::new(Format::SingleTrack, Timing::Metrical(u15::new(480))),
+            tracks: vec![vec![
+                meta(MetaMessage::Tempo(u24::new(500_000))),
+                meta(MetaMessage::TimeSignature(3, 2, 24, 8)),
+            ]],
+        };
+        TempoMap::from_smf(&smf).unwrap()
+    }
+
+    #[test]
+    fn accented_downbeats() {
+        let clicks = click_track(
+            &waltz(),
+            Duration::from_millis(2000),
+            &ClickConfig::default(),
+        );
+        let note_ons = clicks
+            .iter()
+            .filter_map(|event| match event.message {
+                NoteOn { key, vel } => Some((event.time, key.as_int(), vel.as_int())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            note_ons,
+            [
+                (0, 76, 110),
+                (500000, 77, 80),
+                (1000000, 77, 80),
+                (1500000, 76, 110)
+            ]
+        );
+        assert_eq!(clicks.len(), 8);
+        assert_eq!(clicks[1].time, 50000);
+        assert!(clicks.iter().all(|event| event.channel == u4::new(9)));
+    }
+
+    #[test]
+    fn merge_into_playback_score() {
+        let note = |time, key| ScoreEvent {
+            time,
+            channel: u4::new(0),
+            message: NoteOn {
+                key: u7::new(key),
+                vel: u7::new(64),
+            },
+        };
+        let clicks = [note(0, 76), note(500, 77)];
+        assert_eq!(
+            with_click_track(&[note(0, 60), note(700, 62)], &clicks),
+            [note(0, 60), note(0, 76), note(500, 77), note(700, 62)]
+        );
+    }
+}
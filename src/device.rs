@@ -28,14 +28,12 @@ where
     };
 
     let (device_number, in_port_name) = matches[0].clone();
-    eprintln!(
-        "Selecting MIDI input port {} {}",
-        device_number, in_port_name
-    );
+    eprintln!("Selecting MIDI port {} {}", device_number, in_port_name);
     Ok(ports[device_number].clone())
 }
 
 #[cfg(test)]
+#[allow(clippy::mixed_attributes_style)]
 mod tests {
     //! Tests require virtual ports and therefore can't work on Windows or Web MIDI
     #![cfg(not(any(windows, target_arch = "wasm32")))]
@@ -0,0 +1,133 @@
+use crate::ensemble::weighted_mean;
+use crate::follower::{FollowResult, ScoreFollower};
+use crate::score::ScoreNote;
+
+/// One performer of a [`Duet`] with the follower for their part
+struct Part<'a> {
+    follower: Box<dyn ScoreFollower + 'a>,
+    /// The score time at the latest live note of this part, if any
+    score_time: Option<u64>,
+}
+
+/// Follows two performers playing different parts of the same piece, each from their
+/// own live input, and fuses their positions into a single playback clock
+pub struct Duet<'a> {
+    parts: [Part<'a>; 2],
+}
+
+impl<'a> Duet<'a> {
+    /// # Arguments
+    ///
+    /// * first - The follower for the first part, with the score of that part
+    /// * second - The follower for the second part, with the score of that part
+    pub fn new(first: Box<dyn ScoreFollower + 'a>, second: Box<dyn ScoreFollower + 'a>) -> Self {
+        Self {
+            parts: [first, second].map(|follower| Part {
+                follower,
+                score_time: None,
+            }),
+        }
+    }
+
+    /// Follows a new live note played by one of the performers
+    ///
+    /// # Arguments
+    ///
+    /// * part - The index of the performer, 0 or 1
+    /// * note - The note received from that performer's live input
+    pub fn follow(&mut self, part: usize, note: ScoreNote) -> FollowResult {
+        let part = &mut self.parts[part];
+        part.follower.push_live(note);
+        let result = part.follower.follow_score();
+        part.score_time = Some(result.score_time);
+        result
+    }
+
+    /// The follower of one of the performers
+    pub fn follower(&self, part: usize) -> &dyn ScoreFollower {
+        self.parts[part].follower.as_ref()
+    }
+
+    /// Estimates the common score time for playback
+    ///
+    /// The score times of both parts are averaged, weighted by the confidence of their
+    /// followers. If neither follower is confident at all, the parts are weighted
+    /// equally.
+    ///
+    /// # Return value
+    ///
+    /// The score time in microseconds, or `None` if neither performer has played yet
+    pub fn clock(&self) -> Option<u64> {
+        let times = self
+            .parts
+            .iter()
+            .filter_map(|part| {
+                let time = part.score_time? as f64;
+                Some((time, part.follower.confidence() as f64))
+            })
+            .collect::<Vec<_>>();
+        weighted_mean(times.iter().copied())
+            .or_else(|| weighted_mean(times.iter().map(|&(time, _)| (time, 1.0))))
+            .map(|time| time.round() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::follower::HomophonoPedantic;
+    use midly::num::u7;
+
+    #[test]
+    fn no_clock_before_playing() {
+        let score = notes![(0, 60)];
+        let duet = Duet::new(
+            Box::new(HomophonoPedantic::new(&score)),
+            Box::new(HomophonoPedantic::new(&score)),
+        );
+        assert_eq!(duet.clock(), None);
+    }
+
+    #[test]
+    fn fuse_part_positions() {
+        let violin = notes![(0, 76), (100, 77), (200, 79)];
+        let cello = notes![(0, 48), (200, 43)];
+        let mut duet = Duet::new(
+            Box::new(HomophonoPedantic::new(&violin)),
+            Box::new(HomophonoPedantic::new(&cello)),
+        );
+        duet.follow(
+            0,
+            ScoreNote {
+                time: 0,
+                pitch: u7::from(76),
+            },
+        );
+        assert_eq!(duet.clock(), Some(0));
+        duet.follow(
+            1,
+            ScoreNote {
+                time: 5,
+                pitch: u7::from(48),
+            },
+        );
+        duet.follow(
+            0,
+            ScoreNote {
+                time: 100,
+                pitch: u7::from(77),
+            },
+        );
+        assert_eq!(duet.follower(0).matches().len(), 2);
+        // both followers are fully confident, violin at 100 and cello at 0
+        assert_eq!(duet.clock(), Some(50));
+        duet.follow(
+            1,
+            ScoreNote {
+                time: 205,
+                pitch: u7::from(43),
+            },
+        );
+        assert_eq!(duet.clock(), Some(150));
+    }
+}
@@ -0,0 +1,225 @@
+use crate::follower::{diff_matches, FollowResult, ScoreFollower};
+use crate::score::ScoreNote;
+use crate::{IgnoreReason, Match};
+use std::time::Duration;
+
+/// A score follower which runs several followers on the same live performance and
+/// fuses their estimates
+///
+/// The score time and stretch factor are averaged over the members, weighted by their
+/// confidence. Matches and ignored notes are those of the most confident member, the
+/// leader, so they always form one consistent alignment.
+pub struct Ensemble<'a> {
+    members: Vec<Box<dyn ScoreFollower + 'a>>,
+    live: Vec<ScoreNote>,
+    leader: usize,
+    /// The matches of the leader as last reported in a [`FollowResult`]
+    reported: Vec<Match>,
+}
+
+impl<'a> Ensemble<'a> {
+    /// # Panics
+    ///
+    /// If `members` is empty
+    pub fn new(members: Vec<Box<dyn ScoreFollower + 'a>>) -> Self {
+        assert!(
+            !members.is_empty(),
+            "an ensemble needs at least one follower"
+        );
+        Self {
+            members,
+            live: vec![],
+            leader: 0,
+            reported: vec![],
+        }
+    }
+
+    fn leader(&self) -> &dyn ScoreFollower {
+        self.members[self.leader].as_ref()
+    }
+}
+
+/// Calculates the mean of values weighted by the corresponding weights
+///
+/// # Return value
+///
+/// The weighted mean, or `None` if all weights are zero
+pub(crate) fn weighted_mean(values: impl Iterator<Item = (f64, f64)>) -> Option<f64> {
+    let (sum, weight_sum) = values.fold((0.0, 0.0), |(sum, weight_sum), (value, weight)| {
+        (sum + weight * value, weight_sum + weight)
+    });
+    (weight_sum > 0.0).then(|| sum / weight_sum)
+}
+
+impl ScoreFollower for Ensemble<'_> {
+    fn push_live(&mut self, note: ScoreNote) {
+        self.live.push(note);
+        for member in self.members.iter_mut() {
+            member.push_live(note);
+        }
+    }
+
+    fn push_note_off(&mut self, note: ScoreNote) {
+        for member in self.members.iter_mut() {
+            member.push_note_off(note);
+        }
+    }
+
+    fn follow_score(&mut self) -> FollowResult {
+        let mut results = self
+            .members
+            .iter_mut()
+            .map(|member| {
+                let result = member.follow_score();
+                (result, member.confidence())
+            })
+            .collect::<Vec<_>>();
+        // of equally confident members, the first one leads
+        self.leader = results
+            .iter()
+            .enumerate()
+            .rev()
+            .max_by(|(_, (_, a)), (_, (_, b))| a.total_cmp(b))
+            .map(|(index, _)| index)
+            .unwrap_or(0);
+        let score_time = weighted_mean(
+            results
+                .iter()
+                .map(|(result, confidence)| (result.score_time as f64, *confidence as f64)),
+        );
+        let stretch_factor = weighted_mean(
+            results
+                .iter()
+                .map(|(result, confidence)| (result.stretch_factor as f64, *confidence as f64)),
+        );
+        let (leader_result, _) = results.swap_remove(self.leader);
+        // a change of leader replaces the alignment reported so far
+        let (new_matches, retracted) = diff_matches(&self.reported, self.leader().matches());
+        self.reported = self.leader().matches().to_vec();
+        FollowResult {
+            new_matches,
+            retracted,
+            score_time: score_time.map_or(leader_result.score_time, |time| time.round() as u64),
+            stretch_factor: stretch_factor.map_or(leader_result.stretch_factor, |f| f as f32),
+            ..leader_result
+        }
+    }
+
+    fn live(&self) -> &[ScoreNote] {
+        &self.live
+    }
+
+    fn matches(&self) -> &[Match] {
+        self.leader().matches()
+    }
+
+    fn ignored(&self) -> &[usize] {
+        self.leader().ignored()
+    }
+
+    fn ignore_reason(&self, live_index: usize) -> Option<IgnoreReason> {
+        self.leader().ignore_reason(live_index)
+    }
+
+    fn last_match(&self) -> Option<Match> {
+        self.leader().last_match()
+    }
+
+    fn tentative_count(&self) -> usize {
+        self.leader().tentative_count()
+    }
+
+    fn is_finished(&self) -> bool {
+        self.leader().is_finished()
+    }
+
+    fn estimated_score_time(&self, now: Duration) -> Option<u64> {
+        let estimates = self
+            .members
+            .iter()
+            .filter_map(|member| {
+                let time = member.estimated_score_time(now)? as f64;
+                Some((time, member.confidence() as f64))
+            })
+            .collect::<Vec<_>>();
+        weighted_mean(estimates.into_iter())
+            .map(|time| time.round() as u64)
+            .or_else(|| self.leader().estimated_score_time(now))
+    }
+
+    fn extend_score(&mut self, notes: &[ScoreNote]) {
+        for member in self.members.iter_mut() {
+            member.extend_score(notes);
+        }
+    }
+
+    fn reanchor(&mut self, anchor: Match) {
+        for member in self.members.iter_mut() {
+            member.reanchor(anchor);
+        }
+        self.reported = self.leader().matches().to_vec();
+    }
+
+    fn confidence(&self) -> f32 {
+        self.leader().confidence()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beam::BeamSearch;
+    use crate::follower::HomophonoPedantic;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn weighted_mean_of_values() {
+        let mean = weighted_mean([(1.0, 1.0), (4.0, 2.0)].into_iter()).unwrap();
+        assert_approx_eq!(mean, 3.0);
+        assert_eq!(weighted_mean([(1.0, 0.0)].into_iter()), None);
+    }
+
+    #[test]
+    fn agreeing_followers() {
+        let score = notes![(0, 60), (100, 62), (200, 64)];
+        let mut ensemble = Ensemble::new(vec![
+            Box::new(HomophonoPedantic::new(&score)),
+            Box::new(BeamSearch::new(&score)),
+        ]);
+        let mut result = None;
+        for note in notes![(0, 60), (200, 62), (400, 64)] {
+            ensemble.push_live(note);
+            result = Some(ensemble.follow_score());
+        }
+        let result = result.unwrap();
+        assert_eq!(result.score_time, 200);
+        assert_approx_eq!(result.stretch_factor, 2.0);
+        assert_eq!(ensemble.matches().len(), 3);
+        assert_approx_eq!(ensemble.confidence(), 1.0);
+    }
+
+    #[test]
+    fn most_confident_follower_leads() {
+        // the pedantic follower greedily matches the early E and loses the D, while the
+        // beam search follower treats the E as a wrong note
+        let score = notes![(0, 60), (100, 62), (200, 64), (300, 65), (400, 67)];
+        let live = notes![
+            (0, 60),
+            (50, 64),
+            (100, 62),
+            (200, 64),
+            (300, 65),
+            (400, 67)
+        ];
+        let mut ensemble = Ensemble::new(vec![
+            Box::new(HomophonoPedantic::new(&score)),
+            Box::new(BeamSearch::new(&score)),
+        ]);
+        for note in live {
+            ensemble.push_live(note);
+            ensemble.follow_score();
+        }
+        assert_eq!(ensemble.leader, 1);
+        assert_eq!(ensemble.ignored(), [1]);
+    }
+}
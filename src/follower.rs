@@ -0,0 +1,887 @@
+use crate::score::ScoreNote;
+use crate::trace::{trace_result, TraceSink};
+use crate::{
+    extrapolate_score_time, fit_time_mapping, follow_score_mapped, get_score_time,
+    windowed_stretch_factor, IgnoreReason, Match,
+};
+use std::borrow::Cow;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How many of the latest live notes are taken into account in
+/// [`ScoreFollower::confidence`]
+const CONFIDENCE_WINDOW: usize = 8;
+
+/// How the live time of a rolled chord is taken from the live times of its notes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChordTime {
+    /// The time of the first note played
+    First,
+    /// The time of the last note played
+    Last,
+    /// The mean time of all notes played
+    Mean,
+}
+
+impl FromStr for ChordTime {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(ChordTime::First),
+            "last" => Ok(ChordTime::Last),
+            "mean" => Ok(ChordTime::Mean),
+            _ => Err(format!("unknown chord time policy '{}'", s)),
+        }
+    }
+}
+
+/// Settings for matching chords which are rolled, i.e. arpeggiated, in the live
+/// performance although they are simultaneous in the score
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RollConfig {
+    /// The longest spread in microseconds of the live notes of one chord
+    pub window: u64,
+    /// How the live time of a chord is taken from its notes
+    pub chord_time: ChordTime,
+}
+
+impl Default for RollConfig {
+    /// Only notes played at exactly the same time form a chord
+    fn default() -> Self {
+        Self {
+            window: 0,
+            chord_time: ChordTime::First,
+        }
+    }
+}
+
+/// Weights for choosing among several score notes of the pitch of a live note, e.g. in
+/// a passage of repeated notes
+///
+/// The cost of a candidate is `time` times its distance in seconds from the predicted
+/// score time plus `skip` times the number of score notes skipped over to reach it.
+/// The cheapest candidate is matched.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CostConfig {
+    /// The cost of one second between the predicted score time and the score note
+    pub time: f32,
+    /// The cost of each score note skipped over, which makes matches prefer notes near
+    /// the current position in the score
+    pub skip: f32,
+}
+
+impl Default for CostConfig {
+    /// Chooses the candidate closest to the predicted score time
+    fn default() -> Self {
+        Self {
+            time: 1.0,
+            skip: 0.0,
+        }
+    }
+}
+
+impl CostConfig {
+    /// Calculates the cost of a candidate
+    ///
+    /// # Arguments
+    ///
+    /// * skipped - The number of score notes skipped over to reach the candidate
+    /// * time_difference - The distance in microseconds from the predicted score time
+    pub fn of(&self, skipped: usize, time_difference: u64) -> f32 {
+        self.time * time_difference as f32 / 1_000_000.0 + self.skip * skipped as f32
+    }
+}
+
+/// Settings for estimating the time stretch factor in a follower
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StretchConfig {
+    /// The maximum number of recent pairs of matches to estimate the stretch factor
+    /// from, see [`windowed_stretch_factor`]
+    pub window: usize,
+    /// The weight of each pair of matches relative to the next newer one
+    pub decay: f32,
+    /// The smallest allowed stretch factor
+    pub min: f32,
+    /// The largest allowed stretch factor
+    pub max: f32,
+    /// How to take the live time of rolled chords into account
+    pub roll: RollConfig,
+}
+
+impl Default for StretchConfig {
+    /// Uses only the last pair of matches, without limiting the stretch factor
+    fn default() -> Self {
+        Self {
+            window: 1,
+            decay: 1.0,
+            min: 0.0,
+            max: f32::INFINITY,
+            roll: RollConfig::default(),
+        }
+    }
+}
+
+impl StretchConfig {
+    /// Limits a stretch factor to the configured bounds
+    ///
+    /// # Return value
+    ///
+    /// The clamped stretch factor, or `fallback` if `stretch_factor` is not a number
+    /// (which happens when two matched notes have the same time in the score)
+    pub fn clamp(&self, stretch_factor: f32, fallback: f32) -> f32 {
+        if stretch_factor.is_nan() {
+            fallback
+        } else {
+            stretch_factor.clamp(self.min, self.max)
+        }
+    }
+}
+
+/// Settings for a [`HomophonoPedantic`] follower
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Config {
+    /// How to estimate the time stretch factor
+    pub stretch: StretchConfig,
+    /// The maximum number of score notes after the previous match to search for a match
+    /// for each live note, or `None` to search until the end of the score
+    pub search_window: Option<usize>,
+    /// The number of recent matches to fit a linear live-to-score time mapping to, see
+    /// [`fit_time_mapping`], or `None` to map live time from the latest match and the
+    /// stretch factor
+    pub regression_window: Option<usize>,
+    /// The stretch factor to assume until two live notes have been matched
+    pub initial_stretch_factor: f32,
+    /// The largest difference in microseconds between the predicted score time of a live
+    /// note and the time of a matching score note, or `None` to accept matches at any
+    /// distance
+    pub max_time_difference: Option<u64>,
+    /// How to choose among several score notes of the pitch of a live note
+    pub cost: CostConfig,
+}
+
+impl Default for Config {
+    /// Searches the whole score at any distance, starting at the tempo of the score
+    fn default() -> Self {
+        Self {
+            stretch: StretchConfig::default(),
+            search_window: None,
+            regression_window: None,
+            initial_stretch_factor: 1.0,
+            max_time_difference: None,
+            cost: CostConfig::default(),
+        }
+    }
+}
+
+/// The outcome of matching new live notes against the score
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FollowResult {
+    /// The estimated time of the latest live note in the score
+    pub score_time: u64,
+    /// The time stretch factor at the latest matching live note
+    pub stretch_factor: f32,
+    /// Matches found for the new live notes, and revised matches for earlier live notes
+    pub new_matches: Vec<Match>,
+    /// New live notes which couldn't be matched, as live performance indices with the
+    /// reason for ignoring each one
+    pub ignored: Vec<(usize, IgnoreReason)>,
+    /// Previously reported matches which later live notes showed to be wrong
+    pub retracted: Vec<Match>,
+}
+
+/// Compares the matches reported earlier with the current ones
+///
+/// # Arguments
+///
+/// * reported - The matches reported so far, in live performance order
+/// * current - The current matches, in live performance order
+///
+/// # Return value
+///
+/// A 2-tuple of
+/// * current matches which haven't been reported
+/// * reported matches which are no longer current
+pub(crate) fn diff_matches(reported: &[Match], current: &[Match]) -> (Vec<Match>, Vec<Match>) {
+    let common = reported
+        .iter()
+        .zip(current)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (reported, current) = (&reported[common..], &current[common..]);
+    let added = current
+        .iter()
+        .filter(|m| !reported.contains(m))
+        .copied()
+        .collect();
+    let retracted = reported
+        .iter()
+        .filter(|m| !current.contains(m))
+        .copied()
+        .collect();
+    (added, retracted)
+}
+
+/// Takes the live note of a re-anchoring match off the ignored notes of a follower for
+/// [`ScoreFollower::reanchor`], so that it doesn't count as both matched and ignored
+pub(crate) fn unignore(
+    ignored: &mut Vec<usize>,
+    ignore_reasons: &mut Vec<IgnoreReason>,
+    live_index: usize,
+) {
+    if let Ok(position) = ignored.binary_search(&live_index) {
+        ignored.remove(position);
+        ignore_reasons.remove(position);
+    }
+}
+
+/// Appends notes to the score of a follower for [`ScoreFollower::extend_score`]
+pub(crate) fn append_score_notes(score: &mut Cow<[ScoreNote]>, notes: &[ScoreNote]) {
+    let mut end = score.last().map_or(0, |note| note.time);
+    for note in notes {
+        assert!(
+            note.time >= end,
+            "score notes must be appended in time order"
+        );
+        end = note.time;
+    }
+    score.to_mut().extend_from_slice(notes);
+}
+
+/// A score following algorithm which keeps track of the live performance so far
+pub trait ScoreFollower {
+    /// Adds a new note received from the live performance
+    fn push_live(&mut self, note: ScoreNote);
+
+    /// Tells that a live note was released, for followers which use note durations
+    ///
+    /// The time and pitch identify the released note, and the latest live note with that
+    /// pitch which is still held is taken to be the one released.
+    fn push_note_off(&mut self, _note: ScoreNote) {}
+
+    /// Matches live notes added since the previous call against the score
+    fn follow_score(&mut self) -> FollowResult;
+
+    /// The live performance received so far
+    fn live(&self) -> &[ScoreNote];
+
+    /// All matches between the live performance and the score found so far
+    fn matches(&self) -> &[Match];
+
+    /// Indices of all live notes ignored so far, in ascending order
+    fn ignored(&self) -> &[usize];
+
+    /// The latest match between the live performance and the score
+    fn last_match(&self) -> Option<Match>;
+
+    /// Explains why a live note was ignored
+    ///
+    /// # Return value
+    ///
+    /// The reason, or `None` if the live note wasn't ignored
+    fn ignore_reason(&self, live_index: usize) -> Option<IgnoreReason>;
+
+    /// The number of live notes ignored since the latest match
+    fn ignored_streak(&self) -> usize {
+        let after = self.last_match().map(|m| m.live_index);
+        self.ignored()
+            .iter()
+            .rev()
+            .take_while(|&&i| Some(i) > after)
+            .count()
+    }
+
+    /// The number of latest matches which are tentative, i.e. may still be retracted
+    /// when later live notes reveal a better alignment
+    ///
+    /// Matches before them are committed and will never change.
+    fn tentative_count(&self) -> usize {
+        0
+    }
+
+    /// Tells whether the last note of the score has been matched
+    fn is_finished(&self) -> bool;
+
+    /// Estimates the current score position between live notes
+    ///
+    /// # Arguments
+    ///
+    /// * now - The current live time, on the same clock as the live note timestamps
+    ///
+    /// # Return value
+    ///
+    /// The score time in microseconds extrapolated from the latest match at the current
+    /// tempo, or `None` if nothing has been matched yet
+    fn estimated_score_time(&self, now: Duration) -> Option<u64>;
+
+    /// Appends notes to the end of the expected score while following, e.g. for an
+    /// improvised section generated on the fly
+    ///
+    /// A borrowed score is copied on the first call. Later calls only append the new
+    /// notes, and matches found so far stay valid.
+    ///
+    /// # Panics
+    ///
+    /// If the notes aren't in time order or start before the end of the score
+    fn extend_score(&mut self, notes: &[ScoreNote]);
+
+    /// Moves the follower to a new position, e.g. after the performer jumped in the score
+    fn reanchor(&mut self, anchor: Match);
+
+    /// Estimates how certain the follower is about its current position
+    ///
+    /// # Return value
+    ///
+    /// A number between 0.0 (lost) and 1.0 (certain), based on how many of the latest
+    /// live notes were matched and how well their timing agrees with the stretched score
+    fn confidence(&self) -> f32;
+
+    /// The detected transposition of the live performance relative to the score
+    ///
+    /// # Return value
+    ///
+    /// The offset in semitones, or `None` if the follower doesn't detect transpositions
+    /// or hasn't detected one yet
+    fn transposition(&self) -> Option<i8> {
+        None
+    }
+}
+
+/// The naïve monophonic score follower implemented by [`follow_score`](crate::follow_score)
+pub struct HomophonoPedantic<'a> {
+    score: Cow<'a, [ScoreNote]>,
+    live: Vec<ScoreNote>,
+    matches: Vec<Match>,
+    ignored: Vec<usize>,
+    /// The reason for ignoring each live note in `ignored`, in the same order
+    ignore_reasons: Vec<IgnoreReason>,
+    new_live_index: usize,
+    stretch_factor: f32,
+    config: Config,
+    trace: Option<Box<dyn TraceSink + 'a>>,
+}
+
+impl<'a> HomophonoPedantic<'a> {
+    pub fn new(score: &'a [ScoreNote]) -> Self {
+        Self::with_config(score, Config::default())
+    }
+
+    pub fn with_config(score: &'a [ScoreNote], config: Config) -> Self {
+        Self {
+            score: Cow::Borrowed(score),
+            live: vec![],
+            matches: vec![],
+            ignored: vec![],
+            ignore_reasons: vec![],
+            new_live_index: 0,
+            stretch_factor: config.initial_stretch_factor,
+            config,
+            trace: None,
+        }
+    }
+
+    /// Traces the matching decisions for each live note to a sink
+    pub fn with_trace(mut self, sink: Box<dyn TraceSink + 'a>) -> Self {
+        self.trace = Some(sink);
+        self
+    }
+}
+
+impl ScoreFollower for HomophonoPedantic<'_> {
+    fn push_live(&mut self, note: ScoreNote) {
+        self.live.push(note);
+    }
+
+    fn follow_score(&mut self) -> FollowResult {
+        let prev_match = self.last_match();
+        let mapping = self
+            .config
+            .regression_window
+            .and_then(|window| fit_time_mapping(&self.score, &self.live, &self.matches, window));
+        let (mut score_time, estimate, new_matches, ignored) = follow_score_mapped(
+            &self.score,
+            &self.live,
+            prev_match,
+            self.new_live_index,
+            self.stretch_factor,
+            &self.config,
+            mapping.as_ref(),
+        );
+        self.matches.extend(new_matches.iter());
+        let config = self.config.stretch;
+        // with rolled chords, only the windowed estimate uses the chord times
+        let windowed = if (config.window > 1 || config.roll.window > 0) && !new_matches.is_empty() {
+            windowed_stretch_factor(
+                &self.score,
+                &self.live,
+                &self.matches,
+                config.window,
+                config.decay,
+                &config.roll,
+            )
+        } else {
+            None
+        };
+        let estimate = windowed.unwrap_or(estimate);
+        let stretch_factor = config.clamp(estimate, self.stretch_factor);
+        if stretch_factor != estimate && mapping.is_none() {
+            score_time = get_score_time(&self.score, &self.live, prev_match, stretch_factor);
+        }
+        for &(live_index, reason) in &ignored {
+            self.ignored.push(live_index);
+            self.ignore_reasons.push(reason);
+        }
+        self.new_live_index = self.live.len();
+        self.stretch_factor = stretch_factor;
+        let result = FollowResult {
+            score_time,
+            stretch_factor,
+            new_matches,
+            ignored,
+            retracted: vec![],
+        };
+        trace_result(&mut self.trace, &result);
+        result
+    }
+
+    fn live(&self) -> &[ScoreNote] {
+        &self.live
+    }
+
+    fn matches(&self) -> &[Match] {
+        &self.matches
+    }
+
+    fn ignored(&self) -> &[usize] {
+        &self.ignored
+    }
+
+    fn ignore_reason(&self, live_index: usize) -> Option<IgnoreReason> {
+        let position = self.ignored.binary_search(&live_index).ok()?;
+        Some(self.ignore_reasons[position])
+    }
+
+    fn last_match(&self) -> Option<Match> {
+        self.matches.last().copied()
+    }
+
+    fn is_finished(&self) -> bool {
+        self.last_match()
+            .is_some_and(|m| m.score_index + 1 == self.score.len())
+    }
+
+    fn estimated_score_time(&self, now: Duration) -> Option<u64> {
+        extrapolate_score_time(
+            &self.score,
+            &self.live,
+            self.last_match(),
+            self.stretch_factor,
+            now,
+        )
+    }
+
+    fn extend_score(&mut self, notes: &[ScoreNote]) {
+        append_score_notes(&mut self.score, notes);
+    }
+
+    fn reanchor(&mut self, anchor: Match) {
+        unignore(
+            &mut self.ignored,
+            &mut self.ignore_reasons,
+            anchor.live_index,
+        );
+        self.matches.push(anchor);
+    }
+
+    fn confidence(&self) -> f32 {
+        matches_confidence(&self.score, &self.live, &self.matches, self.stretch_factor)
+    }
+}
+
+/// Calculates the confidence of a follower from the matches it has found
+///
+/// The confidence is the product of
+/// * the ratio of matched notes among the latest [`CONFIDENCE_WINDOW`] live notes, and
+/// * one minus the mean relative timing error between consecutive recent matches,
+///   comparing live time differences with stretched score time differences
+pub(crate) fn matches_confidence(
+    score: &[ScoreNote],
+    live: &[ScoreNote],
+    matches: &[Match],
+    stretch_factor: f32,
+) -> f32 {
+    if live.is_empty() {
+        return 0.0;
+    }
+    let window_start = live.len().saturating_sub(CONFIDENCE_WINDOW);
+    let recent = matches
+        .iter()
+        .filter(|m| m.live_index >= window_start)
+        .collect::<Vec<_>>();
+    let match_rate = recent.len() as f32 / (live.len() - window_start) as f32;
+    let residuals = recent
+        .windows(2)
+        .map(|pair| {
+            let elapsed_live =
+                live[pair[1].live_index].time as f32 - live[pair[0].live_index].time as f32;
+            let elapsed_score =
+                score[pair[1].score_index].time as f32 - score[pair[0].score_index].time as f32;
+            let error = (elapsed_live - elapsed_score * stretch_factor).abs();
+            (error / elapsed_live.abs().max(1.0)).min(1.0)
+        })
+        .collect::<Vec<_>>();
+    let timing = if residuals.is_empty() {
+        1.0
+    } else {
+        1.0 - residuals.iter().sum::<f32>() / residuals.len() as f32
+    };
+    match_rate * timing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+    use midly::num::u7;
+
+    fn follow(score: &[ScoreNote], live: &[ScoreNote]) -> f32 {
+        let mut follower = HomophonoPedantic::new(score);
+        for note in live {
+            follower.push_live(*note);
+            follower.follow_score();
+        }
+        follower.confidence()
+    }
+
+    #[test]
+    fn no_confidence_before_live_notes() {
+        let score = notes![(0, 60), (100, 62)];
+        assert_approx_eq!(follow(&score, &[]), 0.0);
+    }
+
+    #[test]
+    fn full_confidence_in_steady_tempo() {
+        let score = notes![(0, 60), (100, 62), (200, 64), (300, 65)];
+        let live = notes![(0, 60), (200, 62), (400, 64), (600, 65)];
+        assert_approx_eq!(follow(&score, &live), 1.0);
+    }
+
+    #[test]
+    fn wrong_notes_reduce_confidence() {
+        let score = notes![(0, 60), (100, 62), (200, 64), (300, 65)];
+        let live = notes![(0, 60), (100, 61), (200, 63), (300, 62)];
+        assert_approx_eq!(follow(&score, &live), 0.5);
+    }
+
+    #[test]
+    fn uneven_timing_reduces_confidence() {
+        let score = notes![(0, 60), (100, 62), (200, 64)];
+        let live = notes![(0, 60), (100, 62), (300, 64)];
+        // stretch factor ends up at 2.0, so the first interval is off by 100%
+        assert_approx_eq!(follow(&score, &live), 0.5);
+    }
+
+    #[test]
+    fn windowed_stretch_factor_smooths_tempo() {
+        let score = notes![(0, 60), (100, 62), (200, 64), (300, 65)];
+        let live = notes![(0, 60), (100, 62), (200, 64), (500, 65)];
+        let config = Config {
+            stretch: StretchConfig {
+                window: 3,
+                decay: 0.5,
+                ..StretchConfig::default()
+            },
+            ..Config::default()
+        };
+        let mut follower = HomophonoPedantic::with_config(&score, config);
+        let mut result = None;
+        for note in live {
+            follower.push_live(note);
+            result = Some(follower.follow_score());
+        }
+        // pairs 2->3 (3.0), 1->2 (1.0) and 0->1 (1.0) with weights 1.0, 0.5 and 0.25
+        assert_approx_eq!(result.unwrap().stretch_factor, 3.75 / 1.75);
+    }
+
+    #[test]
+    fn clamp_stretch_factor() {
+        let score = notes![(0, 60), (100, 62), (200, 64)];
+        let live = notes![(0, 60), (100, 62), (2100, 64)];
+        let config = Config {
+            stretch: StretchConfig {
+                min: 0.5,
+                max: 4.0,
+                ..StretchConfig::default()
+            },
+            ..Config::default()
+        };
+        let mut follower = HomophonoPedantic::with_config(&score, config);
+        let mut result = None;
+        for note in live {
+            follower.push_live(note);
+            result = Some(follower.follow_score());
+        }
+        let result = result.unwrap();
+        assert_approx_eq!(result.stretch_factor, 4.0);
+        // 100 + 2000 / 4.0
+        assert_eq!(result.score_time, 600);
+    }
+
+    #[test]
+    fn regression_time_mapping() {
+        let score = notes![(0, 60), (100, 62), (200, 64), (300, 65)];
+        let live = notes![(0, 60), (110, 62), (190, 64), (300, 61)];
+        let config = Config {
+            regression_window: Some(3),
+            ..Config::default()
+        };
+        let mut follower = HomophonoPedantic::with_config(&score, config);
+        let mut result = None;
+        for note in live {
+            follower.push_live(note);
+            result = Some(follower.follow_score());
+        }
+        let result = result.unwrap();
+        assert_eq!(result.ignored, [(3, IgnoreReason::WrongPitch)]);
+        // slope 19000 / 18200 through the mean point (100, 100)
+        assert_eq!(result.score_time, 309);
+    }
+
+    #[test]
+    fn explain_ignored_notes() {
+        let score = notes![(0, 60), (100, 62), (200, 64)];
+        let config = Config {
+            search_window: Some(1),
+            ..Config::default()
+        };
+        let mut follower = HomophonoPedantic::with_config(&score, config);
+        for note in notes![(0, 60), (100, 64), (200, 61)] {
+            follower.push_live(note);
+            follower.follow_score();
+        }
+        assert_eq!(
+            follower.ignore_reason(1),
+            Some(IgnoreReason::OutsideSearchWindow)
+        );
+        assert_eq!(follower.ignore_reason(2), Some(IgnoreReason::WrongPitch));
+        assert_eq!(follower.ignore_reason(0), None);
+    }
+
+    #[test]
+    fn diff_reported_matches() {
+        let reported = [Match::new(0, 0), Match::new(3, 1), Match::new(4, 2)];
+        let current = [
+            Match::new(0, 0),
+            Match::new(1, 1),
+            Match::new(4, 2),
+            Match::new(5, 3),
+        ];
+        let (added, retracted) = diff_matches(&reported, &current);
+        assert_eq!(added, [Match::new(1, 1), Match::new(5, 3)]);
+        assert_eq!(retracted, [Match::new(3, 1)]);
+    }
+
+    #[test]
+    fn initial_stretch_factor() {
+        let score = notes![(0, 60), (100, 62)];
+        let config = Config {
+            initial_stretch_factor: 2.0,
+            ..Config::default()
+        };
+        let mut follower = HomophonoPedantic::with_config(&score, config);
+        follower.push_live(ScoreNote {
+            time: 1000,
+            pitch: u7::from(61),
+        });
+        let result = follower.follow_score();
+        assert_approx_eq!(result.stretch_factor, 2.0);
+    }
+
+    #[test]
+    fn reject_match_far_from_predicted_time() {
+        let score = notes![(0, 60), (100, 62), (200, 64), (1000, 65)];
+        let live = notes![(0, 60), (100, 62), (200, 65), (300, 64)];
+        let config = Config {
+            max_time_difference: Some(100),
+            ..Config::default()
+        };
+        let mut follower = HomophonoPedantic::with_config(&score, config);
+        for note in live {
+            follower.push_live(note);
+            follower.follow_score();
+        }
+        assert_eq!(follower.ignored(), [2]);
+        assert_eq!(
+            follower.ignore_reason(2),
+            Some(IgnoreReason::TooFarFromExpectedTime),
+            "the F is 800 µs early"
+        );
+        assert_eq!(follower.ignore_reason(3), None);
+        assert_eq!(follower.last_match(), Some(Match::new(2, 3)));
+    }
+
+    #[test]
+    fn keep_stretch_factor_for_simultaneous_score_notes() {
+        let config = StretchConfig {
+            min: 0.5,
+            max: 4.0,
+            ..StretchConfig::default()
+        };
+        assert_approx_eq!(config.clamp(f32::NAN, 1.5), 1.5);
+        assert_approx_eq!(config.clamp(f32::INFINITY, 1.5), 4.0);
+        assert_approx_eq!(config.clamp(0.1, 1.5), 0.5);
+    }
+
+    #[test]
+    fn count_ignored_streak() {
+        let score = notes![(0, 60), (100, 62), (200, 64)];
+        let mut follower = HomophonoPedantic::new(&score);
+        for note in notes![(0, 61), (100, 60), (200, 61), (300, 63)] {
+            follower.push_live(note);
+            follower.follow_score();
+        }
+        assert_eq!(follower.ignored(), [0, 2, 3]);
+        assert_eq!(follower.ignored_streak(), 2);
+        follower.reanchor(Match::new(2, 3));
+        assert_eq!(follower.ignored_streak(), 0);
+    }
+
+    #[test]
+    fn reanchor_unignores_anchor_note() {
+        let score = notes![(0, 60), (100, 62), (200, 64)];
+        let mut follower = HomophonoPedantic::new(&score);
+        for note in notes![(0, 61), (100, 60), (200, 61), (300, 63)] {
+            follower.push_live(note);
+            follower.follow_score();
+        }
+        follower.reanchor(Match::new(2, 3));
+        assert_eq!(follower.ignored(), [0, 2]);
+        assert_eq!(follower.ignore_reason(2), Some(IgnoreReason::WrongPitch));
+        assert!(follower
+            .matches()
+            .iter()
+            .all(|m| !follower.ignored().contains(&m.live_index)));
+    }
+
+    #[test]
+    fn finish_at_last_score_note() {
+        let score = notes![(0, 60), (100, 62)];
+        let mut follower = HomophonoPedantic::new(&score);
+        for note in notes![(0, 60), (100, 61)] {
+            follower.push_live(note);
+            follower.follow_score();
+            assert!(!follower.is_finished());
+        }
+        follower.push_live(ScoreNote {
+            time: 200,
+            pitch: u7::from(62),
+        });
+        follower.follow_score();
+        assert!(follower.is_finished());
+    }
+
+    #[test]
+    fn estimate_score_time_between_notes() {
+        let score = notes![(0, 60), (100, 62), (200, 64)];
+        let mut follower = HomophonoPedantic::new(&score);
+        assert_eq!(
+            follower.estimated_score_time(Duration::from_micros(50)),
+            None
+        );
+        for note in notes![(1000, 60), (1200, 62)] {
+            follower.push_live(note);
+            follower.follow_score();
+        }
+        // half the score tempo, so 100 µs live is 50 µs in the score
+        assert_eq!(
+            follower.estimated_score_time(Duration::from_micros(1300)),
+            Some(150)
+        );
+        // a clock lagging behind the latest note doesn't move the position backwards
+        assert_eq!(
+            follower.estimated_score_time(Duration::from_micros(1100)),
+            Some(100)
+        );
+    }
+
+    #[test]
+    fn rolled_chord() {
+        let score = notes![(0, 60), (0, 64), (0, 67), (100, 72)];
+        let live = notes![(0, 60), (40, 64), (80, 67), (200, 72)];
+        let follow = |roll| {
+            let config = Config {
+                stretch: StretchConfig {
+                    roll,
+                    ..StretchConfig::default()
+                },
+                max_time_difference: Some(30),
+                ..Config::default()
+            };
+            let mut follower = HomophonoPedantic::with_config(&score, config);
+            let mut stretch_factor = 0.0;
+            for note in &live {
+                follower.push_live(*note);
+                stretch_factor = follower.follow_score().stretch_factor;
+            }
+            (follower.matches().len(), stretch_factor)
+        };
+        // without a roll window, the spread-out chord notes are too late
+        let (matched, _) = follow(RollConfig::default());
+        assert_eq!(matched, 1);
+        let (matched, stretch_factor) = follow(RollConfig {
+            window: 100,
+            chord_time: ChordTime::First,
+        });
+        assert_eq!(matched, 4);
+        assert_approx_eq!(stretch_factor, 2.0);
+    }
+
+    #[test]
+    fn follow_extended_score() {
+        let score = notes![(0, 60), (100, 62)];
+        let mut follower = HomophonoPedantic::new(&score);
+        for note in notes![(0, 60), (100, 62)] {
+            follower.push_live(note);
+            follower.follow_score();
+        }
+        assert!(follower.is_finished());
+        follower.extend_score(&notes![(200, 64), (300, 65)]);
+        assert!(!follower.is_finished());
+        for note in notes![(200, 64), (300, 65)] {
+            follower.push_live(note);
+            follower.follow_score();
+        }
+        assert_eq!(follower.matches().len(), 4);
+        assert!(follower.is_finished());
+    }
+
+    #[test]
+    #[should_panic(expected = "time order")]
+    fn extend_score_only_at_end() {
+        let score = notes![(0, 60), (100, 62)];
+        HomophonoPedantic::new(&score).extend_score(&notes![(50, 61)]);
+    }
+
+    #[test]
+    fn follow_result() {
+        let score = notes![(1000, 60), (1100, 62)];
+        let mut follower = HomophonoPedantic::new(&score);
+        follower.push_live(ScoreNote {
+            time: 5,
+            pitch: u7::from(60),
+        });
+        let result = follower.follow_score();
+        assert_eq!(
+            result,
+            FollowResult {
+                score_time: 1000,
+                stretch_factor: 1.0,
+                new_matches: vec![Match::new(0, 0)],
+                ignored: vec![],
+                retracted: vec![],
+            }
+        );
+        assert_eq!(follower.last_match(), Some(Match::new(0, 0)));
+    }
+}
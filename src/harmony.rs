@@ -0,0 +1,166 @@
+use crate::score::{pitch_class_name, ScoreNote};
+use std::fmt;
+
+/// Chord qualities recognized by [`infer_chord`], with their intervals from the root
+/// in semitones
+const QUALITIES: [(ChordQuality, &[u8]); 5] = [
+    (ChordQuality::Major, &[0, 4, 7]),
+    (ChordQuality::Minor, &[0, 3, 7]),
+    (ChordQuality::Diminished, &[0, 3, 6]),
+    (ChordQuality::Dominant7, &[0, 4, 7, 10]),
+    (ChordQuality::Minor7, &[0, 3, 7, 10]),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChordQuality {
+    Major,
+    Minor,
+    Diminished,
+    Dominant7,
+    Minor7,
+}
+
+impl ChordQuality {
+    /// Returns the intervals of the chord tones from the root in semitones
+    pub fn intervals(self) -> &'static [u8] {
+        QUALITIES
+            .iter()
+            .find(|(quality, _)| *quality == self)
+            .map(|(_, intervals)| *intervals)
+            .unwrap()
+    }
+}
+
+/// A chord symbol, e.g. `Am` or `G7`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Chord {
+    /// The pitch class of the root, 0 being C
+    pub root: u8,
+    pub quality: ChordQuality,
+}
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let suffix = match self.quality {
+            ChordQuality::Major => "",
+            ChordQuality::Minor => "m",
+            ChordQuality::Diminished => "dim",
+            ChordQuality::Dominant7 => "7",
+            ChordQuality::Minor7 => "m7",
+        };
+        write!(f, "{}{}", pitch_class_name(self.root), suffix)
+    }
+}
+
+/// Counts the notes of each pitch class starting in the given time range of the score
+///
+/// # Return value
+///
+/// The number of notes for each pitch class, index 0 being C
+pub fn pitch_class_profile(score: &[ScoreNote], start: u64, end: u64) -> [u32; 12] {
+    let mut profile = [0; 12];
+    for note in score.iter().filter(|n| (start..end).contains(&n.time)) {
+        profile[(note.pitch.as_int() % 12) as usize] += 1;
+    }
+    profile
+}
+
+/// Finds the chord which best explains a pitch class profile
+///
+/// Each candidate chord gains the count of every pitch class belonging to it and loses
+/// the count of every other pitch class. Of equally good candidates, the simplest
+/// (triads before seventh chords) with the lowest root is chosen.
+///
+/// # Return value
+///
+/// The best chord, or `None` if the profile is empty
+pub fn infer_chord(profile: &[u32; 12]) -> Option<Chord> {
+    if profile.iter().all(|&count| count == 0) {
+        return None;
+    }
+    let total = profile.iter().sum::<u32>() as i64;
+    let mut best: Option<(i64, Chord)> = None;
+    for (quality, intervals) in QUALITIES {
+        for root in 0..12 {
+            let inside = intervals
+                .iter()
+                .map(|interval| profile[((root + interval) % 12) as usize] as i64)
+                .sum::<i64>();
+            let fit = 2 * inside - total;
+            if best.is_none_or(|(best_fit, _)| fit > best_fit) {
+                best = Some((fit, Chord { root, quality }));
+            }
+        }
+    }
+    best.map(|(_, chord)| chord)
+}
+
+/// Infers the chord in the score just before the given score time
+///
+/// # Arguments
+///
+/// * score - The complete expected musical score with timestamps and pitches
+/// * time - The current score time in microseconds
+/// * window - How far back from `time` to look at score notes, in microseconds
+pub fn chord_at(score: &[ScoreNote], time: u64, window: u64) -> Option<Chord> {
+    infer_chord(&pitch_class_profile(
+        score,
+        time.saturating_sub(window),
+        time + 1,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(pitches: &[u8]) -> [u32; 12] {
+        let mut profile = [0; 12];
+        for pitch in pitches {
+            profile[(pitch % 12) as usize] += 1;
+        }
+        profile
+    }
+
+    #[test]
+    fn no_chord_without_notes() {
+        assert_eq!(infer_chord(&[0; 12]), None);
+    }
+
+    #[test]
+    fn triads() {
+        let c_major = infer_chord(&profile(&[60, 64, 67])).unwrap();
+        assert_eq!(c_major.to_string(), "C");
+        let a_minor = infer_chord(&profile(&[57, 60, 64, 69])).unwrap();
+        assert_eq!(a_minor.to_string(), "Am");
+        let h_diminished = infer_chord(&profile(&[59, 62, 65])).unwrap();
+        assert_eq!(h_diminished.to_string(), "Hdim");
+    }
+
+    #[test]
+    fn seventh_chord() {
+        let g7 = infer_chord(&profile(&[55, 59, 62, 65])).unwrap();
+        assert_eq!(g7.to_string(), "G7");
+    }
+
+    #[test]
+    fn passing_note_does_not_change_chord() {
+        let chord = infer_chord(&profile(&[48, 60, 62, 64, 67])).unwrap();
+        assert_eq!(chord.to_string(), "C");
+    }
+
+    #[test]
+    fn chord_in_time_window() {
+        let score = notes![
+            (0, 57),
+            (0, 60),
+            (0, 64),
+            (1000, 55),
+            (1000, 59),
+            (1000, 62)
+        ];
+        assert_eq!(chord_at(&score, 500, 500).unwrap().to_string(), "Am");
+        assert_eq!(chord_at(&score, 1000, 500).unwrap().to_string(), "G");
+        assert_eq!(chord_at(&score, 2000, 500), None);
+    }
+}
@@ -0,0 +1,79 @@
+use crate::score::pitch_to_name;
+use midly::num::u7;
+use std::str::FromStr;
+
+const ENGLISH_NAMES: [&str; 12] = [
+    "C", "C#", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B",
+];
+const SOLFEGE_NAMES: [&str; 12] = [
+    "Do", "Do#", "Re", "Mib", "Mi", "Fa", "Fa#", "Sol", "Lab", "La", "Sib", "Si",
+];
+
+/// A convention for naming pitches in user-facing output
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoteNaming {
+    /// German names with H for B natural and B for B flat, see [`pitch_to_name`]
+    German,
+    /// English names with scientific octave numbers, e.g. middle C is C4
+    English,
+    /// Fixed-do solfège with scientific octave numbers, e.g. middle C is Do4
+    Solfege,
+}
+
+impl FromStr for NoteNaming {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "german" => Ok(NoteNaming::German),
+            "english" => Ok(NoteNaming::English),
+            "solfege" => Ok(NoteNaming::Solfege),
+            _ => Err(format!("unknown note naming '{}'", s)),
+        }
+    }
+}
+
+/// Returns the name of a pitch in the given naming convention
+pub fn pitch_to_name_in(pitch: u7, naming: NoteNaming) -> String {
+    let pitch_u8 = pitch.as_int();
+    let names = match naming {
+        NoteNaming::German => return pitch_to_name(pitch),
+        NoteNaming::English => ENGLISH_NAMES,
+        NoteNaming::Solfege => SOLFEGE_NAMES,
+    };
+    let octave = (pitch_u8 / 12) as i8 - 1;
+    format!("{}{}", names[(pitch_u8 % 12) as usize], octave)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        pitch,
+        naming,
+        expect,
+        case(60, NoteNaming::German, "C1"),
+        case(70, NoteNaming::German, "B1"),
+        case(71, NoteNaming::German, "H1"),
+        case(0, NoteNaming::English, "C-1"),
+        case(60, NoteNaming::English, "C4"),
+        case(70, NoteNaming::English, "Bb4"),
+        case(71, NoteNaming::English, "B4"),
+        case(127, NoteNaming::English, "G9"),
+        case(60, NoteNaming::Solfege, "Do4"),
+        case(63, NoteNaming::Solfege, "Mib4"),
+        case(67, NoteNaming::Solfege, "Sol4"),
+        case(71, NoteNaming::Solfege, "Si4")
+    )]
+    fn test_pitch_to_name_in(pitch: u8, naming: NoteNaming, expect: &str) {
+        assert_eq!(pitch_to_name_in(u7::from(pitch), naming), expect);
+    }
+
+    #[test]
+    fn parse_note_naming() {
+        assert_eq!("solfege".parse(), Ok(NoteNaming::Solfege));
+        assert!("klingon".parse::<NoteNaming>().is_err());
+    }
+}
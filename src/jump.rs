@@ -0,0 +1,382 @@
+use crate::score::ScoreNote;
+use crate::Match;
+
+/// Counts how many notes of `run` have the same pitch as the notes in the score
+/// starting at `score[score_start]`
+fn count_run_matches(score: &[ScoreNote], score_start: usize, run: &[ScoreNote]) -> usize {
+    score[score_start..]
+        .iter()
+        .zip(run)
+        .filter(|(score_note, live_note)| score_note.pitch == live_note.pitch)
+        .count()
+}
+
+/// Finds the start of the score region which best matches `run`
+///
+/// `starts` must be ordered from the least preferred to the most preferred start
+/// index, since the last of equally good regions is chosen. Regions which would extend
+/// past the end of the score are skipped.
+fn find_best_region(
+    score: &[ScoreNote],
+    run: &[ScoreNote],
+    starts: impl Iterator<Item = usize>,
+    min_matching: usize,
+) -> Option<usize> {
+    starts
+        .filter(|start| start + run.len() <= score.len())
+        .map(|start| (start, count_run_matches(score, start, run)))
+        .filter(|&(_, count)| count >= min_matching)
+        .max_by_key(|&(_, count)| count)
+        .map(|(start, _)| start)
+}
+
+/// Returns the latest `run_length` live notes, or `None` if there aren't enough
+fn latest_run(live: &[ScoreNote], run_length: usize) -> Option<&[ScoreNote]> {
+    if run_length == 0 || live.len() < run_length {
+        return None;
+    }
+    Some(&live[live.len() - run_length..])
+}
+
+/// Finds the score position where the latest live notes match best, searching
+/// backwards from just before the last previous match
+///
+/// This is used to detect repeats or memory slips, i.e. when the performer goes back
+/// to an earlier part of the score. In such a situation the forward-only matching in
+/// [`follow_score`](crate::follow_score) ignores every new note, so the caller should
+/// only try this once a run of consecutive notes has been ignored.
+///
+/// # Arguments
+///
+/// * score - The complete expected musical score with timestamps and pitches
+/// * live - The live performance recorded so far, with timestamps and pitches
+/// * prev_match - The last previous match between the live performance and the score
+/// * run_length - How many of the latest live notes to compare against the score
+/// * min_matching - How many of those notes must have the correct pitch for a region
+///   of the score to count as a match
+///
+/// # Return value
+///
+/// A match between the last live note and the score note at the end of the earlier
+/// score region, or `None` if no earlier region matches well enough. Of equally good
+/// regions, the one closest to the previous match is chosen.
+pub fn find_backward_jump(
+    score: &[ScoreNote],
+    live: &[ScoreNote],
+    prev_match: Option<Match>,
+    run_length: usize,
+    min_matching: usize,
+) -> Option<Match> {
+    let prev_score_index = prev_match?.score_index;
+    let run = latest_run(live, run_length)?;
+    find_best_region(score, run, 0..prev_score_index, min_matching)
+        .map(|start| Match::new(start + run_length - 1, live.len() - 1))
+}
+
+/// Finds the score position where the latest live notes match best, searching
+/// forward from just after the last previous match
+///
+/// This is used to detect skips, i.e. when the performer cuts a section or jumps
+/// directly to a later part of the score. Like [`find_backward_jump`], this should only
+/// be tried once a run of consecutive notes has been ignored.
+///
+/// # Arguments
+///
+/// * score - The complete expected musical score with timestamps and pitches
+/// * live - The live performance recorded so far, with timestamps and pitches
+/// * prev_match - The last previous match between the live performance and the score,
+///   or `None` to search from the beginning of the score
+/// * run_length - How many of the latest live notes to compare against the score
+/// * min_matching - How many of those notes must have the correct pitch for a region
+///   of the score to count as a match
+///
+/// # Return value
+///
+/// A match between the last live note and the score note at the end of the later
+/// score region, or `None` if no later region matches well enough. Of equally good
+/// regions, the one closest to the previous match is chosen.
+pub fn find_forward_jump(
+    score: &[ScoreNote],
+    live: &[ScoreNote],
+    prev_match: Option<Match>,
+    run_length: usize,
+    min_matching: usize,
+) -> Option<Match> {
+    let first_start = prev_match.map_or(0, |m| m.score_index + 1);
+    let run = latest_run(live, run_length)?;
+    find_best_region(score, run, (first_start..score.len()).rev(), min_matching)
+        .map(|start| Match::new(start + run_length - 1, live.len() - 1))
+}
+
+/// Finds the score position near the expected position where the latest live notes
+/// match best
+///
+/// This is a cheap way to recover when the follower gets lost, e.g. after a few wrong
+/// notes made it skip too far ahead, or when a note was left out. Only score regions
+/// starting within `window` notes of the note after the last previous match are
+/// considered, so the cost is bounded regardless of the length of the score.
+///
+/// # Arguments
+///
+/// * score - The complete expected musical score with timestamps and pitches
+/// * live - The live performance recorded so far, with timestamps and pitches
+/// * prev_match - The last previous match between the live performance and the score,
+///   or `None` to search around the beginning of the score
+/// * run_length - How many of the latest live notes to compare against the score
+/// * min_matching - How many of those notes must have the correct pitch for a region
+///   of the score to count as a match
+/// * window - How far from the expected position to search, in score notes
+///
+/// # Return value
+///
+/// A match between the last live note and the score note at the end of the best
+/// matching region, or `None` if no region within the window matches well enough. Of
+/// equally good regions, the one closest to the expected position is chosen.
+pub fn find_local_anchor(
+    score: &[ScoreNote],
+    live: &[ScoreNote],
+    prev_match: Option<Match>,
+    run_length: usize,
+    min_matching: usize,
+    window: usize,
+) -> Option<Match> {
+    let expected = prev_match.map_or(0, |m| m.score_index + 1);
+    let run = latest_run(live, run_length)?;
+    // only starts within the score are collected, however large the window
+    let end = expected
+        .saturating_add(window)
+        .saturating_add(1)
+        .min(score.len());
+    let mut starts = (expected.saturating_sub(window).min(end)..end).collect::<Vec<_>>();
+    starts.sort_by_key(|&start| std::cmp::Reverse(start.abs_diff(expected)));
+    find_best_region(score, run, starts.into_iter(), min_matching)
+        .map(|start| Match::new(start + run_length - 1, live.len() - 1))
+}
+
+/// Detects that the performer stopped and started the piece over from the beginning
+///
+/// The latest live notes must match the opening of the score and follow a pause, so a
+/// return of the opening theme later in the piece isn't mistaken for a restart.
+///
+/// # Arguments
+///
+/// * score - The complete expected musical score with timestamps and pitches
+/// * live - The live performance recorded so far, with timestamps and pitches
+/// * prev_match - The last previous match between the live performance and the score
+/// * run_length - How many of the latest live notes to compare against the opening
+/// * min_matching - How many of those notes must have the correct pitch
+/// * min_pause - The shortest silence before the latest live notes, in microseconds
+///
+/// # Return value
+///
+/// A match between the last live note and the score note at the end of the opening
+/// region, or `None` if the performer didn't restart
+pub fn find_restart(
+    score: &[ScoreNote],
+    live: &[ScoreNote],
+    prev_match: Option<Match>,
+    run_length: usize,
+    min_matching: usize,
+    min_pause: u64,
+) -> Option<Match> {
+    if prev_match?.score_index < run_length {
+        // still within the opening, so there is nothing to restart
+        return None;
+    }
+    let run = latest_run(live, run_length)?;
+    let before = live.len().checked_sub(run_length + 1)?;
+    if run[0].time.saturating_sub(live[before].time) < min_pause {
+        return None;
+    }
+    find_best_region(score, run, 0..1, min_matching)
+        .map(|start| Match::new(start + run_length - 1, live.len() - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score() -> [ScoreNote; 8] {
+        notes![
+            (0, 60),
+            (100, 62),
+            (200, 64),
+            (300, 65),
+            (400, 67),
+            (500, 69),
+            (600, 71),
+            (700, 72)
+        ]
+    }
+
+    #[test]
+    fn jump_back_to_beginning() {
+        let live = notes![
+            (0, 60),
+            (100, 62),
+            (200, 64),
+            (300, 65),
+            (400, 67),
+            (500, 60),
+            (600, 62),
+            (700, 64)
+        ];
+        let jump = find_backward_jump(&score(), &live, Some(Match::new(4, 4)), 3, 3);
+        assert_eq!(jump, Some(Match::new(2, 7)));
+    }
+
+    #[test]
+    fn jump_back_with_wrong_note() {
+        let live = notes![
+            (0, 60),
+            (100, 62),
+            (200, 64),
+            (300, 65),
+            (400, 62),
+            (500, 63),
+            (600, 65)
+        ];
+        let jump = find_backward_jump(&score(), &live, Some(Match::new(3, 3)), 3, 2);
+        assert_eq!(jump, Some(Match::new(3, 6)));
+    }
+
+    #[test]
+    fn no_jump_without_earlier_match() {
+        let live = notes![
+            (0, 60),
+            (100, 62),
+            (200, 64),
+            (300, 61),
+            (400, 61),
+            (500, 61)
+        ];
+        let jump = find_backward_jump(&score(), &live, Some(Match::new(2, 2)), 3, 2);
+        assert_eq!(jump, None);
+    }
+
+    #[test]
+    fn no_jump_back_to_previous_match() {
+        let live = notes![
+            (0, 60),
+            (100, 62),
+            (200, 64),
+            (300, 64),
+            (400, 65),
+            (500, 67)
+        ];
+        let jump = find_backward_jump(&score(), &live, Some(Match::new(2, 2)), 3, 3);
+        assert_eq!(jump, None);
+    }
+
+    #[test]
+    fn no_jump_without_previous_match() {
+        let live = notes![(0, 60), (100, 62), (200, 64)];
+        let jump = find_backward_jump(&score(), &live, None, 3, 3);
+        assert_eq!(jump, None);
+    }
+
+    #[test]
+    fn jump_forward_to_coda() {
+        let live = notes![(0, 60), (100, 62), (200, 69), (300, 71), (400, 72)];
+        let jump = find_forward_jump(&score(), &live, Some(Match::new(1, 1)), 3, 3);
+        assert_eq!(jump, Some(Match::new(7, 4)));
+    }
+
+    #[test]
+    fn jump_forward_prefers_closest_region() {
+        let score = notes![
+            (0, 60),
+            (100, 62),
+            (200, 64),
+            (300, 60),
+            (400, 62),
+            (500, 64)
+        ];
+        let live = notes![(0, 62), (100, 64)];
+        let jump = find_forward_jump(&score, &live, None, 2, 2);
+        assert_eq!(jump, Some(Match::new(2, 1)));
+    }
+
+    #[test]
+    fn no_forward_jump_past_end_of_score() {
+        let live = notes![(0, 60), (100, 72), (200, 74)];
+        let jump = find_forward_jump(&score(), &live, Some(Match::new(0, 0)), 2, 1);
+        assert_eq!(jump, None);
+    }
+
+    #[test]
+    fn local_anchor_after_missed_note() {
+        let live = notes![(0, 60), (100, 62), (200, 65), (300, 67), (400, 69)];
+        let anchor = find_local_anchor(&score(), &live, Some(Match::new(1, 1)), 3, 3, 2);
+        assert_eq!(anchor, Some(Match::new(5, 4)));
+    }
+
+    #[test]
+    fn local_anchor_outside_window() {
+        let live = notes![(0, 60), (100, 62), (200, 69), (300, 71), (400, 72)];
+        let anchor = find_local_anchor(&score(), &live, Some(Match::new(1, 1)), 3, 3, 2);
+        assert_eq!(anchor, None);
+    }
+
+    #[test]
+    fn local_anchor_prefers_closest_region() {
+        let score = notes![
+            (0, 60),
+            (100, 62),
+            (200, 60),
+            (300, 62),
+            (400, 60),
+            (500, 62)
+        ];
+        let live = notes![(0, 60), (100, 62)];
+        let anchor = find_local_anchor(&score, &live, Some(Match::new(1, 1)), 2, 2, 4);
+        assert_eq!(anchor, Some(Match::new(3, 1)));
+    }
+
+    #[test]
+    fn local_anchor_with_unbounded_window() {
+        let live = notes![(0, 60), (100, 62), (200, 65), (300, 67), (400, 69)];
+        let anchor = find_local_anchor(&score(), &live, Some(Match::new(1, 1)), 3, 3, usize::MAX);
+        assert_eq!(anchor, Some(Match::new(5, 4)));
+    }
+
+    #[test]
+    fn restart_after_pause() {
+        let live = notes![
+            (0, 60),
+            (100, 62),
+            (200, 64),
+            (300, 65),
+            (400, 67),
+            (3000, 60),
+            (3100, 62),
+            (3200, 64)
+        ];
+        let restart = find_restart(&score(), &live, Some(Match::new(4, 4)), 3, 3, 1000);
+        assert_eq!(restart, Some(Match::new(2, 7)));
+        let too_short = find_restart(&score(), &live, Some(Match::new(4, 4)), 3, 3, 5000);
+        assert_eq!(too_short, None);
+    }
+
+    #[test]
+    fn no_restart_within_opening() {
+        let live = notes![(0, 60), (3000, 60), (3100, 62), (3200, 64)];
+        let restart = find_restart(&score(), &live, Some(Match::new(0, 0)), 3, 3, 1000);
+        assert_eq!(restart, None);
+    }
+
+    #[test]
+    fn no_restart_with_other_notes() {
+        let live = notes![
+            (0, 60),
+            (100, 62),
+            (200, 64),
+            (300, 65),
+            (3000, 67),
+            (3100, 69),
+            (3200, 71)
+        ];
+        let restart = find_restart(&score(), &live, Some(Match::new(3, 3)), 3, 3, 1000);
+        assert_eq!(restart, None);
+    }
+}
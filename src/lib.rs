@@ -1,11 +1,41 @@
-use midly::num::u7;
+use crate::follower::{ChordTime, Config, CostConfig, RollConfig};
 use crate::score::ScoreNote;
+use midly::num::u7;
+use std::time::Duration;
 
 #[macro_use]
 pub mod score;
+pub mod abc;
+pub mod accompaniment;
+pub mod algorithm;
+pub mod arming;
+pub mod beam;
+pub mod cache;
+pub mod click;
 pub mod device;
+pub mod duet;
+pub mod ensemble;
+pub mod follower;
+pub mod harmony;
+#[cfg(feature = "i18n")]
+pub mod i18n;
+pub mod jump;
+pub mod overlay;
+pub mod passage;
+pub mod playback;
+pub mod report;
+pub mod rng;
+pub mod signature;
+pub mod simulate;
+pub mod stats;
+pub mod tempo;
+pub mod trace;
+pub mod transpose;
+pub mod velocity;
+pub mod voice;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Match {
     pub score_index: usize,
     pub live_index: usize,
@@ -20,12 +50,82 @@ impl Match {
     }
 }
 
-/// Finds the next note with given `pitch`, starting from `score[index]`
-fn find_next_match_starting_at(score: &[ScoreNote], index: usize, pitch: u7) -> Option<usize> {
-    score[index..]
+/// Why a live note was left without a match in the score
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum IgnoreReason {
+    /// The pitch doesn't occur in the rest of the score
+    WrongPitch,
+    /// The pitch occurs in the score, but beyond the search window
+    OutsideSearchWindow,
+    /// The pitch occurs in the search window, but too far from the predicted score time
+    TooFarFromExpectedTime,
+    /// The note could have been matched, but the rest of the performance aligns better
+    /// with the score without it
+    Unaligned,
+}
+
+/// Finds the next note with given `pitch`, starting from `score[index]` and looking at
+/// no more than `search_window` notes
+///
+/// If the pitch occurs several times within the window, e.g. in a passage of repeated
+/// notes, and an `expected_time` is given, the occurrence with the lowest cost is
+/// chosen instead of the first one, see [`CostConfig`]. By default that is the
+/// occurrence closest to that score time. Of two equally cheap occurrences, the earlier
+/// one wins.
+fn find_next_match_starting_at(
+    score: &[ScoreNote],
+    index: usize,
+    pitch: u7,
+    search_window: usize,
+    expected_time: Option<u64>,
+    cost: &CostConfig,
+) -> Option<usize> {
+    let mut candidates = score[index..]
         .iter()
-        .position(|note| note.pitch == pitch)
-        .map(|i| index + i)
+        .take(search_window)
+        .enumerate()
+        .filter(|(_, note)| note.pitch == pitch)
+        .map(|(i, note)| (index + i, note.time));
+    let (mut best_index, best_time) = candidates.next()?;
+    if let Some(expected_time) = expected_time {
+        let mut best_cost = cost.of(best_index - index, best_time.abs_diff(expected_time));
+        for (candidate_index, time) in candidates {
+            let candidate_cost = cost.of(candidate_index - index, time.abs_diff(expected_time));
+            if candidate_cost < best_cost {
+                best_index = candidate_index;
+                best_cost = candidate_cost;
+            } else if time >= expected_time {
+                // score times never decrease, so both parts of the cost only grow from
+                // here on
+                break;
+            }
+        }
+    }
+    Some(best_index)
+}
+
+/// Predicts the score time of a live note from a previous match and the stretch factor
+///
+/// # Return value
+///
+/// The predicted score time, or `None` without a previous match or a usable stretch
+/// factor
+fn expected_score_time(
+    score: &[ScoreNote],
+    live: &[ScoreNote],
+    prev_match: Option<Match>,
+    live_index: usize,
+    stretch_factor: f32,
+) -> Option<u64> {
+    let prev_match = prev_match?;
+    if !stretch_factor.is_finite() || stretch_factor <= 0.0 {
+        return None;
+    }
+    let elapsed_live = live[live_index]
+        .time
+        .checked_sub(live[prev_match.live_index].time)?;
+    Some(score[prev_match.score_index].time + (elapsed_live as f32 / stretch_factor) as u64)
 }
 
 /// Calculates the time difference between notes `score[index1]` and `score[index2]`
@@ -33,42 +133,119 @@ fn time_difference(score: &[ScoreNote], index1: usize, index2: usize) -> u64 {
     score[index2].time - score[index1].time
 }
 
+/// Tells whether a live note continues a rolled chord started by the previous match
+fn in_roll(
+    score: &[ScoreNote],
+    live: &[ScoreNote],
+    prev_match: Option<Match>,
+    score_index: usize,
+    live_index: usize,
+    roll: &RollConfig,
+) -> bool {
+    prev_match.is_some_and(|m| {
+        score[m.score_index].time == score[score_index].time
+            && live[live_index]
+                .time
+                .saturating_sub(live[m.live_index].time)
+                <= roll.window
+    })
+}
+
 /// Finds matches in the score for new notes in the live performance
 ///
 /// # Arguments
 ///
 /// * score - The complete expected musical score with timestamps and pitches
 /// * live - The live performance recorded so far, with timestamps and pitches
-/// * prev_match_score_index - Index of the last note so far which has been matched
-///                            between the live performance and the expected score
+/// * prev_match - The last match so far between the live performance and the expected
+///   score
 /// * new_live_index - Index of the first new note received for the live performance
-///                    since the previous round
+///   since the previous round
+/// * stretch_factor - The time stretch factor for predicting the score time of each new
+///   live note
+/// * config - Limits for the search, see [`Config::search_window`] and
+///   [`Config::max_time_difference`]
+/// * mapping - A live-to-score time mapping for predicting the score time of each new
+///   live note instead of the previous match and `stretch_factor`
 ///
 /// # Return value
 ///
 /// A 2-tuple of
 /// * newly found matches between the live performance and the expected score
-/// * ignored new input notes (as a list of live performance indices)
+/// * ignored new input notes (as live performance indices with the reason for ignoring)
 fn find_new_matches(
     score: &[ScoreNote],
     live: &[ScoreNote],
-    prev_match_score_index: Option<usize>,
+    mut prev_match: Option<Match>,
     new_live_index: usize,
-) -> (Vec<Match>, Vec<usize>) {
-    let mut score_pointer = match prev_match_score_index {
-        Some(i) => i + 1, // continue in the score just after last previous match, or
-        None => 0,        // start from beginning of score if nothing matched yet
+    stretch_factor: f32,
+    config: &Config,
+    mapping: Option<&LinearMapping>,
+) -> (Vec<Match>, Vec<(usize, IgnoreReason)>) {
+    let mut score_pointer = match prev_match {
+        Some(m) => m.score_index + 1, // continue in the score just after last match, or
+        None => 0,                    // start from beginning of score if nothing matched yet
     };
     let mut matches: Vec<Match> = vec![];
-    let mut ignored: Vec<usize> = vec![];
+    let mut ignored: Vec<(usize, IgnoreReason)> = vec![];
     for (live_index, live_note) in live.iter().enumerate().skip(new_live_index) {
-        let matching_index = find_next_match_starting_at(score, score_pointer, live_note.pitch);
+        let expected_time = match mapping {
+            Some(mapping) => Some(mapping.score_time(live_note.time)),
+            None => expected_score_time(score, live, prev_match, live_index, stretch_factor),
+        };
+        let search_window = config.search_window.unwrap_or(usize::MAX);
+        let matching_index = find_next_match_starting_at(
+            score,
+            score_pointer,
+            live_note.pitch,
+            search_window,
+            expected_time,
+            &config.cost,
+        );
+        let too_far = |score_index: usize| {
+            // a rolled chord is expected to spread out in time
+            if in_roll(
+                score,
+                live,
+                prev_match,
+                score_index,
+                live_index,
+                &config.stretch.roll,
+            ) {
+                return false;
+            }
+            match (expected_time, config.max_time_difference) {
+                (Some(expected_time), Some(max)) => {
+                    score[score_index].time.abs_diff(expected_time) > max
+                }
+                _ => false,
+            }
+        };
         match matching_index {
+            Some(score_index) if too_far(score_index) => {
+                ignored.push((live_index, IgnoreReason::TooFarFromExpectedTime))
+            }
             Some(score_index) => {
-                matches.push(Match::new(score_index, live_index));
+                let m = Match::new(score_index, live_index);
+                matches.push(m);
+                prev_match = Some(m);
                 score_pointer = score_index + 1;
             }
-            None => ignored.push(live_index),
+            None => {
+                let later = find_next_match_starting_at(
+                    score,
+                    score_pointer,
+                    live_note.pitch,
+                    usize::MAX,
+                    None,
+                    &config.cost,
+                );
+                let reason = match later {
+                    Some(_) => IgnoreReason::OutsideSearchWindow,
+                    None => IgnoreReason::WrongPitch,
+                };
+                ignored.push((live_index, reason));
+            }
         };
     }
     (matches, ignored)
@@ -88,6 +265,180 @@ fn get_stretch_factor(elapsed_score: u64, elapsed_live: u64) -> f32 {
     (elapsed_live as f32) / (elapsed_score as f32)
 }
 
+/// Estimates the stretch factor from several recent matches instead of just the last two
+///
+/// The stretch factor is calculated for each pair of consecutive matches among the
+/// latest `window + 1` matches, and the exponentially weighted mean of those is
+/// returned. Pairs with no elapsed time in the score (e.g. notes of a chord) or going
+/// backwards in the score (e.g. after a repeat) are skipped.
+///
+/// # Arguments
+///
+/// * score - The complete expected musical score with timestamps and pitches
+/// * live - The live performance recorded so far, with timestamps and pitches
+/// * matches - All matches so far, in live performance order
+/// * window - The maximum number of consecutive match pairs to take into account
+/// * decay - The weight of each pair relative to the next newer one, between 0.0 and 1.0
+/// * roll - How to find the live time of rolled chords, see [`chord_live_time`]
+///
+/// # Return value
+///
+/// The weighted stretch factor, or `None` if there are no usable pairs of matches
+pub fn windowed_stretch_factor(
+    score: &[ScoreNote],
+    live: &[ScoreNote],
+    matches: &[Match],
+    window: usize,
+    decay: f32,
+    roll: &RollConfig,
+) -> Option<f32> {
+    let start = matches.len().saturating_sub(window + 1);
+    let (weighted_sum, weight_sum, _) = (start + 1..matches.len())
+        .rev()
+        .filter_map(|index| {
+            let (first, second) = (matches[index - 1], matches[index]);
+            let elapsed_score = score[second.score_index]
+                .time
+                .checked_sub(score[first.score_index].time)?;
+            let elapsed_live = chord_live_time(score, live, matches, index, roll)
+                - chord_live_time(score, live, matches, index - 1, roll);
+            (elapsed_score > 0).then(|| elapsed_live as f32 / elapsed_score as f32)
+        })
+        .fold(
+            (0.0, 0.0, 1.0),
+            |(weighted_sum, weight_sum, weight), factor| {
+                (
+                    weighted_sum + weight * factor,
+                    weight_sum + weight,
+                    weight * decay,
+                )
+            },
+        );
+    (weight_sum > 0.0).then(|| weighted_sum / weight_sum)
+}
+
+/// Finds the live time of a matched note, or of the rolled chord it is part of
+///
+/// Consecutive matches of simultaneous score notes form a chord if their live notes
+/// are all played within the roll window from the first one of them.
+///
+/// # Arguments
+///
+/// * score - The complete expected musical score with timestamps and pitches
+/// * live - The live performance recorded so far, with timestamps and pitches
+/// * matches - All matches so far, in live performance order
+/// * index - The index of the match in `matches`
+/// * roll - The roll window and the policy for taking the time of a chord
+///
+/// # Return value
+///
+/// The live time in microseconds
+pub fn chord_live_time(
+    score: &[ScoreNote],
+    live: &[ScoreNote],
+    matches: &[Match],
+    index: usize,
+    roll: &RollConfig,
+) -> f64 {
+    let score_time = score[matches[index].score_index].time;
+    let live_time = |m: &Match| live[m.live_index].time;
+    let same_chord = |m: &Match| score[m.score_index].time == score_time;
+    let first = matches[..index]
+        .iter()
+        .rev()
+        .take_while(|m| {
+            same_chord(m) && live_time(&matches[index]).saturating_sub(live_time(m)) <= roll.window
+        })
+        .count();
+    let chord = &matches[index - first..];
+    let start = live_time(&chord[0]);
+    let length = chord
+        .iter()
+        .take_while(|m| same_chord(m) && live_time(m).saturating_sub(start) <= roll.window)
+        .count();
+    let chord = &chord[..length];
+    match roll.chord_time {
+        ChordTime::First => start as f64,
+        ChordTime::Last => chord.last().map_or(start, live_time) as f64,
+        ChordTime::Mean => {
+            chord.iter().map(|m| live_time(m) as f64).sum::<f64>() / length.max(1) as f64
+        }
+    }
+}
+
+/// A linear mapping from live time to score time
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LinearMapping {
+    /// Score time elapsed per live time, i.e. the inverse of the stretch factor
+    pub slope: f64,
+    /// The score time at live time zero
+    pub intercept: f64,
+}
+
+impl LinearMapping {
+    /// Maps a live time to a score time, saturating at zero
+    pub fn score_time(&self, live_time: u64) -> u64 {
+        (self.slope * live_time as f64 + self.intercept)
+            .max(0.0)
+            .round() as u64
+    }
+}
+
+/// Fits a linear live-to-score time mapping to recent matches with least squares
+///
+/// This is smoother than mapping from just the last match and the stretch factor, since
+/// the timing deviation of a single note has less effect.
+///
+/// # Arguments
+///
+/// * score - The complete expected musical score with timestamps and pitches
+/// * live - The live performance recorded so far, with timestamps and pitches
+/// * matches - All matches so far, in live performance order
+/// * window - The maximum number of latest matches to fit the mapping to
+///
+/// # Return value
+///
+/// The fitted mapping, or `None` if the matches don't span any live time
+pub fn fit_time_mapping(
+    score: &[ScoreNote],
+    live: &[ScoreNote],
+    matches: &[Match],
+    window: usize,
+) -> Option<LinearMapping> {
+    let recent = &matches[matches.len().saturating_sub(window)..];
+    let last = recent.last()?;
+    // center on the latest match to keep the sums small
+    let (live_origin, score_origin) = (live[last.live_index].time, score[last.score_index].time);
+    let points = recent
+        .iter()
+        .map(|m| {
+            (
+                live[m.live_index].time as f64 - live_origin as f64,
+                score[m.score_index].time as f64 - score_origin as f64,
+            )
+        })
+        .collect::<Vec<_>>();
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let covariance = points
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum::<f64>();
+    let variance = points
+        .iter()
+        .map(|(x, _)| (x - mean_x).powi(2))
+        .sum::<f64>();
+    if variance == 0.0 {
+        return None;
+    }
+    let slope = covariance / variance;
+    Some(LinearMapping {
+        slope,
+        intercept: score_origin as f64 + mean_y - slope * (live_origin as f64 + mean_x),
+    })
+}
+
 /// Returns the score time in milliseconds corresponding to the latest live note
 /// (whether matched or unmatched)
 ///
@@ -107,7 +458,8 @@ fn get_stretch_factor(elapsed_score: u64, elapsed_live: u64) -> f32 {
 /// # Return value
 ///
 /// The estimated current time in the expected score in milliseconds.
-fn get_score_time(
+#[allow(clippy::doc_overindented_list_items)]
+pub(crate) fn get_score_time(
     score: &[ScoreNote],
     live: &[ScoreNote],
     prev_match: Option<Match>,
@@ -122,6 +474,31 @@ fn get_score_time(
     prev_score_time + (elapsed_live as f32 / stretch_factor) as u64
 }
 
+/// Extrapolates the score time at a moment between live notes from the latest match
+///
+/// # Arguments
+///
+/// * score - The complete expected musical score with timestamps and pitches
+/// * live - The live performance received so far
+/// * prev_match - The latest match between the live performance and the score
+/// * stretch_factor - Live time elapsed per score time
+/// * now - The live time to estimate the score position at
+///
+/// # Return value
+///
+/// The estimated score time in microseconds, or `None` if nothing has been matched yet
+pub(crate) fn extrapolate_score_time(
+    score: &[ScoreNote],
+    live: &[ScoreNote],
+    prev_match: Option<Match>,
+    stretch_factor: f32,
+    now: Duration,
+) -> Option<u64> {
+    let prev_match = prev_match?;
+    let elapsed_live = (now.as_micros() as u64).saturating_sub(live[prev_match.live_index].time);
+    Some(score[prev_match.score_index].time + (elapsed_live as f32 / stretch_factor) as u64)
+}
+
 /// Matches incoming notes with next notes in the score.
 /// This is a super naïve algorithm which
 /// * supports only monophony (order of events matters),
@@ -171,6 +548,7 @@ fn get_score_time(
 /// * the time stretch factor at the last new matching input note
 /// * for all matched notes, the index in the score and in the live performance
 /// * ignored new input notes as a list of live performance indices
+#[allow(clippy::doc_overindented_list_items)]
 pub fn follow_score(
     score: &[ScoreNote],
     live: &[ScoreNote],
@@ -178,11 +556,69 @@ pub fn follow_score(
     new_live_index: usize,
     prev_stretch_factor: f32,
 ) -> (u64, f32, Vec<Match>, Vec<usize>) {
+    follow_score_within(
+        score,
+        live,
+        prev_match,
+        new_live_index,
+        prev_stretch_factor,
+        usize::MAX,
+    )
+}
+
+/// Matches incoming notes with next notes in the score like [`follow_score`], but only
+/// looks for a match among the next `search_window` notes after the previous match
+///
+/// This bounds the time spent on each live note, which keeps latency predictable for
+/// long scores. Live notes with no match within the window are ignored.
+pub fn follow_score_within(
+    score: &[ScoreNote],
+    live: &[ScoreNote],
+    prev_match: Option<Match>,
+    new_live_index: usize,
+    prev_stretch_factor: f32,
+    search_window: usize,
+) -> (u64, f32, Vec<Match>, Vec<usize>) {
+    let config = Config {
+        search_window: Some(search_window),
+        ..Config::default()
+    };
+    let (score_time, stretch_factor, new_matches, ignored) = follow_score_mapped(
+        score,
+        live,
+        prev_match,
+        new_live_index,
+        prev_stretch_factor,
+        &config,
+        None,
+    );
+    let ignored = ignored
+        .into_iter()
+        .map(|(live_index, _)| live_index)
+        .collect();
+    (score_time, stretch_factor, new_matches, ignored)
+}
+
+/// Matches incoming notes with next notes in the score like [`follow_score_within`], but
+/// limits the search as configured and predicts score times with a live-to-score time
+/// mapping if one is given
+pub(crate) fn follow_score_mapped(
+    score: &[ScoreNote],
+    live: &[ScoreNote],
+    prev_match: Option<Match>,
+    new_live_index: usize,
+    prev_stretch_factor: f32,
+    config: &Config,
+    mapping: Option<&LinearMapping>,
+) -> (u64, f32, Vec<Match>, Vec<(usize, IgnoreReason)>) {
     let (new_matches, ignored) = find_new_matches(
         score,
         live,
-        prev_match.map(|m| m.score_index),
+        prev_match,
         new_live_index,
+        prev_stretch_factor,
+        config,
+        mapping,
     );
     let prev_matches = match prev_match {
         Some(m) => vec![m],
@@ -193,6 +629,20 @@ pub fn follow_score(
     let matches = prev_matches_iter.chain(next_matches_iter);
     let last_two = matches.rev().take(2).collect::<Vec<&Match>>();
     let stretch_factor = match last_two[..] {
+        // the notes of a rolled chord tell nothing about the tempo
+        [last, second_last]
+            if config.stretch.roll.window > 0
+                && in_roll(
+                    score,
+                    live,
+                    Some(*second_last),
+                    last.score_index,
+                    last.live_index,
+                    &config.stretch.roll,
+                ) =>
+        {
+            prev_stretch_factor
+        }
         [last, second_last] => {
             let elapsed_score = time_difference(score, second_last.score_index, last.score_index);
             let elapsed_live = time_difference(live, second_last.live_index, last.live_index);
@@ -200,7 +650,10 @@ pub fn follow_score(
         }
         _ => prev_stretch_factor,
     };
-    let score_time = get_score_time(score, live, prev_match, stretch_factor);
+    let score_time = match mapping {
+        Some(mapping) => mapping.score_time(live[live.len() - 1].time),
+        None => get_score_time(score, live, prev_match, stretch_factor),
+    };
     (score_time, stretch_factor, new_matches, ignored)
 }
 
@@ -209,7 +662,9 @@ mod tests {
     use super::*;
     use assert_approx_eq::assert_approx_eq;
     use once_cell::sync::Lazy;
+    use rstest::rstest;
 
+    #[rustfmt::skip]
     static TEST_SCORE: Lazy<[ScoreNote; 3]> = Lazy::new(|| {
         notes![(1000, 60), (1100, 62), (1200, 64)]
     });
@@ -270,6 +725,115 @@ mod tests {
         assert!(ignored.is_empty());
     }
 
+    #[test]
+    fn windowed_stretch_factor_weights_recent_pairs() {
+        let score = notes![(0, 60), (100, 62), (200, 64), (300, 65)];
+        let live = notes![(0, 60), (100, 62), (300, 64), (700, 65)];
+        let matches = [
+            Match::new(0, 0),
+            Match::new(1, 1),
+            Match::new(2, 2),
+            Match::new(3, 3),
+        ];
+        let factor =
+            windowed_stretch_factor(&score, &live, &matches, 2, 0.5, &RollConfig::default())
+                .unwrap();
+        // pairs 2->3 (4.0) and 1->2 (2.0) with weights 1.0 and 0.5
+        assert_approx_eq!(factor, 5.0 / 1.5);
+        let factor =
+            windowed_stretch_factor(&score, &live, &matches, 1, 0.5, &RollConfig::default())
+                .unwrap();
+        assert_approx_eq!(factor, 4.0);
+    }
+
+    #[test]
+    fn windowed_stretch_factor_skips_chords() {
+        let score = notes![(0, 60), (0, 64), (100, 62)];
+        let live = notes![(0, 60), (10, 64), (210, 62)];
+        let matches = [Match::new(0, 0), Match::new(1, 1), Match::new(2, 2)];
+        let factor =
+            windowed_stretch_factor(&score, &live, &matches, 3, 0.5, &RollConfig::default())
+                .unwrap();
+        assert_approx_eq!(factor, 2.0);
+        assert_eq!(
+            windowed_stretch_factor(&score, &live, &matches[..2], 3, 0.5, &RollConfig::default()),
+            None
+        );
+    }
+
+    #[rstest(
+        chord_time,
+        expect,
+        case(ChordTime::First, 0.0),
+        case(ChordTime::Last, 30.0),
+        case(ChordTime::Mean, 40.0 / 3.0)
+    )]
+    fn rolled_chord_time(chord_time: ChordTime, expect: f64) {
+        let score = notes![(0, 60), (0, 64), (0, 67), (0, 72), (100, 62)];
+        let live = notes![(0, 60), (10, 64), (30, 67), (500, 72), (530, 62)];
+        let matches = [
+            Match::new(0, 0),
+            Match::new(1, 1),
+            Match::new(2, 2),
+            Match::new(3, 3),
+            Match::new(4, 4),
+        ];
+        let roll = RollConfig {
+            window: 50,
+            chord_time,
+        };
+        for index in 0..3 {
+            assert_approx_eq!(
+                chord_live_time(&score, &live, &matches, index, &roll),
+                expect
+            );
+        }
+        // played too late to be part of the rolled chord
+        assert_approx_eq!(chord_live_time(&score, &live, &matches, 3, &roll), 500.0);
+    }
+
+    #[test]
+    fn windowed_stretch_factor_of_rolled_chords() {
+        let score = notes![(0, 60), (0, 64), (100, 62), (100, 65)];
+        let live = notes![(0, 60), (40, 64), (200, 62), (240, 65)];
+        let matches = [
+            Match::new(0, 0),
+            Match::new(1, 1),
+            Match::new(2, 2),
+            Match::new(3, 3),
+        ];
+        let roll = RollConfig {
+            window: 50,
+            chord_time: ChordTime::First,
+        };
+        let factor = windowed_stretch_factor(&score, &live, &matches, 3, 0.5, &roll).unwrap();
+        assert_approx_eq!(factor, 2.0);
+        let factor =
+            windowed_stretch_factor(&score, &live, &matches, 3, 0.5, &RollConfig::default())
+                .unwrap();
+        assert_approx_eq!(factor, 1.6);
+    }
+
+    #[test]
+    fn ignore_note_outside_search_window() {
+        let live = notes![(5, 60), (55, 64)];
+        let (time, stretch_factor, new_matches, ignored) =
+            follow_score_within(&*TEST_SCORE, &live, Some(Match::new(0, 0)), 1, 1.0, 1);
+        assert_eq!(time, 1050);
+        assert_approx_eq!(stretch_factor, 1.0);
+        assert!(new_matches.is_empty());
+        assert_eq!(ignored, vec![1]);
+    }
+
+    #[test]
+    fn match_note_at_end_of_search_window() {
+        let live = notes![(5, 60), (55, 64)];
+        let (_, _, new_matches, ignored) =
+            follow_score_within(&*TEST_SCORE, &live, Some(Match::new(0, 0)), 1, 1.0, 2);
+        assert_eq!(new_matches, [Match::new(2, 1)]);
+        assert!(ignored.is_empty());
+    }
+
     #[test]
     fn only_wrong_notes() {
         let live = notes![(5, 60), (55, 63), (105, 66)];
@@ -280,4 +844,85 @@ mod tests {
         assert!(new_matches.is_empty());
         assert_eq!(ignored, vec![1, 2]);
     }
+
+    #[test]
+    fn repeated_note_closest_to_expected_time() {
+        let score = notes![(0, 60), (100, 60), (200, 60), (300, 60)];
+        let pitch = u7::from(60);
+        let find = |search_window, expected_time| {
+            find_next_match_starting_at(
+                &score,
+                1,
+                pitch,
+                search_window,
+                expected_time,
+                &CostConfig::default(),
+            )
+        };
+        assert_eq!(find(usize::MAX, None), Some(1));
+        assert_eq!(find(usize::MAX, Some(210)), Some(2));
+        assert_eq!(
+            find(usize::MAX, Some(150)),
+            Some(1),
+            "the earlier of equally close occurrences"
+        );
+        assert_eq!(
+            find(2, Some(1000)),
+            Some(2),
+            "only within the search window"
+        );
+    }
+
+    #[test]
+    fn repeated_note_near_score_head() {
+        let score = notes![(0, 60), (100000, 60), (200000, 60), (300000, 60)];
+        let find = |cost| {
+            find_next_match_starting_at(&score, 1, u7::from(60), usize::MAX, Some(290000), &cost)
+        };
+        assert_eq!(find(CostConfig::default()), Some(3));
+        // the last note is 0.08 seconds closer in time but one more note away
+        let cost = CostConfig {
+            time: 1.0,
+            skip: 0.09,
+        };
+        assert_eq!(find(cost), Some(2));
+        let cost = CostConfig {
+            time: 1.0,
+            skip: 1.0,
+        };
+        assert_eq!(find(cost), Some(1));
+    }
+
+    #[test]
+    fn skip_missing_repeated_note() {
+        let score = notes![(0, 60), (100, 60), (200, 60), (300, 62)];
+        let live = notes![(1000, 60), (1400, 60)];
+        let (time, stretch_factor, new_matches, ignored) =
+            follow_score(&score, &live, Some(Match::new(0, 0)), 1, 2.0);
+        assert_eq!(time, 200);
+        assert_approx_eq!(stretch_factor, 2.0);
+        assert_eq!(new_matches, [Match::new(2, 1)]);
+        assert!(ignored.is_empty());
+    }
+
+    #[test]
+    fn fit_time_mapping_smooths_jitter() {
+        let score = notes![(0, 60), (100, 62), (200, 64), (300, 65)];
+        let live = notes![(1000, 60), (1210, 62), (1390, 64), (1600, 65)];
+        let matches = (0..4).map(|i| Match::new(i, i)).collect::<Vec<_>>();
+        let mapping = fit_time_mapping(&score, &live, &matches, 4).unwrap();
+        assert_approx_eq!(mapping.slope, 0.5, 0.01);
+        assert_eq!(mapping.score_time(1800), 402);
+        assert_eq!(mapping.score_time(0), 0, "saturates at zero");
+    }
+
+    #[test]
+    fn fit_time_mapping_needs_elapsed_live_time() {
+        let score = notes![(0, 60), (100, 62)];
+        let live = notes![(1000, 60), (1000, 62)];
+        let matches = [Match::new(0, 0), Match::new(1, 1)];
+        assert_eq!(fit_time_mapping(&score, &live, &matches, 2), None);
+        assert_eq!(fit_time_mapping(&score, &live, &matches[..1], 2), None);
+        assert_eq!(fit_time_mapping(&score, &live, &[], 2), None);
+    }
 }
@@ -1,17 +1,70 @@
-use midir::{Ignore, MidiInput};
+use midir::{Ignore, MidiInput, MidiOutput};
 use midly::live::{LiveEvent, LiveEvent::Midi};
-use midly::num::u4;
-use midly::MidiMessage::NoteOn;
+use midly::num::{u4, u7};
+use midly::MidiMessage;
+use selim::abc::{
+    inline_abc_into_events, inline_abc_into_score, is_abc_file, load_abc_file,
+    load_abc_file_events, GraceNotes,
+};
+use selim::accompaniment::{accompaniment_events, generate_accompaniment, CompingStyle};
+use selim::algorithm::{Algorithm, FollowerSettings};
+use selim::arming::Arming;
+use selim::beam::BeamConfig;
+use selim::cache::{
+    load_channel_events_cached, load_midi_file_cached, load_midi_file_tempo_map_cached,
+};
+use selim::click::{click_track, with_click_track, ClickConfig};
 use selim::device::{find_port, DeviceSelector};
-use selim::score::{load_midi_file, pitch_to_name, ScoreNote};
-use selim::{follow_score, Match};
+use selim::duet::Duet;
+use selim::follower::{
+    ChordTime, Config, CostConfig, FollowResult, RollConfig, ScoreFollower, StretchConfig,
+};
+use selim::harmony::{chord_at, Chord};
+#[cfg(feature = "i18n")]
+use selim::i18n::{pitch_to_name_in, NoteNaming};
+use selim::jump::{find_backward_jump, find_forward_jump, find_local_anchor, find_restart};
+use selim::overlay::{spawn_overlay_server, OverlayStatus};
+use selim::passage::{wrong_passage_before, wrong_passages};
+use selim::playback::{
+    describe_event, encode_midi_event, retain_event_classes, spawn_playback, ChannelGain,
+    EventClass, FlushOrder, MixCommand, PauseDetector, PauseEvent, PlaybackAnchor, PlaybackCommand,
+    PlaybackScheduler, TempoRamp,
+};
+use selim::report::session_report;
+use selim::score::{
+    check_midi_file, describe_midi_file, duplicate_notes, filter_pitch_range,
+    leading_silence_offset, load_channel_events, load_midi_file_with_durations, load_score_file,
+    name_to_pitch, note_off_key, note_on_key, pitch_to_name, quantize, shift_events, shift_score,
+    slice_events, slice_time, DuplicateNotes, Excerpt, LoadError, ScoreEvent, ScoreNote,
+    TrackChannels,
+};
+use selim::stats::match_stats;
+use selim::tempo::{load_midi_file_tempo_map, TempoMap};
+use selim::transpose::{transpose_events, transpose_score, Transposing};
+use selim::velocity::VelocityMode;
+use selim::voice::assign_voices;
+use selim::Match;
 use std::boxed::Box;
 use std::error::Error;
-use std::io::{stdout, Write};
-use std::path::PathBuf;
-use std::sync::mpsc::{self, Sender};
+use std::fs;
+use std::io::{stdout, BufRead, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
 
+/// How far back from the current score position to look for notes when inferring the
+/// current chord, in microseconds
+const CHORD_WINDOW: u64 = 1_000_000;
+/// How many consecutive ignored live notes are reported as a wrong passage
+const WRONG_PASSAGE_MIN_NOTES: usize = 2;
+/// How many of the largest tempo deviations to list in the session report
+const REPORT_TEMPO_DEVIATIONS: usize = 5;
+/// How often to move the score position of the overlay on while waiting for live notes
+const OVERLAY_REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+
 #[derive(StructOpt)]
 struct Cli {
     // TODO: `conflicts_with` doesn't seem to work!
@@ -27,48 +80,668 @@ struct Cli {
         conflicts_with = "rec_device_num"
     )]
     rec_device_name: Option<String>,
-    #[structopt(short = "i", long = "--input-score-file", parse(from_os_str))]
-    input_score_file: PathBuf,
+    /// Number of the MIDI output port to play the accompaniment to. Without an output
+    /// port, the performance is followed without playing anything.
+    #[structopt(long = "play-device-num", conflicts_with = "play-device-name")]
+    play_device_num: Option<usize>,
+    /// A part of the name of the MIDI output port to play the accompaniment to
+    #[structopt(long = "play-device-name")]
+    play_device_name: Option<String>,
+    /// Print the accompaniment events with their live times instead of sending them to
+    /// a MIDI output port, e.g. for checking the playback without a synthesizer
+    #[structopt(
+        long = "no-output",
+        conflicts_with_all = &["play-device-num", "play-device-name"]
+    )]
+    no_output: bool,
+    /// Time in milliseconds for the accompaniment to converge to each new tempo
+    /// estimate, or 0 for following it at once
+    #[structopt(long = "ramp-ms", default_value = "0")]
+    ramp_ms: u64,
+    /// How to order events sent at the same moment: offs-before-ons, or as-loaded to
+    /// keep the order of the playback score
+    #[structopt(long = "flush-order", default_value = "offs-before-ons")]
+    flush_order: FlushOrder,
+    /// Velocities of the accompaniment notes: score, live to use the velocity of the
+    /// latest matched note, scaled or scaled:<weight> to blend the two, or fixed:<velocity>
+    #[structopt(long = "velocity-mode", default_value = "score")]
+    velocity_mode: VelocityMode,
+    /// A factor for the note-on velocities of an output channel, like `2:1.2` with the
+    /// channel numbered from 1, e.g. for balancing the parts of the accompaniment. Can be
+    /// given for several channels.
+    #[structopt(long = "channel-gain")]
+    channel_gain: Vec<ChannelGain>,
+    /// MIDI or ABC notation file of the part to follow
+    #[structopt(
+        short = "i",
+        long = "--input-score-file",
+        parse(from_os_str),
+        required_unless = "input-abc"
+    )]
+    input_score_file: Option<PathBuf>,
+    /// The part to follow in ABC notation instead of a file, e.g. "CDEF GABc". Header
+    /// fields may precede the music on lines of their own.
+    #[structopt(long = "input-abc", conflicts_with = "input-score-file")]
+    input_abc: Option<String>,
+    /// MIDI or ABC notation file to play back; without it, an accompaniment is generated from the
+    /// chords implied by the input score
     #[structopt(short = "p", long = "--playback-score-file", parse(from_os_str))]
-    playback_score_file: PathBuf,
+    playback_score_file: Option<PathBuf>,
+    /// The part to play back in ABC notation instead of a file, like --input-abc
+    #[structopt(long = "playback-abc", conflicts_with = "playback-score-file")]
+    playback_abc: Option<String>,
+    /// Pattern for playing a generated accompaniment: block, arpeggio or alberti
+    #[structopt(long = "comping-style", default_value = "block")]
+    comping_style: CompingStyle,
+    /// Length of one beat of a generated accompaniment in milliseconds
+    #[structopt(long = "beat-ms", default_value = "500")]
+    beat_ms: u64,
+    /// Score following algorithm
+    #[structopt(long = "algorithm", default_value = "pedantic", possible_values = &["pedantic", "beam", "ensemble"])]
+    algorithm: Algorithm,
+    /// Number of alignment hypotheses kept by the beam search algorithm
+    #[structopt(long = "beam-width", default_value = "8")]
+    beam_width: usize,
+    /// Number of recent pairs of matched notes to estimate the tempo from
+    #[structopt(long = "stretch-window", default_value = "1")]
+    stretch_window: usize,
+    /// Weight of each pair of matched notes relative to the next newer one when
+    /// estimating the tempo
+    #[structopt(long = "stretch-decay", default_value = "0.5")]
+    stretch_decay: f32,
+    /// Smallest allowed time stretch factor
+    #[structopt(long = "min-stretch", default_value = "0.25")]
+    min_stretch: f32,
+    /// Largest allowed time stretch factor
+    #[structopt(long = "max-stretch", default_value = "4.0")]
+    max_stretch: f32,
+    /// Longest spread in milliseconds of the live notes of a rolled chord, which are
+    /// simultaneous in the score
+    #[structopt(long = "roll-window-ms", default_value = "0")]
+    roll_window_ms: u64,
+    /// Live time of a rolled chord for estimating the tempo: first, last or mean
+    #[structopt(long = "chord-time", default_value = "first")]
+    chord_time: ChordTime,
+    /// Time stretch factor to assume until two live notes have been matched
+    #[structopt(long = "initial-stretch", default_value = "1.0")]
+    initial_stretch: f32,
+    /// Largest difference in milliseconds between the predicted and the actual score
+    /// time of a note for accepting it as a match
+    #[structopt(long = "max-time-difference")]
+    max_time_difference_ms: Option<u64>,
+    /// Cost of one second between the predicted and the written time of a score note
+    /// when choosing among score notes of the same pitch
+    #[structopt(long = "time-cost", default_value = "1.0")]
+    time_cost: f32,
+    /// Cost of each score note skipped over when choosing among score notes of the
+    /// same pitch, to prefer notes near the current position in the score
+    #[structopt(long = "skip-cost", default_value = "0.0")]
+    skip_cost: f32,
+    /// Maximum number of score notes to search ahead for a match for each live note
+    #[structopt(long = "search-window")]
+    search_window: Option<usize>,
+    /// Number of recent matched notes to fit a linear live-to-score time mapping to,
+    /// instead of mapping from the latest match only
+    #[structopt(long = "regression-window")]
+    regression_window: Option<usize>,
+    /// Number of consecutive ignored live notes after which the follower searches the
+    /// score for the position the performer is at
+    #[structopt(long = "reanchor-after", default_value = "4")]
+    reanchor_after: usize,
+    /// Shortest silence in milliseconds after which playing the opening of the score
+    /// counts as starting the piece over
+    #[structopt(long = "restart-pause-ms", default_value = "2000")]
+    restart_pause_ms: u64,
+    /// Silence in milliseconds after which the accompaniment pauses until the performer
+    /// plays again
+    #[structopt(long = "pause-after-ms", default_value = "3000")]
+    pause_after_ms: u64,
+    /// Number of score notes around the expected position to search first when
+    /// re-anchoring, before searching the whole score
+    #[structopt(long = "reanchor-window", default_value = "16")]
+    reanchor_window: usize,
+    /// Note naming convention for output: german, english or solfege
+    #[cfg(feature = "i18n")]
+    #[structopt(long = "note-naming", default_value = "german")]
+    note_naming: NoteNaming,
+    /// Number of opening score notes which need to be played in order before following
+    /// starts, discarding any warm-up playing before them
+    #[structopt(long = "arm-notes", conflicts_with = "detect-transposition")]
+    arm_notes: Option<usize>,
+    /// Detect whether the performer plays in a different key than the score, and follow
+    /// the transposed performance
+    #[structopt(long = "detect-transposition")]
+    detect_transposition: bool,
+    /// Number of live notes to detect a transposition from
+    #[structopt(long = "transposition-phrase", default_value = "8")]
+    transposition_phrase: usize,
+    /// Largest transposition to detect, in semitones
+    #[structopt(long = "max-transposition", default_value = "6")]
+    max_transposition: u8,
+    /// Shift the pitches of the input and playback scores by a number of semitones, e.g.
+    /// for accompanying a transposing instrument
+    #[structopt(long = "transpose", default_value = "0", allow_hyphen_values = true)]
+    transpose: i8,
+    /// Lowest pitch of the input score to follow, as a MIDI note number or a name like
+    /// C#1; lower notes are left out, e.g. to follow only the melody of a piano score
+    #[structopt(long = "min-pitch", default_value = "0", parse(try_from_str = parse_pitch))]
+    min_pitch: u7,
+    /// Highest pitch of the input score to follow, as a MIDI note number or a name
+    #[structopt(long = "max-pitch", default_value = "127", parse(try_from_str = parse_pitch))]
+    max_pitch: u7,
+    /// Tracks and channels of the input score, like `2:1`, `2:1-8`, `2:*` or `2:!10` with
+    /// tracks and channels numbered from 1
+    #[structopt(long = "input-channels", default_value = "2:1")]
+    input_channels: Vec<TrackChannels>,
+    /// Tracks and channels of the playback score, in the same format as
+    /// --input-channels
+    #[structopt(long = "playback-channels", default_value = "3:2")]
+    playback_channels: Vec<TrackChannels>,
+    /// Classes of MIDI messages of the playback score to play, separated by commas:
+    /// notes, program, controller, pitch-bend and aftertouch. All of them by default, so
+    /// that instrument assignments and the sustain pedal are kept.
+    #[structopt(
+        long = "playback-events",
+        use_delimiter = true,
+        default_value = "notes,program,controller,pitch-bend,aftertouch"
+    )]
+    playback_events: Vec<EventClass>,
+    /// Play a metronome click on every beat along with the playback, following the
+    /// tempo of the performer like the accompaniment. Needs a MIDI input score with beat
+    /// timing.
+    #[structopt(long = "click")]
+    click: bool,
+    /// Voices of an ABC input score to follow, as the IDs of their V: fields separated
+    /// by commas. All voices by default.
+    #[structopt(long = "input-voices", use_delimiter = true)]
+    input_voices: Vec<String>,
+    /// Voices of an ABC playback score, in the same format as --input-voices
+    #[structopt(long = "playback-voices", use_delimiter = true)]
+    playback_voices: Vec<String>,
+    /// What to do with grace notes in ABC scores: skip, or play them as short notes
+    /// just before the notes they ornament
+    #[structopt(long = "grace-notes", default_value = "skip")]
+    grace_notes: GraceNotes,
+    /// Follow notes on channel 10 of the input score too. By default the General MIDI
+    /// percussion channel is left out, since drum hits share note numbers with melodic
+    /// notes.
+    #[structopt(long = "keep-percussion")]
+    keep_percussion: bool,
+    /// What to do with notes of the same pitch starting at the same time in the input
+    /// score, e.g. from doubled parts in merged tracks: keep-all, merge or keep-loudest.
+    /// Followed notes have no velocity, so merge and keep-loudest both follow one note.
+    #[structopt(long = "duplicate-notes", default_value = "keep-all")]
+    duplicate_notes: DuplicateNotes,
+    /// Snap the note times of the input score to a grid of this many milliseconds before
+    /// following, to clean up sloppy MIDI exports
+    #[structopt(long = "quantize-ms", default_value = "0")]
+    quantize_ms: u64,
+    /// Shift all score times by this many milliseconds, negative to move them earlier,
+    /// e.g. to line up an upbeat
+    #[structopt(long = "shift-ms", default_value = "0", allow_hyphen_values = true)]
+    shift_ms: i64,
+    /// Move the first note of the input score to the start, shifting the other scores
+    /// by the same amount, so an empty first bar doesn't skew the initial tempo
+    /// estimate
+    #[structopt(long = "trim-leading-silence")]
+    trim_leading_silence: bool,
+    /// Split the input score into this many voices by pitch, e.g. 2 to separate the
+    /// hands of a single-track piano score
+    #[structopt(long = "voices", default_value = "1")]
+    voices: usize,
+    /// Follow only this voice of the input score split with --voices, 1 being the
+    /// highest, e.g. 1 for the right hand. Give the full score as the playback score to
+    /// still play back the whole texture.
+    #[structopt(long = "follow-voice", default_value = "1")]
+    follow_voice: usize,
+    /// Rehearse only an excerpt of the scores, e.g. 1:30-2:45 for a time range in
+    /// minutes and seconds or m33-48 for measures of a MIDI score
+    #[structopt(long = "excerpt")]
+    excerpt: Option<Excerpt>,
+    /// Compare how long live notes are held with the score when choosing between
+    /// alignments, with the beam search and ensemble algorithms
+    #[structopt(long = "use-durations")]
+    use_durations: bool,
+    /// Score file for a second part, followed from a second MIDI input in duet mode
+    #[structopt(long = "second-input-score-file", parse(from_os_str))]
+    second_input_score_file: Option<PathBuf>,
+    #[structopt(long = "second-rec-device-num")]
+    second_rec_device_num: Option<usize>,
+    #[structopt(long = "second-rec-device-name")]
+    second_rec_device_name: Option<String>,
+    /// Number of live notes between printouts of match statistics
+    #[structopt(long = "stats-every", default_value = "50")]
+    stats_every: usize,
+    /// Length of the score sections in the session report, in milliseconds
+    #[structopt(long = "report-section-ms", default_value = "10000")]
+    report_section_ms: u64,
+    /// File to write the session report to as JSON at the end of the score
+    #[structopt(long = "report-json", parse(from_os_str))]
+    report_json: Option<PathBuf>,
+    /// Print the matching decisions of the follower for each live note to stderr
+    #[structopt(long = "trace")]
+    trace: bool,
+    /// Address for serving a score following status overlay for streaming software,
+    /// e.g. 127.0.0.1:8080
+    #[structopt(long = "overlay-addr")]
+    overlay_addr: Option<String>,
+    /// Refuse MIDI files which violate the specification. By default the recoverable
+    /// part of a defective file is loaded with a warning.
+    #[structopt(long = "strict-midi")]
+    strict_midi: bool,
+    /// Directory for storing parsed scores, playback events and tempo maps to speed up
+    /// loading large MIDI files
+    #[structopt(long = "score-cache-dir", parse(from_os_str))]
+    score_cache_dir: Option<PathBuf>,
 }
 
 fn main() {
     let args = Cli::from_args();
-    let device = match (args.rec_device_num, args.rec_device_name) {
+    let device = match (args.rec_device_num, &args.rec_device_name) {
         (Some(rec_device_num), None) => DeviceSelector::Number(rec_device_num),
-        (None, Some(rec_device_name)) => DeviceSelector::NameSubstring(rec_device_name),
+        (None, Some(rec_device_name)) => DeviceSelector::NameSubstring(rec_device_name.clone()),
         _ => {
             panic!("-d/--device or -D/--device-name required")
         }
     };
-    let input_score = load_midi_file(&args.input_score_file, &[(1, &[u4::from(0)])]);
-    let playback_score = load_midi_file(&args.playback_score_file, &[(2, &[u4::from(1)])]);
+    let select = |source: &Path, score: &[ScoreNote], pitch_range: &RangeInclusive<u7>| {
+        exit_on_error(
+            source,
+            transpose_score(&filter_pitch_range(score, pitch_range), args.transpose),
+        )
+    };
+    let check = |path: &PathBuf| {
+        if !is_abc_file(path) {
+            for warning in exit_on_error(path, check_midi_file(path)) {
+                match args.strict_midi {
+                    true => exit_on_error(path, Err(warning)),
+                    false => eprintln!("Warning: {}: {}", path.display(), warning),
+                }
+            }
+        }
+    };
+    let exit_without_notes = |path: &PathBuf| {
+        eprintln!(
+            "Error: {}: no notes in the selected tracks and channels",
+            path.display()
+        );
+        for (index, track) in exit_on_error(path, describe_midi_file(path))
+            .iter()
+            .enumerate()
+        {
+            eprintln!("  track {}: {}", index + 1, track);
+        }
+        std::process::exit(1);
+    };
+    let load = |path: &PathBuf,
+                channels: &[(usize, &[u4])],
+                voices: &[String],
+                pitch_range: &RangeInclusive<u7>| {
+        check(path);
+        let score = exit_on_error(
+            path,
+            match &args.score_cache_dir {
+                Some(cache_dir) if !is_abc_file(path) => {
+                    load_midi_file_cached(path, channels, cache_dir)
+                }
+                _ if is_abc_file(path) => load_abc_file(path, voices, args.grace_notes),
+                _ => load_score_file(path, channels),
+            },
+        );
+        if score.is_empty() && !is_abc_file(path) {
+            exit_without_notes(path);
+        }
+        select(path, &score, pitch_range)
+    };
+    // the playback score keeps all channel messages of the notes, e.g. their velocities
+    let load_events = |path: &PathBuf| {
+        check(path);
+        let events = exit_on_error(
+            path,
+            match (is_abc_file(path), &args.score_cache_dir) {
+                (true, _) => load_abc_file_events(path, &args.playback_voices, args.grace_notes),
+                (false, Some(cache_dir)) => load_channel_events_cached(
+                    path,
+                    &TrackChannels::as_slices(&args.playback_channels),
+                    cache_dir,
+                ),
+                (false, None) => fs::read(path).map_err(LoadError::from).and_then(|data| {
+                    load_channel_events(&data, &TrackChannels::as_slices(&args.playback_channels))
+                }),
+            },
+        );
+        if !events
+            .iter()
+            .any(|event| note_on_key(event.message).is_some())
+            && !is_abc_file(path)
+        {
+            exit_without_notes(path);
+        }
+        events
+    };
+    // errors in inline ABC tunes are reported for their option
+    let load_inline = |option: &str, text: &str, voices: &[String], pitch_range| {
+        let option = Path::new(option);
+        let score = exit_on_error(
+            option,
+            inline_abc_into_score(text, voices, args.grace_notes),
+        );
+        select(option, &score, pitch_range)
+    };
+    // the pitch range applies to the written pitches, before transposition
+    let pitch_range = args.min_pitch..=args.max_pitch;
+    let input_channels = match args.keep_percussion {
+        true => args.input_channels.clone(),
+        false => TrackChannels::without_percussion(&args.input_channels),
+    };
+    let input_channels = TrackChannels::as_slices(&input_channels);
+    let input_score = quantize(
+        &match (&args.input_abc, &args.input_score_file) {
+            (Some(abc), _) => load_inline("--input-abc", abc, &args.input_voices, &pitch_range),
+            (None, Some(path)) => load(path, &input_channels, &args.input_voices, &pitch_range),
+            (None, None) => panic!("--input-score-file or --input-abc required"),
+        },
+        1000 * args.quantize_ms,
+    );
+    let input_end = input_score.last().map_or(0, |note| note.time);
+    // tempo maps and durations come from MIDI input scores only
+    let input_midi_file = args
+        .input_score_file
+        .as_ref()
+        .filter(|path| !is_abc_file(path));
+    if !(1..=args.voices.max(1)).contains(&args.follow_voice) {
+        panic!("--follow-voice must be between 1 and --voices");
+    }
+    let tempo_map = match (input_midi_file, &args.score_cache_dir) {
+        // the score has been loaded already, so the file is known to be valid
+        (Some(path), Some(cache_dir)) => load_midi_file_tempo_map_cached(path, cache_dir)
+            .ok()
+            .flatten(),
+        (Some(path), None) => load_midi_file_tempo_map(path).ok().flatten(),
+        (None, _) => None,
+    };
+    let excerpt = args.excerpt.as_ref().map(|excerpt| {
+        excerpt
+            .time_range(tempo_map.as_ref())
+            .unwrap_or_else(|| panic!("measure excerpts need a MIDI score with beat timing"))
+    });
+    let excerpt_start = excerpt.as_ref().map_or(0, |range| range.start);
+    let in_excerpt = |time: u64| excerpt.as_ref().is_none_or(|range| range.contains(&time));
+    // the selected notes are kept for selecting the durations of the same notes
+    let input_voices = assign_voices(&input_score, args.voices);
+    let input_duplicates = match args.duplicate_notes {
+        DuplicateNotes::KeepAll => vec![false; input_score.len()],
+        _ => duplicate_notes(&input_score),
+    };
+    let input_selected = input_score
+        .iter()
+        .zip(input_voices)
+        .zip(input_duplicates)
+        .map(|((note, voice), duplicate)| {
+            voice + 1 == args.follow_voice && in_excerpt(note.time) && !duplicate
+        })
+        .collect::<Vec<_>>();
+    let input_score = input_score
+        .iter()
+        .zip(&input_selected)
+        .filter(|(_, selected)| **selected)
+        .map(|(note, _)| *note)
+        .collect::<Vec<_>>();
+    // all scores are shifted together to keep them in sync, an excerpt to start at zero
+    let offset = 1000 * args.shift_ms
+        + match args.trim_leading_silence {
+            true => leading_silence_offset(&input_score),
+            false => -(excerpt_start as i64),
+        };
+    let input_score = shift_score(&input_score, offset);
+    let slice = |score: Vec<ScoreNote>| match &excerpt {
+        // slicing already moves the start of the excerpt to zero
+        Some(range) => shift_score(&slice_time(&score, range), offset + range.start as i64),
+        None => shift_score(&score, offset),
+    };
+    let place_events = |events: &[ScoreEvent]| match &excerpt {
+        Some(range) => shift_events(&slice_events(events, range), offset + range.start as i64),
+        None => shift_events(events, offset),
+    };
+    let slice_playback = |source: &Path, events: Vec<ScoreEvent>| {
+        place_events(&exit_on_error(
+            source,
+            transpose_events(&events, args.transpose),
+        ))
+    };
+    let mut playback_events = match (&args.playback_abc, &args.playback_score_file) {
+        (Some(abc), _) => {
+            let option = Path::new("--playback-abc");
+            let events = exit_on_error(
+                option,
+                inline_abc_into_events(abc, &args.playback_voices, args.grace_notes),
+            );
+            slice_playback(option, events)
+        }
+        (None, Some(path)) => slice_playback(path, load_events(path)),
+        (None, None) => {
+            let beat = 1000 * args.beat_ms;
+            let accompaniment = generate_accompaniment(&input_score, beat, args.comping_style);
+            accompaniment_events(&accompaniment, beat)
+        }
+    };
+    retain_event_classes(&mut playback_events, &args.playback_events);
+    if args.click {
+        let tempo_map = tempo_map
+            .as_ref()
+            .unwrap_or_else(|| panic!("the click track needs a MIDI score with beat timing"));
+        let mut clicks = click_track(
+            tempo_map,
+            Duration::from_micros(input_end),
+            &ClickConfig::default(),
+        );
+        // clicks before a trimmed start would all sound at once
+        clicks.retain(|click| click.time as i64 + offset >= 0);
+        playback_events = with_click_track(&playback_events, &place_events(&clicks));
+    }
     assert!(!input_score.is_empty());
-    if let Err(err) = run(device, input_score, playback_score) {
+    let result = match &args.second_input_score_file {
+        Some(path) => {
+            let second_device = match (args.second_rec_device_num, &args.second_rec_device_name) {
+                (Some(num), None) => DeviceSelector::Number(num),
+                (None, Some(name)) => DeviceSelector::NameSubstring(name.clone()),
+                _ => panic!("--second-rec-device-num or --second-rec-device-name required"),
+            };
+            let second_score = slice(quantize(
+                &load(path, &input_channels, &args.input_voices, &pitch_range),
+                1000 * args.quantize_ms,
+            ));
+            assert!(!second_score.is_empty());
+            run_duet(
+                &args,
+                [device, second_device],
+                [input_score, second_score],
+                playback_events,
+            )
+        }
+        None => {
+            // ABC scores carry no note durations
+            let input_durations = input_midi_file.filter(|_| args.use_durations).map(|path| {
+                exit_on_error(path, load_midi_file_with_durations(path, &input_channels))
+                    .iter()
+                    .filter(|note| pitch_range.contains(&note.note.pitch))
+                    .zip(&input_selected)
+                    .filter(|(_, selected)| **selected)
+                    .map(|(note, _)| note.duration)
+                    .collect()
+            });
+            let positions = tempo_map.map(|tempo_map| ScorePositions { tempo_map, offset });
+            run(
+                &args,
+                device,
+                input_score,
+                input_durations,
+                playback_events,
+                positions,
+            )
+        }
+    };
+    if let Err(err) = result {
         eprintln!("Error: {}", err)
     }
 }
 
-fn callback(microsecond: u64, message: &[u8], tx: &mut Sender<ScoreNote>) {
+/// Returns the value of a result, or reports the error with the path of the score file
+/// it concerns and exits
+fn exit_on_error<T, E: std::fmt::Display>(path: &Path, result: Result<T, E>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("Error: {}: {}", path.display(), err);
+        std::process::exit(1)
+    })
+}
+
+/// Parses a MIDI note number or a pitch name, see [`name_to_pitch`]
+fn parse_pitch(s: &str) -> Result<u7, String> {
+    s.parse::<u8>()
+        .ok()
+        .and_then(u7::try_from)
+        .or_else(|| name_to_pitch(s))
+        .ok_or_else(|| format!("invalid MIDI pitch '{}'", s))
+}
+
+/// A note started or released on a live input
+enum LiveInput {
+    /// A note started with a velocity
+    NoteOn(ScoreNote, u7),
+    NoteOff(ScoreNote),
+    /// Ctrl-C was pressed
+    Interrupt,
+    /// A command was typed on the terminal
+    Command(TerminalCommand),
+}
+
+/// A command typed on the terminal during a live run
+///
+/// Parsed from an empty line for exiting, `panic` or `p` for silencing the
+/// accompaniment, or a [`MixCommand`] like `mute 2`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TerminalCommand {
+    Exit,
+    Panic,
+    Mix(MixCommand),
+}
+
+impl std::str::FromStr for TerminalCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_whitespace().next() {
+            None => Ok(TerminalCommand::Exit),
+            Some("panic" | "p") => Ok(TerminalCommand::Panic),
+            Some("mute" | "unmute" | "solo" | "unsolo") => s.parse().map(TerminalCommand::Mix),
+            Some(_) => Err(format!("unknown command '{}'", s.trim())),
+        }
+    }
+}
+
+/// Reads commands typed on the terminal in a thread and sends them to the main loop,
+/// reporting lines which aren't commands
+fn spawn_terminal_reader(tx: Sender<LiveInput>) {
+    thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            match line.parse() {
+                Ok(command) => {
+                    if tx.send(LiveInput::Command(command)).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => eprintln!("Error: {}", err),
+            }
+        }
+    });
+}
+
+fn parse_note(microsecond: u64, message: &[u8]) -> Option<LiveInput> {
     let event = LiveEvent::parse(message).unwrap();
-    if let Midi {
-        channel: _,
-        message: NoteOn { key, vel: _ },
-    } = event
-    {
-        tx.send(ScoreNote {
-            time: microsecond,
-            pitch: key,
-        })
-        .unwrap();
+    let message = match event {
+        Midi { message, .. } => message,
+        _ => return None,
+    };
+    let note = |key| ScoreNote {
+        time: microsecond,
+        pitch: key,
+    };
+    match (message, note_off_key(message)) {
+        (MidiMessage::NoteOn { key, vel }, None) => Some(LiveInput::NoteOn(note(key), vel)),
+        (_, Some(key)) => Some(LiveInput::NoteOff(note(key))),
+        _ => None,
     }
 }
 
+fn callback(microsecond: u64, message: &[u8], tx: &mut Sender<LiveInput>) {
+    if let Some(input) = parse_note(microsecond, message) {
+        tx.send(input).unwrap();
+    }
+}
+
+fn duet_callback(
+    microsecond: u64,
+    message: &[u8],
+    data: &mut (usize, Sender<(usize, ScoreNote, u7)>),
+) {
+    let (part, tx) = data;
+    if let Some(LiveInput::NoteOn(note, velocity)) = parse_note(microsecond, message) {
+        tx.send((*part, note, velocity)).unwrap();
+    }
+}
+
+/// Creates the score follower selected on the command line for a score, using the
+/// durations of the score notes if they are given
+fn make_follower<'a>(
+    args: &Cli,
+    score: &'a [ScoreNote],
+    durations: Option<&'a [Option<u64>]>,
+) -> Box<dyn ScoreFollower + 'a> {
+    let config = Config {
+        stretch: StretchConfig {
+            window: args.stretch_window,
+            decay: args.stretch_decay,
+            min: args.min_stretch,
+            max: args.max_stretch,
+            roll: RollConfig {
+                window: 1000 * args.roll_window_ms,
+                chord_time: args.chord_time,
+            },
+        },
+        search_window: args.search_window,
+        regression_window: args.regression_window,
+        initial_stretch_factor: args.initial_stretch,
+        max_time_difference: args.max_time_difference_ms.map(|ms| 1000 * ms),
+        cost: CostConfig {
+            time: args.time_cost,
+            skip: args.skip_cost,
+        },
+    };
+    let beam_config = BeamConfig {
+        beam_width: args.beam_width,
+        lookahead: args
+            .search_window
+            .unwrap_or(BeamConfig::default().lookahead),
+        stretch: config.stretch,
+        initial_stretch_factor: config.initial_stretch_factor,
+    };
+    let settings = FollowerSettings {
+        config,
+        beam: beam_config,
+        durations,
+        trace: args.trace,
+    };
+    args.algorithm.new_follower(score, &settings)
+}
+
 fn run(
+    args: &Cli,
     device: DeviceSelector,
     input_score: Vec<ScoreNote>,
-    _playback_score: Vec<ScoreNote>,
+    input_durations: Option<Vec<Option<u64>>>,
+    playback_events: Vec<ScoreEvent>,
+    positions: Option<ScorePositions>,
 ) -> Result<(), Box<dyn Error>> {
     assert!(!input_score.is_empty());
     let mut midi_input = MidiInput::new("selim")?;
@@ -77,50 +750,473 @@ fn run(
     let in_port_name = midi_input.port_name(&in_port);
     // _conn_in needs to be a named parameter, because it needs to be kept alive
     // until the end of the scope
-    let (tx, rx) = mpsc::channel::<ScoreNote>();
+    let (tx, rx) = mpsc::channel::<LiveInput>();
+    let interrupt = tx.clone();
+    spawn_terminal_reader(tx.clone());
     let _conn_in = midi_input.connect(&in_port, "selim-live-to-score", callback, tx)?;
+    // live note timestamps count from opening the connection
+    let connected = Instant::now();
 
     eprintln!(
-        "Connection open, reading input from '{}' (press enter to exit) ...",
+        "Connection open, reading input from '{}' (press enter to exit, type panic, \
+         mute, unmute, solo or unsolo and a channel to control the playback) ...",
         in_port_name.unwrap()
     );
+    let playback = start_playback(args, &playback_events, connected)?;
+    let stop = playback.commands.clone();
+    ctrlc::set_handler(move || {
+        // playback is stopped right away, also while the end of the accompaniment plays
+        if let Some(stop) = &stop {
+            let _ = stop.send(PlaybackCommand::Stop);
+        }
+        let _ = interrupt.send(LiveInput::Interrupt);
+    })?;
 
-    let mut live = vec![];
-    let mut prev_match = None;
-    let mut new_live_index = 0;
-    let mut prev_stretch_factor = 1.0;
-    let mut matches = vec![];
+    let make_follower = || make_follower(args, &input_score, input_durations.as_deref());
+    let mut follower = if args.detect_transposition {
+        Box::new(Transposing::new(
+            &input_score,
+            make_follower,
+            args.transposition_phrase,
+            args.max_transposition,
+        ))
+    } else if let Some(arm_notes) = args.arm_notes {
+        Box::new(Arming::new(&input_score, make_follower(), arm_notes))
+    } else {
+        make_follower()
+    };
+    let overlay = match &args.overlay_addr {
+        Some(addr) => Some(spawn_overlay_server(addr)?),
+        None => None,
+    };
+    let score_end = input_score.last().unwrap().time;
+    let mut pause_detector = PauseDetector::new(1000 * args.pause_after_ms);
+    let mut panicked = false;
+    #[cfg(feature = "i18n")]
+    let note_name = |pitch| pitch_to_name_in(pitch, args.note_naming);
+    #[cfg(not(feature = "i18n"))]
+    let note_name = pitch_to_name;
     loop {
-        print_expect(&input_score, prev_match);
-        let note = rx.recv().unwrap();
-        live.push(note);
-        let (score_time, stretch_factor, new_matches, ignored) = follow_score(
+        print_expect(
             &input_score,
-            &live,
-            prev_match,
-            new_live_index,
-            prev_stretch_factor,
+            follower.last_match(),
+            positions.as_ref(),
+            &note_name,
         );
+        let (note, velocity) = loop {
+            let expecting = follower.last_match().is_some() && !follower.is_finished();
+            // without an overlay to move on, the loop only wakes up for pausing
+            let refresh = overlay
+                .as_ref()
+                .filter(|_| !pause_detector.is_paused())
+                .map(|_| connected.elapsed() + OVERLAY_REFRESH_INTERVAL);
+            let pause = pause_detector
+                .deadline()
+                .filter(|_| expecting)
+                .map(Duration::from_micros);
+            let input = match refresh.into_iter().chain(pause).min() {
+                Some(deadline) => rx.recv_timeout(deadline.saturating_sub(connected.elapsed())),
+                None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match input {
+                Ok(LiveInput::NoteOn(note, velocity)) => break (note, velocity),
+                Ok(LiveInput::NoteOff(note)) => follower.push_note_off(note),
+                Ok(LiveInput::Interrupt) => {
+                    println!("\ninterrupted");
+                    return Ok(());
+                }
+                Ok(LiveInput::Command(TerminalCommand::Exit)) => return Ok(()),
+                Ok(LiveInput::Command(TerminalCommand::Panic)) => {
+                    println!("panic, accompaniment silenced until the next match");
+                    playback.send(PlaybackCommand::Panic);
+                    panicked = true;
+                }
+                Ok(LiveInput::Command(TerminalCommand::Mix(command))) => {
+                    playback.send(PlaybackCommand::Mix(command));
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    let now = connected.elapsed().as_micros() as u64;
+                    if let (Some(overlay), Some(score_time)) =
+                        (&overlay, follower.estimated_score_time(connected.elapsed()))
+                    {
+                        if !pause_detector.is_paused() {
+                            overlay.lock().unwrap().score_time = score_time.min(score_end);
+                        }
+                    }
+                    if pause_detector.tick(now, expecting) == Some(PauseEvent::Pause) {
+                        playback.send(PlaybackCommand::Pause);
+                        println!("\npaused accompaniment, waiting for the performer");
+                        print_expect(
+                            &input_score,
+                            follower.last_match(),
+                            positions.as_ref(),
+                            &note_name,
+                        );
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        };
+        if pause_detector.note(note.time) == Some(PauseEvent::Resume) {
+            // playback continues from the next match
+            playback.send(PlaybackCommand::Resume);
+            print!(", resumed accompaniment");
+        }
+        follower.push_live(note);
+        let transposition = follower.transposition();
+        let result = follower.follow_score();
+        if let (None, Some(offset)) = (transposition, follower.transposition()) {
+            println!("detected transposition of {} semitones", -offset);
+        }
+        if !result.new_matches.is_empty() {
+            if panicked {
+                playback.send(PlaybackCommand::Resume);
+                panicked = false;
+            }
+            playback.send(PlaybackCommand::LiveVelocity(velocity));
+            playback.send(PlaybackCommand::Anchor(PlaybackAnchor {
+                score_time: result.score_time,
+                live_time: note.time,
+                stretch_factor: result.stretch_factor,
+            }));
+        }
         print_got(
-            &live,
+            follower.live(),
             note,
-            score_time,
-            stretch_factor,
-            &new_matches,
-            &ignored,
+            &result,
+            follower.confidence(),
+            chord_at(&input_score, result.score_time, CHORD_WINDOW),
+            positions.as_ref(),
+            &note_name,
         );
-        matches.extend(new_matches.iter());
-        new_live_index = live.len();
-        prev_stretch_factor = stretch_factor;
-        prev_match = if matches.is_empty() {
-            None
-        } else {
-            Some(matches[matches.len() - 1])
-        };
+        for retracted in &result.retracted {
+            println!(
+                "retracted match {}->{}",
+                retracted.live_index, retracted.score_index
+            );
+        }
+        if let Some(overlay) = &overlay {
+            *overlay.lock().unwrap() = OverlayStatus {
+                score_time: result.score_time,
+                score_end,
+                confidence: follower.confidence(),
+                stretch_factor: result.stretch_factor,
+            };
+        }
+        if let Some(first) = result.new_matches.first() {
+            if let Some(passage) = wrong_passage_before(
+                follower.matches(),
+                follower.ignored(),
+                first.live_index,
+                WRONG_PASSAGE_MIN_NOTES,
+            ) {
+                println!(
+                    "wrong passage at score {}..{}, {} wrong notes",
+                    passage.score_start,
+                    first.score_index,
+                    passage.wrong_notes()
+                );
+            }
+        }
+        let restart = find_restart(
+            &input_score,
+            follower.live(),
+            follower.last_match(),
+            args.reanchor_after,
+            args.reanchor_after,
+            1000 * args.restart_pause_ms,
+        );
+        if let Some(restart) = restart {
+            println!("restarted from the beginning");
+            follower.reanchor(restart);
+            playback.send(PlaybackCommand::Seek(PlaybackAnchor {
+                score_time: input_score[restart.score_index].time,
+                live_time: note.time,
+                stretch_factor: result.stretch_factor,
+            }));
+        } else if follower.ignored_streak() >= args.reanchor_after {
+            let run_length = args.reanchor_after;
+            // allow one wrong note among the notes compared
+            let min_matching = run_length.saturating_sub(1).max(1);
+            let prev_match = follower.last_match();
+            let live = follower.live();
+            let jump = find_local_anchor(
+                &input_score,
+                live,
+                prev_match,
+                run_length,
+                min_matching,
+                args.reanchor_window,
+            )
+            .or_else(|| {
+                find_backward_jump(&input_score, live, prev_match, run_length, min_matching)
+            })
+            .or_else(|| {
+                find_forward_jump(&input_score, live, prev_match, run_length, min_matching)
+            });
+            if let Some(jump) = jump {
+                let time = input_score[jump.score_index].time;
+                let measure = positions.as_ref().map_or(String::new(), |positions| {
+                    let (measure, beat) = positions.measure(time);
+                    format!(" (measure {} beat {:.1})", measure, beat + 1.0)
+                });
+                println!(
+                    "jumped to score {:>3} {:>7.3}{}",
+                    jump.score_index,
+                    time as f64 / 1000000.0,
+                    measure,
+                );
+                follower.reanchor(jump);
+                playback.send(PlaybackCommand::Seek(PlaybackAnchor {
+                    score_time: time,
+                    live_time: note.time,
+                    stretch_factor: result.stretch_factor,
+                }));
+            }
+        }
+        if follower.is_finished() {
+            print_summary(args, &input_score, follower.as_ref(), result.stretch_factor)?;
+            playback.finish();
+            return Ok(());
+        }
+        if args.stats_every > 0 && follower.live().len() % args.stats_every == 0 {
+            let stats = match_stats(
+                &input_score,
+                follower.live(),
+                follower.matches(),
+                result.stretch_factor,
+            );
+            println!("{}", stats);
+        }
+    }
+}
+
+/// Starts playing the playback score to the output port selected on the command line,
+/// or printing it with --no-output
+///
+/// # Arguments
+///
+/// * args - The command line options
+/// * playback_events - The playback score
+/// * connected - The moment when live time is zero
+///
+/// # Return value
+///
+/// The playback, which plays nothing without an output port
+fn start_playback(
+    args: &Cli,
+    playback_events: &[ScoreEvent],
+    connected: Instant,
+) -> Result<Playback, Box<dyn Error>> {
+    let play_device = match (args.play_device_num, &args.play_device_name) {
+        (Some(num), _) => Some(DeviceSelector::Number(num)),
+        (None, Some(name)) => Some(DeviceSelector::NameSubstring(name.clone())),
+        (None, None) => None,
+    };
+    let send: Box<dyn FnMut(ScoreEvent) + Send> = match (args.no_output, play_device) {
+        (true, _) => Box::new(|event| {
+            let seconds = event.time as f64 / 1000000.0;
+            println!("{:>11.6} {}", seconds, describe_event(&event));
+        }),
+        (false, Some(device)) => {
+            let midi_output = MidiOutput::new("selim")?;
+            let out_port = find_port(&midi_output, device)?;
+            let mut conn_out = midi_output.connect(&out_port, "selim-playback")?;
+            let gains = args.channel_gain.clone();
+            Box::new(move |event| {
+                if let Err(err) = conn_out.send(&encode_midi_event(&event, &gains)) {
+                    eprintln!("Error: {}", err);
+                }
+            })
+        }
+        (false, None) => return Ok(Playback::default()),
+    };
+    let scheduler = PlaybackScheduler::new(playback_events)
+        .with_flush_order(args.flush_order)
+        .with_velocity_mode(args.velocity_mode);
+    let (commands, thread) = spawn_playback(
+        scheduler,
+        connected,
+        TempoRamp::new(1000 * args.ramp_ms),
+        send,
+    );
+    Ok(Playback {
+        commands: Some(commands),
+        thread: Some(thread),
+    })
+}
+
+/// The accompaniment playing in a thread of its own, see [`spawn_playback`], or nothing
+/// without an output port
+///
+/// Dropping it stops playback, releasing all sounding notes and the sustain pedal.
+#[derive(Default)]
+struct Playback {
+    commands: Option<Sender<PlaybackCommand>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Playback {
+    fn send(&self, command: PlaybackCommand) {
+        if let Some(commands) = &self.commands {
+            // the thread ends after the last event, so later commands have no effect
+            let _ = commands.send(command);
+        }
+    }
+
+    /// Waits until the rest of the accompaniment has been played
+    fn finish(mut self) {
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap();
+        }
+    }
+}
+
+impl Drop for Playback {
+    fn drop(&mut self) {
+        // the thread releases the notes and the pedal once its commands are cut off
+        self.commands.take();
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap();
+        }
+    }
+}
+
+/// Prints statistics of the performance once the end of the score has been reached
+fn print_summary(
+    args: &Cli,
+    score: &[ScoreNote],
+    follower: &dyn ScoreFollower,
+    stretch_factor: f32,
+) -> Result<(), Box<dyn Error>> {
+    let passages = wrong_passages(
+        follower.matches(),
+        follower.ignored(),
+        WRONG_PASSAGE_MIN_NOTES,
+    )
+    .count();
+    println!(
+        "end of score reached: {} live notes, {} matched, {} ignored, {} wrong passages, final tempo {:.1}%",
+        follower.live().len(),
+        follower.matches().len(),
+        follower.ignored().len(),
+        passages,
+        100.0 / stretch_factor,
+    );
+    let stats = match_stats(score, follower.live(), follower.matches(), stretch_factor);
+    println!("{}", stats);
+    let report = session_report(
+        score,
+        follower.live(),
+        follower.matches(),
+        follower.ignored(),
+        1000 * args.report_section_ms,
+        REPORT_TEMPO_DEVIATIONS,
+    );
+    println!("{}", report);
+    if let Some(path) = &args.report_json {
+        fs::write(path, report.to_json())?;
+    }
+    Ok(())
+}
+
+/// Follows two performers from two MIDI inputs against the two parts of a duet
+fn run_duet(
+    args: &Cli,
+    devices: [DeviceSelector; 2],
+    scores: [Vec<ScoreNote>; 2],
+    playback_events: Vec<ScoreEvent>,
+) -> Result<(), Box<dyn Error>> {
+    let (tx, rx) = mpsc::channel::<(usize, ScoreNote, u7)>();
+    // the connections need to be kept alive until the end of the scope
+    let mut _connections = vec![];
+    for (part, device) in devices.into_iter().enumerate() {
+        let mut midi_input = MidiInput::new("selim")?;
+        midi_input.ignore(Ignore::All);
+        let in_port = find_port(&midi_input, device).unwrap();
+        let in_port_name = midi_input.port_name(&in_port)?;
+        _connections.push(midi_input.connect(
+            &in_port,
+            "selim-live-to-score",
+            duet_callback,
+            (part, tx.clone()),
+        )?);
+        eprintln!("Reading part {} from '{}'", part + 1, in_port_name);
     }
+    // live note timestamps count from opening the connections
+    let connected = Instant::now();
+    let playback = start_playback(args, &playback_events, connected)?;
+    let mut duet = Duet::new(
+        make_follower(args, &scores[0], None),
+        make_follower(args, &scores[1], None),
+    );
+    loop {
+        let (part, note, velocity) = rx.recv().unwrap();
+        let result = duet.follow(part, note);
+        // playback follows the fused clock of both parts
+        if let (false, Some(clock)) = (result.new_matches.is_empty(), duet.clock()) {
+            playback.send(PlaybackCommand::LiveVelocity(velocity));
+            playback.send(PlaybackCommand::Anchor(PlaybackAnchor {
+                score_time: clock,
+                live_time: note.time,
+                stretch_factor: result.stretch_factor,
+            }));
+        }
+        println!(
+            "part {} got {} -> {:>7.3} {:>3.0}%, clock {:>7.3}",
+            part + 1,
+            pitch_to_name(note.pitch),
+            result.score_time as f64 / 1000000.0,
+            100.0 * duet.follower(part).confidence(),
+            duet.clock().unwrap_or(0) as f64 / 1000000.0,
+        );
+        if duet.follower(0).is_finished() && duet.follower(1).is_finished() {
+            println!("end of both parts reached");
+            playback.finish();
+            return Ok(());
+        }
+    }
+}
+
+/// Maps times of the followed score to measures and beats of the score file, which the
+/// followed score may have been shifted or sliced from
+struct ScorePositions {
+    tempo_map: TempoMap,
+    /// The offset in microseconds added to the times of the score file
+    offset: i64,
 }
 
-fn print_expect(input_score: &[ScoreNote], prev_match: Option<Match>) {
+impl ScorePositions {
+    fn file_time(&self, time: u64) -> Duration {
+        Duration::from_micros(time.saturating_add_signed(-self.offset))
+    }
+
+    /// Finds the measure and the beat within it, see [`TempoMap::time_to_measure`]
+    fn measure(&self, time: u64) -> (usize, f64) {
+        self.tempo_map.time_to_measure(self.file_time(time))
+    }
+
+    /// Formats a position as `measure:beat`, see [`TempoMap::format_position`]
+    fn format(&self, time: u64) -> String {
+        self.tempo_map.format_position(self.file_time(time))
+    }
+}
+
+/// Formats a score position as `measure:beat` if the score has a tempo map, or else as
+/// the index of the score note
+fn format_score_position(positions: Option<&ScorePositions>, index: usize, time: u64) -> String {
+    match positions {
+        Some(positions) => positions.format(time),
+        None => index.to_string(),
+    }
+}
+
+fn print_expect(
+    input_score: &[ScoreNote],
+    prev_match: Option<Match>,
+    positions: Option<&ScorePositions>,
+    note_name: &dyn Fn(u7) -> String,
+) {
     let score_next = match prev_match {
         Some(Match {
             score_index,
@@ -129,11 +1225,12 @@ fn print_expect(input_score: &[ScoreNote], prev_match: Option<Match>) {
         _ => 0,
     };
     if score_next < input_score.len() {
+        let time = input_score[score_next].time;
         print!(
-            "score {:>3} {:>7.3} expect {}",
-            score_next,
-            input_score[score_next].time as f64 / 1000000.0,
-            pitch_to_name(input_score[score_next].pitch),
+            "score {:>6} {:>7.3} expect {}",
+            format_score_position(positions, score_next, time),
+            time as f64 / 1000000.0,
+            note_name(input_score[score_next].pitch),
         );
     } else {
         print!("score ended, expect nothing more");
@@ -144,19 +1241,27 @@ fn print_expect(input_score: &[ScoreNote], prev_match: Option<Match>) {
 fn print_got(
     live: &[ScoreNote],
     note: ScoreNote,
-    score_time: u64,
-    stretch_factor: f32,
-    new_matches: &[Match],
-    ignored: &[usize],
+    result: &FollowResult,
+    confidence: f32,
+    chord: Option<Chord>,
+    positions: Option<&ScorePositions>,
+    note_name: &dyn Fn(u7) -> String,
 ) {
+    let position = positions.map_or(String::new(), |positions| {
+        format!(" {:>6}", positions.format(result.score_time))
+    });
     println!(
-        ", got {} at live {:>3} {:>7.3} -> {:>7.3} {:>5.1}% {:?} {:?}",
-        pitch_to_name(note.pitch),
+        ", got {} at live {:>3} {:>7.3} ->{} {:>7.3} {:>5.1}% {:>3.0}% {:<5} {:?} {:?}",
+        note_name(note.pitch),
         live.len() - 1,
         note.time as f64 / 1000000.0,
-        score_time as f64 / 100000.0,
-        100.0 * stretch_factor,
-        new_matches
+        position,
+        result.score_time as f64 / 100000.0,
+        100.0 * result.stretch_factor,
+        100.0 * confidence,
+        chord.map_or(String::new(), |chord| chord.to_string()),
+        result
+            .new_matches
             .iter()
             .map(|m| {
                 format!(
@@ -165,6 +1270,95 @@ fn print_got(
                 )
             })
             .collect::<Vec<_>>(),
-        ignored
+        result.ignored
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[test]
+    fn arming_conflicts_with_detecting_transposition() {
+        let args = |extra: &[&str]| {
+            let mut args = vec!["selim", "--input-abc", "CDEF", "--arm-notes", "4"];
+            args.extend(extra);
+            Cli::from_iter_safe(args)
+        };
+        assert!(args(&[]).is_ok());
+        assert!(args(&["--detect-transposition"]).is_err());
+    }
+
+    #[test]
+    fn select_playback_event_classes() {
+        let args = |extra: &[&str]| {
+            let mut args = vec!["selim", "--input-abc", "CDEF"];
+            args.extend(extra);
+            Cli::from_iter_safe(args).unwrap().playback_events
+        };
+        assert_eq!(args(&[]), EventClass::ALL);
+        assert_eq!(
+            args(&["--playback-events", "notes,program"]),
+            [EventClass::Notes, EventClass::ProgramChange]
+        );
+    }
+
+    #[test]
+    fn channel_gains() {
+        let args = Cli::from_iter_safe([
+            "selim",
+            "--input-abc",
+            "CDEF",
+            "--channel-gain",
+            "2:1.5",
+            "--channel-gain",
+            "10:0",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.channel_gain,
+            [
+                ChannelGain {
+                    channel: u4::from(1),
+                    gain: 1.5
+                },
+                ChannelGain {
+                    channel: u4::from(9),
+                    gain: 0.0
+                }
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case("", TerminalCommand::Exit)]
+    #[case("  ", TerminalCommand::Exit)]
+    #[case("panic", TerminalCommand::Panic)]
+    #[case("p", TerminalCommand::Panic)]
+    #[case("mute 2", TerminalCommand::Mix(MixCommand::Mute(u4::from(1))))]
+    #[case("solo 10", TerminalCommand::Mix(MixCommand::Solo(u4::from(9))))]
+    fn parse_terminal_command(#[case] line: &str, #[case] expected: TerminalCommand) {
+        assert_eq!(line.parse::<TerminalCommand>(), Ok(expected));
+    }
+
+    #[rstest]
+    #[case("stop", "unknown command 'stop'")]
+    #[case("mute", "invalid mix command 'mute'")]
+    #[case("solo 17", "invalid mix command 'solo 17'")]
+    fn reject_terminal_command(#[case] line: &str, #[case] expected: &str) {
+        assert_eq!(line.parse::<TerminalCommand>(), Err(expected.to_string()));
+    }
+
+    #[test]
+    fn printing_conflicts_with_output_port() {
+        let args = |extra: &[&str]| {
+            let mut args = vec!["selim", "--input-abc", "CDEF", "--no-output"];
+            args.extend(extra);
+            Cli::from_iter_safe(args)
+        };
+        assert!(args(&[]).unwrap().no_output);
+        assert!(args(&["--play-device-num", "1"]).is_err());
+        assert!(args(&["--play-device-name", "synth"]).is_err());
+    }
+}
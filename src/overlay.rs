@@ -0,0 +1,191 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// How often the overlay page reloads the status image, in seconds
+const REFRESH_SECONDS: u32 = 1;
+/// Width and height of the status image in pixels
+const SVG_SIZE: (u32, u32) = (400, 60);
+
+/// The state of score following shown on the overlay
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct OverlayStatus {
+    /// The current score time in microseconds
+    pub score_time: u64,
+    /// The time of the last note in the score in microseconds
+    pub score_end: u64,
+    /// How sure the follower is about its position, between 0.0 and 1.0
+    pub confidence: f32,
+    /// Live time elapsed per score time
+    pub stretch_factor: f32,
+}
+
+impl OverlayStatus {
+    /// Returns how far into the score the performance is, between 0.0 and 1.0
+    pub fn progress(&self) -> f32 {
+        if self.score_end == 0 {
+            return 0.0;
+        }
+        (self.score_time as f32 / self.score_end as f32).min(1.0)
+    }
+}
+
+/// Renders the status as a JSON object for browser sources which draw their own overlay
+pub fn render_json(status: &OverlayStatus) -> String {
+    format!(
+        "{{\"score_time\":{},\"score_end\":{},\"progress\":{:.3},\"confidence\":{:.3},\"stretch_factor\":{:.3}}}",
+        status.score_time,
+        status.score_end,
+        status.progress(),
+        status.confidence,
+        status.stretch_factor,
+    )
+}
+
+/// Renders the status as an SVG image with a progress bar colored by confidence
+pub fn render_svg(status: &OverlayStatus) -> String {
+    let (width, height) = SVG_SIZE;
+    let bar_width = (status.progress() * width as f32).round() as u32;
+    // from red for no confidence to green for full confidence
+    let red = (255.0 * (1.0 - status.confidence.clamp(0.0, 1.0))).round() as u8;
+    let green = (255.0 * status.confidence.clamp(0.0, 1.0)).round() as u8;
+    format!(
+        concat!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\">",
+            "<rect width=\"{width}\" height=\"{height}\" fill=\"#000\" fill-opacity=\"0.5\"/>",
+            "<rect width=\"{bar_width}\" height=\"10\" fill=\"#{red:02x}{green:02x}00\"/>",
+            "<text x=\"8\" y=\"40\" fill=\"#fff\" font-family=\"sans-serif\" font-size=\"20\">",
+            "{time:.1} s, tempo {tempo:.0}%, confidence {confidence:.0}%",
+            "</text></svg>"
+        ),
+        width = width,
+        height = height,
+        bar_width = bar_width,
+        red = red,
+        green = green,
+        time = status.score_time as f64 / 1000000.0,
+        tempo = 100.0 / status.stretch_factor,
+        confidence = 100.0 * status.confidence,
+    )
+}
+
+/// Renders an HTML page which shows the status image and reloads itself periodically
+fn render_page() -> String {
+    format!(
+        concat!(
+            "<!DOCTYPE html><html><head><meta http-equiv=\"refresh\" content=\"{}\">",
+            "<style>body{{margin:0;background:transparent}}</style></head>",
+            "<body><img src=\"/overlay.svg\"></body></html>"
+        ),
+        REFRESH_SECONDS
+    )
+}
+
+/// Returns the HTTP status line, content type and body for a requested path
+fn respond(path: &str, status: &OverlayStatus) -> (&'static str, &'static str, String) {
+    match path {
+        "/" => ("200 OK", "text/html", render_page()),
+        "/overlay.svg" => ("200 OK", "image/svg+xml", render_svg(status)),
+        "/status.json" => ("200 OK", "application/json", render_json(status)),
+        _ => ("404 Not Found", "text/plain", "not found".to_string()),
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, status: &Mutex<OverlayStatus>) -> io::Result<()> {
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let current = *status.lock().unwrap();
+    let (status_line, content_type, body) = respond(path, &current);
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        status_line,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+/// Starts a background HTTP server for streaming software overlays
+///
+/// The server answers `/` with a self-refreshing page for a browser source,
+/// `/overlay.svg` with the status image and `/status.json` with the raw status.
+///
+/// # Arguments
+///
+/// * addr - The address to listen on, e.g. `127.0.0.1:8080`
+///
+/// # Return value
+///
+/// The shared status to update as the performance is followed
+pub fn spawn_overlay_server(addr: impl ToSocketAddrs) -> io::Result<Arc<Mutex<OverlayStatus>>> {
+    let listener = TcpListener::bind(addr)?;
+    let status = Arc::new(Mutex::new(OverlayStatus::default()));
+    let shared = Arc::clone(&status);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // a misbehaving client must not stop the overlay
+            let _ = handle_connection(stream, &shared);
+        }
+    });
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+    use std::io::Read;
+
+    fn status() -> OverlayStatus {
+        OverlayStatus {
+            score_time: 1500000,
+            score_end: 6000000,
+            confidence: 0.75,
+            stretch_factor: 1.25,
+        }
+    }
+
+    #[test]
+    fn progress() {
+        assert_approx_eq!(status().progress(), 0.25);
+        assert_approx_eq!(OverlayStatus::default().progress(), 0.0);
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(
+            render_json(&status()),
+            "{\"score_time\":1500000,\"score_end\":6000000,\"progress\":0.250,\"confidence\":0.750,\"stretch_factor\":1.250}"
+        );
+    }
+
+    #[test]
+    fn svg() {
+        let svg = render_svg(&status());
+        assert!(svg.contains("width=\"100\" height=\"10\" fill=\"#40bf00\""));
+        assert!(svg.contains("1.5 s, tempo 80%, confidence 75%"));
+    }
+
+    #[test]
+    fn unknown_path() {
+        assert_eq!(respond("/favicon.ico", &status()).0, "404 Not Found");
+    }
+
+    #[test]
+    fn serve_status() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client
+            .write_all(b"GET /status.json HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        handle_connection(stream, &Mutex::new(status())).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(&render_json(&status())));
+    }
+}
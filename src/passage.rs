@@ -0,0 +1,204 @@
+use crate::Match;
+use std::ops::Range;
+
+/// A run of consecutive ignored live notes, with its position in the score
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WrongPassage {
+    /// Indices of the ignored notes in the live performance
+    pub live_range: Range<usize>,
+    /// Index of the first score note after the last match before the passage
+    pub score_start: usize,
+    /// Index of the score note matched first after the passage, or `None` if no live
+    /// note has been matched after the passage yet
+    pub score_end: Option<usize>,
+}
+
+impl WrongPassage {
+    /// The number of wrong notes played in the passage
+    pub fn wrong_notes(&self) -> usize {
+        self.live_range.len()
+    }
+}
+
+/// Finds the score context of a run of ignored live notes
+///
+/// Since the matches are in live order, the matches around the run are found with a
+/// binary search instead of scanning all matches.
+fn passage(matches: &[Match], live_range: Range<usize>) -> WrongPassage {
+    let before = matches.partition_point(|m| m.live_index < live_range.start);
+    let after = matches.partition_point(|m| m.live_index < live_range.end);
+    WrongPassage {
+        score_start: before
+            .checked_sub(1)
+            .map_or(0, |i| matches[i].score_index + 1),
+        score_end: matches.get(after).map(|m| m.score_index),
+        live_range,
+    }
+}
+
+/// An iterator over the wrong passages of a performance, created by [`wrong_passages`]
+#[derive(Clone, Debug)]
+pub struct WrongPassages<'a> {
+    matches: &'a [Match],
+    /// The ignored live notes not yet clustered into passages
+    ignored: &'a [usize],
+    min_wrong_notes: usize,
+}
+
+impl Iterator for WrongPassages<'_> {
+    type Item = WrongPassage;
+
+    fn next(&mut self) -> Option<WrongPassage> {
+        loop {
+            let start = *self.ignored.first()?;
+            let length = self
+                .ignored
+                .iter()
+                .zip(start..)
+                .take_while(|(&live_index, expected)| live_index == *expected)
+                .count();
+            self.ignored = &self.ignored[length..];
+            if length < self.min_wrong_notes {
+                continue;
+            }
+            return Some(passage(self.matches, start..start + length));
+        }
+    }
+}
+
+/// Clusters consecutive ignored live notes into passages
+///
+/// The passages are clustered lazily while iterating, so finding a single passage
+/// doesn't allocate anything.
+///
+/// # Arguments
+///
+/// * matches - All matches between the live performance and the score, in live order
+/// * ignored - Indices of all ignored live notes, in ascending order
+/// * min_wrong_notes - Shorter runs of ignored notes are left out of the result
+///
+/// # Return value
+///
+/// An iterator over the wrong passages in the order they were played. The score context
+/// of each passage reaches from just after the last match before the passage to the
+/// first match after it.
+pub fn wrong_passages<'a>(
+    matches: &'a [Match],
+    ignored: &'a [usize],
+    min_wrong_notes: usize,
+) -> WrongPassages<'a> {
+    WrongPassages {
+        matches,
+        ignored,
+        min_wrong_notes: min_wrong_notes.max(1),
+    }
+}
+
+/// Finds the wrong passage which ends just before a given live note
+///
+/// Only the ignored notes of the passage itself are examined, so the time taken doesn't
+/// grow with the length of the performance.
+///
+/// # Arguments
+///
+/// * matches - All matches between the live performance and the score, in live order
+/// * ignored - Indices of all ignored live notes, in ascending order
+/// * live_index - The index of the live note following the passage
+/// * min_wrong_notes - A shorter run of ignored notes isn't counted as a passage
+///
+/// # Return value
+///
+/// The passage, or `None` if the previous live note wasn't ignored or the run is too
+/// short
+pub fn wrong_passage_before(
+    matches: &[Match],
+    ignored: &[usize],
+    live_index: usize,
+    min_wrong_notes: usize,
+) -> Option<WrongPassage> {
+    let end = ignored.partition_point(|&i| i < live_index);
+    let length = ignored[..end]
+        .iter()
+        .rev()
+        .zip((0..live_index).rev())
+        .take_while(|(&ignored_index, expected)| ignored_index == *expected)
+        .count();
+    if length == 0 || length < min_wrong_notes {
+        return None;
+    }
+    Some(passage(matches, live_index - length..live_index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_ignored_notes() {
+        let matches = [Match::new(0, 0), Match::new(1, 1)];
+        assert_eq!(wrong_passages(&matches, &[], 1).next(), None);
+    }
+
+    #[test]
+    fn cluster_consecutive_notes() {
+        let matches = [Match::new(0, 0), Match::new(4, 4), Match::new(5, 6)];
+        let passages = wrong_passages(&matches, &[1, 2, 3, 5], 1).collect::<Vec<_>>();
+        assert_eq!(
+            passages,
+            [
+                WrongPassage {
+                    live_range: 1..4,
+                    score_start: 1,
+                    score_end: Some(4),
+                },
+                WrongPassage {
+                    live_range: 5..6,
+                    score_start: 5,
+                    score_end: Some(5),
+                },
+            ]
+        );
+        assert_eq!(passages[0].wrong_notes(), 3);
+    }
+
+    #[test]
+    fn skip_short_runs() {
+        let matches = [Match::new(0, 0), Match::new(4, 4), Match::new(5, 6)];
+        let passages = wrong_passages(&matches, &[1, 2, 3, 5], 2).collect::<Vec<_>>();
+        assert_eq!(passages.len(), 1);
+        assert_eq!(passages[0].live_range, 1..4);
+    }
+
+    #[test]
+    fn unfinished_passage_at_start() {
+        let passages = wrong_passages(&[], &[0, 1], 1).collect::<Vec<_>>();
+        assert_eq!(
+            passages,
+            [WrongPassage {
+                live_range: 0..2,
+                score_start: 0,
+                score_end: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn passage_before_live_note() {
+        let matches = [Match::new(0, 0), Match::new(4, 4), Match::new(5, 6)];
+        let ignored = [1, 2, 3, 5];
+        assert_eq!(
+            wrong_passage_before(&matches, &ignored, 4, 2),
+            Some(WrongPassage {
+                live_range: 1..4,
+                score_start: 1,
+                score_end: Some(4),
+            })
+        );
+        assert_eq!(wrong_passage_before(&matches, &ignored, 6, 2), None);
+        assert_eq!(wrong_passage_before(&matches, &ignored, 5, 1), None);
+        assert_eq!(
+            wrong_passage_before(&matches, &ignored, 6, 1),
+            wrong_passages(&matches, &ignored, 1).nth(1)
+        );
+    }
+}
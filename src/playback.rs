@@ -0,0 +1,1749 @@
+use crate::follower::ScoreFollower;
+use crate::score::{note_off_key, note_on_key, pitch_to_name, ScoreEvent, ScoreNote};
+use crate::velocity::{apply_velocity_mode, VelocityMode};
+use midly::live::LiveEvent;
+use midly::num::{u15, u24, u28, u4, u7};
+use midly::MidiMessage::{
+    self, Aftertouch, ChannelAftertouch, Controller, NoteOff, NoteOn, PitchBend, ProgramChange,
+};
+use midly::{Format, Header, MetaMessage, Smf, Timing, TrackEvent, TrackEventKind};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Controller numbers for the most and least significant bytes of bank select
+const BANK_SELECT_CONTROLLERS: [u8; 2] = [0, 32];
+/// Controller number of the sustain pedal
+const SUSTAIN_CONTROLLER: u7 = u7::new(64);
+/// The lowest value of the sustain controller which holds the pedal down
+const SUSTAIN_DOWN: u7 = u7::new(64);
+/// Controller number of the all sound off channel mode message
+const ALL_SOUND_OFF_CONTROLLER: u8 = 120;
+/// Controller number of the all notes off channel mode message
+const ALL_NOTES_OFF_CONTROLLER: u8 = 123;
+/// The tempo of rendered MIDI files in microseconds per beat
+const RENDER_BEAT_LENGTH: u32 = 500_000;
+/// The resolution of rendered MIDI files, making each tick a millisecond
+const RENDER_TICKS_PER_BEAT: u16 = 500;
+/// The longest time in microseconds between estimating the times of playback events
+/// again while the tempo ramps
+const RAMP_STEP: u64 = 5000;
+
+/// How to order MIDI events which are sent out at the same moment
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlushOrder {
+    /// Keep the order in which the events appear in the playback score
+    AsLoaded,
+    /// Send note-offs first, then bank and program changes, then other channel messages
+    /// and finally note-ons, keeping the original order within each group
+    ///
+    /// Some synthesizers glitch if a note is started before a simultaneous note-off for
+    /// the same key, or before the program change meant for it.
+    OffsBeforeOns,
+}
+
+impl std::str::FromStr for FlushOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "as-loaded" => Ok(FlushOrder::AsLoaded),
+            "offs-before-ons" => Ok(FlushOrder::OffsBeforeOns),
+            _ => Err(format!("unknown flush order '{}'", s)),
+        }
+    }
+}
+
+/// Returns the rank of a MIDI message for [`FlushOrder::OffsBeforeOns`]
+fn flush_rank(message: MidiMessage) -> u8 {
+    match message {
+        NoteOff { .. } => 0,
+        NoteOn { .. } if note_on_key(message).is_none() => 0,
+        ProgramChange { .. } => 1,
+        Controller { controller, .. } if BANK_SELECT_CONTROLLERS.contains(&controller.as_int()) => {
+            1
+        }
+        NoteOn { .. } => 3,
+        _ => 2,
+    }
+}
+
+/// Sorts MIDI events which are to be sent out at the same moment
+///
+/// # Arguments
+///
+/// * events - The events to send, all with the same timestamp
+/// * order - The policy for ordering the events
+pub fn order_flush(events: &mut [ScoreEvent], order: FlushOrder) {
+    match order {
+        FlushOrder::AsLoaded => {}
+        FlushOrder::OffsBeforeOns => events.sort_by_key(|event| flush_rank(event.message)),
+    }
+}
+
+/// A class of MIDI channel messages which can be kept in or dropped from a playback
+/// score
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventClass {
+    /// Note-ons and note-offs
+    Notes,
+    /// Program changes, which assign instruments to channels
+    ProgramChange,
+    /// Control changes, including bank select and the sustain pedal
+    Controller,
+    PitchBend,
+    /// Polyphonic and channel aftertouch
+    Aftertouch,
+}
+
+impl EventClass {
+    /// All event classes, which a playback score keeps by default so that instrument
+    /// assignments and expression survive loading
+    pub const ALL: [EventClass; 5] = [
+        EventClass::Notes,
+        EventClass::ProgramChange,
+        EventClass::Controller,
+        EventClass::PitchBend,
+        EventClass::Aftertouch,
+    ];
+
+    /// Returns the class of a MIDI message
+    pub fn of(message: MidiMessage) -> Self {
+        match message {
+            NoteOn { .. } | NoteOff { .. } => EventClass::Notes,
+            ProgramChange { .. } => EventClass::ProgramChange,
+            Controller { .. } => EventClass::Controller,
+            PitchBend { .. } => EventClass::PitchBend,
+            Aftertouch { .. } | ChannelAftertouch { .. } => EventClass::Aftertouch,
+        }
+    }
+}
+
+impl std::str::FromStr for EventClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "notes" => Ok(EventClass::Notes),
+            "program" => Ok(EventClass::ProgramChange),
+            "controller" => Ok(EventClass::Controller),
+            "pitch-bend" => Ok(EventClass::PitchBend),
+            "aftertouch" => Ok(EventClass::Aftertouch),
+            _ => Err(format!("unknown event class '{}'", s)),
+        }
+    }
+}
+
+/// Drops the events of a playback score which don't belong to any of the given classes
+pub fn retain_event_classes(events: &mut Vec<ScoreEvent>, classes: &[EventClass]) {
+    events.retain(|event| classes.contains(&EventClass::of(event.message)));
+}
+
+/// A change in whether the accompaniment should be advancing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PauseEvent {
+    /// The performer has been silent for too long, so playback should stop
+    Pause,
+    /// The performer has played again after a pause, so playback should continue
+    Resume,
+}
+
+/// Pauses the accompaniment when no live notes arrive for a while
+///
+/// Playback is only paused while live notes are expected, i.e. after the performance
+/// has started and before the end of the score.
+#[derive(Clone, Debug)]
+pub struct PauseDetector {
+    /// The longest silence in microseconds before pausing
+    timeout: u64,
+    /// The live time of the latest live note, if any
+    last_note: Option<u64>,
+    paused: bool,
+}
+
+impl PauseDetector {
+    /// # Arguments
+    ///
+    /// * timeout - The longest silence in microseconds before pausing
+    pub fn new(timeout: u64) -> Self {
+        Self {
+            timeout,
+            last_note: None,
+            paused: false,
+        }
+    }
+
+    /// Records a new live note
+    ///
+    /// # Return value
+    ///
+    /// [`PauseEvent::Resume`] if playback was paused
+    pub fn note(&mut self, time: u64) -> Option<PauseEvent> {
+        self.last_note = Some(time);
+        if self.paused {
+            self.paused = false;
+            return Some(PauseEvent::Resume);
+        }
+        None
+    }
+
+    /// Checks for silence at the given live time
+    ///
+    /// # Arguments
+    ///
+    /// * now - The current live time in microseconds
+    /// * expecting - Whether the performer is expected to play more notes
+    ///
+    /// # Return value
+    ///
+    /// [`PauseEvent::Pause`] if the performer has just been silent for too long
+    pub fn tick(&mut self, now: u64, expecting: bool) -> Option<PauseEvent> {
+        let last_note = self.last_note?;
+        if self.paused || !expecting || now < last_note + self.timeout {
+            return None;
+        }
+        self.paused = true;
+        Some(PauseEvent::Pause)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Returns the live time when playback pauses unless another live note arrives, or
+    /// `None` before the first note and while paused
+    pub fn deadline(&self) -> Option<u64> {
+        match self.paused {
+            true => None,
+            false => self.last_note.map(|time| time + self.timeout),
+        }
+    }
+}
+
+/// Returns a sustain pedal message with the given controller value
+fn sustain(value: u8) -> MidiMessage {
+    Controller {
+        controller: SUSTAIN_CONTROLLER,
+        value: u7::from(value),
+    }
+}
+
+fn all_notes_off() -> MidiMessage {
+    Controller {
+        controller: u7::from(ALL_NOTES_OFF_CONTROLLER),
+        value: u7::from(0),
+    }
+}
+
+/// A factor for the note-on velocities of one output channel, for balancing the parts of
+/// an accompaniment, e.g. strings against piano, without editing the playback score
+///
+/// Parsed from `channel:gain` with the channel numbered from 1, e.g. `2:1.2`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChannelGain {
+    pub channel: u4,
+    pub gain: f64,
+}
+
+impl std::str::FromStr for ChannelGain {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid channel gain '{}'", s);
+        let (channel, gain) = s.split_once(':').ok_or_else(invalid)?;
+        let channel = channel
+            .parse::<u8>()
+            .ok()
+            .filter(|channel| (1..=16).contains(channel))
+            .ok_or_else(invalid)?;
+        let gain = gain
+            .parse::<f64>()
+            .ok()
+            .filter(|gain| *gain >= 0.0 && gain.is_finite())
+            .ok_or_else(invalid)?;
+        Ok(ChannelGain {
+            channel: u4::from(channel - 1),
+            gain,
+        })
+    }
+}
+
+/// Encodes an event of a playback score as the bytes of a MIDI message for sending to
+/// an output port
+///
+/// All channel messages are encoded, including controllers like the sustain pedal.
+///
+/// # Arguments
+///
+/// * event - The event to send
+/// * gains - Factors for the note-on velocities of channels, the last one given for a
+///   channel applying. Scaled velocities are kept between 1 and 127, so that no note-on
+///   turns into a note-off.
+pub fn encode_midi_event(event: &ScoreEvent, gains: &[ChannelGain]) -> Vec<u8> {
+    let gain = gains
+        .iter()
+        .rev()
+        .find(|gain| gain.channel == event.channel)
+        .map_or(1.0, |gain| gain.gain);
+    let message = match event.message {
+        NoteOn { key, vel } if vel > 0 => NoteOn {
+            key,
+            vel: u7::from((vel.as_int() as f64 * gain).round().clamp(1.0, 127.0) as u8),
+        },
+        message => message,
+    };
+    let mut bytes = vec![];
+    LiveEvent::Midi {
+        channel: event.channel,
+        message,
+    }
+    .write_std(&mut bytes)
+    .unwrap();
+    bytes
+}
+
+/// A change to the playback running in a thread, see [`spawn_playback`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlaybackCommand {
+    /// Follow a new position of the performance
+    Anchor(PlaybackAnchor),
+    /// Move to a new position of the performance at once without playing the events
+    /// skipped over, e.g. after the performer jumped in the score, see
+    /// [`PlaybackScheduler::seek`]
+    Seek(PlaybackAnchor),
+    /// Release all notes and the sustain pedal, silence the channels of the playback
+    /// score and wait, see [`PlaybackScheduler::stop`] and [`silence_events`]
+    Pause,
+    /// Continue from the next anchor, moving to it at once without a ramp, see
+    /// [`PlaybackScheduler::resume`]
+    Resume,
+    /// Silence all channels at once and pause, e.g. when a note hangs, see
+    /// [`panic_events`]
+    Panic,
+    /// Mute or solo a channel, see [`ChannelMix`]
+    Mix(MixCommand),
+    /// The velocity of the latest matched live note, see
+    /// [`PlaybackScheduler::set_live_velocity`]
+    LiveVelocity(u7),
+    /// Release all notes and the sustain pedal and end playback, e.g. on Ctrl-C
+    Stop,
+}
+
+/// A command for muting or soloing a playback channel during a performance
+///
+/// Parsed from `mute`, `unmute`, `solo` or `unsolo` followed by a channel numbered
+/// from 1, e.g. `mute 2`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MixCommand {
+    Mute(u4),
+    Unmute(u4),
+    Solo(u4),
+    Unsolo(u4),
+}
+
+impl std::str::FromStr for MixCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid mix command '{}'", s);
+        let (command, channel) = s.trim().split_once(' ').ok_or_else(invalid)?;
+        let channel = channel
+            .trim()
+            .parse::<u8>()
+            .ok()
+            .filter(|channel| (1..=16).contains(channel))
+            .map(|channel| u4::from(channel - 1))
+            .ok_or_else(invalid)?;
+        match command {
+            "mute" => Ok(MixCommand::Mute(channel)),
+            "unmute" => Ok(MixCommand::Unmute(channel)),
+            "solo" => Ok(MixCommand::Solo(channel)),
+            "unsolo" => Ok(MixCommand::Unsolo(channel)),
+            _ => Err(format!("unknown mix command '{}'", s)),
+        }
+    }
+}
+
+/// Which playback channels are heard, changed by [`MixCommand`]s during a performance
+///
+/// A channel is heard unless it is muted, or other channels are soloed and it isn't.
+/// The notes of channels which aren't heard are skipped, but their other messages are
+/// still sent, so that their instruments and controllers are right when they are heard
+/// again.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChannelMix {
+    muted: Vec<u4>,
+    soloed: Vec<u4>,
+}
+
+impl ChannelMix {
+    /// Tells whether the notes of a channel are played
+    pub fn is_audible(&self, channel: u4) -> bool {
+        !self.muted.contains(&channel) && (self.soloed.is_empty() || self.soloed.contains(&channel))
+    }
+
+    /// Applies a mix command
+    ///
+    /// # Return value
+    ///
+    /// The channels which were heard before the command but aren't anymore, for
+    /// silencing their sounding notes
+    pub fn apply(&mut self, command: MixCommand) -> Vec<u4> {
+        let audible = (0..16)
+            .map(u4::from)
+            .filter(|&channel| self.is_audible(channel))
+            .collect::<Vec<_>>();
+        let (list, channel, add) = match command {
+            MixCommand::Mute(channel) => (&mut self.muted, channel, true),
+            MixCommand::Unmute(channel) => (&mut self.muted, channel, false),
+            MixCommand::Solo(channel) => (&mut self.soloed, channel, true),
+            MixCommand::Unsolo(channel) => (&mut self.soloed, channel, false),
+        };
+        list.retain(|&listed| listed != channel);
+        if add {
+            list.push(channel);
+        }
+        audible
+            .into_iter()
+            .filter(|&channel| !self.is_audible(channel))
+            .collect()
+    }
+
+    /// Tells whether to send an event, i.e. whether it isn't a note-on of a channel
+    /// which isn't heard
+    pub fn passes(&self, event: &ScoreEvent) -> bool {
+        note_on_key(event.message).is_none() || self.is_audible(event.channel)
+    }
+}
+
+/// Plays back a score in a thread of its own, sending each event at its exact live time
+/// instead of when the next live note happens to arrive
+///
+/// Playback starts with the first anchor. It ends when all events have been sent, on
+/// [`PlaybackCommand::Stop`] or when the command sender is dropped, each of which first
+/// releases all notes and the pedal, see [`PlaybackScheduler::stop`].
+///
+/// # Arguments
+///
+/// * scheduler - The playback score to play
+/// * start - The moment when live time is zero
+/// * ramp - How playback converges to new anchors
+/// * send - Sends an event to the output, e.g. encoded by [`encode_midi_event`]
+///
+/// # Return value
+///
+/// The sender for commands to the playback and the handle of its thread
+pub fn spawn_playback(
+    mut scheduler: PlaybackScheduler,
+    start: Instant,
+    mut ramp: TempoRamp,
+    mut send: impl FnMut(ScoreEvent) + Send + 'static,
+) -> (Sender<PlaybackCommand>, JoinHandle<()>) {
+    let (tx, rx) = mpsc::channel();
+    let handle = thread::spawn(move || {
+        let now = || start.elapsed().as_micros() as u64;
+        let mut paused = false;
+        // paused until the next anchor, which the course of playback before the pause
+        // doesn't lead to
+        let mut resuming = false;
+        let mut mix = ChannelMix::default();
+        loop {
+            let now_time = now();
+            let anchor = ramp.anchor_at(now_time);
+            let next_time = match paused {
+                true => None,
+                false => next_wakeup(&scheduler, &ramp, now_time),
+            };
+            let command = match next_time {
+                Some(time) => rx.recv_timeout(Duration::from_micros(time.saturating_sub(now_time))),
+                // the score may leave the pedal down after the last note
+                None if anchor.is_some() && !paused => Ok(PlaybackCommand::Stop),
+                None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            let events = match command {
+                Ok(PlaybackCommand::Anchor(anchor)) if resuming => {
+                    (paused, resuming) = (false, false);
+                    ramp.jump(anchor);
+                    scheduler.resume(now())
+                }
+                Ok(PlaybackCommand::Anchor(anchor)) => {
+                    ramp.follow(anchor);
+                    continue;
+                }
+                Ok(PlaybackCommand::Seek(anchor)) => {
+                    // the course before the jump doesn't lead to the new position
+                    ramp.jump(anchor);
+                    scheduler.seek(anchor.score_time, now())
+                }
+                Ok(PlaybackCommand::Pause) => {
+                    paused = true;
+                    let now = now();
+                    let mut events = scheduler.stop(now);
+                    events.extend(silence_events(now, &scheduler.channels()));
+                    events
+                }
+                Ok(PlaybackCommand::Resume) => {
+                    resuming = paused;
+                    continue;
+                }
+                Ok(PlaybackCommand::Mix(command)) => silence_events(now(), &mix.apply(command)),
+                Ok(PlaybackCommand::LiveVelocity(velocity)) => {
+                    scheduler.set_live_velocity(velocity);
+                    continue;
+                }
+                Ok(PlaybackCommand::Panic) => {
+                    paused = true;
+                    // the panic messages end the notes and the pedal already
+                    scheduler.stop(now());
+                    panic_events(now())
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    // only reached with an anchor, since nothing is due before the first
+                    let now = now();
+                    let anchor = ramp.anchor_at(now).unwrap();
+                    scheduler.due(anchor.score_time, now, anchor.stretch_factor)
+                }
+                Ok(PlaybackCommand::Stop) | Err(RecvTimeoutError::Disconnected) => {
+                    scheduler.stop(now()).into_iter().for_each(&mut send);
+                    return;
+                }
+            };
+            events
+                .into_iter()
+                .filter(|event| mix.passes(event))
+                .for_each(&mut send);
+        }
+    });
+    (tx, handle)
+}
+
+/// Returns the live time to check for due events next, or `None` before the first anchor
+/// and after the last event
+fn next_wakeup(scheduler: &PlaybackScheduler, ramp: &TempoRamp, now: u64) -> Option<u64> {
+    let time = scheduler.next_time(&ramp.anchor_at(now)?)?;
+    // the course of playback bends while ramping, so the time of the next event is
+    // estimated again every step
+    Some(match ramp.is_ramping(now) {
+        true => time.min(now + RAMP_STEP),
+        false => time,
+    })
+}
+
+/// Follows a recorded performance, collecting the anchors for playing back along with it
+///
+/// # Arguments
+///
+/// * follower - The follower of the score of the performer
+/// * live - The notes of the performance in time order
+///
+/// # Return value
+///
+/// An anchor at each live note which got new matches
+pub fn follow_performance(
+    follower: &mut dyn ScoreFollower,
+    live: &[ScoreNote],
+) -> Vec<PlaybackAnchor> {
+    live.iter()
+        .filter_map(|&note| {
+            follower.push_live(note);
+            let result = follower.follow_score();
+            (!result.new_matches.is_empty()).then_some(PlaybackAnchor {
+                score_time: result.score_time,
+                live_time: note.time,
+                stretch_factor: result.stretch_factor,
+            })
+        })
+        .collect()
+}
+
+/// Plays back a score offline along anchors collected from a recorded performance, see
+/// [`follow_performance`]
+///
+/// Events are scheduled like in [`spawn_playback`], only on a simulated clock. After the
+/// last anchor, the rest of the score is played at the last tempo.
+///
+/// # Return value
+///
+/// The events with the live times they would have been sent at, in time order
+pub fn render_playback(
+    mut scheduler: PlaybackScheduler,
+    mut ramp: TempoRamp,
+    anchors: &[PlaybackAnchor],
+) -> Vec<ScoreEvent> {
+    let mut rendered = vec![];
+    let mut anchors = anchors.iter().peekable();
+    let mut now = match anchors.peek() {
+        Some(anchor) => anchor.live_time,
+        None => return rendered,
+    };
+    loop {
+        while let Some(anchor) = anchors.next_if(|anchor| anchor.live_time <= now) {
+            ramp.follow(*anchor);
+        }
+        if let Some(anchor) = ramp.anchor_at(now) {
+            rendered.extend(scheduler.due(anchor.score_time, now, anchor.stretch_factor));
+        }
+        let next_anchor = anchors.peek().map(|anchor| anchor.live_time);
+        now = match next_wakeup(&scheduler, &ramp, now)
+            .into_iter()
+            .chain(next_anchor)
+            .min()
+        {
+            // everything due by now has been sent, so the clock always advances
+            Some(next) => next.max(now + 1),
+            None => return rendered,
+        };
+    }
+}
+
+/// Converts playback events into the raw bytes of a single track MIDI file, e.g. for
+/// saving an accompaniment rendered with [`render_playback`]
+///
+/// The file has a constant tempo of 120 beats per minute and a tick of one millisecond,
+/// which the times of the events are rounded to.
+pub fn events_to_midi_data(events: &[ScoreEvent]) -> Vec<u8> {
+    let micros_per_tick = RENDER_BEAT_LENGTH / RENDER_TICKS_PER_BEAT as u32;
+    let mut track = vec![TrackEvent {
+        delta: u28::from(0),
+        kind: TrackEventKind::Meta(MetaMessage::Tempo(u24::from(RENDER_BEAT_LENGTH))),
+    }];
+    let mut tick = 0;
+    for event in events {
+        let event_tick = (event.time + micros_per_tick as u64 / 2) / micros_per_tick as u64;
+        track.push(TrackEvent {
+            delta: u28::from(event_tick.saturating_sub(tick) as u32),
+            kind: TrackEventKind::Midi {
+                channel: event.channel,
+                message: event.message,
+            },
+        });
+        tick = tick.max(event_tick);
+    }
+    track.push(TrackEvent {
+        delta: u28::from(0),
+        kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+    });
+    let smf = Smf {
+        header: Header::new(
+            Format::SingleTrack,
+            Timing::Metrical(u15::from(RENDER_TICKS_PER_BEAT)),
+        ),
+        tracks: vec![track],
+    };
+    let mut data = vec![];
+    smf.write_std(&mut data).unwrap();
+    data
+}
+
+/// Describes a playback event for printing instead of sending it, e.g.
+/// `channel 2 note-on C4 velocity 64`, with channels numbered from 1
+pub fn describe_event(event: &ScoreEvent) -> String {
+    let message = match event.message {
+        message if note_off_key(message).is_some() => {
+            format!("note-off {}", pitch_to_name(note_off_key(message).unwrap()))
+        }
+        NoteOn { key, vel } => format!("note-on {} velocity {}", pitch_to_name(key), vel),
+        ProgramChange { program } => format!("program {}", program),
+        Controller { controller, value } => format!("controller {} value {}", controller, value),
+        PitchBend { bend } => format!("pitch-bend {}", bend.as_int()),
+        Aftertouch { key, vel } => format!("aftertouch {} pressure {}", pitch_to_name(key), vel),
+        ChannelAftertouch { vel } => format!("channel aftertouch pressure {}", vel),
+        NoteOff { .. } => unreachable!(),
+    };
+    format!("channel {} {}", event.channel.as_int() + 1, message)
+}
+
+/// Returns the messages which silence the given channels when playback pauses
+///
+/// The sustain pedal is released before all notes are turned off, since notes held by
+/// the pedal would otherwise keep sounding on some synthesizers.
+pub fn silence_events(time: u64, channels: &[u4]) -> Vec<ScoreEvent> {
+    channels
+        .iter()
+        .flat_map(|&channel| {
+            [sustain(0), all_notes_off()].map(|message| ScoreEvent {
+                time,
+                channel,
+                message,
+            })
+        })
+        .collect()
+}
+
+/// Returns the messages which silence all 16 channels of an output immediately, for
+/// stopping stuck notes without knowing which channels are sounding
+///
+/// All sound off also cuts the release tails of the notes, and all notes off stops
+/// notes on synthesizers which ignore all sound off.
+pub fn panic_events(time: u64) -> Vec<ScoreEvent> {
+    let all_sound_off = Controller {
+        controller: u7::from(ALL_SOUND_OFF_CONTROLLER),
+        value: u7::from(0),
+    };
+    (0..16)
+        .flat_map(|channel| {
+            [sustain(0), all_sound_off, all_notes_off()].map(|message| ScoreEvent {
+                time,
+                channel: u4::from(channel),
+                message,
+            })
+        })
+        .collect()
+}
+
+/// The position of the performance which playback follows, updated after each match
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlaybackAnchor {
+    /// A score time reached by the performer
+    pub score_time: u64,
+    /// The live time in microseconds when the score time was reached
+    pub live_time: u64,
+    /// The ratio of live time to score time at the current tempo
+    pub stretch_factor: f32,
+}
+
+impl PlaybackAnchor {
+    /// Extrapolates the score time at a live time
+    pub fn score_time_at(&self, live_time: u64) -> u64 {
+        let elapsed = live_time.saturating_sub(self.live_time) as f64;
+        self.score_time + (elapsed / self.stretch_factor as f64) as u64
+    }
+
+    /// Extrapolates the live time when a score time is reached, which is the time of the
+    /// anchor for earlier score times
+    pub fn live_time_at(&self, score_time: u64) -> u64 {
+        let ahead = score_time.saturating_sub(self.score_time) as f64;
+        self.live_time + (ahead * self.stretch_factor as f64) as u64
+    }
+}
+
+/// Moves playback smoothly to each new anchor instead of jumping to its score time and
+/// tempo
+///
+/// Over the ramp time after a new anchor, the score time of playback blends from the
+/// course it was following into the course of the new anchor. The blend weight follows
+/// a smoothstep curve, so neither the score time nor the tempo of playback jump.
+#[derive(Clone, Debug)]
+pub struct TempoRamp {
+    /// The time in microseconds it takes to converge to a new anchor, or 0 for jumping
+    ramp_time: u64,
+    /// The course playback followed when the latest anchor arrived
+    from: Option<PlaybackAnchor>,
+    /// The latest anchor
+    to: Option<PlaybackAnchor>,
+}
+
+impl TempoRamp {
+    /// # Arguments
+    ///
+    /// * ramp_time - The time in microseconds it takes to converge to a new anchor
+    pub fn new(ramp_time: u64) -> Self {
+        Self {
+            ramp_time,
+            from: None,
+            to: None,
+        }
+    }
+
+    /// Starts converging to a new anchor from its live time on
+    pub fn follow(&mut self, anchor: PlaybackAnchor) {
+        self.from = self.anchor_at(anchor.live_time);
+        self.to = Some(anchor);
+    }
+
+    /// Moves to a new anchor at once, e.g. when playback resumes after a pause and the
+    /// course it was following is stale
+    pub fn jump(&mut self, anchor: PlaybackAnchor) {
+        self.from = None;
+        self.to = Some(anchor);
+    }
+
+    /// Returns the progress of the ramp to the latest anchor from 0 to 1 at a live time
+    fn progress(&self, live_time: u64) -> f64 {
+        let start = self.to.map_or(0, |to| to.live_time);
+        match self.ramp_time {
+            0 => 1.0,
+            ramp_time => (live_time.saturating_sub(start) as f64 / ramp_time as f64).min(1.0),
+        }
+    }
+
+    /// Tells whether playback is still converging to the latest anchor at a live time
+    pub fn is_ramping(&self, live_time: u64) -> bool {
+        self.from.is_some() && self.progress(live_time) < 1.0
+    }
+
+    /// Returns the course of playback at a live time as an anchor at that time, or
+    /// `None` before the first anchor
+    pub fn anchor_at(&self, live_time: u64) -> Option<PlaybackAnchor> {
+        let to = self.to?;
+        let from = match self.from {
+            Some(from) if self.is_ramping(live_time) => from,
+            _ => {
+                return Some(PlaybackAnchor {
+                    score_time: to.score_time_at(live_time),
+                    live_time,
+                    stretch_factor: to.stretch_factor,
+                })
+            }
+        };
+        // score times and their rates of change along both courses
+        let course = |anchor: PlaybackAnchor| {
+            let elapsed = live_time as f64 - anchor.live_time as f64;
+            let rate = 1.0 / anchor.stretch_factor as f64;
+            (anchor.score_time as f64 + elapsed * rate, rate)
+        };
+        let ((from_time, from_rate), (to_time, to_rate)) = (course(from), course(to));
+        let x = self.progress(live_time);
+        let weight = x * x * (3.0 - 2.0 * x);
+        let weight_rate = 6.0 * x * (1.0 - x) / self.ramp_time as f64;
+        let score_time = from_time + weight * (to_time - from_time);
+        let rate = from_rate + weight * (to_rate - from_rate) + weight_rate * (to_time - from_time);
+        Some(PlaybackAnchor {
+            score_time: score_time.max(0.0) as u64,
+            live_time,
+            // playback never runs backwards
+            stretch_factor: (1.0 / rate.max(f64::EPSILON)) as f32,
+        })
+    }
+}
+
+/// Schedules the events of a playback score at live times, following the tempo of the
+/// performer
+///
+/// Events are sent once the estimated score time of the performance reaches them. The
+/// release of a note, i.e. its note-off or note-on with zero velocity, is instead
+/// scheduled when the note starts, at a live time stretched from the written duration
+/// of the note by the tempo at that moment. This way notes ring for their full length
+/// even when the score time estimate jumps between live notes.
+#[derive(Clone, Debug)]
+pub struct PlaybackScheduler {
+    /// The events which aren't releases in score order, each note-on with the release
+    /// of its note
+    events: Vec<(ScoreEvent, Option<ScoreEvent>)>,
+    /// The index of the first event in `events` not sent yet
+    next: usize,
+    /// Releases of sounding notes at live times, in the order they were scheduled
+    releases: Vec<ScoreEvent>,
+    /// The channels with the sustain pedal held down by the events sent
+    pedal: Vec<u4>,
+    /// The channels whose sustain pedal was released by [`PlaybackScheduler::stop`]
+    stopped_pedal: Vec<u4>,
+    /// How to order events which are due at the same moment
+    flush_order: FlushOrder,
+    /// Where the velocities of the notes come from
+    velocity_mode: VelocityMode,
+    /// The velocity of the latest matched live note, if any
+    live_velocity: Option<u7>,
+}
+
+impl PlaybackScheduler {
+    /// # Arguments
+    ///
+    /// * events - The events of the playback score in time order. Overlapping notes of
+    ///   the same pitch on the same channel are released in the order they were started.
+    pub fn new(events: &[ScoreEvent]) -> Self {
+        let mut scheduled: Vec<(ScoreEvent, Option<ScoreEvent>)> = vec![];
+        // the indices in `scheduled` of the notes not released yet
+        let mut sounding: Vec<usize> = vec![];
+        for &event in events {
+            let key = match note_off_key(event.message) {
+                Some(key) => key,
+                None => {
+                    if note_on_key(event.message).is_some() {
+                        sounding.push(scheduled.len());
+                    }
+                    scheduled.push((event, None));
+                    continue;
+                }
+            };
+            let started = sounding.iter().position(|&index| {
+                let (start, _) = scheduled[index];
+                start.channel == event.channel && note_on_key(start.message) == Some(key)
+            });
+            match started {
+                Some(position) => {
+                    scheduled[sounding.remove(position)].1 = Some(event);
+                }
+                // a release without a note to end is sent like any other event
+                None => scheduled.push((event, None)),
+            }
+        }
+        Self {
+            events: scheduled,
+            next: 0,
+            releases: vec![],
+            pedal: vec![],
+            stopped_pedal: vec![],
+            flush_order: FlushOrder::OffsBeforeOns,
+            velocity_mode: VelocityMode::Score,
+            live_velocity: None,
+        }
+    }
+
+    /// Chooses the velocities of the notes by a velocity mode instead of keeping the
+    /// velocities of the playback score
+    pub fn with_velocity_mode(mut self, mode: VelocityMode) -> Self {
+        self.velocity_mode = mode;
+        self
+    }
+
+    /// Records the velocity of the latest matched live note for the velocity mode
+    pub fn set_live_velocity(&mut self, velocity: u7) {
+        self.live_velocity = Some(velocity);
+    }
+
+    /// Orders events due at the same moment by a policy other than the default
+    /// [`FlushOrder::OffsBeforeOns`]
+    pub fn with_flush_order(mut self, order: FlushOrder) -> Self {
+        self.flush_order = order;
+        self
+    }
+
+    /// Returns the events to send by now
+    ///
+    /// # Arguments
+    ///
+    /// * score_time - The estimated score time of the performance
+    /// * now - The current live time in microseconds
+    /// * stretch_factor - The ratio of live time to score time at the current tempo
+    ///
+    /// # Return value
+    ///
+    /// The events due by now in time order, with their times converted to live times.
+    /// Events reached by the score time are due now, and releases at the live times
+    /// they were scheduled for. Events at the same live time are ordered by the flush
+    /// order. Note-ons get their velocities by the velocity mode.
+    pub fn due(&mut self, score_time: u64, now: u64, stretch_factor: f32) -> Vec<ScoreEvent> {
+        let mut due = vec![];
+        while let Some(&(event, release)) = self.events.get(self.next) {
+            if event.time > score_time {
+                break;
+            }
+            self.next += 1;
+            due.push(ScoreEvent { time: now, ..event });
+            if let Controller { controller, value } = event.message {
+                if controller == SUSTAIN_CONTROLLER {
+                    self.pedal.retain(|&channel| channel != event.channel);
+                    if value >= SUSTAIN_DOWN {
+                        self.pedal.push(event.channel);
+                    }
+                }
+            }
+            if let Some(release) = release {
+                let duration = (release.time - event.time) as f64 * stretch_factor as f64;
+                self.releases.push(ScoreEvent {
+                    time: now + duration as u64,
+                    ..release
+                });
+            }
+        }
+        let (released, sounding) = self
+            .releases
+            .iter()
+            .partition::<Vec<_>, _>(|release| release.time <= now);
+        self.releases = sounding;
+        // releases of notes sent just now may also be due if their duration is zero
+        due.splice(0..0, released);
+        due.sort_by_key(|event| event.time);
+        apply_velocity_mode(&mut due, self.velocity_mode, self.live_velocity);
+        for batch in due.chunk_by_mut(|a, b| a.time == b.time) {
+            order_flush(batch, self.flush_order);
+        }
+        due
+    }
+
+    /// Releases all sounding notes and the sustain pedal, e.g. when playback pauses or
+    /// stops
+    ///
+    /// # Return value
+    ///
+    /// The releases of the notes followed by a pedal-up for each channel with the
+    /// pedal held down, all at the current live time
+    pub fn stop(&mut self, now: u64) -> Vec<ScoreEvent> {
+        self.stopped_pedal = std::mem::take(&mut self.pedal);
+        let mut events = self.release_notes(now);
+        events.extend(self.stopped_pedal.iter().map(|&channel| ScoreEvent {
+            time: now,
+            channel,
+            message: sustain(0),
+        }));
+        events
+    }
+
+    /// Moves playback to a new score time without sending the events skipped over, e.g.
+    /// after the performer jumped ahead in the score
+    ///
+    /// Moving backwards, e.g. when the performer starts over, plays the events from the
+    /// new score time on again.
+    ///
+    /// # Arguments
+    ///
+    /// * score_time - The score time to continue from
+    /// * now - The current live time in microseconds
+    ///
+    /// # Return value
+    ///
+    /// The releases of the notes still sounding, all at the current live time
+    pub fn seek(&mut self, score_time: u64, now: u64) -> Vec<ScoreEvent> {
+        self.next = self
+            .events
+            .partition_point(|(event, _)| event.time < score_time);
+        self.release_notes(now)
+    }
+
+    /// Releases all sounding notes at the current live time
+    fn release_notes(&mut self, now: u64) -> Vec<ScoreEvent> {
+        self.releases
+            .drain(..)
+            .map(|release| ScoreEvent {
+                time: now,
+                ..release
+            })
+            .collect()
+    }
+
+    /// Presses the sustain pedal again after [`PlaybackScheduler::stop`] on the
+    /// channels it was held down on, so that playback resumes as written
+    pub fn resume(&mut self, now: u64) -> Vec<ScoreEvent> {
+        self.pedal = std::mem::take(&mut self.stopped_pedal);
+        self.pedal
+            .iter()
+            .map(|&channel| ScoreEvent {
+                time: now,
+                channel,
+                message: sustain(127),
+            })
+            .collect()
+    }
+
+    /// Returns the live time when the next event is due, or `None` if all events have
+    /// been sent and all notes released
+    pub fn next_time(&self, anchor: &PlaybackAnchor) -> Option<u64> {
+        let release = self.releases.iter().map(|release| release.time).min();
+        let event = self
+            .events
+            .get(self.next)
+            .map(|(event, _)| anchor.live_time_at(event.time));
+        release.into_iter().chain(event).min()
+    }
+
+    /// Tells whether all events have been sent and all notes released
+    pub fn is_finished(&self) -> bool {
+        self.next == self.events.len() && self.releases.is_empty()
+    }
+
+    /// Returns the channels of the playback score in ascending order
+    pub fn channels(&self) -> Vec<u4> {
+        let mut channels = self
+            .events
+            .iter()
+            .map(|(event, _)| event.channel)
+            .collect::<Vec<_>>();
+        channels.sort();
+        channels.dedup();
+        channels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::follower::HomophonoPedantic;
+    use crate::score::load_channel_events;
+    use midly::MidiMessage::PitchBend;
+    use midly::PitchBend as Bend;
+    use rstest::rstest;
+    use std::sync::{Arc, Mutex};
+
+    fn event(channel: u8, message: MidiMessage) -> ScoreEvent {
+        ScoreEvent {
+            time: 0,
+            channel: u4::from(channel),
+            message,
+        }
+    }
+
+    fn note_on(channel: u8, key: u8, vel: u8) -> ScoreEvent {
+        event(
+            channel,
+            NoteOn {
+                key: u7::from(key),
+                vel: u7::from(vel),
+            },
+        )
+    }
+
+    fn controller(channel: u8, controller: u8) -> ScoreEvent {
+        event(
+            channel,
+            Controller {
+                controller: u7::from(controller),
+                value: u7::from(1),
+            },
+        )
+    }
+
+    fn program(channel: u8) -> ScoreEvent {
+        event(
+            channel,
+            ProgramChange {
+                program: u7::from(5),
+            },
+        )
+    }
+
+    #[test]
+    fn as_loaded_keeps_order() {
+        let mut events = [note_on(0, 60, 64), note_on(1, 60, 0), program(1)];
+        let expect = events;
+        order_flush(&mut events, FlushOrder::AsLoaded);
+        assert_eq!(events, expect);
+    }
+
+    #[test]
+    fn offs_before_ons() {
+        let bend = event(
+            1,
+            PitchBend {
+                bend: Bend::from_int(0),
+            },
+        );
+        let mut events = [
+            note_on(0, 60, 64),
+            controller(1, 7),
+            note_on(1, 62, 64),
+            program(1),
+            note_on(0, 60, 0),
+            controller(1, 0),
+            bend,
+            event(
+                1,
+                NoteOff {
+                    key: u7::from(62),
+                    vel: u7::from(0),
+                },
+            ),
+        ];
+        order_flush(&mut events, FlushOrder::OffsBeforeOns);
+        assert_eq!(
+            events,
+            [
+                note_on(0, 60, 0),
+                event(
+                    1,
+                    NoteOff {
+                        key: u7::from(62),
+                        vel: u7::from(0),
+                    },
+                ),
+                program(1),
+                controller(1, 0),
+                controller(1, 7),
+                bend,
+                note_on(0, 60, 64),
+                note_on(1, 62, 64),
+            ]
+        );
+    }
+
+    #[test]
+    fn retain_notes_and_programs() {
+        let mut events = vec![
+            program(0),
+            controller(0, 7),
+            note_on(0, 60, 64),
+            event(0, ChannelAftertouch { vel: u7::from(10) }),
+            note_on(0, 60, 0),
+        ];
+        retain_event_classes(&mut events, &[EventClass::Notes, EventClass::ProgramChange]);
+        assert_eq!(events, [program(0), note_on(0, 60, 64), note_on(0, 60, 0)]);
+    }
+
+    #[test]
+    fn parse_event_class() {
+        assert_eq!("pitch-bend".parse(), Ok(EventClass::PitchBend));
+        assert!("sysex".parse::<EventClass>().is_err());
+    }
+
+    #[test]
+    fn parse_flush_order() {
+        assert_eq!("as-loaded".parse(), Ok(FlushOrder::AsLoaded));
+        assert_eq!("offs-before-ons".parse(), Ok(FlushOrder::OffsBeforeOns));
+        assert!("random".parse::<FlushOrder>().is_err());
+    }
+
+    #[test]
+    fn pause_after_silence_and_resume() {
+        let mut detector = PauseDetector::new(1000);
+        assert_eq!(detector.tick(5000, true), None);
+        assert_eq!(detector.deadline(), None);
+        assert_eq!(detector.note(100), None);
+        assert_eq!(detector.deadline(), Some(1100));
+        assert_eq!(detector.tick(1099, true), None);
+        assert_eq!(detector.tick(1100, true), Some(PauseEvent::Pause));
+        assert_eq!(detector.tick(2000, true), None);
+        assert!(detector.is_paused());
+        assert_eq!(detector.deadline(), None);
+        assert_eq!(detector.note(2500), Some(PauseEvent::Resume));
+        assert!(!detector.is_paused());
+    }
+
+    #[test]
+    fn no_pause_when_nothing_expected() {
+        let mut detector = PauseDetector::new(1000);
+        detector.note(0);
+        assert_eq!(detector.tick(5000, false), None);
+        assert!(!detector.is_paused());
+    }
+
+    fn at(time: u64, event: ScoreEvent) -> ScoreEvent {
+        ScoreEvent { time, ..event }
+    }
+
+    #[test]
+    fn schedule_releases_at_stretched_times() {
+        let note_off = event(
+            0,
+            NoteOff {
+                key: u7::from(60),
+                vel: u7::from(40),
+            },
+        );
+        let mut scheduler = PlaybackScheduler::new(&[
+            at(0, note_on(0, 60, 64)),
+            at(500, note_on(0, 64, 64)),
+            at(1000, note_off),
+            at(1000, note_on(0, 64, 0)),
+        ]);
+        // at half the written tempo
+        assert_eq!(scheduler.due(0, 100, 2.0), [at(100, note_on(0, 60, 64))]);
+        assert_eq!(
+            scheduler.due(500, 1200, 1.0),
+            [at(1200, note_on(0, 64, 64))]
+        );
+        // a jump of the score time ends no notes early
+        assert_eq!(scheduler.due(3000, 1600, 1.0), []);
+        assert_eq!(
+            scheduler.due(3000, 1800, 1.0),
+            [at(1700, note_on(0, 64, 0))]
+        );
+        assert!(!scheduler.is_finished());
+        assert_eq!(scheduler.due(3000, 2500, 1.0), [at(2100, note_off)]);
+        assert!(scheduler.is_finished());
+    }
+
+    #[test]
+    fn schedule_release_without_note() {
+        let mut scheduler = PlaybackScheduler::new(&[program(1), at(500, note_on(1, 60, 0))]);
+        assert_eq!(
+            scheduler.due(500, 900, 1.0),
+            [at(900, note_on(1, 60, 0)), at(900, program(1))]
+        );
+    }
+
+    #[rstest]
+    #[case(FlushOrder::AsLoaded, [note_on(0, 60, 0), note_on(1, 64, 64), program(1)])]
+    #[case(FlushOrder::OffsBeforeOns, [note_on(0, 60, 0), program(1), note_on(1, 64, 64)])]
+    fn order_simultaneous_due_events(#[case] order: FlushOrder, #[case] expect: [ScoreEvent; 3]) {
+        let mut scheduler = PlaybackScheduler::new(&[
+            at(0, note_on(0, 60, 64)),
+            at(100, note_on(1, 64, 64)),
+            at(100, program(1)),
+            at(100, note_on(0, 60, 0)),
+        ])
+        .with_flush_order(order);
+        scheduler.due(0, 0, 1.0);
+        assert_eq!(
+            scheduler.due(100, 100, 1.0),
+            expect.map(|event| at(100, event))
+        );
+    }
+
+    fn pedal(channel: u8, value: u8) -> ScoreEvent {
+        event(
+            channel,
+            Controller {
+                controller: u7::from(64),
+                value: u7::from(value),
+            },
+        )
+    }
+
+    #[test]
+    fn follow_live_velocity() {
+        let mut scheduler = PlaybackScheduler::new(&[
+            note_on(0, 60, 100),
+            at(100, note_on(0, 60, 0)),
+            at(200, note_on(0, 62, 100)),
+        ])
+        .with_velocity_mode(VelocityMode::Scaled(0.5));
+        // the score velocity is kept before the first match
+        assert_eq!(scheduler.due(0, 0, 1.0), [note_on(0, 60, 100)]);
+        scheduler.set_live_velocity(u7::from(40));
+        assert_eq!(
+            scheduler.due(200, 200, 1.0),
+            [at(100, note_on(0, 60, 0)), at(200, note_on(0, 62, 70))]
+        );
+    }
+
+    #[test]
+    fn release_pedal_on_stop() {
+        let mut scheduler = PlaybackScheduler::new(&[
+            pedal(0, 127),
+            pedal(1, 100),
+            note_on(0, 60, 64),
+            pedal(1, 0),
+            at(1000, note_on(0, 60, 0)),
+        ]);
+        assert_eq!(scheduler.due(0, 0, 1.0).len(), 4);
+        assert_eq!(
+            scheduler.stop(500),
+            [at(500, note_on(0, 60, 0)), at(500, pedal(0, 0))]
+        );
+        assert_eq!(scheduler.due(1000, 1500, 1.0), []);
+        assert_eq!(scheduler.resume(2000), [at(2000, pedal(0, 127))]);
+        assert_eq!(scheduler.stop(2500), [at(2500, pedal(0, 0))]);
+        assert!(scheduler.is_finished());
+    }
+
+    #[test]
+    fn seek_forward_without_skipped_events() {
+        let mut scheduler = PlaybackScheduler::new(&[
+            note_on(0, 60, 64),
+            at(1000, note_on(0, 62, 64)),
+            at(2000, note_on(0, 64, 64)),
+            at(3000, note_on(0, 65, 64)),
+            at(4000, note_on(0, 67, 64)),
+            at(5000, note_on(0, 60, 0)),
+        ]);
+        assert_eq!(scheduler.due(0, 0, 1.0), [note_on(0, 60, 64)]);
+        assert_eq!(scheduler.seek(3500, 100), [at(100, note_on(0, 60, 0))]);
+        assert_eq!(scheduler.due(3500, 200, 1.0), []);
+        assert_eq!(scheduler.due(4000, 700, 1.0), [at(700, note_on(0, 67, 64))]);
+        assert!(scheduler.is_finished());
+    }
+
+    #[test]
+    fn seek_back_to_restart() {
+        let mut scheduler = PlaybackScheduler::new(&[
+            pedal(0, 127),
+            note_on(0, 60, 64),
+            at(1000, note_on(0, 62, 64)),
+            at(1500, note_on(0, 62, 0)),
+            at(3000, note_on(0, 60, 0)),
+        ]);
+        assert_eq!(scheduler.due(1000, 1000, 1.0).len(), 3);
+        assert_eq!(
+            scheduler.seek(0, 1200),
+            [at(1200, note_on(0, 60, 0)), at(1200, note_on(0, 62, 0))]
+        );
+        assert_eq!(
+            scheduler.due(0, 1300, 1.0),
+            [at(1300, pedal(0, 127)), at(1300, note_on(0, 60, 64))]
+        );
+        assert!(!scheduler.is_finished());
+    }
+
+    #[test]
+    fn encode_sustain_pedal() {
+        assert_eq!(encode_midi_event(&pedal(1, 127), &[]), [0xB1, 64, 127]);
+    }
+
+    #[test]
+    fn encode_with_channel_gains() {
+        let gains = ["1:0.5", "2:1.5", "2:2"].map(|gain| gain.parse().unwrap());
+        assert_eq!(
+            encode_midi_event(&note_on(0, 60, 80), &gains),
+            [0x90, 60, 40]
+        );
+        assert_eq!(
+            encode_midi_event(&note_on(1, 60, 80), &gains),
+            [0x91, 60, 127]
+        );
+        assert_eq!(
+            encode_midi_event(&note_on(2, 60, 80), &gains),
+            [0x92, 60, 80]
+        );
+        // releases keep their zero velocity, and quiet notes don't turn into releases
+        assert_eq!(encode_midi_event(&note_on(1, 60, 0), &gains), [0x91, 60, 0]);
+        let silent = [ChannelGain {
+            channel: u4::from(0),
+            gain: 0.0,
+        }];
+        assert_eq!(
+            encode_midi_event(&note_on(0, 60, 80), &silent),
+            [0x90, 60, 1]
+        );
+    }
+
+    #[rstest(spec, case("0:1"), case("17:1"), case("1:-1"), case("1"), case("1:x"))]
+    fn parse_invalid_channel_gain(spec: &str) {
+        assert!(spec.parse::<ChannelGain>().is_err());
+    }
+
+    #[test]
+    fn extrapolate_anchor() {
+        let anchor = PlaybackAnchor {
+            score_time: 1000,
+            live_time: 5000,
+            stretch_factor: 2.0,
+        };
+        assert_eq!(anchor.score_time_at(7000), 2000);
+        assert_eq!(anchor.live_time_at(1500), 6000);
+        assert_eq!(anchor.live_time_at(500), 5000);
+    }
+
+    #[test]
+    fn ramp_to_new_anchor() {
+        let mut ramp = TempoRamp::new(1000);
+        assert_eq!(ramp.anchor_at(0), None);
+        let anchor = |score_time, live_time, stretch_factor| PlaybackAnchor {
+            score_time,
+            live_time,
+            stretch_factor,
+        };
+        // the first anchor applies at once
+        ramp.follow(anchor(0, 0, 1.0));
+        assert!(!ramp.is_ramping(500));
+        assert_eq!(ramp.anchor_at(500), Some(anchor(500, 500, 1.0)));
+        // the performer is ahead and twice as fast
+        ramp.follow(anchor(1200, 1000, 0.5));
+        assert!(ramp.is_ramping(1000));
+        assert_eq!(ramp.anchor_at(1000), Some(anchor(1000, 1000, 1.0)));
+        let halfway = ramp.anchor_at(1500).unwrap();
+        assert_eq!(halfway.score_time, 1850);
+        assert!(halfway.stretch_factor < 0.5);
+        assert!(!ramp.is_ramping(2000));
+        assert_eq!(ramp.anchor_at(2000), Some(anchor(3200, 2000, 0.5)));
+    }
+
+    #[test]
+    fn next_event_time() {
+        let mut scheduler = PlaybackScheduler::new(&[
+            note_on(0, 60, 64),
+            at(4000, note_on(0, 60, 0)),
+            at(1000, program(0)),
+        ]);
+        let anchor = PlaybackAnchor {
+            score_time: 0,
+            live_time: 100,
+            stretch_factor: 2.0,
+        };
+        assert_eq!(scheduler.next_time(&anchor), Some(100));
+        scheduler.due(0, 100, 2.0);
+        assert_eq!(scheduler.next_time(&anchor), Some(2100));
+        scheduler.due(1000, 2100, 2.0);
+        assert_eq!(scheduler.next_time(&anchor), Some(8100));
+        scheduler.due(1000, 8100, 2.0);
+        assert_eq!(scheduler.next_time(&anchor), None);
+    }
+
+    #[test]
+    fn play_in_thread() {
+        let sent = Arc::new(Mutex::new(vec![]));
+        let shared = Arc::clone(&sent);
+        let scheduler = PlaybackScheduler::new(&[
+            note_on(0, 60, 64),
+            at(2000, note_on(0, 62, 64)),
+            at(3000, note_on(0, 60, 0)),
+            at(4000, note_on(0, 62, 0)),
+        ]);
+        let start = Instant::now();
+        let (tx, handle) = spawn_playback(scheduler, start, TempoRamp::new(0), move |event| {
+            shared.lock().unwrap().push(event)
+        });
+        tx.send(PlaybackCommand::Anchor(PlaybackAnchor {
+            score_time: 0,
+            live_time: 0,
+            stretch_factor: 1.0,
+        }))
+        .unwrap();
+        handle.join().unwrap();
+        let sent = sent.lock().unwrap();
+        let expected = [
+            note_on(0, 60, 64),
+            note_on(0, 62, 64),
+            note_on(0, 60, 0),
+            note_on(0, 62, 0),
+        ];
+        assert_eq!(
+            sent.iter().map(|event| event.message).collect::<Vec<_>>(),
+            expected.map(|event| event.message)
+        );
+        // no event is sent before its time
+        for (event, time) in sent.iter().zip([0, 2000, 3000, 4000]) {
+            assert!(event.time >= time);
+        }
+    }
+
+    #[test]
+    fn release_pedal_at_end_of_playback() {
+        let sent = Arc::new(Mutex::new(vec![]));
+        let shared = Arc::clone(&sent);
+        let scheduler = PlaybackScheduler::new(&[
+            pedal(0, 127),
+            note_on(0, 60, 64),
+            at(1000, note_on(0, 60, 0)),
+        ]);
+        let (tx, handle) =
+            spawn_playback(scheduler, Instant::now(), TempoRamp::new(0), move |event| {
+                shared.lock().unwrap().push(event.message)
+            });
+        tx.send(PlaybackCommand::Anchor(PlaybackAnchor {
+            score_time: 0,
+            live_time: 0,
+            stretch_factor: 1.0,
+        }))
+        .unwrap();
+        handle.join().unwrap();
+        assert_eq!(sent.lock().unwrap().last(), Some(&pedal(0, 0).message));
+    }
+
+    #[test]
+    fn stop_releases_notes_and_pedal() {
+        let sent = Arc::new(Mutex::new(vec![]));
+        let shared = Arc::clone(&sent);
+        let scheduler = PlaybackScheduler::new(&[
+            pedal(1, 127),
+            note_on(1, 60, 64),
+            at(1000000, note_on(1, 60, 0)),
+        ]);
+        let (tx, handle) =
+            spawn_playback(scheduler, Instant::now(), TempoRamp::new(0), move |event| {
+                shared.lock().unwrap().push(event.message)
+            });
+        tx.send(PlaybackCommand::Anchor(PlaybackAnchor {
+            score_time: 0,
+            live_time: 0,
+            stretch_factor: 1.0,
+        }))
+        .unwrap();
+        thread::sleep(Duration::from_millis(10));
+        tx.send(PlaybackCommand::Stop).unwrap();
+        handle.join().unwrap();
+        assert_eq!(
+            *sent.lock().unwrap(),
+            [
+                pedal(1, 127).message,
+                note_on(1, 60, 64).message,
+                note_on(1, 60, 0).message,
+                pedal(1, 0).message
+            ]
+        );
+    }
+
+    #[test]
+    fn pause_and_resume_at_next_anchor() {
+        let sent = Arc::new(Mutex::new(vec![]));
+        let shared = Arc::clone(&sent);
+        let scheduler = PlaybackScheduler::new(&[
+            at(20000, note_on(1, 62, 64)),
+            at(10000000, note_on(1, 62, 0)),
+        ]);
+        let start = Instant::now();
+        let (tx, handle) = spawn_playback(scheduler, start, TempoRamp::new(0), move |event| {
+            shared.lock().unwrap().push(event.message)
+        });
+        let anchor = |score_time| {
+            PlaybackCommand::Anchor(PlaybackAnchor {
+                score_time,
+                live_time: start.elapsed().as_micros() as u64,
+                stretch_factor: 1.0,
+            })
+        };
+        tx.send(anchor(0)).unwrap();
+        tx.send(PlaybackCommand::Pause).unwrap();
+        let silence = silence_events(0, &[u4::from(1)])
+            .iter()
+            .map(|event| event.message)
+            .collect::<Vec<_>>();
+        thread::sleep(Duration::from_millis(40));
+        assert_eq!(*sent.lock().unwrap(), silence);
+        // the stale course of playback would have sent the note by now
+        tx.send(PlaybackCommand::Resume).unwrap();
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(*sent.lock().unwrap(), silence);
+        tx.send(anchor(20000)).unwrap();
+        thread::sleep(Duration::from_millis(20));
+        drop(tx);
+        handle.join().unwrap();
+        assert_eq!(
+            sent.lock().unwrap()[silence.len()..],
+            [note_on(1, 62, 64).message, note_on(1, 62, 0).message]
+        );
+    }
+
+    #[test]
+    fn panic_and_pause() {
+        let sent = Arc::new(Mutex::new(vec![]));
+        let shared = Arc::clone(&sent);
+        let scheduler = PlaybackScheduler::new(&[at(1000000, note_on(0, 60, 64))]);
+        let (tx, handle) =
+            spawn_playback(scheduler, Instant::now(), TempoRamp::new(0), move |event| {
+                shared.lock().unwrap().push(event.message)
+            });
+        tx.send(PlaybackCommand::Anchor(PlaybackAnchor {
+            score_time: 0,
+            live_time: 0,
+            stretch_factor: 1.0,
+        }))
+        .unwrap();
+        tx.send(PlaybackCommand::Panic).unwrap();
+        drop(tx);
+        handle.join().unwrap();
+        assert_eq!(
+            *sent.lock().unwrap(),
+            panic_events(0)
+                .iter()
+                .map(|event| event.message)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn panic_on_all_channels() {
+        let events = panic_events(10);
+        assert_eq!(events.len(), 48);
+        assert_eq!(
+            events[47],
+            ScoreEvent {
+                time: 10,
+                channel: u4::from(15),
+                message: Controller {
+                    controller: u7::from(123),
+                    value: u7::from(0),
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn mute_and_solo() {
+        let channel = u4::from;
+        let mut mix = ChannelMix::default();
+        assert_eq!(mix.apply("mute 2".parse().unwrap()), [channel(1)]);
+        assert!(!mix.passes(&note_on(1, 60, 64)));
+        assert!(mix.passes(&note_on(1, 60, 0)));
+        assert!(mix.passes(&program(1)));
+        // soloing silences all other channels still heard
+        let silenced = mix.apply(MixCommand::Solo(channel(2)));
+        assert_eq!(silenced.len(), 14);
+        assert!(!silenced.contains(&channel(1)));
+        assert!(mix.is_audible(channel(2)));
+        assert!(mix.apply(MixCommand::Solo(channel(1))).is_empty());
+        // a muted channel stays silent while soloed
+        assert!(!mix.is_audible(channel(1)));
+        mix.apply(MixCommand::Unmute(channel(1)));
+        assert!(mix.is_audible(channel(1)));
+        mix.apply(MixCommand::Unsolo(channel(1)));
+        mix.apply(MixCommand::Unsolo(channel(2)));
+        assert!(mix.is_audible(channel(0)));
+    }
+
+    #[rstest(spec, case("mute"), case("mute 0"), case("mute 17"), case("loud 1"))]
+    fn parse_invalid_mix_command(spec: &str) {
+        assert!(spec.parse::<MixCommand>().is_err());
+    }
+
+    #[test]
+    fn render_along_anchors() {
+        let scheduler = PlaybackScheduler::new(&[
+            note_on(0, 60, 64),
+            at(1000, note_on(0, 60, 0)),
+            at(2000, note_on(0, 62, 64)),
+            at(3000, note_on(0, 62, 0)),
+        ]);
+        let anchors = [
+            PlaybackAnchor {
+                score_time: 0,
+                live_time: 10000,
+                stretch_factor: 1.0,
+            },
+            // the performer slows down to half the tempo
+            PlaybackAnchor {
+                score_time: 1000,
+                live_time: 12000,
+                stretch_factor: 2.0,
+            },
+        ];
+        assert_eq!(
+            render_playback(scheduler, TempoRamp::new(0), &anchors),
+            [
+                at(10000, note_on(0, 60, 64)),
+                at(11000, note_on(0, 60, 0)),
+                at(14000, note_on(0, 62, 64)),
+                at(16000, note_on(0, 62, 0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn nothing_rendered_without_anchors() {
+        let scheduler = PlaybackScheduler::new(&[note_on(0, 60, 64)]);
+        assert!(render_playback(scheduler, TempoRamp::new(0), &[]).is_empty());
+    }
+
+    #[test]
+    fn anchors_of_performance() {
+        let score = notes![(0, 60), (1000, 62), (2000, 64)];
+        let live = notes![(500, 60), (2500, 62), (4500, 64)];
+        let mut follower = HomophonoPedantic::new(&score);
+        let anchors = follow_performance(&mut follower, &live);
+        assert_eq!(
+            anchors
+                .iter()
+                .map(|anchor| (anchor.score_time, anchor.live_time))
+                .collect::<Vec<_>>(),
+            [(0, 500), (1000, 2500), (2000, 4500)]
+        );
+        assert_eq!(anchors[2].stretch_factor, 2.0);
+    }
+
+    #[test]
+    fn save_rendered_events() {
+        let events = [at(10000, note_on(0, 60, 64)), at(11600, note_on(0, 60, 0))];
+        let data = events_to_midi_data(&events);
+        // times are rounded to milliseconds
+        assert_eq!(
+            load_channel_events(&data, &[(0, &[u4::from(0)])]).unwrap(),
+            [at(10000, note_on(0, 60, 64)), at(12000, note_on(0, 60, 0))]
+        );
+    }
+
+    #[test]
+    fn describe_events() {
+        let descriptions = [
+            note_on(1, 60, 64),
+            note_on(1, 60, 0),
+            controller(0, 64),
+            program(15),
+        ]
+        .map(|event| describe_event(&event));
+        assert_eq!(
+            descriptions,
+            [
+                format!(
+                    "channel 2 note-on {} velocity 64",
+                    pitch_to_name(u7::from(60))
+                ),
+                format!("channel 2 note-off {}", pitch_to_name(u7::from(60))),
+                "channel 1 controller 64 value 1".to_string(),
+                "channel 16 program 5".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn silence_channels() {
+        let off = |controller| {
+            event(
+                1,
+                Controller {
+                    controller: u7::from(controller),
+                    value: u7::from(0),
+                },
+            )
+        };
+        assert_eq!(silence_events(0, &[u4::from(1)]), [off(64), off(123)]);
+    }
+}
@@ -0,0 +1,318 @@
+use crate::passage::{wrong_passages, WrongPassage};
+use crate::score::ScoreNote;
+use crate::Match;
+use std::fmt;
+
+/// How well one section of the score was matched
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SectionReport {
+    /// The score time where the section starts, in microseconds
+    pub start_time: u64,
+    /// The number of score notes in the section
+    pub score_notes: usize,
+    /// The number of score notes in the section matched with a live note
+    pub matched: usize,
+}
+
+impl SectionReport {
+    /// The ratio of matched score notes in the section, between 0.0 and 1.0
+    pub fn match_rate(&self) -> f64 {
+        if self.score_notes == 0 {
+            return 1.0;
+        }
+        self.matched as f64 / self.score_notes as f64
+    }
+}
+
+/// A tempo change between two consecutive matched notes
+#[derive(Clone, Debug, PartialEq)]
+pub struct TempoDeviation {
+    /// Index of the later score note of the two
+    pub score_index: usize,
+    /// Live time elapsed per score time between the two notes
+    pub stretch_factor: f64,
+}
+
+/// A structured summary of a session for locating rehearsal problems in the score
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionReport {
+    /// The match rates of consecutive sections of the score, in score order
+    pub sections: Vec<SectionReport>,
+    /// The median stretch factor between consecutive matched notes, or `None` if there
+    /// are fewer than two matched notes
+    pub median_stretch_factor: Option<f64>,
+    /// The tempo changes deviating most from the median, the largest first
+    pub tempo_deviations: Vec<TempoDeviation>,
+    /// The longest run of ignored live notes, if any
+    pub longest_wrong_passage: Option<WrongPassage>,
+}
+
+/// Produces a report of a session from the matches found by a follower
+///
+/// # Arguments
+///
+/// * score - The complete expected musical score with timestamps and pitches
+/// * live - The complete live performance
+/// * matches - All matches between the live performance and the score, in live order
+/// * ignored - Indices of all ignored live notes, in ascending order
+/// * section_length - The length of each reported section of the score in microseconds
+/// * max_deviations - The number of largest tempo deviations to report
+pub fn session_report(
+    score: &[ScoreNote],
+    live: &[ScoreNote],
+    matches: &[Match],
+    ignored: &[usize],
+    section_length: u64,
+    max_deviations: usize,
+) -> SessionReport {
+    let section_length = section_length.max(1);
+    let section_count = score
+        .last()
+        .map_or(0, |last| (last.time / section_length + 1) as usize);
+    let mut sections = (0..section_count)
+        .map(|section| SectionReport {
+            start_time: section as u64 * section_length,
+            score_notes: 0,
+            matched: 0,
+        })
+        .collect::<Vec<_>>();
+    let mut is_matched = vec![false; score.len()];
+    for m in matches {
+        is_matched[m.score_index] = true;
+    }
+    for (note, matched) in score.iter().zip(is_matched) {
+        let section = &mut sections[(note.time / section_length) as usize];
+        section.score_notes += 1;
+        section.matched += matched as usize;
+    }
+
+    let mut deviations = matches
+        .windows(2)
+        .filter_map(|pair| {
+            let elapsed_score =
+                score[pair[1].score_index].time as f64 - score[pair[0].score_index].time as f64;
+            // notes of a chord tell nothing about the tempo
+            if elapsed_score <= 0.0 {
+                return None;
+            }
+            let elapsed_live =
+                live[pair[1].live_index].time as f64 - live[pair[0].live_index].time as f64;
+            Some(TempoDeviation {
+                score_index: pair[1].score_index,
+                stretch_factor: elapsed_live / elapsed_score,
+            })
+        })
+        .collect::<Vec<_>>();
+    let median_stretch_factor = if deviations.is_empty() {
+        None
+    } else {
+        let mut factors = deviations
+            .iter()
+            .map(|d| d.stretch_factor)
+            .collect::<Vec<_>>();
+        factors.sort_by(f64::total_cmp);
+        Some(factors[factors.len() / 2])
+    };
+    if let Some(median) = median_stretch_factor {
+        // slowing down to half the tempo deviates as much as speeding up to double
+        let deviation = |d: &TempoDeviation| (d.stretch_factor / median).ln().abs();
+        deviations.sort_by(|a, b| deviation(b).total_cmp(&deviation(a)));
+    }
+    deviations.truncate(max_deviations);
+
+    // of equally long passages, the first one is reported
+    let longest_wrong_passage = wrong_passages(matches, ignored, 1).reduce(|longest, passage| {
+        if passage.wrong_notes() > longest.wrong_notes() {
+            passage
+        } else {
+            longest
+        }
+    });
+    SessionReport {
+        sections,
+        median_stretch_factor,
+        tempo_deviations: deviations,
+        longest_wrong_passage,
+    }
+}
+
+impl SessionReport {
+    /// Renders the report as a JSON object
+    pub fn to_json(&self) -> String {
+        let sections = self
+            .sections
+            .iter()
+            .map(|section| {
+                format!(
+                    "{{\"start_time\":{},\"score_notes\":{},\"matched\":{},\"match_rate\":{:.3}}}",
+                    section.start_time,
+                    section.score_notes,
+                    section.matched,
+                    section.match_rate()
+                )
+            })
+            .collect::<Vec<_>>();
+        let deviations = self
+            .tempo_deviations
+            .iter()
+            .map(|deviation| {
+                format!(
+                    "{{\"score_index\":{},\"stretch_factor\":{:.3}}}",
+                    deviation.score_index, deviation.stretch_factor
+                )
+            })
+            .collect::<Vec<_>>();
+        let passage = self
+            .longest_wrong_passage
+            .as_ref()
+            .map_or("null".to_string(), |passage| {
+                format!(
+                    "{{\"live_start\":{},\"wrong_notes\":{},\"score_start\":{},\"score_end\":{}}}",
+                    passage.live_range.start,
+                    passage.wrong_notes(),
+                    passage.score_start,
+                    passage
+                        .score_end
+                        .map_or("null".to_string(), |end| end.to_string())
+                )
+            });
+        format!(
+            "{{\"sections\":[{}],\"median_stretch_factor\":{},\"tempo_deviations\":[{}],\"longest_wrong_passage\":{}}}",
+            sections.join(","),
+            self.median_stretch_factor
+                .map_or("null".to_string(), |factor| format!("{:.3}", factor)),
+            deviations.join(","),
+            passage
+        )
+    }
+}
+
+impl fmt::Display for SessionReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for section in &self.sections {
+            writeln!(
+                f,
+                "section at {:>7.3}: {:>3}/{:>3} score notes matched ({:.0}%)",
+                section.start_time as f64 / 1000000.0,
+                section.matched,
+                section.score_notes,
+                100.0 * section.match_rate()
+            )?;
+        }
+        for deviation in &self.tempo_deviations {
+            writeln!(
+                f,
+                "tempo {:.0}% at score {}",
+                100.0 / deviation.stretch_factor,
+                deviation.score_index
+            )?;
+        }
+        match &self.longest_wrong_passage {
+            Some(passage) => write!(
+                f,
+                "longest wrong passage: {} notes at score {}",
+                passage.wrong_notes(),
+                passage.score_start
+            ),
+            None => write!(f, "no wrong notes"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn report() -> SessionReport {
+        let score = notes![(0, 60), (100, 62), (200, 64), (300, 65), (400, 67)];
+        let live = notes![
+            (0, 60),
+            (100, 62),
+            (150, 70),
+            (160, 71),
+            (400, 64),
+            (500, 65),
+            (600, 66)
+        ];
+        let matches = [
+            Match::new(0, 0),
+            Match::new(1, 1),
+            Match::new(2, 4),
+            Match::new(3, 5),
+        ];
+        session_report(&score, &live, &matches, &[2, 3, 6], 200, 1)
+    }
+
+    #[test]
+    fn sections() {
+        let report = report();
+        assert_eq!(
+            report.sections,
+            [
+                SectionReport {
+                    start_time: 0,
+                    score_notes: 2,
+                    matched: 2
+                },
+                SectionReport {
+                    start_time: 200,
+                    score_notes: 2,
+                    matched: 2
+                },
+                SectionReport {
+                    start_time: 400,
+                    score_notes: 1,
+                    matched: 0
+                },
+            ]
+        );
+        assert_approx_eq!(report.sections[2].match_rate(), 0.0);
+    }
+
+    #[test]
+    fn largest_tempo_deviation() {
+        let report = report();
+        // stretch factors 1.0, 3.0 and 1.0
+        assert_eq!(report.median_stretch_factor, Some(1.0));
+        assert_eq!(
+            report.tempo_deviations,
+            [TempoDeviation {
+                score_index: 2,
+                stretch_factor: 3.0
+            }]
+        );
+    }
+
+    #[test]
+    fn longest_wrong_passage() {
+        let passage = report().longest_wrong_passage.unwrap();
+        assert_eq!(passage.live_range, 2..4);
+        assert_eq!(passage.score_end, Some(2));
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(
+            report().to_json(),
+            concat!(
+                "{\"sections\":[",
+                "{\"start_time\":0,\"score_notes\":2,\"matched\":2,\"match_rate\":1.000},",
+                "{\"start_time\":200,\"score_notes\":2,\"matched\":2,\"match_rate\":1.000},",
+                "{\"start_time\":400,\"score_notes\":1,\"matched\":0,\"match_rate\":0.000}],",
+                "\"median_stretch_factor\":1.000,",
+                "\"tempo_deviations\":[{\"score_index\":2,\"stretch_factor\":3.000}],",
+                "\"longest_wrong_passage\":{\"live_start\":2,\"wrong_notes\":2,\"score_start\":2,\"score_end\":2}}"
+            )
+        );
+    }
+
+    #[test]
+    fn empty_session() {
+        let report = session_report(&notes![(0, 60)], &[], &[], &[], 1000, 3);
+        assert_eq!(report.median_stretch_factor, None);
+        assert!(report.tempo_deviations.is_empty());
+        assert_eq!(report.longest_wrong_passage, None);
+        assert_eq!(report.to_string().lines().last(), Some("no wrong notes"));
+    }
+}
@@ -1,24 +1,118 @@
-use midi_reader_writer::{midly_0_5::merge_tracks, ConvertTicksToMicroseconds};
+use crate::abc::{is_abc_file, load_abc_file, AbcError, GraceNotes};
+use crate::tempo::TempoMap;
+use itertools::{Either, Itertools};
+use midi_reader_writer::{
+    midly_0_5::merge_tracks, ConvertTicksToMicroseconds, TimeConversionError,
+};
 use midly::{
     num::{u4, u7},
-    MidiMessage::NoteOn,
-    TrackEventKind::Midi,
+    Format, MetaMessage,
+    MidiMessage::{self, NoteOff, NoteOn},
+    Smf, TrackEventKind,
+    TrackEventKind::{Meta, Midi},
 };
 use once_cell::sync::Lazy;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+use std::ops::{Range, RangeInclusive};
 use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
 
 /// A note with a given pitch at a given timestamp in a score or in a live performance
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ScoreNote {
     pub time: u64,
+    #[cfg_attr(feature = "serde", serde(with = "serde_u7"))]
     pub pitch: u7,
 }
 
+impl ScoreNote {
+    /// Creates a note, e.g. for defining a small score in code
+    ///
+    /// # Arguments
+    ///
+    /// * time - The time of the note in microseconds
+    /// * pitch - The pitch of the note as a MIDI note number
+    ///
+    /// # Panics
+    ///
+    /// If the pitch is above 127
+    pub fn new(time: u64, pitch: u8) -> Self {
+        let pitch = u7::try_from(pitch)
+            .unwrap_or_else(|| panic!("MIDI note number {} is above 127", pitch));
+        ScoreNote { time, pitch }
+    }
+
+    /// Creates a score from `(time, pitch)` pairs, see [`ScoreNote::new`]
+    pub fn from_tuples(notes: &[(u64, u8)]) -> Vec<ScoreNote> {
+        notes
+            .iter()
+            .map(|&(time, pitch)| ScoreNote::new(time, pitch))
+            .collect()
+    }
+}
+
+/// Serializes 7-bit MIDI values as plain numbers, rejecting numbers above 127
+#[cfg(feature = "serde")]
+mod serde_u7 {
+    use midly::num::u7;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &u7, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(value.as_int())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u7, D::Error> {
+        let value = u8::deserialize(deserializer)?;
+        u7::try_from(value)
+            .ok_or_else(|| D::Error::custom(format!("MIDI value {} is above 127", value)))
+    }
+}
+
+/// A score note paired with the time it is held, from its NoteOn and NoteOff messages
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScoreNoteWithDuration {
+    pub note: ScoreNote,
+    /// How long the note is held in microseconds, or `None` if it is never ended
+    pub duration: Option<u64>,
+}
+
+impl ScoreNoteWithDuration {
+    /// The time in microseconds when the note is released, if it is ever ended
+    pub fn end_time(&self) -> Option<u64> {
+        Some(self.note.time + self.duration?)
+    }
+}
+
+/// A MIDI event at a given timestamp in a playback score
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScoreEvent {
+    pub time: u64,
+    pub channel: u4,
+    pub message: MidiMessage,
+}
+
+/// Creates an array of score notes from `(time, pitch)` pairs, with times in
+/// microseconds and pitches as MIDI note numbers, see [`ScoreNote::new`]
+///
+/// ```
+/// use selim::notes;
+///
+/// let score = notes![(0, 60), (500000, 64), (1000000, 67)];
+/// assert_eq!(score[1].pitch.as_int(), 64);
+/// ```
+#[macro_export]
 macro_rules! notes {
     (
         $( ($t: expr, $p: expr) ),+
     ) => {
-        [ $( ScoreNote {time: $t, pitch: u7::from($p)} ),+ ]
+        [ $( $crate::score::ScoreNote::new($t, $p) ),+ ]
     }
 }
 
@@ -32,63 +126,1115 @@ static ALL_CHANNELS: Lazy<[u4; 16]> = Lazy::new(|| {
         .expect("wrong size iterator")
 });
 
+/// The General MIDI percussion channel 10, counting from 0
+pub const PERCUSSION_CHANNEL: u4 = u4::new(9);
+
+/// A selection of channels of one MIDI track
+///
+/// Parsed from `track:channels` where both are numbered from 1 like `selim-mid-info`
+/// prints them. Channels are a comma-separated list of numbers (`2:1,3`), ranges
+/// (`2:1-8`), all channels (`2:*`) and exclusions (`2:!10`), applied from left to
+/// right. A list starting with an exclusion excludes from all channels.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrackChannels {
+    /// The index of the track, counting from 0
+    pub track: usize,
+    pub channels: Vec<u4>,
+}
+
+impl TrackChannels {
+    /// Converts selections into the tracks and channels argument of the loading
+    /// functions
+    pub fn as_slices(selections: &[TrackChannels]) -> Vec<(usize, &[u4])> {
+        selections
+            .iter()
+            .map(|selection| (selection.track, &selection.channels[..]))
+            .collect()
+    }
+
+    /// Drops the percussion channel from selections
+    ///
+    /// Drum hits on General MIDI files share note numbers with melodic notes, so a
+    /// percussion part selected with the melody would corrupt matching.
+    pub fn without_percussion(selections: &[TrackChannels]) -> Vec<TrackChannels> {
+        selections
+            .iter()
+            .map(|selection| TrackChannels {
+                track: selection.track,
+                channels: selection
+                    .channels
+                    .iter()
+                    .copied()
+                    .filter(|&channel| channel != PERCUSSION_CHANNEL)
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+impl FromStr for TrackChannels {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid track and channels '{}'", s);
+        let (track, list) = s.split_once(':').ok_or_else(invalid)?;
+        let track = track
+            .parse::<usize>()
+            .ok()
+            .and_then(|track| track.checked_sub(1))
+            .ok_or_else(invalid)?;
+        let channel = |number: &str| {
+            number
+                .parse::<u8>()
+                .ok()
+                .filter(|number| (1..=16).contains(number))
+                .ok_or_else(invalid)
+        };
+        let mut selected = [list.starts_with('!'); 16];
+        for item in list.split(',') {
+            let (item, select) = match item.strip_prefix('!') {
+                Some(item) => (item, false),
+                None => (item, true),
+            };
+            let (first, last) = match item.split_once('-') {
+                _ if item == "*" => (1, 16),
+                Some((first, last)) => (channel(first)?, channel(last)?),
+                None => (channel(item)?, channel(item)?),
+            };
+            if first > last {
+                return Err(invalid());
+            }
+            selected[first as usize - 1..last as usize].fill(select);
+        }
+        Ok(Self {
+            track,
+            channels: (0..16)
+                .filter(|&channel| selected[channel as usize])
+                .map(u4::from)
+                .collect(),
+        })
+    }
+}
+
+/// Why a score couldn't be loaded
+#[derive(Debug)]
+pub enum LoadError {
+    /// The score file couldn't be read
+    Io(io::Error),
+    /// The data isn't a valid Standard MIDI File
+    Midi(midly::Error),
+    /// The timing of the MIDI file can't be converted into microseconds
+    Timing(TimeConversionError),
+    /// A track was requested which the MIDI file doesn't have
+    MissingTrack {
+        /// The number of tracks in the MIDI file
+        tracks: usize,
+        /// The index of the requested track, counting from 0
+        requested: usize,
+    },
+    /// The ABC notation couldn't be parsed
+    Abc(AbcError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "{}", err),
+            LoadError::Midi(err) => write!(f, "invalid MIDI file: {}", err),
+            LoadError::Timing(err) => write!(f, "unsupported MIDI timing: {}", err),
+            LoadError::MissingTrack { tracks, requested } => write!(
+                f,
+                "MIDI file has only {} tracks, track {} requested",
+                tracks,
+                requested + 1
+            ),
+            LoadError::Abc(err) => write!(f, "invalid ABC notation: {}", err),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Io(err) => Some(err),
+            LoadError::Midi(err) => Some(err),
+            LoadError::Timing(err) => Some(err),
+            LoadError::Abc(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> Self {
+        LoadError::Io(err)
+    }
+}
+
+impl From<midly::Error> for LoadError {
+    fn from(err: midly::Error) -> Self {
+        LoadError::Midi(err)
+    }
+}
+
+impl From<TimeConversionError> for LoadError {
+    fn from(err: TimeConversionError) -> Self {
+        LoadError::Timing(err)
+    }
+}
+
+/// A violation of the MIDI file specification which loading recovers from
+///
+/// MIDI files from scanning software and old sequencers often have minor defects.
+/// Instead of failing, loading keeps everything up to a malformed event in each track,
+/// so these are only reported by [`check_midi_data`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoadWarning {
+    /// The header declares a different number of tracks than the file has
+    TrackCount { declared: usize, found: usize },
+    /// A track doesn't end with an end of track event because it's truncated or has a
+    /// malformed event, so the end of the track may be missing
+    UnterminatedTrack {
+        /// The index of the track, counting from 0
+        track: usize,
+    },
+}
+
+impl fmt::Display for LoadWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadWarning::TrackCount { declared, found } => write!(
+                f,
+                "MIDI header declares {} tracks but the file has {}",
+                declared, found
+            ),
+            LoadWarning::UnterminatedTrack { track } => write!(
+                f,
+                "track {} is truncated or malformed, events after the defect are skipped",
+                track + 1
+            ),
+        }
+    }
+}
+
+/// Checks the raw bytes of a MIDI file for defects which loading recovers from
+///
+/// # Return value
+///
+/// The recoverable defects, or an error if the file can't be loaded at all
+pub fn check_midi_data(data: &[u8]) -> Result<Vec<LoadWarning>, LoadError> {
+    let (_, tracks) = midly::parse(data)?;
+    // before iterating, the size hint is the track count declared in the header
+    let declared = tracks.size_hint().0;
+    let tracks = tracks.collect::<Result<Vec<_>, _>>()?;
+    let mut warnings = vec![];
+    if tracks.len() != declared {
+        warnings.push(LoadWarning::TrackCount {
+            declared,
+            found: tracks.len(),
+        });
+    }
+    for (track, events) in tracks.into_iter().enumerate() {
+        let last = events.last().transpose()?;
+        if !last.is_some_and(|event| event.kind == Meta(MetaMessage::EndOfTrack)) {
+            warnings.push(LoadWarning::UnterminatedTrack { track });
+        }
+    }
+    Ok(warnings)
+}
+
+pub fn check_midi_file(path: &Path) -> Result<Vec<LoadWarning>, LoadError> {
+    let data = std::fs::read(path)?;
+    check_midi_data(&data)
+}
+
 fn make_tracks_and_channels_index<'a>(
     include_tracks_with_channels: &'a [(usize, &[u4])],
     tracks_available: usize,
-) -> Vec<&'a [u4]> {
+) -> Result<Vec<&'a [u4]>, LoadError> {
     let mut track_channels: Vec<&[u4]> = vec![&*ALL_CHANNELS; tracks_available];
-    if !include_tracks_with_channels.is_empty() {
-        let highest_track = include_tracks_with_channels
-            .iter()
-            .map(|(track_index, _)| *track_index)
-            .max()
-            .unwrap();
+    if let Some(highest_track) = include_tracks_with_channels
+        .iter()
+        .map(|(track_index, _)| *track_index)
+        .max()
+    {
         if highest_track >= track_channels.len() {
-            panic!(
-                "MIDI file has only {} tracks, track {} requested",
-                track_channels.len(),
-                highest_track + 1
-            );
+            return Err(LoadError::MissingTrack {
+                tracks: track_channels.len(),
+                requested: highest_track,
+            });
         }
         track_channels.fill(&[]);
         for (track_num, channel_nums) in include_tracks_with_channels {
             track_channels[*track_num] = channel_nums;
         }
     }
-    track_channels
-}
-
-pub fn load_midi_file(path: &Path, channels: &[(usize, &[u4])]) -> Vec<ScoreNote> {
-    let data = std::fs::read(path).unwrap();
-    let smf = midly::Smf::parse(&data).unwrap();
-    let mut ticks_to_microseconds = ConvertTicksToMicroseconds::try_from(smf.header).unwrap();
-    let track_channels = make_tracks_and_channels_index(channels, smf.tracks.len());
-    merge_tracks(&smf.tracks)
-        .filter_map(|(ticks, track_index, event)| {
-            match (track_channels[track_index].len(), event) {
-                (0, _) => None,
-                (
-                    _,
-                    Midi {
+    Ok(track_channels)
+}
+
+/// Returns the pitch of a MIDI message if it starts a new note
+///
+/// A NoteOn message with zero velocity is a NoteOff according to the MIDI specification,
+/// so it never starts a note.
+pub fn note_on_key(message: MidiMessage) -> Option<u7> {
+    match message {
+        NoteOn { key, vel } if vel > 0 => Some(key),
+        _ => None,
+    }
+}
+
+pub fn load_midi_file(
+    path: &Path,
+    channels: &[(usize, &[u4])],
+) -> Result<Vec<ScoreNote>, LoadError> {
+    let data = std::fs::read(path)?;
+    load_midi_data(&data, channels)
+}
+
+/// Loads a score from a MIDI file, or from an ABC notation file with the `.abc`
+/// extension
+///
+/// # Arguments
+///
+/// * path - The path of the score file
+/// * channels - The tracks and channels to read from a MIDI file, see
+///   [`load_channel_events`]. ABC files are read in full.
+pub fn load_score_file(
+    path: &Path,
+    channels: &[(usize, &[u4])],
+) -> Result<Vec<ScoreNote>, LoadError> {
+    if is_abc_file(path) {
+        load_abc_file(path, &[], GraceNotes::Skip)
+    } else {
+        load_midi_file(path, channels)
+    }
+}
+
+/// Converts the raw bytes of a MIDI file into a score
+pub fn load_midi_data(
+    data: &[u8],
+    channels: &[(usize, &[u4])],
+) -> Result<Vec<ScoreNote>, LoadError> {
+    stream_midi_notes(data, channels)?.collect()
+}
+
+/// Reads a MIDI file from any source, e.g. standard input or a network download, and
+/// converts it into a score
+///
+/// Scores embedded in the application can be converted with [`load_midi_data`] without
+/// copying them.
+pub fn load_midi_reader(
+    mut reader: impl Read,
+    channels: &[(usize, &[u4])],
+) -> Result<Vec<ScoreNote>, LoadError> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    load_midi_data(&data, channels)
+}
+
+/// Converts the raw bytes of a MIDI file lazily into a score, see
+/// [`stream_channel_events`]
+pub fn stream_midi_notes<'a>(
+    data: &'a [u8],
+    channels: &[(usize, &[u4])],
+) -> Result<impl Iterator<Item = Result<ScoreNote, LoadError>> + 'a, LoadError> {
+    Ok(
+        stream_channel_events(data, channels)?.filter_map(|event| match event {
+            Ok(event) => note_on_key(event.message).map(|key| {
+                Ok(ScoreNote {
+                    time: event.time,
+                    pitch: key,
+                })
+            }),
+            Err(err) => Some(Err(err)),
+        }),
+    )
+}
+
+/// Converts the raw bytes of a MIDI file into the channel messages of the given tracks
+/// and channels
+pub fn load_channel_events(
+    data: &[u8],
+    channels: &[(usize, &[u4])],
+) -> Result<Vec<ScoreEvent>, LoadError> {
+    stream_channel_events(data, channels)?.collect()
+}
+
+/// Converts the raw bytes of a MIDI file lazily into the channel messages of the given
+/// tracks and channels
+///
+/// Events are parsed only as the iterator advances, so the start of a very long file
+/// can be followed before the rest of it has been converted, and converted events
+/// need not be held in memory all at once.
+///
+/// # Return value
+///
+/// The events, or an error if the file header or the track list is invalid. Errors in
+/// the events themselves are yielded where they occur.
+pub fn stream_channel_events<'a>(
+    data: &'a [u8],
+    channels: &[(usize, &[u4])],
+) -> Result<impl Iterator<Item = Result<ScoreEvent, LoadError>> + 'a, LoadError> {
+    Ok(stream_track_events(data, channels)?.map(|event| event.map(|(_, event)| event)))
+}
+
+/// Merges the tracks of a parsed MIDI file into one stream of events with absolute
+/// times in ticks, paired with the index of their track
+///
+/// The tracks of a format 2 file are independent sequences, so they are played one
+/// after another instead of simultaneously like in [`merge_tracks`].
+pub fn merge_smf_tracks<'a, 'b>(
+    smf: &'b Smf<'a>,
+) -> impl Iterator<Item = (u64, usize, TrackEventKind<'a>)> + 'b {
+    match smf.header.format {
+        Format::Sequential => Either::Left(
+            smf.tracks
+                .iter()
+                .enumerate()
+                .flat_map(|(track_index, events)| events.iter().map(move |e| (track_index, e)))
+                .scan(0, |ticks, (track_index, event)| {
+                    *ticks += event.delta.as_int() as u64;
+                    Some((*ticks, track_index, event.kind))
+                }),
+        ),
+        _ => Either::Right(merge_tracks(&smf.tracks)),
+    }
+}
+
+/// Converts the raw bytes of a MIDI file lazily into the channel messages of the given
+/// tracks and channels, each paired with the index of its track
+///
+/// The sequences of a format 2 file are played one after another like in
+/// [`merge_smf_tracks`], leaving out the ones not selected so that picking a single
+/// sequence starts it from the beginning of the score.
+fn stream_track_events<'a>(
+    data: &'a [u8],
+    channels: &[(usize, &[u4])],
+) -> Result<impl Iterator<Item = Result<(usize, ScoreEvent), LoadError>> + 'a, LoadError> {
+    let (header, tracks) = midly::parse(data)?;
+    // only the track chunk headers are read here, events are parsed on demand
+    let tracks = tracks.collect::<Result<Vec<_>, _>>()?;
+    let mut ticks_to_microseconds = ConvertTicksToMicroseconds::try_from(header)?;
+    let track_channels = make_tracks_and_channels_index(channels, tracks.len())?
+        .into_iter()
+        .map(<[u4]>::to_vec)
+        .collect::<Vec<_>>();
+    let tracks = tracks.into_iter().enumerate();
+    let events = match header.format {
+        Format::Sequential => {
+            let selected = track_channels
+                .iter()
+                .map(|channels| !channels.is_empty())
+                .collect::<Vec<_>>();
+            let mut ticks = 0;
+            Either::Left(
+                tracks
+                    .filter(move |(track_index, _)| selected[*track_index])
+                    .flat_map(|(track_index, events)| events.map(move |event| (track_index, event)))
+                    .map(move |(track_index, event)| {
+                        let event = event?;
+                        ticks += event.delta.as_int() as u64;
+                        Ok((ticks, track_index, event.kind))
+                    }),
+            )
+        }
+        _ => Either::Right(
+            tracks
+                .map(|(track_index, events)| {
+                    let mut ticks = 0;
+                    events.map(move |event| {
+                        let event = event?;
+                        ticks += event.delta.as_int() as u64;
+                        Ok((ticks, track_index, event.kind))
+                    })
+                })
+                // merged in the same order as `merge_tracks`, with errors first so they
+                // end the score as soon as possible
+                .kmerge_by(|a: &Result<_, midly::Error>, b| match (a, b) {
+                    (Ok((ticks1, _, _)), Ok((ticks2, _, _))) => ticks1 < ticks2,
+                    (a, _) => a.is_err(),
+                }),
+        ),
+    };
+    Ok(events.filter_map(move |event| match event {
+        Ok((ticks, track_index, event)) => match event {
+            Midi { channel, message } if track_channels[track_index].contains(&channel) => {
+                Some(Ok((
+                    track_index,
+                    ScoreEvent {
+                        time: ticks_to_microseconds.convert(ticks, &event),
                         channel,
-                        message: NoteOn { key, vel: _ },
+                        message,
                     },
-                ) => {
-                    if track_channels[track_index].contains(&channel) {
-                        Some(ScoreNote {
-                            time: ticks_to_microseconds.convert(ticks, &event),
-                            pitch: key,
-                        })
-                    } else {
-                        None
-                    }
+                )))
+            }
+            _ => None,
+        },
+        Err(err) => Some(Err(err.into())),
+    }))
+}
+
+/// A summary of one track of a MIDI file, e.g. for choosing the tracks and channels to
+/// follow
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrackInfo {
+    /// The name of the track from its first track name event
+    pub name: Option<String>,
+    /// The channels with channel messages in the track, each with its number of notes
+    pub channels: Vec<(u4, usize)>,
+    /// The times of the first and the last note of the track in microseconds
+    pub note_span: Option<RangeInclusive<u64>>,
+}
+
+impl TrackInfo {
+    /// The number of notes on all channels of the track
+    pub fn notes(&self) -> usize {
+        self.channels.iter().map(|(_, notes)| notes).sum()
+    }
+}
+
+impl fmt::Display for TrackInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = vec![];
+        if let Some(name) = &self.name {
+            parts.push(format!("\"{}\"", name));
+        }
+        match self.channels.is_empty() {
+            true => parts.push("no channel messages".to_string()),
+            false => parts.extend(self.channels.iter().map(|(channel, notes)| {
+                format!("channel {}: {} notes", channel.as_int() + 1, notes)
+            })),
+        }
+        if let Some(span) = &self.note_span {
+            parts.push(format!(
+                "{:.3}-{:.3} s",
+                *span.start() as f64 / 1000000.0,
+                *span.end() as f64 / 1000000.0
+            ));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// Summarizes the tracks of a parsed MIDI file, see [`TrackInfo`]
+pub fn describe(smf: &Smf) -> Result<Vec<TrackInfo>, LoadError> {
+    let mut ticks_to_microseconds = ConvertTicksToMicroseconds::try_from(smf.header)?;
+    let mut infos = vec![TrackInfo::default(); smf.tracks.len()];
+    let mut channel_notes = vec![[None; 16]; smf.tracks.len()];
+    for (ticks, track_index, event) in merge_smf_tracks(smf) {
+        // every event goes through the conversion so that tempo changes are applied
+        let time = ticks_to_microseconds.convert(ticks, &event);
+        let info = &mut infos[track_index];
+        match event {
+            Meta(MetaMessage::TrackName(name)) if info.name.is_none() => {
+                info.name = Some(String::from_utf8_lossy(name).into_owned());
+            }
+            Midi { channel, message } => {
+                let notes = channel_notes[track_index][channel.as_int() as usize].get_or_insert(0);
+                if note_on_key(message).is_some() {
+                    *notes += 1;
+                    info.note_span = Some(match &info.note_span {
+                        Some(span) => *span.start()..=time,
+                        None => time..=time,
+                    });
                 }
-                _ => None,
             }
+            _ => {}
+        }
+    }
+    for (info, notes) in infos.iter_mut().zip(channel_notes) {
+        info.channels = (0..16)
+            .filter_map(|channel| Some((u4::from(channel), notes[channel as usize]?)))
+            .collect();
+    }
+    Ok(infos)
+}
+
+pub fn describe_midi_file(path: &Path) -> Result<Vec<TrackInfo>, LoadError> {
+    let data = std::fs::read(path)?;
+    describe(&Smf::parse(&data)?)
+}
+
+/// Keeps only the notes of a score within a pitch range, e.g. the melody line of a
+/// dense piano score
+pub fn filter_pitch_range(score: &[ScoreNote], range: &RangeInclusive<u7>) -> Vec<ScoreNote> {
+    score
+        .iter()
+        .filter(|note| range.contains(&note.pitch))
+        .copied()
+        .collect()
+}
+
+/// Snaps the times of score notes to the nearest point on a grid, e.g. to clean up the
+/// onsets of a sloppily played MIDI export
+///
+/// # Arguments
+///
+/// * score - The score to quantize
+/// * grid - The interval of the grid in microseconds, 0 for no quantization
+pub fn quantize(score: &[ScoreNote], grid: u64) -> Vec<ScoreNote> {
+    if grid == 0 {
+        return score.to_vec();
+    }
+    score
+        .iter()
+        .map(|note| ScoreNote {
+            time: (note.time + grid / 2) / grid * grid,
+            pitch: note.pitch,
+        })
+        .collect()
+}
+
+/// What to do with notes of the same pitch starting at the same time, e.g. from doubled
+/// parts in merged tracks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DuplicateNotes {
+    /// Keep every note
+    KeepAll,
+    /// Combine duplicates into the first one of them, with the highest velocity among
+    /// them
+    Merge,
+    /// Keep only the loudest one of the duplicates, on its own channel
+    KeepLoudest,
+}
+
+impl FromStr for DuplicateNotes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep-all" => Ok(DuplicateNotes::KeepAll),
+            "merge" => Ok(DuplicateNotes::Merge),
+            "keep-loudest" => Ok(DuplicateNotes::KeepLoudest),
+            _ => Err(format!("unknown duplicate note policy '{}'", s)),
+        }
+    }
+}
+
+/// Finds the notes of a score which repeat an earlier note at the same time and pitch
+///
+/// # Return value
+///
+/// For each note, whether it is a duplicate of an earlier note
+pub fn duplicate_notes(score: &[ScoreNote]) -> Vec<bool> {
+    let mut seen = HashSet::new();
+    score
+        .iter()
+        .map(|note| !seen.insert((note.time, note.pitch)))
+        .collect()
+}
+
+/// Resolves note-ons of the same pitch at the same time in a playback score, on any
+/// channel, according to a policy
+///
+/// Note-offs are kept as they are.
+pub fn resolve_duplicate_note_ons(
+    events: &[ScoreEvent],
+    policy: DuplicateNotes,
+) -> Vec<ScoreEvent> {
+    let mut events = events.to_vec();
+    if policy == DuplicateNotes::KeepAll {
+        return events;
+    }
+    let velocity = |event: &ScoreEvent| match event.message {
+        NoteOn { vel, .. } => vel,
+        _ => u7::from(0),
+    };
+    let mut groups: HashMap<(u64, u7), Vec<usize>> = HashMap::new();
+    for (index, event) in events.iter().enumerate() {
+        if let Some(key) = note_on_key(event.message) {
+            groups.entry((event.time, key)).or_default().push(index);
+        }
+    }
+    let mut kept = vec![true; events.len()];
+    for indices in groups.values().filter(|indices| indices.len() > 1) {
+        // the first one of equally loud duplicates is the loudest
+        let loudest = *indices
+            .iter()
+            .max_by_key(|&&index| (velocity(&events[index]), Reverse(index)))
+            .unwrap();
+        let keep = match policy {
+            DuplicateNotes::KeepLoudest => loudest,
+            _ => indices[0],
+        };
+        let loudest_velocity = velocity(&events[loudest]);
+        if let NoteOn { vel, .. } = &mut events[keep].message {
+            *vel = loudest_velocity;
+        }
+        for &index in indices {
+            kept[index] = index == keep;
+        }
+    }
+    events
+        .into_iter()
+        .zip(kept)
+        .filter(|(_, kept)| *kept)
+        .map(|(event, _)| event)
+        .collect()
+}
+
+/// Shifts all times of a score by a signed offset, e.g. to line up an upbeat
+///
+/// Notes which would move before the start of the score are placed at its start.
+///
+/// # Arguments
+///
+/// * score - The score to shift
+/// * offset - The offset in microseconds, negative to move notes earlier
+pub fn shift_score(score: &[ScoreNote], offset: i64) -> Vec<ScoreNote> {
+    score
+        .iter()
+        .map(|note| ScoreNote {
+            time: note.time.saturating_add_signed(offset),
+            pitch: note.pitch,
+        })
+        .collect()
+}
+
+/// Shifts all times of a playback score by a signed offset like [`shift_score`]
+pub fn shift_events(events: &[ScoreEvent], offset: i64) -> Vec<ScoreEvent> {
+    events
+        .iter()
+        .map(|event| ScoreEvent {
+            time: event.time.saturating_add_signed(offset),
+            ..*event
+        })
+        .collect()
+}
+
+/// Returns the offset which moves the first note of a score to its start, for trimming
+/// leading silence with [`shift_score`]
+pub fn leading_silence_offset(score: &[ScoreNote]) -> i64 {
+    -(score.first().map_or(0, |note| note.time) as i64)
+}
+
+/// Keeps only the notes of a score within a time range, e.g. a rehearsal excerpt, and
+/// moves the start of the range to the start of the score
+///
+/// # Arguments
+///
+/// * score - The score to slice
+/// * range - The times of the notes to keep, in microseconds
+pub fn slice_time(score: &[ScoreNote], range: &Range<u64>) -> Vec<ScoreNote> {
+    score
+        .iter()
+        .filter(|note| range.contains(&note.time))
+        .map(|note| ScoreNote {
+            time: note.time - range.start,
+            pitch: note.pitch,
+        })
+        .collect()
+}
+
+/// Keeps only the events of a playback score for an excerpt like [`slice_time`]
+///
+/// Notes starting within the range are kept, and the ones still sounding at its end
+/// are released there. Other messages before the range, like program changes, are
+/// moved to its start, so that the instruments are set up as in the complete score.
+pub fn slice_events(events: &[ScoreEvent], range: &Range<u64>) -> Vec<ScoreEvent> {
+    // the channels and keys of the notes kept and not released yet
+    let mut sounding = vec![];
+    events
+        .iter()
+        .filter_map(|event| {
+            let kept = match (note_on_key(event.message), note_off_key(event.message)) {
+                (Some(key), _) if range.contains(&event.time) => {
+                    sounding.push((event.channel, key));
+                    true
+                }
+                (Some(_), _) => false,
+                (None, Some(key)) => sounding
+                    .iter()
+                    .position(|&note| note == (event.channel, key))
+                    .map(|position| sounding.remove(position))
+                    .is_some(),
+                (None, None) => event.time < range.end,
+            };
+            kept.then_some(ScoreEvent {
+                time: event.time.clamp(range.start, range.end) - range.start,
+                ..*event
+            })
         })
         .collect()
 }
 
+/// An excerpt of a score
+///
+/// Parsed from a range of times like `1:30-2:45` or `90-165.5`, in seconds with
+/// optional minutes, or from an inclusive range of measures like `m33-48`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Excerpt {
+    /// A time range in microseconds
+    Time(Range<u64>),
+    /// Measure numbers counting from 1
+    Measures(RangeInclusive<usize>),
+}
+
+impl Excerpt {
+    /// Returns the times covered by the excerpt in microseconds, or `None` for measures
+    /// of a score without a tempo map
+    pub fn time_range(&self, tempo_map: Option<&TempoMap>) -> Option<Range<u64>> {
+        match self {
+            Excerpt::Time(range) => Some(range.clone()),
+            Excerpt::Measures(measures) => {
+                let tempo_map = tempo_map?;
+                let time = |measure| tempo_map.measure_to_time(measure).as_micros() as u64;
+                Some(time(*measures.start())..time(measures.end() + 1))
+            }
+        }
+    }
+}
+
+impl FromStr for Excerpt {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid excerpt '{}'", s);
+        if let Some(measures) = s.strip_prefix('m') {
+            let (first, last) = measures.split_once('-').ok_or_else(invalid)?;
+            let first = first.parse::<usize>().map_err(|_| invalid())?;
+            let last = last.parse::<usize>().map_err(|_| invalid())?;
+            if first == 0 || first > last {
+                return Err(invalid());
+            }
+            return Ok(Excerpt::Measures(first..=last));
+        }
+        let time = |time: &str| {
+            let (minutes, seconds) = time.split_once(':').unwrap_or(("0", time));
+            let minutes = minutes.parse::<u64>().ok()?;
+            let seconds = seconds.parse::<f64>().ok().filter(|s| *s >= 0.0)?;
+            Some(60_000_000 * minutes + (1_000_000.0 * seconds).round() as u64)
+        };
+        let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+        let start = time(start).ok_or_else(invalid)?;
+        let end = time(end).ok_or_else(invalid)?;
+        if start >= end {
+            return Err(invalid());
+        }
+        Ok(Excerpt::Time(start..end))
+    }
+}
+
+/// The largest difference in microseconds between the times of a note in two scores
+/// which [`diff`] doesn't report, to allow for rounding in MIDI exports
+pub const DIFF_TOLERANCE: u64 = 10_000;
+
+/// A difference between two scores found by [`diff`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoteDiff {
+    /// A note of the first score which is missing from the second one
+    Deleted { a_index: usize },
+    /// A note of the second score which is missing from the first one
+    Inserted { b_index: usize },
+    /// A note found in both scores at different times
+    Shifted {
+        a_index: usize,
+        b_index: usize,
+        /// The time of the note in the second score minus its time in the first one,
+        /// in microseconds
+        offset: i64,
+    },
+}
+
+/// Compares two scores, e.g. two editions of the same piece, with the default
+/// tolerance, see [`diff_with_tolerance`]
+pub fn diff(a: &[ScoreNote], b: &[ScoreNote]) -> Vec<NoteDiff> {
+    diff_with_tolerance(a, b, DIFF_TOLERANCE)
+}
+
+/// Compares two scores, e.g. two editions of the same piece
+///
+/// The scores are aligned by the longest common subsequence of their pitches, with the
+/// notes of each chord in pitch order. Unlike the score followers, this takes time and
+/// memory proportional to the product of the lengths of the differing middle parts of
+/// the scores.
+///
+/// # Arguments
+///
+/// * a - The first score
+/// * b - The second score
+/// * tolerance - The largest difference in microseconds between the times of a note in
+///   the scores which isn't reported as a shift
+///
+/// # Return value
+///
+/// The deleted, inserted and shifted notes in score order
+pub fn diff_with_tolerance(a: &[ScoreNote], b: &[ScoreNote], tolerance: u64) -> Vec<NoteDiff> {
+    let order = |score: &[ScoreNote]| {
+        let mut indices = (0..score.len()).collect::<Vec<_>>();
+        indices.sort_by_key(|&index| (score[index].time, score[index].pitch));
+        indices
+    };
+    let (a_order, b_order) = (order(a), order(b));
+    let same = |i: usize, j: usize| a[a_order[i]].pitch == b[b_order[j]].pitch;
+    let shift = |i: usize, j: usize| {
+        let (a_index, b_index) = (a_order[i], b_order[j]);
+        let offset = b[b_index].time as i64 - a[a_index].time as i64;
+        (offset.unsigned_abs() > tolerance).then_some(NoteDiff::Shifted {
+            a_index,
+            b_index,
+            offset,
+        })
+    };
+    // the common start and end of the scores need no table
+    let (n, m) = (a.len(), b.len());
+    let prefix = (0..n.min(m)).take_while(|&k| same(k, k)).count();
+    let suffix = (0..(n - prefix).min(m - prefix))
+        .take_while(|&k| same(n - 1 - k, m - 1 - k))
+        .count();
+    let (rows, columns) = (n - prefix - suffix, m - prefix - suffix);
+    // lengths of the longest common subsequences of the rest of the middle parts
+    let width = columns + 1;
+    let mut lengths = vec![0u32; (rows + 1) * width];
+    for i in (0..rows).rev() {
+        for j in (0..columns).rev() {
+            lengths[i * width + j] = match same(prefix + i, prefix + j) {
+                true => lengths[(i + 1) * width + j + 1] + 1,
+                false => lengths[(i + 1) * width + j].max(lengths[i * width + j + 1]),
+            };
+        }
+    }
+    let mut diffs = (0..prefix).filter_map(|k| shift(k, k)).collect::<Vec<_>>();
+    let (mut i, mut j) = (0, 0);
+    while i < rows || j < columns {
+        if i < rows && j < columns && same(prefix + i, prefix + j) {
+            diffs.extend(shift(prefix + i, prefix + j));
+            i += 1;
+            j += 1;
+        } else if j == columns
+            || (i < rows && lengths[(i + 1) * width + j] >= lengths[i * width + j + 1])
+        {
+            diffs.push(NoteDiff::Deleted {
+                a_index: a_order[prefix + i],
+            });
+            i += 1;
+        } else {
+            diffs.push(NoteDiff::Inserted {
+                b_index: b_order[prefix + j],
+            });
+            j += 1;
+        }
+    }
+    diffs.extend((0..suffix).filter_map(|k| shift(n - suffix + k, m - suffix + k)));
+    diffs
+}
+
+/// Finds the meta events of a MIDI file selected by a function, e.g. markers or key
+/// signatures
+///
+/// # Arguments
+///
+/// * data - The raw bytes of the MIDI file
+/// * select - Converts a meta event into the returned value, or returns `None` to skip
+///   the event
+///
+/// # Return value
+///
+/// The time and converted value of each selected event in time order
+pub fn load_meta_events<T>(
+    data: &[u8],
+    mut select: impl FnMut(&MetaMessage) -> Option<T>,
+) -> Result<Vec<(Duration, T)>, LoadError> {
+    let smf = Smf::parse(data)?;
+    let mut ticks_to_microseconds = ConvertTicksToMicroseconds::try_from(smf.header)?;
+    Ok(merge_smf_tracks(&smf)
+        .filter_map(|(ticks, _, event)| {
+            // every event is converted so the converter sees all tempo changes
+            let time = Duration::from_micros(ticks_to_microseconds.convert(ticks, &event));
+            match event {
+                Meta(message) => select(&message).map(|value| (time, value)),
+                _ => None,
+            }
+        })
+        .collect())
+}
+
+/// Finds the markers and cue points of a MIDI file, e.g. rehearsal letters and section
+/// names, as anchors for navigating the score
+///
+/// # Return value
+///
+/// The time and text of each marker and cue point in time order
+pub fn load_markers(data: &[u8]) -> Result<Vec<(Duration, String)>, LoadError> {
+    load_meta_events(data, |message| match message {
+        MetaMessage::Marker(text) | MetaMessage::CuePoint(text) => {
+            Some(String::from_utf8_lossy(text).trim().to_string())
+        }
+        _ => None,
+    })
+}
+
+pub fn load_midi_file_markers(path: &Path) -> Result<Vec<(Duration, String)>, LoadError> {
+    let data = std::fs::read(path)?;
+    load_markers(&data)
+}
+
+/// A score note with the details of the MIDI message which started it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DetailedNote {
+    pub note: ScoreNote,
+    pub velocity: u7,
+    pub channel: u4,
+    /// The index of the MIDI track of the note, counting from 0
+    pub track: usize,
+}
+
+/// Converts the raw bytes of a MIDI file into a score with the velocity, channel and
+/// track of each note
+///
+/// # Return value
+///
+/// The notes returned by [`load_midi_data`] for the same arguments, with their details
+pub fn load_detailed_notes(
+    data: &[u8],
+    channels: &[(usize, &[u4])],
+) -> Result<Vec<DetailedNote>, LoadError> {
+    let mut notes = vec![];
+    for event in stream_track_events(data, channels)? {
+        let (track, event) = event?;
+        if let NoteOn { key, vel } = event.message {
+            if vel > 0 {
+                notes.push(DetailedNote {
+                    note: ScoreNote {
+                        time: event.time,
+                        pitch: key,
+                    },
+                    velocity: vel,
+                    channel: event.channel,
+                    track,
+                });
+            }
+        }
+    }
+    Ok(notes)
+}
+
+pub fn load_midi_file_detailed_notes(
+    path: &Path,
+    channels: &[(usize, &[u4])],
+) -> Result<Vec<DetailedNote>, LoadError> {
+    let data = std::fs::read(path)?;
+    load_detailed_notes(&data, channels)
+}
+
+/// Renders notes as CSV with a header row, for inspecting and diffing scores in
+/// external tools
+pub fn export_csv(notes: &[DetailedNote]) -> String {
+    let mut csv = "time,pitch,name,velocity,channel,track\n".to_string();
+    for note in notes {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            note.note.time,
+            note.note.pitch,
+            pitch_to_name(note.note.pitch),
+            note.velocity,
+            note.channel,
+            note.track
+        ));
+    }
+    csv
+}
+
+/// Renders notes as a JSON array of objects, for inspecting and diffing scores in
+/// external tools
+pub fn export_json(notes: &[DetailedNote]) -> String {
+    let notes = notes
+        .iter()
+        .map(|note| {
+            format!(
+                "{{\"time\":{},\"pitch\":{},\"name\":\"{}\",\"velocity\":{},\"channel\":{},\"track\":{}}}",
+                note.note.time,
+                note.note.pitch,
+                pitch_to_name(note.note.pitch),
+                note.velocity,
+                note.channel,
+                note.track
+            )
+        })
+        .collect::<Vec<_>>();
+    format!("[{}]", notes.join(","))
+}
+
+/// Returns the pitch of a MIDI message if it ends a note
+pub fn note_off_key(message: MidiMessage) -> Option<u7> {
+    match message {
+        NoteOff { key, .. } => Some(key),
+        NoteOn { key, vel } if vel == 0 => Some(key),
+        _ => None,
+    }
+}
+
+/// Converts the raw bytes of a MIDI file into a score, pairing each note with its release
+///
+/// Overlapping notes of the same pitch on the same channel are ended in the order they
+/// were started.
+///
+/// # Return value
+///
+/// The notes returned by [`load_midi_data`] for the same arguments, with their durations
+pub fn load_notes_with_durations(
+    data: &[u8],
+    channels: &[(usize, &[u4])],
+) -> Result<Vec<ScoreNoteWithDuration>, LoadError> {
+    let mut notes: Vec<ScoreNoteWithDuration> = vec![];
+    let mut held: HashMap<(u4, u7), VecDeque<usize>> = HashMap::new();
+    for event in stream_channel_events(data, channels)? {
+        let event = event?;
+        if let Some(key) = note_on_key(event.message) {
+            held.entry((event.channel, key))
+                .or_default()
+                .push_back(notes.len());
+            notes.push(ScoreNoteWithDuration {
+                note: ScoreNote {
+                    time: event.time,
+                    pitch: key,
+                },
+                duration: None,
+            });
+        } else if let Some(key) = note_off_key(event.message) {
+            let started = held
+                .get_mut(&(event.channel, key))
+                .and_then(|notes| notes.pop_front());
+            if let Some(index) = started {
+                notes[index].duration = Some(event.time - notes[index].note.time);
+            }
+        }
+    }
+    Ok(notes)
+}
+
+pub fn load_midi_file_with_durations(
+    path: &Path,
+    channels: &[(usize, &[u4])],
+) -> Result<Vec<ScoreNoteWithDuration>, LoadError> {
+    let data = std::fs::read(path)?;
+    load_notes_with_durations(&data, channels)
+}
+
+/// Finds out how long each note of a score is held, see [`load_notes_with_durations`]
+///
+/// # Return value
+///
+/// The duration in microseconds of each note returned by [`load_midi_data`] for the same
+/// arguments, or `None` for a note which is never ended
+pub fn load_note_durations(
+    data: &[u8],
+    channels: &[(usize, &[u4])],
+) -> Result<Vec<Option<u64>>, LoadError> {
+    Ok(load_notes_with_durations(data, channels)?
+        .iter()
+        .map(|note| note.duration)
+        .collect())
+}
+
+pub fn load_midi_file_durations(
+    path: &Path,
+    channels: &[(usize, &[u4])],
+) -> Result<Vec<Option<u64>>, LoadError> {
+    let data = std::fs::read(path)?;
+    load_note_durations(&data, channels)
+}
+
 const NOTE_NAMES: [&str; 12] = [
     "C", "C#", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "B", "H",
 ];
@@ -109,6 +1255,11 @@ const OCTAVES: [(&str, bool); 11] = [
     ("6", false),  // 120
 ];
 
+/// Returns the name of a pitch class without octave, 0 being C
+pub fn pitch_class_name(pitch_class: u8) -> &'static str {
+    NOTE_NAMES[(pitch_class % 12) as usize]
+}
+
 pub fn pitch_to_name(pitch: u7) -> String {
     let pitch_u8 = pitch.as_int();
     let pitch_class = (pitch_u8 % 12) as usize;
@@ -120,6 +1271,42 @@ pub fn pitch_to_name(pitch: u7) -> String {
     format!("{}{}", pitch_symbol, octave)
 }
 
+/// Parses a pitch name written by [`pitch_to_name`], e.g. `C#1` or `eb`
+///
+/// Enharmonic spellings with a sharp or a flat after the note letter like `D#1` or
+/// `Gb2` are accepted too. `B` is already the flattened `H`, so it takes no accidental.
+///
+/// # Return value
+///
+/// The MIDI note number, or `None` if the name isn't a valid pitch name
+pub fn name_to_pitch(name: &str) -> Option<u7> {
+    let mut chars = name.chars();
+    let letter = chars.next()?;
+    let lower = letter.is_lowercase();
+    let pitch_class = match letter.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 10,
+        'H' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (alteration, octave) = match rest.chars().next() {
+        Some('#') if pitch_class != 10 => (1, &rest[1..]),
+        Some('b') if pitch_class != 10 => (-1, &rest[1..]),
+        _ => (0, rest),
+    };
+    let octave_index = OCTAVES
+        .iter()
+        .position(|&candidate| candidate == (octave, lower))?;
+    let pitch = 12 * octave_index as i32 + pitch_class + alteration;
+    u8::try_from(pitch).ok().and_then(u7::try_from)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -129,27 +1316,667 @@ mod tests {
     #[test]
     fn load_midi_file_clementi() {
         let path = AsRef::<Path>::as_ref("test-asset").join("Clementi.mid");
-        let score = load_midi_file(&path, &[]);
-        assert_eq!(score.len(), 1332);
+        let score = load_midi_file(&path, &[]).unwrap();
+        assert_eq!(score.len(), 666);
         assert_eq!(
             score[..5],
-            notes![(0, 48), (0, 72), (500000, 72), (500000, 76), (500000, 48)]
+            notes![(0, 48), (0, 72), (500000, 76), (750000, 72), (1000000, 67)]
+        );
+    }
+
+    #[test]
+    fn load_midi_file_clementi_durations() {
+        let path = AsRef::<Path>::as_ref("test-asset").join("Clementi.mid");
+        let channels: &[(usize, &[u4])] = &[(1, &[u4::from(0)])];
+        let durations = load_midi_file_durations(&path, channels).unwrap();
+        assert_eq!(
+            durations.len(),
+            load_midi_file(&path, channels).unwrap().len()
+        );
+        assert!(durations.iter().all(|duration| duration.is_some()));
+    }
+
+    #[test]
+    fn load_midi_file_clementi_with_durations() {
+        let path = AsRef::<Path>::as_ref("test-asset").join("Clementi.mid");
+        let channels: &[(usize, &[u4])] = &[(1, &[u4::from(0)])];
+        let notes = load_midi_file_with_durations(&path, channels).unwrap();
+        assert_eq!(
+            notes.iter().map(|n| n.note).collect::<Vec<_>>(),
+            load_midi_file(&path, channels).unwrap()
+        );
+        let first = notes[0];
+        assert_eq!(
+            first.end_time(),
+            Some(first.note.time + first.duration.unwrap())
+        );
+    }
+
+    #[test]
+    fn load_midi_reader_clementi() {
+        let data = std::fs::read(Path::new("test-asset").join("Clementi.mid")).unwrap();
+        assert_eq!(
+            load_midi_reader(io::Cursor::new(&data), &[]).unwrap(),
+            load_midi_data(&data, &[]).unwrap()
+        );
+    }
+
+    #[test]
+    fn stream_midi_notes_clementi() {
+        let data = std::fs::read(Path::new("test-asset").join("Clementi.mid")).unwrap();
+        let channels: &[(usize, &[u4])] = &[(1, &[u4::from(0)])];
+        let mut stream = stream_midi_notes(&data, channels).unwrap();
+        assert_eq!(
+            stream
+                .by_ref()
+                .take(3)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+            load_midi_data(&data, channels).unwrap()[..3]
+        );
+        assert_eq!(
+            stream.count(),
+            load_midi_data(&data, channels).unwrap().len() - 3
+        );
+    }
+
+    #[test]
+    fn move_score_events_to_thread() {
+        let data = std::fs::read(Path::new("test-asset").join("Clementi.mid")).unwrap();
+        let events = load_channel_events(&data, &[(2, &[u4::from(1)])]).unwrap();
+        drop(data);
+        let count = events.len();
+        assert_eq!(
+            std::thread::spawn(move || events.len()).join().unwrap(),
+            count
         );
     }
 
+    #[test]
+    fn load_errors() {
+        let path = AsRef::<Path>::as_ref("test-asset").join("Clementi.mid");
+        assert!(matches!(
+            load_midi_file(&path, &[(9, &[u4::from(0)])]),
+            Err(LoadError::MissingTrack {
+                tracks: 3,
+                requested: 9
+            })
+        ));
+        assert!(matches!(
+            load_midi_data(b"not a MIDI file", &[]),
+            Err(LoadError::Midi(_))
+        ));
+        let missing = AsRef::<Path>::as_ref("test-asset").join("missing.mid");
+        assert!(matches!(
+            load_midi_file(&missing, &[]),
+            Err(LoadError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn describe_clementi() {
+        let path = AsRef::<Path>::as_ref("test-asset").join("Clementi.mid");
+        let tracks = describe_midi_file(&path).unwrap();
+        assert_eq!(tracks.len(), 3);
+        assert_eq!(
+            tracks.iter().map(TrackInfo::notes).sum::<usize>(),
+            load_midi_file(&path, &[]).unwrap().len()
+        );
+        assert_eq!(
+            tracks[1],
+            TrackInfo {
+                name: None,
+                channels: vec![(u4::from(0), 454)],
+                note_span: Some(0..=112500000)
+            }
+        );
+        assert_eq!(tracks[2].channels, [(u4::from(1), 212)]);
+    }
+
+    #[test]
+    fn format_track_info() {
+        let info = TrackInfo {
+            name: Some("Piano".to_string()),
+            channels: vec![(u4::from(0), 120), (u4::from(9), 0)],
+            note_span: Some(500000..=61250000),
+        };
+        assert_eq!(
+            info.to_string(),
+            "\"Piano\", channel 1: 120 notes, channel 10: 0 notes, 0.500-61.250 s"
+        );
+        assert_eq!(TrackInfo::default().to_string(), "no channel messages");
+    }
+
+    #[test]
+    fn find_duplicate_notes() {
+        let score = notes![(0, 60), (0, 64), (0, 60), (500, 60), (500, 60)];
+        assert_eq!(duplicate_notes(&score), [false, false, true, false, true]);
+    }
+
+    #[rstest(policy, expected,
+        case(DuplicateNotes::KeepAll, vec![(0, 0, 60, 40), (0, 1, 60, 90), (0, 0, 64, 50), (500, 0, 60, 70)]),
+        case(DuplicateNotes::Merge, vec![(0, 0, 60, 90), (0, 0, 64, 50), (500, 0, 60, 70)]),
+        case(DuplicateNotes::KeepLoudest, vec![(0, 1, 60, 90), (0, 0, 64, 50), (500, 0, 60, 70)]),
+    )]
+    fn resolve_duplicates(policy: DuplicateNotes, expected: Vec<(u64, u8, u8, u8)>) {
+        let events = |notes: &[(u64, u8, u8, u8)]| {
+            notes
+                .iter()
+                .map(|&(time, channel, key, vel)| ScoreEvent {
+                    time,
+                    channel: u4::from(channel),
+                    message: NoteOn {
+                        key: u7::from(key),
+                        vel: u7::from(vel),
+                    },
+                })
+                .collect::<Vec<_>>()
+        };
+        let score = events(&[
+            (0, 0, 60, 40),
+            (0, 1, 60, 90),
+            (0, 0, 64, 50),
+            (500, 0, 60, 70),
+        ]);
+        assert_eq!(
+            resolve_duplicate_note_ons(&score, policy),
+            events(&expected)
+        );
+    }
+
+    #[test]
+    fn score_from_tuples() {
+        assert_eq!(
+            ScoreNote::from_tuples(&[(0, 60), (500000, 127)]),
+            notes![(0, 60), (500000, 127)]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "MIDI note number 128 is above 127")]
+    fn note_pitch_out_of_range() {
+        ScoreNote::new(0, 128);
+    }
+
+    #[test]
+    fn load_defective_midi_file() {
+        use midly::num::{u15, u28};
+        use midly::{Header, Timing, TrackEvent};
+        let note = |delta: u32, vel: u8| TrackEvent {
+            delta: u28::from(delta),
+            kind: Midi {
+                channel: u4::from(0),
+                message: NoteOn {
+                    key: u7::from(60),
+                    vel: u7::from(vel),
+                },
+            },
+        };
+        let end = TrackEvent {
+            delta: u28::from(0),
+            kind: Meta(MetaMessage::EndOfTrack),
+        };
+        let smf = Smf {
+            header: Header::new(Format::SingleTrack, Timing::Metrical(u15::from(480))),
+            tracks: vec![vec![
+                note(0, 64),
+                note(480, 0),
+                note(0, 64),
+                note(480, 0),
+                end,
+            ]],
+        };
+        let mut data = vec![];
+        smf.write_std(&mut data).unwrap();
+        assert_eq!(check_midi_data(&data).unwrap(), []);
+        // declare two tracks and cut off the end of track and the last note-off
+        data[11] = 2;
+        data.truncate(data.len() - 5);
+        assert_eq!(
+            check_midi_data(&data).unwrap(),
+            [
+                LoadWarning::TrackCount {
+                    declared: 2,
+                    found: 1
+                },
+                LoadWarning::UnterminatedTrack { track: 0 }
+            ]
+        );
+        assert_eq!(
+            load_midi_data(&data, &[]).unwrap(),
+            notes![(0, 60), (500000, 60)]
+        );
+    }
+
+    #[test]
+    fn load_error_message() {
+        let err = LoadError::MissingTrack {
+            tracks: 3,
+            requested: 9,
+        };
+        assert_eq!(
+            err.to_string(),
+            "MIDI file has only 3 tracks, track 10 requested"
+        );
+    }
+
+    #[test]
+    fn load_midi_file_clementi_detailed_notes() {
+        let path = AsRef::<Path>::as_ref("test-asset").join("Clementi.mid");
+        let notes = load_midi_file_detailed_notes(&path, &[]).unwrap();
+        assert_eq!(
+            notes.iter().map(|n| n.note).collect::<Vec<_>>(),
+            load_midi_file(&path, &[]).unwrap()
+        );
+        assert!(notes.iter().all(|n| n.velocity > 0 && n.track > 0));
+    }
+
+    fn detailed_notes() -> Vec<DetailedNote> {
+        vec![
+            DetailedNote {
+                note: ScoreNote {
+                    time: 0,
+                    pitch: u7::from(60),
+                },
+                velocity: u7::from(80),
+                channel: u4::from(0),
+                track: 1,
+            },
+            DetailedNote {
+                note: ScoreNote {
+                    time: 500000,
+                    pitch: u7::from(70),
+                },
+                velocity: u7::from(64),
+                channel: u4::from(1),
+                track: 2,
+            },
+        ]
+    }
+
+    #[test]
+    fn export_notes_csv() {
+        assert_eq!(
+            export_csv(&detailed_notes()),
+            "time,pitch,name,velocity,channel,track\n0,60,C1,80,0,1\n500000,70,B1,64,1,2\n"
+        );
+    }
+
+    #[test]
+    fn export_notes_json() {
+        assert_eq!(
+            export_json(&detailed_notes()),
+            concat!(
+                "[{\"time\":0,\"pitch\":60,\"name\":\"C1\",\"velocity\":80,\"channel\":0,\"track\":1},",
+                "{\"time\":500000,\"pitch\":70,\"name\":\"B1\",\"velocity\":64,\"channel\":1,\"track\":2}]"
+            )
+        );
+        assert_eq!(export_json(&[]), "[]");
+    }
+
+    #[test]
+    fn markers() {
+        use midly::num::{u15, u24, u28};
+        use midly::{Format, Header, Smf, Timing, TrackEvent, TrackEventKind};
+        let meta = |delta: u32, message| TrackEvent {
+            delta: u28::from(delta),
+            kind: TrackEventKind::Meta(message),
+        };
+        let smf = Smf {
+            header: Header::new(Format::SingleTrack, Timing::Metrical(u15::from(480))),
+            tracks: vec![vec![
+                meta(0, MetaMessage::Tempo(u24::from(500_000))),
+                meta(960, MetaMessage::Marker(b"A ")),
+                meta(0, MetaMessage::Tempo(u24::from(1_000_000))),
+                meta(480, MetaMessage::CuePoint(b"Coda")),
+                meta(0, MetaMessage::EndOfTrack),
+            ]],
+        };
+        let mut data = vec![];
+        smf.write_std(&mut data).unwrap();
+        assert_eq!(
+            load_markers(&data).unwrap(),
+            [
+                (Duration::from_secs(1), "A".to_string()),
+                (Duration::from_secs(2), "Coda".to_string())
+            ]
+        );
+    }
+
+    /// A MIDI file with SMPTE timecode timing and two notes one and two seconds' worth
+    /// of frames from its start, with a tempo change which must not affect their times
+    fn timecode_midi_data(fps: midly::Fps, ticks_per_frame: u8) -> Vec<u8> {
+        use midly::num::{u24, u28};
+        use midly::{Format, Header, Smf, Timing, TrackEvent};
+        // a second of nominal frames, i.e. 30 for 29.97 fps
+        let second = fps.as_f32().round() as u32 * ticks_per_frame as u32;
+        let event = |delta: u32, kind| TrackEvent {
+            delta: u28::from(delta),
+            kind,
+        };
+        let note_on = |delta, key: u8| {
+            event(
+                delta,
+                Midi {
+                    channel: u4::from(0),
+                    message: NoteOn {
+                        key: u7::from(key),
+                        vel: u7::from(64),
+                    },
+                },
+            )
+        };
+        let smf = Smf {
+            header: Header::new(Format::SingleTrack, Timing::Timecode(fps, ticks_per_frame)),
+            tracks: vec![vec![
+                event(0, Meta(MetaMessage::Tempo(u24::from(1_000_000)))),
+                note_on(second, 60),
+                note_on(second, 62),
+                event(0, Meta(MetaMessage::EndOfTrack)),
+            ]],
+        };
+        let mut data = vec![];
+        smf.write_std(&mut data).unwrap();
+        data
+    }
+
+    #[rstest(fps, ticks_per_frame, expected,
+        case(midly::Fps::Fps24, 4, notes![(1000000, 60), (2000000, 62)]),
+        case(midly::Fps::Fps25, 40, notes![(1000000, 60), (2000000, 62)]),
+        // 29.97 drop-frame timecode runs slightly slower than the nominal 30 frames
+        case(midly::Fps::Fps29, 80, notes![(1001000, 60), (2002000, 62)]),
+        case(midly::Fps::Fps30, 100, notes![(1000000, 60), (2000000, 62)]),
+    )]
+    fn load_timecode_timing(fps: midly::Fps, ticks_per_frame: u8, expected: [ScoreNote; 2]) {
+        let data = timecode_midi_data(fps, ticks_per_frame);
+        assert_eq!(load_midi_data(&data, &[]).unwrap(), expected);
+        assert_eq!(
+            load_notes_with_durations(&data, &[]).unwrap()[1].note,
+            expected[1]
+        );
+    }
+
+    #[test]
+    fn load_timecode_zero_ticks_per_frame() {
+        let data = timecode_midi_data(midly::Fps::Fps25, 0);
+        assert!(matches!(
+            load_midi_data(&data, &[]),
+            Err(LoadError::Timing(_))
+        ));
+    }
+
+    /// A MIDI file with two tracks of two notes each, half a second apart at the default
+    /// tempo
+    fn two_track_midi_data(format: Format) -> Vec<u8> {
+        use midly::num::{u15, u28};
+        use midly::{Header, Timing, TrackEvent};
+        let note_on = |delta: u32, key: u8| TrackEvent {
+            delta: u28::from(delta),
+            kind: Midi {
+                channel: u4::from(0),
+                message: NoteOn {
+                    key: u7::from(key),
+                    vel: u7::from(64),
+                },
+            },
+        };
+        let smf = Smf {
+            header: Header::new(format, Timing::Metrical(u15::from(480))),
+            tracks: vec![
+                vec![note_on(0, 60), note_on(480, 62)],
+                vec![note_on(240, 72), note_on(480, 74)],
+            ],
+        };
+        let mut data = vec![];
+        smf.write_std(&mut data).unwrap();
+        data
+    }
+
+    #[rstest(format, tracks, expected,
+        case(
+            Format::Parallel,
+            vec![],
+            notes![(0, 60), (250000, 72), (500000, 62), (750000, 74)].to_vec()
+        ),
+        case(
+            Format::Sequential,
+            vec![],
+            notes![(0, 60), (500000, 62), (750000, 72), (1250000, 74)].to_vec()
+        ),
+        case(
+            Format::Sequential,
+            vec![1],
+            notes![(250000, 72), (750000, 74)].to_vec()
+        ),
+    )]
+    fn load_track_formats(format: Format, tracks: Vec<usize>, expected: Vec<ScoreNote>) {
+        let data = two_track_midi_data(format);
+        let channels = tracks
+            .into_iter()
+            .map(|track| (track, &ALL_CHANNELS[..]))
+            .collect::<Vec<_>>();
+        assert_eq!(load_midi_data(&data, &channels).unwrap(), expected);
+    }
+
+    #[test]
+    fn filter_melody_line() {
+        let score = notes![(0, 48), (0, 72), (500, 55), (500, 60), (1000, 79)];
+        assert_eq!(
+            filter_pitch_range(&score, &(u7::from(60)..=u7::from(127))),
+            notes![(0, 72), (500, 60), (1000, 79)]
+        );
+    }
+
+    #[rstest(grid, expected,
+        case(0, notes![(0, 60), (9999, 62), (15000, 64), (24000, 65)]),
+        case(10000, notes![(0, 60), (10000, 62), (20000, 64), (20000, 65)]),
+    )]
+    fn quantize_to_grid(grid: u64, expected: [ScoreNote; 4]) {
+        let score = notes![(0, 60), (9999, 62), (15000, 64), (24000, 65)];
+        assert_eq!(quantize(&score, grid), expected);
+    }
+
+    #[rstest(spec, track, channels,
+        case("2:1", 1, vec![0]),
+        case("1:1-8", 0, (0..8).collect()),
+        case("3:*", 2, (0..16).collect()),
+        case("1:!10", 0, (0..16).filter(|&c| c != 9).collect()),
+        case("1:1-4,!2,10", 0, vec![0, 2, 3, 9]),
+    )]
+    fn parse_track_channels(spec: &str, track: usize, channels: Vec<u8>) {
+        assert_eq!(
+            spec.parse(),
+            Ok(TrackChannels {
+                track,
+                channels: channels.into_iter().map(u4::from).collect()
+            })
+        );
+    }
+
+    #[rstest(spec, case("1"), case("0:1"), case("1:17"), case("1:8-1"), case("1:"))]
+    fn parse_invalid_track_channels(spec: &str) {
+        assert!(spec.parse::<TrackChannels>().is_err());
+    }
+
+    #[test]
+    fn drop_percussion_channel() {
+        let selections = ["2:*".parse().unwrap(), "3:10".parse().unwrap()];
+        assert_eq!(
+            TrackChannels::without_percussion(&selections),
+            [
+                "2:!10".parse().unwrap(),
+                TrackChannels {
+                    track: 2,
+                    channels: vec![]
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn slice_excerpt() {
+        let score = notes![(0, 60), (1000, 62), (2000, 64), (3000, 65)];
+        assert_eq!(
+            slice_time(&score, &(1000..3000)),
+            notes![(0, 62), (1000, 64)]
+        );
+    }
+
+    #[test]
+    fn slice_playback_excerpt() {
+        let event = |time, message| ScoreEvent {
+            time,
+            channel: u4::new(0),
+            message,
+        };
+        let on = |time, key| {
+            event(
+                time,
+                NoteOn {
+                    key: u7::new(key),
+                    vel: u7::new(64),
+                },
+            )
+        };
+        let off = |time, key| {
+            event(
+                time,
+                NoteOff {
+                    key: u7::new(key),
+                    vel: u7::new(0),
+                },
+            )
+        };
+        let program = event(
+            0,
+            MidiMessage::ProgramChange {
+                program: u7::new(5),
+            },
+        );
+        let events = [
+            program,
+            on(0, 60),
+            off(1500, 60),
+            on(1000, 62),
+            off(2000, 62),
+            on(2500, 64),
+            off(3500, 64),
+            on(3000, 65),
+            off(4000, 65),
+        ];
+        assert_eq!(
+            slice_events(&events, &(1000..3000)),
+            [
+                program,
+                on(0, 62),
+                off(1000, 62),
+                on(1500, 64),
+                off(2000, 64)
+            ]
+        );
+        assert_eq!(shift_events(&events[..2], -500), [program, on(0, 60)]);
+    }
+
+    #[test]
+    fn diff_editions() {
+        let a = notes![(0, 64), (0, 60), (500000, 62), (1000000, 64), (1500000, 65)];
+        let b = notes![
+            (0, 60),
+            (0, 64),
+            (505000, 62),
+            (1100000, 64),
+            (1500000, 67),
+            (2000000, 69)
+        ];
+        assert_eq!(
+            diff(&a, &b),
+            [
+                NoteDiff::Shifted {
+                    a_index: 3,
+                    b_index: 3,
+                    offset: 100000
+                },
+                NoteDiff::Deleted { a_index: 4 },
+                NoteDiff::Inserted { b_index: 4 },
+                NoteDiff::Inserted { b_index: 5 },
+            ]
+        );
+        assert_eq!(diff(&b, &b), []);
+        assert_eq!(diff(&a[..1], &[]), [NoteDiff::Deleted { a_index: 0 }]);
+    }
+
+    #[rstest(spec, expected,
+        case("1:30-2:45", Excerpt::Time(90_000_000..165_000_000)),
+        case("90-165.5", Excerpt::Time(90_000_000..165_500_000)),
+        case("m33-48", Excerpt::Measures(33..=48)),
+    )]
+    fn parse_excerpt(spec: &str, expected: Excerpt) {
+        assert_eq!(spec.parse(), Ok(expected));
+    }
+
+    #[rstest(
+        spec,
+        case("90"),
+        case("2:45-1:30"),
+        case("m0-4"),
+        case("m5-4"),
+        case("a-b")
+    )]
+    fn parse_invalid_excerpt(spec: &str) {
+        assert!(spec.parse::<Excerpt>().is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let score = notes![(0, 60), (500000, 127)];
+        let json = serde_json::to_string(&score).unwrap();
+        assert_eq!(
+            json,
+            "[{\"time\":0,\"pitch\":60},{\"time\":500000,\"pitch\":127}]"
+        );
+        assert_eq!(
+            serde_json::from_str::<[ScoreNote; 2]>(&json).unwrap(),
+            score
+        );
+        assert!(serde_json::from_str::<ScoreNote>("{\"time\":0,\"pitch\":128}").is_err());
+    }
+
+    #[test]
+    fn shift_times() {
+        let score = notes![(1000, 60), (1500, 62)];
+        assert_eq!(shift_score(&score, 500), notes![(1500, 60), (2000, 62)]);
+        assert_eq!(shift_score(&score, -1200), notes![(0, 60), (300, 62)]);
+        assert_eq!(
+            shift_score(&score, leading_silence_offset(&score)),
+            notes![(0, 60), (500, 62)]
+        );
+        assert_eq!(leading_silence_offset(&[]), 0);
+    }
+
+    #[test]
+    fn note_off_keys() {
+        let key = u7::from(60);
+        let vel = u7::from(0);
+        assert_eq!(note_off_key(NoteOff { key, vel }), Some(key));
+        assert_eq!(note_off_key(NoteOn { key, vel }), Some(key));
+        let vel = u7::from(64);
+        assert_eq!(note_off_key(NoteOn { key, vel }), None);
+    }
+
     #[test]
     fn load_midi_file_clementi_track_1_channel_1() {
         let path = AsRef::<Path>::as_ref("test-asset").join("Clementi.mid");
-        let score = load_midi_file(&path, &[(1, &[u4::from(0)])]);
-        assert_eq!(score.len(), 908);
+        let score = load_midi_file(&path, &[(1, &[u4::from(0)])]).unwrap();
+        assert_eq!(score.len(), 454);
         assert_eq!(
             score[..5],
             notes![
                 (0, 72),
-                (500000, 72),
                 (500000, 76),
-                (750000, 76),
-                (750000, 72)
+                (750000, 72),
+                (1000000, 67),
+                (1500000, 67)
             ]
         );
     }
@@ -157,17 +1984,34 @@ mod tests {
     #[test]
     fn load_midi_file_clementi_track_1_channel_2() {
         let path = AsRef::<Path>::as_ref("test-asset").join("Clementi.mid");
-        let score = load_midi_file(&path, &[(1, &[u4::from(1)])]);
+        let score = load_midi_file(&path, &[(1, &[u4::from(1)])]).unwrap();
         assert_eq!(score.len(), 0);
     }
 
     #[test]
     fn load_midi_file_clementi_track_3_channel_2() {
         let path = AsRef::<Path>::as_ref("test-asset").join("Clementi.mid");
-        let score = load_midi_file(&path, &[(1, &[u4::from(2)])]);
+        let score = load_midi_file(&path, &[(1, &[u4::from(2)])]).unwrap();
         assert_eq!(score.len(), 0);
     }
 
+    #[test]
+    fn note_on_key_ignores_zero_velocity() {
+        let note_on = |vel: u8| NoteOn {
+            key: u7::from(60),
+            vel: u7::from(vel),
+        };
+        assert_eq!(note_on_key(note_on(64)), Some(u7::from(60)));
+        assert_eq!(note_on_key(note_on(0)), None);
+        assert_eq!(
+            note_on_key(MidiMessage::NoteOff {
+                key: u7::from(60),
+                vel: u7::from(64)
+            }),
+            None
+        );
+    }
+
     #[rstest(
         pitch,
         expect,
@@ -304,4 +2148,33 @@ mod tests {
         let note_name = pitch_to_name(u7::from(pitch));
         assert_eq!(note_name, expect);
     }
+
+    #[test]
+    fn name_to_pitch_inverts_pitch_to_name() {
+        for pitch in 0..=127 {
+            let pitch = u7::from(pitch);
+            assert_eq!(name_to_pitch(&pitch_to_name(pitch)), Some(pitch));
+        }
+    }
+
+    #[rstest(
+        name,
+        expect,
+        case("C1", Some(60)),
+        case("D#1", Some(63)),
+        case("Gb2", Some(78)),
+        case("db", Some(49)),
+        case("Cb1", Some(59)),
+        case("H#", Some(48)),
+        case("Cb-3", None),
+        case("Ab6", None),
+        case("c1", None),
+        case("Bb1", None),
+        case("X1", None),
+        case("C7", None),
+        case("", None)
+    )]
+    fn test_name_to_pitch(name: &str, expect: Option<u8>) {
+        assert_eq!(name_to_pitch(name), expect.map(u7::from));
+    }
 }
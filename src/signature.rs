@@ -0,0 +1,212 @@
+use crate::score::{load_meta_events, pitch_class_name, LoadError};
+use midly::MetaMessage;
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+/// The time signature of a MIDI file without time signature events
+pub(crate) const DEFAULT_TIME_SIGNATURE: TimeSignature = TimeSignature {
+    numerator: 4,
+    denominator: 4,
+};
+
+/// A time signature like 3/4 or 6/8
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeSignature {
+    pub numerator: u8,
+    pub denominator: u8,
+}
+
+impl TimeSignature {
+    /// The length of a measure in quarter notes, i.e. MIDI beats
+    pub fn measure_length(&self) -> f64 {
+        4.0 * self.numerator as f64 / self.denominator as f64
+    }
+
+    /// The length of a counted beat in quarter notes, which is a dotted note in
+    /// compound meters like 6/8
+    pub fn beat_length(&self) -> f64 {
+        let note = 4.0 / self.denominator as f64;
+        match self.denominator >= 8 && self.numerator > 3 && self.numerator.is_multiple_of(3) {
+            true => 3.0 * note,
+            false => note,
+        }
+    }
+}
+
+impl fmt::Display for TimeSignature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.numerator, self.denominator)
+    }
+}
+
+/// A key signature as stored in MIDI files
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeySignature {
+    /// The number of sharps, or a negative number of flats
+    pub sharps: i8,
+    pub minor: bool,
+}
+
+impl KeySignature {
+    /// Returns the pitch class of the tonic, 0 being C
+    pub fn tonic(&self) -> u8 {
+        let major_tonic = (7 * self.sharps as i32).rem_euclid(12);
+        match self.minor {
+            true => ((major_tonic + 9) % 12) as u8,
+            false => major_tonic as u8,
+        }
+    }
+}
+
+impl fmt::Display for KeySignature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mode = match self.minor {
+            true => "minor",
+            false => "major",
+        };
+        write!(f, "{} {}", pitch_class_name(self.tonic()), mode)
+    }
+}
+
+/// The time and key signatures of a score, each in time order
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Signatures {
+    pub time: Vec<(Duration, TimeSignature)>,
+    pub key: Vec<(Duration, KeySignature)>,
+}
+
+impl Signatures {
+    /// Returns the time signature in effect at a moment of the score, 4/4 if the score
+    /// doesn't specify one
+    pub fn time_at(&self, time: Duration) -> TimeSignature {
+        signature_at(&self.time, time).unwrap_or(DEFAULT_TIME_SIGNATURE)
+    }
+
+    /// Returns the key signature in effect at a moment of the score, if the score
+    /// specifies one
+    pub fn key_at(&self, time: Duration) -> Option<KeySignature> {
+        signature_at(&self.key, time)
+    }
+}
+
+/// Finds the last signature starting at or before a moment, or the first one if all
+/// start later
+fn signature_at<T: Copy>(signatures: &[(Duration, T)], time: Duration) -> Option<T> {
+    signatures
+        .iter()
+        .rev()
+        .find(|(start, _)| *start <= time)
+        .or_else(|| signatures.first())
+        .map(|(_, signature)| *signature)
+}
+
+enum Signature {
+    Time(TimeSignature),
+    Key(KeySignature),
+}
+
+/// Extracts the time and key signatures from the raw bytes of a MIDI file
+pub fn load_signatures(data: &[u8]) -> Result<Signatures, LoadError> {
+    let mut signatures = Signatures::default();
+    let events = load_meta_events(data, |message| match *message {
+        MetaMessage::TimeSignature(numerator, denominator_power, ..) => {
+            Some(Signature::Time(TimeSignature {
+                numerator,
+                denominator: 1 << denominator_power.min(7),
+            }))
+        }
+        MetaMessage::KeySignature(sharps, minor) => {
+            Some(Signature::Key(KeySignature { sharps, minor }))
+        }
+        _ => None,
+    })?;
+    for (time, signature) in events {
+        match signature {
+            Signature::Time(signature) => signatures.time.push((time, signature)),
+            Signature::Key(signature) => signatures.key.push((time, signature)),
+        }
+    }
+    Ok(signatures)
+}
+
+pub fn load_midi_file_signatures(path: &Path) -> Result<Signatures, LoadError> {
+    let data = std::fs::read(path)?;
+    load_signatures(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use midly::num::{u15, u24, u28};
+    use midly::{Format, Header, Smf, Timing, TrackEvent, TrackEventKind};
+    use rstest::rstest;
+
+    #[rstest(sharps, minor, expected,
+        case(0, false, "C major"),
+        case(1, false, "G major"),
+        case(-3, false, "Eb major"),
+        case(0, true, "A minor"),
+        case(2, true, "H minor"),
+        case(-1, true, "D minor"),
+    )]
+    fn key_signature_names(sharps: i8, minor: bool, expected: &str) {
+        assert_eq!(KeySignature { sharps, minor }.to_string(), expected);
+    }
+
+    #[test]
+    fn load_time_and_key_signatures() {
+        let meta = |delta: u32, message| TrackEvent {
+            delta: u28::from(delta),
+            kind: TrackEventKind::Meta(message),
+        };
+        let smf = Smf {
+            header: Header::new(Format::SingleTrack, Timing::Metrical(u15::from(480))),
+            tracks: vec![vec![
+                meta(0, MetaMessage::Tempo(u24::from(500_000))),
+                meta(0, MetaMessage::TimeSignature(3, 2, 24, 8)),
+                meta(0, MetaMessage::KeySignature(-2, false)),
+                meta(960, MetaMessage::TimeSignature(6, 3, 36, 8)),
+                meta(0, MetaMessage::KeySignature(1, true)),
+                meta(0, MetaMessage::EndOfTrack),
+            ]],
+        };
+        let mut data = vec![];
+        smf.write_std(&mut data).unwrap();
+        let signatures = load_signatures(&data).unwrap();
+        let three_four = TimeSignature {
+            numerator: 3,
+            denominator: 4,
+        };
+        let six_eight = TimeSignature {
+            numerator: 6,
+            denominator: 8,
+        };
+        assert_eq!(
+            signatures.time,
+            [
+                (Duration::from_secs(0), three_four),
+                (Duration::from_secs(1), six_eight)
+            ]
+        );
+        assert_eq!(signatures.time_at(Duration::from_millis(999)), three_four);
+        assert_eq!(signatures.time_at(Duration::from_secs(5)), six_eight);
+        assert_eq!(six_eight.measure_length(), 3.0);
+        assert_eq!(six_eight.beat_length(), 1.5);
+        assert_eq!(three_four.beat_length(), 1.0);
+        assert_eq!(
+            signatures.key_at(Duration::from_secs(1)),
+            Some(KeySignature {
+                sharps: 1,
+                minor: true
+            })
+        );
+    }
+
+    #[test]
+    fn default_signatures() {
+        let signatures = Signatures::default();
+        assert_eq!(signatures.time_at(Duration::ZERO), DEFAULT_TIME_SIGNATURE);
+        assert_eq!(signatures.key_at(Duration::ZERO), None);
+    }
+}
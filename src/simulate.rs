@@ -0,0 +1,171 @@
+use crate::rng::SeededRng;
+use crate::score::ScoreNote;
+use midly::num::u7;
+use std::str::FromStr;
+
+/// Number of simultaneous notes in each chord of [`StressPattern::PolyphonyBurst`]
+const BURST_SIZE: usize = 32;
+
+/// Parameters for simulating a human performance of a score
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Performance {
+    /// Live time elapsed per score time, e.g. 2.0 plays at half the tempo
+    pub stretch_factor: f32,
+    /// Maximum random deviation of each note from its exact time, in microseconds
+    pub jitter: u64,
+    /// Probability of playing a wrong pitch (one semitone off) instead of a score note
+    pub wrong_note_rate: f32,
+}
+
+/// Simulates a live performance of a score
+///
+/// # Arguments
+///
+/// * score - The score to perform
+/// * performance - How accurately and how fast to perform the score
+/// * rng - The source of randomness, seeded by the caller for reproducible results
+///
+/// # Return value
+///
+/// The performed notes, with times starting from zero and in chronological order
+pub fn simulate_performance(
+    score: &[ScoreNote],
+    performance: Performance,
+    rng: &mut SeededRng,
+) -> Vec<ScoreNote> {
+    let start = score.first().map_or(0, |note| note.time);
+    let mut live = score
+        .iter()
+        .map(|note| {
+            let exact = ((note.time - start) as f32 * performance.stretch_factor) as u64;
+            let deviation = rng.below(2 * performance.jitter + 1);
+            let time = (exact + deviation).saturating_sub(performance.jitter);
+            let pitch = if rng.next_f32() < performance.wrong_note_rate {
+                let pitch = note.pitch.as_int();
+                u7::from(if pitch < 127 { pitch + 1 } else { pitch - 1 })
+            } else {
+                note.pitch
+            };
+            ScoreNote { time, pitch }
+        })
+        .collect::<Vec<_>>();
+    live.sort_by_key(|note| note.time);
+    live
+}
+
+/// A pathological live input for testing that score followers cope with corner cases
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StressPattern {
+    /// Bursts of many random pitches all at the same moment
+    PolyphonyBurst,
+    /// The same pitch over and over again
+    RepeatedPitch,
+    /// Random pitches at random times
+    RandomNoise,
+}
+
+impl FromStr for StressPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "polyphony-burst" => Ok(StressPattern::PolyphonyBurst),
+            "repeated-pitch" => Ok(StressPattern::RepeatedPitch),
+            "random-noise" => Ok(StressPattern::RandomNoise),
+            _ => Err(format!("unknown stress pattern '{}'", s)),
+        }
+    }
+}
+
+/// Generates a pathological live input
+///
+/// # Arguments
+///
+/// * pattern - The kind of input to generate
+/// * length - The number of notes to generate
+/// * rng - The source of randomness, seeded by the caller for reproducible results
+///
+/// # Return value
+///
+/// The generated notes, with times starting from zero and in chronological order
+pub fn stress_stream(pattern: StressPattern, length: usize, rng: &mut SeededRng) -> Vec<ScoreNote> {
+    let mut time = 0;
+    (0..length)
+        .map(|index| {
+            let pitch = match pattern {
+                StressPattern::RepeatedPitch => u7::from(60),
+                _ => u7::from(rng.below(128) as u8),
+            };
+            time += match pattern {
+                StressPattern::PolyphonyBurst if index % BURST_SIZE != 0 => 0,
+                StressPattern::RandomNoise => rng.below(100_000),
+                _ => 100_000,
+            };
+            ScoreNote { time, pitch }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_performance() {
+        let score = notes![(1000, 60), (1100, 62), (1300, 64)];
+        let performance = Performance {
+            stretch_factor: 2.0,
+            jitter: 0,
+            wrong_note_rate: 0.0,
+        };
+        let live = simulate_performance(&score, performance, &mut SeededRng::new(1));
+        assert_eq!(live, notes![(0, 60), (200, 62), (600, 64)]);
+    }
+
+    #[test]
+    fn sloppy_performance_is_reproducible() {
+        let score = notes![(0, 60), (100, 62), (200, 64), (300, 65)];
+        let performance = Performance {
+            stretch_factor: 1.0,
+            jitter: 20,
+            wrong_note_rate: 0.5,
+        };
+        let live1 = simulate_performance(&score, performance, &mut SeededRng::new(3));
+        let live2 = simulate_performance(&score, performance, &mut SeededRng::new(3));
+        assert_eq!(live1, live2);
+        assert!(live1
+            .iter()
+            .zip(&score)
+            .all(|(live, expect)| live.time.abs_diff(expect.time) <= 20));
+    }
+
+    #[test]
+    fn polyphony_burst() {
+        let live = stress_stream(
+            StressPattern::PolyphonyBurst,
+            2 * BURST_SIZE,
+            &mut SeededRng::new(1),
+        );
+        assert_eq!(live.len(), 2 * BURST_SIZE);
+        assert!(live[..BURST_SIZE].iter().all(|note| note.time == 100_000));
+        assert!(live[BURST_SIZE..].iter().all(|note| note.time == 200_000));
+    }
+
+    #[test]
+    fn repeated_pitch() {
+        let live = stress_stream(StressPattern::RepeatedPitch, 3, &mut SeededRng::new(1));
+        assert_eq!(live, notes![(100_000, 60), (200_000, 60), (300_000, 60)]);
+    }
+
+    #[test]
+    fn random_noise_is_chronological() {
+        let live = stress_stream(StressPattern::RandomNoise, 100, &mut SeededRng::new(5));
+        assert!(live.windows(2).all(|pair| pair[0].time <= pair[1].time));
+    }
+
+    #[test]
+    fn parse_stress_pattern() {
+        assert_eq!("random-noise".parse(), Ok(StressPattern::RandomNoise));
+        assert!("silence".parse::<StressPattern>().is_err());
+    }
+}
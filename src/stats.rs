@@ -0,0 +1,169 @@
+use crate::score::ScoreNote;
+use crate::Match;
+use std::fmt;
+
+/// Running statistics of how well the live performance matches the score
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchStats {
+    /// The number of live notes received
+    pub live_notes: usize,
+    /// The number of live notes matched with the score
+    pub matched: usize,
+    /// Absolute timing errors between consecutive matched notes in microseconds, in
+    /// ascending order
+    ///
+    /// Each error compares the live time elapsed between two consecutive matches with
+    /// the score time elapsed between them, stretched to the current tempo.
+    pub timing_errors: Vec<u64>,
+    /// The variance of the tempo between consecutive matches, as the variance of their
+    /// time stretch factors, or `None` if fewer than two could be measured
+    pub tempo_variance: Option<f64>,
+}
+
+impl MatchStats {
+    /// The ratio of matched live notes, between 0.0 and 1.0
+    pub fn match_rate(&self) -> f64 {
+        if self.live_notes == 0 {
+            return 0.0;
+        }
+        self.matched as f64 / self.live_notes as f64
+    }
+
+    /// The mean absolute timing error in microseconds, or `None` if nothing was measured
+    pub fn mean_timing_error(&self) -> Option<f64> {
+        if self.timing_errors.is_empty() {
+            return None;
+        }
+        Some(self.timing_errors.iter().sum::<u64>() as f64 / self.timing_errors.len() as f64)
+    }
+
+    /// Returns a percentile of the absolute timing errors using the nearest-rank method
+    ///
+    /// # Arguments
+    ///
+    /// * percent - The percentile to return, between 0.0 and 100.0
+    ///
+    /// # Return value
+    ///
+    /// The timing error in microseconds, or `None` if nothing was measured
+    pub fn timing_error_percentile(&self, percent: f64) -> Option<u64> {
+        let count = self.timing_errors.len();
+        if count == 0 {
+            return None;
+        }
+        let rank = (percent / 100.0 * count as f64).ceil() as usize;
+        Some(self.timing_errors[rank.clamp(1, count) - 1])
+    }
+}
+
+impl fmt::Display for MatchStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ms = |time: Option<f64>| time.map_or("-".to_string(), |t| format!("{:.0}", t / 1000.0));
+        write!(
+            f,
+            "matched {}/{} ({:.0}%), timing error mean {} ms, median {} ms, 90th percentile {} ms, tempo variance {}",
+            self.matched,
+            self.live_notes,
+            100.0 * self.match_rate(),
+            ms(self.mean_timing_error()),
+            ms(self.timing_error_percentile(50.0).map(|t| t as f64)),
+            ms(self.timing_error_percentile(90.0).map(|t| t as f64)),
+            self.tempo_variance
+                .map_or("-".to_string(), |variance| format!("{:.4}", variance)),
+        )
+    }
+}
+
+/// Computes statistics of a follower's matches
+///
+/// # Arguments
+///
+/// * score - The complete expected musical score with timestamps and pitches
+/// * live - The live performance received so far
+/// * matches - All matches between the live performance and the score, in live order
+/// * stretch_factor - The current time stretch factor of the follower
+pub fn match_stats(
+    score: &[ScoreNote],
+    live: &[ScoreNote],
+    matches: &[Match],
+    stretch_factor: f32,
+) -> MatchStats {
+    let mut timing_errors = vec![];
+    let mut stretch_factors = vec![];
+    for pair in matches.windows(2) {
+        let elapsed_live =
+            live[pair[1].live_index].time as f64 - live[pair[0].live_index].time as f64;
+        let elapsed_score =
+            score[pair[1].score_index].time as f64 - score[pair[0].score_index].time as f64;
+        let error = elapsed_live - elapsed_score * stretch_factor as f64;
+        timing_errors.push(error.abs().round() as u64);
+        // notes of a chord tell nothing about the tempo
+        if elapsed_score > 0.0 {
+            stretch_factors.push(elapsed_live / elapsed_score);
+        }
+    }
+    timing_errors.sort_unstable();
+    let tempo_variance = (stretch_factors.len() >= 2).then(|| {
+        let mean = stretch_factors.iter().sum::<f64>() / stretch_factors.len() as f64;
+        stretch_factors
+            .iter()
+            .map(|factor| (factor - mean).powi(2))
+            .sum::<f64>()
+            / stretch_factors.len() as f64
+    });
+    MatchStats {
+        live_notes: live.len(),
+        matched: matches.len(),
+        timing_errors,
+        tempo_variance,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn no_live_notes() {
+        let stats = match_stats(&notes![(0, 60)], &[], &[], 1.0);
+        assert_approx_eq!(stats.match_rate(), 0.0);
+        assert_eq!(stats.mean_timing_error(), None);
+        assert_eq!(stats.timing_error_percentile(50.0), None);
+        assert_eq!(stats.tempo_variance, None);
+    }
+
+    #[test]
+    fn steady_performance() {
+        let score = notes![(0, 60), (100, 62), (200, 64)];
+        let live = notes![(0, 60), (150, 61), (200, 62), (400, 64)];
+        let matches = [Match::new(0, 0), Match::new(1, 2), Match::new(2, 3)];
+        let stats = match_stats(&score, &live, &matches, 2.0);
+        assert_approx_eq!(stats.match_rate(), 0.75);
+        assert_eq!(stats.timing_errors, [0, 0]);
+        assert_approx_eq!(stats.tempo_variance.unwrap(), 0.0);
+    }
+
+    #[test]
+    fn uneven_performance() {
+        let score = notes![(0, 60), (100, 62), (200, 64), (300, 65)];
+        let live = notes![(0, 60), (100, 62), (300, 64), (400, 65)];
+        let matches = [
+            Match::new(0, 0),
+            Match::new(1, 1),
+            Match::new(2, 2),
+            Match::new(3, 3),
+        ];
+        let stats = match_stats(&score, &live, &matches, 1.0);
+        assert_eq!(stats.timing_errors, [0, 0, 100]);
+        assert_approx_eq!(stats.mean_timing_error().unwrap(), 33.333, 1e-3);
+        assert_eq!(stats.timing_error_percentile(50.0), Some(0));
+        assert_eq!(stats.timing_error_percentile(90.0), Some(100));
+        // stretch factors 1.0, 2.0 and 1.0
+        assert_approx_eq!(stats.tempo_variance.unwrap(), 2.0 / 9.0);
+        assert_eq!(
+            stats.to_string(),
+            "matched 4/4 (100%), timing error mean 0 ms, median 0 ms, 90th percentile 0 ms, tempo variance 0.2222"
+        );
+    }
+}
@@ -0,0 +1,404 @@
+use crate::cache::{read_f64, read_varint, write_f64, write_varint};
+use crate::score::{merge_smf_tracks, LoadError};
+use crate::signature::{TimeSignature, DEFAULT_TIME_SIGNATURE};
+use midly::{MetaMessage, Smf, Timing, TrackEventKind::Meta};
+use std::path::Path;
+use std::time::Duration;
+
+/// The tempo of a MIDI file without tempo events, in microseconds per beat
+const DEFAULT_TEMPO: u64 = 500_000;
+
+/// A stretch of the score with a constant tempo
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct TempoSegment {
+    /// The beat where the segment starts
+    beat: f64,
+    /// The time where the segment starts, in microseconds
+    time: u64,
+    /// The length of a beat in microseconds
+    beat_length: u64,
+}
+
+/// A stretch of the score with a constant time signature
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct MeterSegment {
+    /// The beat where the segment starts
+    beat: f64,
+    /// The number of the first measure of the segment, counting from 1
+    measure: usize,
+    /// The length of a measure in beats
+    measure_length: f64,
+    /// The length of a counted beat of the time signature in beats
+    click_length: f64,
+}
+
+/// The tempo changes and time signatures of a MIDI file
+///
+/// Beats are quarter notes as in the MIDI file format, counted from 0 at the start of
+/// the file. Measures are counted from 1 like in printed scores.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TempoMap {
+    tempos: Vec<TempoSegment>,
+    meters: Vec<MeterSegment>,
+}
+
+impl TempoMap {
+    /// Extracts the tempo map of a parsed MIDI file
+    ///
+    /// # Return value
+    ///
+    /// The tempo map, or `None` if the file uses SMPTE timecode instead of beats
+    pub fn from_smf(smf: &Smf) -> Option<Self> {
+        let ticks_per_beat = match smf.header.timing {
+            Timing::Metrical(ticks) => ticks.as_int() as f64,
+            Timing::Timecode(..) => return None,
+        };
+        let mut tempos = vec![TempoSegment {
+            beat: 0.0,
+            time: 0,
+            beat_length: DEFAULT_TEMPO,
+        }];
+        let mut signatures = vec![(0.0, DEFAULT_TIME_SIGNATURE)];
+        for (ticks, _, event) in merge_smf_tracks(smf) {
+            let beat = ticks as f64 / ticks_per_beat;
+            match event {
+                Meta(MetaMessage::Tempo(beat_length)) => {
+                    let time = beat_to_micros(&tempos, beat);
+                    // of several tempo events at the same moment, the last one wins
+                    tempos.retain(|segment| segment.beat < beat);
+                    tempos.push(TempoSegment {
+                        beat,
+                        time,
+                        beat_length: beat_length.as_int() as u64,
+                    });
+                }
+                Meta(MetaMessage::TimeSignature(numerator, denominator_power, ..)) => {
+                    signatures.retain(|&(start, _)| start < beat);
+                    signatures.push((
+                        beat,
+                        TimeSignature {
+                            numerator,
+                            denominator: 1 << denominator_power.min(7),
+                        },
+                    ));
+                }
+                _ => {}
+            }
+        }
+        let mut meters: Vec<MeterSegment> = vec![];
+        for (beat, signature) in signatures {
+            let measure = match meters.last() {
+                // a time signature change in the middle of a measure starts a new one
+                Some(prev) => {
+                    prev.measure + ((beat - prev.beat) / prev.measure_length).ceil() as usize
+                }
+                None => 1,
+            };
+            meters.push(MeterSegment {
+                beat,
+                measure,
+                measure_length: signature.measure_length(),
+                click_length: signature.beat_length(),
+            });
+        }
+        Some(Self { tempos, meters })
+    }
+
+    /// Appends the tempo map to a score cache file, see [`crate::cache`]
+    pub(crate) fn encode(&self, bytes: &mut Vec<u8>) {
+        write_varint(bytes, self.tempos.len() as u64);
+        for segment in &self.tempos {
+            write_f64(bytes, segment.beat);
+            write_varint(bytes, segment.time);
+            write_varint(bytes, segment.beat_length);
+        }
+        write_varint(bytes, self.meters.len() as u64);
+        for segment in &self.meters {
+            write_f64(bytes, segment.beat);
+            write_varint(bytes, segment.measure as u64);
+            write_f64(bytes, segment.measure_length);
+            write_f64(bytes, segment.click_length);
+        }
+    }
+
+    /// Reads a tempo map written by [`TempoMap::encode`]
+    ///
+    /// # Return value
+    ///
+    /// The tempo map, or `None` if the bytes end early or the map has no segments
+    pub(crate) fn decode(bytes: &mut impl Iterator<Item = u8>) -> Option<Self> {
+        let mut tempos = vec![];
+        for _ in 0..read_varint(bytes)? {
+            tempos.push(TempoSegment {
+                beat: read_f64(bytes)?,
+                time: read_varint(bytes)?,
+                beat_length: read_varint(bytes)?,
+            });
+        }
+        let mut meters = vec![];
+        for _ in 0..read_varint(bytes)? {
+            meters.push(MeterSegment {
+                beat: read_f64(bytes)?,
+                measure: read_varint(bytes)? as usize,
+                measure_length: read_f64(bytes)?,
+                click_length: read_f64(bytes)?,
+            });
+        }
+        // lookups fall back to the first segments, so both lists need at least one
+        match tempos.is_empty() || meters.is_empty() {
+            true => None,
+            false => Some(Self { tempos, meters }),
+        }
+    }
+
+    /// The tempo changes as the time of each change and the new tempo in beats per
+    /// minute
+    pub fn changes(&self) -> Vec<(Duration, f64)> {
+        self.tempos
+            .iter()
+            .map(|segment| {
+                (
+                    Duration::from_micros(segment.time),
+                    60_000_000.0 / segment.beat_length as f64,
+                )
+            })
+            .collect()
+    }
+
+    /// Converts a beat position into time from the start of the score
+    pub fn beat_to_time(&self, beat: f64) -> Duration {
+        Duration::from_micros(beat_to_micros(&self.tempos, beat))
+    }
+
+    /// Converts time from the start of the score into a beat position
+    pub fn time_to_beat(&self, time: Duration) -> f64 {
+        let time = time.as_micros() as u64;
+        let segment = self
+            .tempos
+            .iter()
+            .rev()
+            .find(|segment| segment.time <= time)
+            .unwrap_or(&self.tempos[0]);
+        segment.beat + (time - segment.time) as f64 / segment.beat_length as f64
+    }
+
+    /// Returns the beat where a measure starts
+    ///
+    /// # Arguments
+    ///
+    /// * measure - The number of the measure, counting from 1
+    pub fn measure_to_beat(&self, measure: usize) -> f64 {
+        let meter = self
+            .meters
+            .iter()
+            .rev()
+            .find(|meter| meter.measure <= measure)
+            .unwrap_or(&self.meters[0]);
+        meter.beat + (measure.max(1) - meter.measure) as f64 * meter.measure_length
+    }
+
+    /// Returns the time from the start of the score where a measure starts
+    ///
+    /// # Arguments
+    ///
+    /// * measure - The number of the measure, counting from 1
+    pub fn measure_to_time(&self, measure: usize) -> Duration {
+        self.beat_to_time(self.measure_to_beat(measure))
+    }
+
+    /// Returns the time from the start of the score of a position in measures and beats
+    ///
+    /// # Arguments
+    ///
+    /// * measure - The number of the measure, counting from 1
+    /// * beat - The beat within the measure, counting from 0
+    pub fn measure_beat_to_time(&self, measure: usize, beat: f64) -> Duration {
+        self.beat_to_time(self.measure_to_beat(measure) + beat)
+    }
+
+    /// Formats the position of a moment as `measure:beat`, both counting from 1 like in
+    /// printed scores
+    pub fn format_position(&self, time: Duration) -> String {
+        let (measure, beat) = self.time_to_measure(time);
+        format!("{}:{:.1}", measure, beat + 1.0)
+    }
+
+    /// Lists the counted beats of the time signatures up to a time, e.g. for a metronome
+    ///
+    /// Beats are counted from the start of each measure, and are dotted quarter notes
+    /// in compound meters like 6/8.
+    ///
+    /// # Return value
+    ///
+    /// The time of each beat before `end`, and whether the beat starts a measure
+    pub fn counted_beats(&self, end: Duration) -> Vec<(Duration, bool)> {
+        let end_beat = self.time_to_beat(end);
+        let mut beats = vec![];
+        for (index, meter) in self.meters.iter().enumerate() {
+            let segment_end = self
+                .meters
+                .get(index + 1)
+                .map_or(end_beat, |next| next.beat.min(end_beat));
+            let mut measure_start = meter.beat;
+            while measure_start < segment_end {
+                // a time signature change can cut the measure short
+                let measure_end = (measure_start + meter.measure_length).min(segment_end);
+                let mut beat = measure_start;
+                while beat < measure_end - 1e-9 {
+                    beats.push((self.beat_to_time(beat), beat == measure_start));
+                    beat += meter.click_length;
+                }
+                measure_start += meter.measure_length;
+            }
+        }
+        beats
+    }
+
+    /// Finds the position of a moment in measures and beats
+    ///
+    /// # Return value
+    ///
+    /// A 2-tuple of
+    /// * the number of the measure, counting from 1
+    /// * the beat within the measure, counting from 0
+    pub fn time_to_measure(&self, time: Duration) -> (usize, f64) {
+        let beat = self.time_to_beat(time);
+        let meter = self
+            .meters
+            .iter()
+            .rev()
+            .find(|meter| meter.beat <= beat)
+            .unwrap_or(&self.meters[0]);
+        let measures = ((beat - meter.beat) / meter.measure_length).floor();
+        (
+            meter.measure + measures as usize,
+            beat - meter.beat - measures * meter.measure_length,
+        )
+    }
+}
+
+/// Converts a beat position into microseconds using tempo segments in beat order
+fn beat_to_micros(tempos: &[TempoSegment], beat: f64) -> u64 {
+    let segment = tempos
+        .iter()
+        .rev()
+        .find(|segment| segment.beat <= beat)
+        .unwrap_or(&tempos[0]);
+    segment.time + ((beat - segment.beat) * segment.beat_length as f64).round() as u64
+}
+
+/// Extracts the tempo map from the raw bytes of a MIDI file, see [`TempoMap::from_smf`]
+pub fn load_tempo_map(data: &[u8]) -> Result<Option<TempoMap>, LoadError> {
+    Ok(TempoMap::from_smf(&Smf::parse(data)?))
+}
+
+pub fn load_midi_file_tempo_map(path: &Path) -> Result<Option<TempoMap>, LoadError> {
+    let data = std::fs::read(path)?;
+    load_tempo_map(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+    use midly::num::{u15, u24, u28};
+    use midly::{Format, Header, TrackEvent, TrackEventKind};
+
+    const TICKS_PER_BEAT: u16 = 480;
+
+    fn meta(delta_beats: u32, message: MetaMessage<'static>) -> TrackEvent<'static> {
+        TrackEvent {
+            delta: u28::from(delta_beats * TICKS_PER_BEAT as u32),
+            kind: TrackEventKind::Meta(message),
+        }
+    }
+
+    /// 4/4 at 120 bpm for two measures, then 3/4 at 60 bpm
+    fn tempo_map() -> TempoMap {
+        let smf = Smf {
+            header: Header::new(
+                Format::SingleTrack,
+                Timing::Metrical(u15::from(TICKS_PER_BEAT)),
+            ),
+            tracks: vec![vec![
+                meta(0, MetaMessage::Tempo(u24::from(500_000))),
+                meta(8, MetaMessage::Tempo(u24::from(1_000_000))),
+                meta(0, MetaMessage::TimeSignature(3, 2, 24, 8)),
+                meta(0, MetaMessage::EndOfTrack),
+            ]],
+        };
+        TempoMap::from_smf(&smf).unwrap()
+    }
+
+    #[test]
+    fn tempo_changes() {
+        assert_eq!(
+            tempo_map().changes(),
+            [
+                (Duration::from_secs(0), 120.0),
+                (Duration::from_secs(4), 60.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn convert_beats_and_time() {
+        let map = tempo_map();
+        assert_eq!(map.beat_to_time(2.0), Duration::from_secs(1));
+        assert_eq!(map.beat_to_time(10.0), Duration::from_secs(6));
+        assert_approx_eq!(map.time_to_beat(Duration::from_secs(1)), 2.0);
+        assert_approx_eq!(map.time_to_beat(Duration::from_millis(6500)), 10.5);
+    }
+
+    #[test]
+    fn convert_measures() {
+        let map = tempo_map();
+        assert_approx_eq!(map.measure_to_beat(2), 4.0);
+        assert_approx_eq!(map.measure_to_beat(4), 11.0);
+        assert_eq!(map.measure_to_time(3), Duration::from_secs(4));
+        let (measure, beat) = map.time_to_measure(Duration::from_secs(9));
+        assert_eq!(measure, 4);
+        assert_approx_eq!(beat, 2.0);
+        assert_eq!(map.measure_beat_to_time(4, 2.0), Duration::from_secs(9));
+        assert_eq!(map.format_position(Duration::from_millis(8500)), "4:2.5");
+    }
+
+    #[test]
+    fn list_counted_beats() {
+        let beats = tempo_map().counted_beats(Duration::from_secs(7));
+        let millis = beats
+            .iter()
+            .map(|(time, _)| time.as_millis() as u64)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            millis,
+            [0, 500, 1000, 1500, 2000, 2500, 3000, 3500, 4000, 5000, 6000]
+        );
+        let downbeats = beats.iter().filter(|(_, downbeat)| *downbeat).count();
+        assert_eq!(downbeats, 3);
+        assert!(beats[8].1);
+    }
+
+    #[test]
+    fn default_tempo() {
+        let smf = Smf {
+            header: Header::new(
+                Format::SingleTrack,
+                Timing::Metrical(u15::from(TICKS_PER_BEAT)),
+            ),
+            tracks: vec![vec![]],
+        };
+        let map = TempoMap::from_smf(&smf).unwrap();
+        assert_eq!(map.changes(), [(Duration::from_secs(0), 120.0)]);
+        assert_eq!(map.measure_to_time(2), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn timecode_has_no_tempo_map() {
+        let smf = Smf {
+            header: Header::new(Format::SingleTrack, Timing::Timecode(midly::Fps::Fps25, 40)),
+            tracks: vec![vec![meta(0, MetaMessage::Tempo(u24::from(500_000)))]],
+        };
+        assert_eq!(TempoMap::from_smf(&smf), None);
+    }
+}
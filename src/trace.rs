@@ -0,0 +1,86 @@
+use crate::follower::FollowResult;
+use std::fmt;
+use std::sync::mpsc::Sender;
+
+/// A destination for debug traces of the matching decisions of a follower
+///
+/// Followers only trace when a sink has been given to them, so nothing is formatted in
+/// the matching hot path unless somebody is listening.
+pub trait TraceSink {
+    /// Records one trace message
+    fn trace(&mut self, message: fmt::Arguments);
+}
+
+/// Writes traces to the standard error stream
+pub struct StderrTrace;
+
+impl TraceSink for StderrTrace {
+    fn trace(&mut self, message: fmt::Arguments) {
+        eprintln!("{}", message);
+    }
+}
+
+/// Sends traces to another thread, e.g. one which writes them to a log file without
+/// delaying score following
+impl TraceSink for Sender<String> {
+    fn trace(&mut self, message: fmt::Arguments) {
+        // a receiver which has gone away just stops tracing
+        let _ = self.send(message.to_string());
+    }
+}
+
+/// Traces the matching decisions reported in the result of following new live notes
+pub(crate) fn trace_result(sink: &mut Option<Box<dyn TraceSink + '_>>, result: &FollowResult) {
+    let sink = match sink {
+        Some(sink) => sink,
+        None => return,
+    };
+    for m in &result.retracted {
+        sink.trace(format_args!(
+            "retracted live {} -> score {}",
+            m.live_index, m.score_index
+        ));
+    }
+    for m in &result.new_matches {
+        sink.trace(format_args!(
+            "matched live {} -> score {}",
+            m.live_index, m.score_index
+        ));
+    }
+    for (live_index, reason) in &result.ignored {
+        sink.trace(format_args!("ignored live {}: {:?}", live_index, reason));
+    }
+    sink.trace(format_args!(
+        "score time {}, stretch factor {:.3}",
+        result.score_time, result.stretch_factor
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::follower::{HomophonoPedantic, ScoreFollower};
+    use std::sync::mpsc;
+
+    #[test]
+    fn trace_matching_decisions() {
+        let score = notes![(0, 60), (100, 62)];
+        let (tx, rx) = mpsc::channel();
+        let mut follower = HomophonoPedantic::new(&score).with_trace(Box::new(tx));
+        for note in notes![(0, 60), (50, 61), (100, 62)] {
+            follower.push_live(note);
+            follower.follow_score();
+        }
+        drop(follower);
+        assert_eq!(
+            rx.iter().collect::<Vec<_>>(),
+            [
+                "matched live 0 -> score 0",
+                "score time 0, stretch factor 1.000",
+                "ignored live 1: WrongPitch",
+                "score time 50, stretch factor 1.000",
+                "matched live 2 -> score 1",
+                "score time 100, stretch factor 1.000",
+            ]
+        );
+    }
+}
@@ -0,0 +1,369 @@
+use crate::follower::{diff_matches, FollowResult, ScoreFollower};
+use crate::score::{ScoreEvent, ScoreNote};
+use crate::{IgnoreReason, Match};
+use midly::num::u7;
+use midly::MidiMessage::{Aftertouch, NoteOff, NoteOn};
+use std::time::Duration;
+
+/// Shifts a pitch by a number of semitones
+///
+/// # Return value
+///
+/// The shifted pitch, or `None` if it falls outside the MIDI pitch range
+pub fn transpose_pitch(pitch: u7, offset: i8) -> Option<u7> {
+    let shifted = pitch.as_int() as i16 + offset as i16;
+    u7::try_from(u8::try_from(shifted).ok()?)
+}
+
+/// Shifts all pitches of a score by a number of semitones, e.g. for accompanying a
+/// transposing instrument
+///
+/// # Return value
+///
+/// The transposed score, or an error naming the first note which would fall outside the
+/// MIDI pitch range
+pub fn transpose_score(score: &[ScoreNote], semitones: i8) -> Result<Vec<ScoreNote>, String> {
+    score
+        .iter()
+        .enumerate()
+        .map(
+            |(index, note)| match transpose_pitch(note.pitch, semitones) {
+                Some(pitch) => Ok(ScoreNote { pitch, ..*note }),
+                None => Err(format!(
+                    "score note {} at {:.3} s can't be transposed by {} semitones",
+                    index,
+                    note.time as f64 / 1000000.0,
+                    semitones
+                )),
+            },
+        )
+        .collect()
+}
+
+/// Shifts all pitches of a playback score by a number of semitones like
+/// [`transpose_score`]
+///
+/// # Return value
+///
+/// The transposed events, or an error naming the first note which would fall outside
+/// the MIDI pitch range
+pub fn transpose_events(events: &[ScoreEvent], semitones: i8) -> Result<Vec<ScoreEvent>, String> {
+    events
+        .iter()
+        .map(|event| {
+            let message = match event.message {
+                NoteOn { key, vel } => {
+                    transpose_pitch(key, semitones).map(|key| NoteOn { key, vel })
+                }
+                NoteOff { key, vel } => {
+                    transpose_pitch(key, semitones).map(|key| NoteOff { key, vel })
+                }
+                Aftertouch { key, vel } => {
+                    transpose_pitch(key, semitones).map(|key| Aftertouch { key, vel })
+                }
+                message => Some(message),
+            };
+            match message {
+                Some(message) => Ok(ScoreEvent { message, ..*event }),
+                None => Err(format!(
+                    "playback note at {:.3} s can't be transposed by {} semitones",
+                    event.time as f64 / 1000000.0,
+                    semitones
+                )),
+            }
+        })
+        .collect()
+}
+
+/// Counts the notes of the longest common subsequence of two pitch sequences
+fn common_subsequence_length(a: &[u7], b: &[u7]) -> usize {
+    let mut previous = vec![0; b.len() + 1];
+    for &pitch in a {
+        let mut current = vec![0; b.len() + 1];
+        for (j, &other) in b.iter().enumerate() {
+            current[j + 1] = if pitch == other {
+                previous[j] + 1
+            } else {
+                previous[j + 1].max(current[j])
+            };
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Detects a consistent transposition between the opening of the score and the first
+/// phrase of the live performance
+///
+/// Each candidate offset is applied to the live phrase, and the offset under which most
+/// live notes can be matched in order against the opening of the score wins. Of equally
+/// good offsets, the smallest transposition is preferred.
+///
+/// # Arguments
+///
+/// * score - The complete expected musical score with timestamps and pitches
+/// * live - The first phrase of the live performance
+/// * max_offset - The largest transposition to consider, in semitones
+///
+/// # Return value
+///
+/// The offset in semitones to add to live pitches to get score pitches, or `None` if no
+/// offset lets at least half of the live notes be matched
+pub fn detect_transposition(score: &[ScoreNote], live: &[ScoreNote], max_offset: u8) -> Option<i8> {
+    // allow for extra notes in the score, e.g. an accompaniment or ornaments
+    let opening = score
+        .iter()
+        .take(2 * live.len())
+        .map(|note| note.pitch)
+        .collect::<Vec<_>>();
+    let max_offset = max_offset.min(i8::MAX as u8) as i8;
+    let mut offsets = (-max_offset..=max_offset).collect::<Vec<_>>();
+    offsets.sort_by_key(|offset| offset.abs());
+    let mut best: Option<(usize, i8)> = None;
+    for offset in offsets {
+        let shifted = live
+            .iter()
+            .filter_map(|note| transpose_pitch(note.pitch, offset))
+            .collect::<Vec<_>>();
+        let matching = common_subsequence_length(&shifted, &opening);
+        if best.is_none_or(|(best_matching, _)| matching > best_matching) {
+            best = Some((matching, offset));
+        }
+    }
+    best.filter(|&(matching, _)| matching > 0 && 2 * matching >= live.len())
+        .map(|(_, offset)| offset)
+}
+
+/// A score follower which detects a transposition in the first phrase of the live
+/// performance and then feeds transposed live notes to another follower
+///
+/// Until the first phrase is complete, live notes are followed without transposition.
+/// If a transposition is then detected, a fresh follower is created and the whole
+/// performance so far is replayed to it transposed.
+pub struct Transposing<'a> {
+    score: &'a [ScoreNote],
+    make_follower: Box<dyn Fn() -> Box<dyn ScoreFollower + 'a> + 'a>,
+    inner: Box<dyn ScoreFollower + 'a>,
+    phrase_length: usize,
+    max_offset: u8,
+    offset: Option<i8>,
+    /// Notes appended to the score since creating the follower, for replaying to a fresh
+    /// inner follower
+    extension: Vec<ScoreNote>,
+    /// The matches of the inner follower as last reported in a [`FollowResult`]
+    reported: Vec<Match>,
+}
+
+impl<'a> Transposing<'a> {
+    /// # Arguments
+    ///
+    /// * score - The complete expected musical score with timestamps and pitches
+    /// * make_follower - Creates the follower to feed transposed live notes to
+    /// * phrase_length - The number of live notes to detect the transposition from
+    /// * max_offset - The largest transposition to consider, in semitones
+    pub fn new(
+        score: &'a [ScoreNote],
+        make_follower: impl Fn() -> Box<dyn ScoreFollower + 'a> + 'a,
+        phrase_length: usize,
+        max_offset: u8,
+    ) -> Self {
+        let inner = make_follower();
+        Self {
+            score,
+            make_follower: Box::new(make_follower),
+            inner,
+            phrase_length,
+            max_offset,
+            offset: None,
+            extension: vec![],
+            reported: vec![],
+        }
+    }
+
+    fn transposed(&self, note: ScoreNote) -> ScoreNote {
+        let offset = self.offset.unwrap_or(0);
+        ScoreNote {
+            time: note.time,
+            // out-of-range notes can't match anything, so keep them as they are
+            pitch: transpose_pitch(note.pitch, offset).unwrap_or(note.pitch),
+        }
+    }
+}
+
+impl ScoreFollower for Transposing<'_> {
+    fn push_live(&mut self, note: ScoreNote) {
+        let note = self.transposed(note);
+        self.inner.push_live(note);
+    }
+
+    fn push_note_off(&mut self, note: ScoreNote) {
+        let note = self.transposed(note);
+        self.inner.push_note_off(note);
+    }
+
+    fn follow_score(&mut self) -> FollowResult {
+        let live = self.inner.live();
+        if self.offset.is_none() && live.len() >= self.phrase_length {
+            let offset = detect_transposition(self.score, live, self.max_offset).unwrap_or(0);
+            self.offset = Some(offset);
+            if offset != 0 {
+                let live = live.to_vec();
+                self.inner = (self.make_follower)();
+                if !self.extension.is_empty() {
+                    self.inner.extend_score(&self.extension);
+                }
+                for note in live {
+                    let note = self.transposed(note);
+                    self.inner.push_live(note);
+                }
+            }
+        }
+        let result = self.inner.follow_score();
+        // replaying to a fresh follower replaces the alignment reported so far
+        let (new_matches, retracted) = diff_matches(&self.reported, self.inner.matches());
+        self.reported = self.inner.matches().to_vec();
+        FollowResult {
+            new_matches,
+            retracted,
+            ..result
+        }
+    }
+
+    fn live(&self) -> &[ScoreNote] {
+        self.inner.live()
+    }
+
+    fn matches(&self) -> &[Match] {
+        self.inner.matches()
+    }
+
+    fn ignored(&self) -> &[usize] {
+        self.inner.ignored()
+    }
+
+    fn ignore_reason(&self, live_index: usize) -> Option<IgnoreReason> {
+        self.inner.ignore_reason(live_index)
+    }
+
+    fn last_match(&self) -> Option<Match> {
+        self.inner.last_match()
+    }
+
+    fn tentative_count(&self) -> usize {
+        self.inner.tentative_count()
+    }
+
+    fn is_finished(&self) -> bool {
+        self.inner.is_finished()
+    }
+
+    fn estimated_score_time(&self, now: Duration) -> Option<u64> {
+        self.inner.estimated_score_time(now)
+    }
+
+    fn extend_score(&mut self, notes: &[ScoreNote]) {
+        self.inner.extend_score(notes);
+        self.extension.extend_from_slice(notes);
+    }
+
+    fn reanchor(&mut self, anchor: Match) {
+        self.inner.reanchor(anchor);
+        self.reported = self.inner.matches().to_vec();
+    }
+
+    fn confidence(&self) -> f32 {
+        self.inner.confidence()
+    }
+
+    fn transposition(&self) -> Option<i8> {
+        self.offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::follower::HomophonoPedantic;
+
+    #[test]
+    fn transpose_within_range() {
+        assert_eq!(transpose_pitch(u7::from(60), -3), Some(u7::from(57)));
+        assert_eq!(transpose_pitch(u7::from(126), 2), None);
+        assert_eq!(transpose_pitch(u7::from(1), -2), None);
+    }
+
+    #[test]
+    fn transpose_whole_score() {
+        let score = notes![(0, 60), (100, 120)];
+        assert_eq!(
+            transpose_score(&score, 7),
+            Ok(notes![(0, 67), (100, 127)].to_vec())
+        );
+        assert_eq!(
+            transpose_score(&score, 8),
+            Err("score note 1 at 0.000 s can't be transposed by 8 semitones".to_string())
+        );
+    }
+
+    #[test]
+    fn transpose_playback_events() {
+        use midly::num::u4;
+        use midly::MidiMessage::ProgramChange;
+        let event = |time, message| ScoreEvent {
+            time,
+            channel: u4::new(0),
+            message,
+        };
+        let note = |key| NoteOn {
+            key: u7::new(key),
+            vel: u7::new(64),
+        };
+        let program = ProgramChange {
+            program: u7::new(127),
+        };
+        assert_eq!(
+            transpose_events(&[event(0, program), event(0, note(60))], 7),
+            Ok(vec![event(0, program), event(0, note(67))])
+        );
+        assert_eq!(
+            transpose_events(&[event(500000, note(126))], 2),
+            Err("playback note at 0.500 s can't be transposed by 2 semitones".to_string())
+        );
+    }
+
+    #[test]
+    fn detect_offset() {
+        let score = notes![(0, 60), (100, 62), (200, 64), (300, 65), (400, 67)];
+        let live = notes![(0, 62), (100, 64), (200, 66), (300, 67)];
+        assert_eq!(detect_transposition(&score, &live, 6), Some(-2));
+    }
+
+    #[test]
+    fn detect_no_offset_despite_wrong_note() {
+        let score = notes![(0, 60), (100, 62), (200, 64), (300, 65), (400, 67)];
+        let live = notes![(0, 60), (100, 63), (200, 64), (300, 65)];
+        assert_eq!(detect_transposition(&score, &live, 6), Some(0));
+    }
+
+    #[test]
+    fn no_consistent_offset() {
+        let score = notes![(0, 60), (100, 60), (200, 60), (300, 60)];
+        let live = notes![(0, 80), (100, 81), (200, 82), (300, 83)];
+        assert_eq!(detect_transposition(&score, &live, 6), None);
+    }
+
+    #[test]
+    fn follow_transposed_performance() {
+        let score = notes![(0, 60), (100, 62), (200, 64), (300, 65), (400, 67)];
+        let live = notes![(0, 65), (100, 67), (200, 69), (300, 70), (400, 72)];
+        let mut follower =
+            Transposing::new(&score, || Box::new(HomophonoPedantic::new(&score)), 3, 6);
+        for note in live {
+            follower.push_live(note);
+            follower.follow_score();
+        }
+        assert_eq!(follower.transposition(), Some(-5));
+        assert_eq!(follower.matches().len(), 5);
+        assert!(follower.ignored().is_empty());
+    }
+}
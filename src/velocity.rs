@@ -0,0 +1,384 @@
+use crate::score::ScoreEvent;
+use midly::num::u7;
+use midly::{MidiMessage::NoteOn, Smf, TrackEventKind::Midi};
+
+/// A mapping of note velocities, for giving flat or over-quantized exports reasonable
+/// dynamics before playback
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VelocityCurve {
+    /// Scale all velocities so that the loudest note gets the maximum velocity 127
+    Normalize,
+    /// Stretch or squeeze the velocities linearly to fill a range
+    Range(u7, u7),
+    /// Divide the distance of each velocity from the mean velocity by a ratio, i.e.
+    /// reduce the dynamic range for ratios above 1 and expand it for ratios below 1
+    Compress(f64),
+    /// Raise velocities as fractions of the maximum velocity to a power, i.e. make
+    /// soft notes louder for exponents below 1 and quieter for exponents above 1
+    Gamma(f64),
+}
+
+impl std::str::FromStr for VelocityCurve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid velocity curve '{}'", s);
+        let positive = |number: &str| {
+            number
+                .parse::<f64>()
+                .ok()
+                .filter(|number| *number > 0.0)
+                .ok_or_else(invalid)
+        };
+        let velocity = |number: &str| {
+            number
+                .parse::<u8>()
+                .ok()
+                .filter(|number| *number > 0)
+                .and_then(u7::try_from)
+                .ok_or_else(invalid)
+        };
+        match s.split_once(':') {
+            None if s == "normalize" => Ok(VelocityCurve::Normalize),
+            Some(("range", range)) => {
+                let (min, max) = range.split_once('-').ok_or_else(invalid)?;
+                let (min, max) = (velocity(min)?, velocity(max)?);
+                if min > max {
+                    return Err(invalid());
+                }
+                Ok(VelocityCurve::Range(min, max))
+            }
+            Some(("compress", ratio)) => Ok(VelocityCurve::Compress(positive(ratio)?)),
+            Some(("gamma", exponent)) => Ok(VelocityCurve::Gamma(positive(exponent)?)),
+            _ => Err(format!("unknown velocity curve '{}'", s)),
+        }
+    }
+}
+
+impl VelocityCurve {
+    /// Maps the velocities of the notes of a score
+    ///
+    /// Mapped velocities are kept between 1 and 127, since a note-on with velocity 0
+    /// would end a note instead of starting it.
+    ///
+    /// # Arguments
+    ///
+    /// * velocities - The velocities of all notes of the score, which the normalizing,
+    ///   range and compression curves are fitted to
+    ///
+    /// # Return value
+    ///
+    /// The mapped velocity of each note
+    pub fn apply(&self, velocities: &[u7]) -> Vec<u7> {
+        let values = velocities
+            .iter()
+            .map(|velocity| velocity.as_int() as f64)
+            .collect::<Vec<_>>();
+        let lowest = values.iter().copied().fold(f64::INFINITY, f64::min);
+        let highest = values.iter().copied().fold(0.0, f64::max);
+        let mean = values.iter().sum::<f64>() / values.len().max(1) as f64;
+        values
+            .iter()
+            .map(|&value| {
+                let mapped = match *self {
+                    VelocityCurve::Normalize => value * 127.0 / highest,
+                    VelocityCurve::Range(min, max) => {
+                        let (min, max) = (min.as_int() as f64, max.as_int() as f64);
+                        match highest > lowest {
+                            true => min + (value - lowest) * (max - min) / (highest - lowest),
+                            false => (min + max) / 2.0,
+                        }
+                    }
+                    VelocityCurve::Compress(ratio) => mean + (value - mean) / ratio,
+                    VelocityCurve::Gamma(exponent) => 127.0 * (value / 127.0).powf(exponent),
+                };
+                u7::from(mapped.round().clamp(1.0, 127.0) as u8)
+            })
+            .collect()
+    }
+}
+
+/// The weight of the live velocity in [`VelocityMode::Scaled`] when none is given
+const DEFAULT_LIVE_WEIGHT: f64 = 0.5;
+
+/// Where the velocities of the notes played back come from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VelocityMode {
+    /// Keep the velocities of the playback score, i.e. the dynamics of the composer
+    Score,
+    /// Play every note with the velocity of the latest matched live note, i.e. follow
+    /// the dynamics of the performer
+    Live,
+    /// Blend the score velocity and the live velocity, giving the live velocity the
+    /// weight from 0 to 1
+    Scaled(f64),
+    /// Play every note with the same velocity
+    Fixed(u7),
+}
+
+impl std::str::FromStr for VelocityMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid velocity mode '{}'", s);
+        match s.split_once(':') {
+            None if s == "score" => Ok(VelocityMode::Score),
+            None if s == "live" => Ok(VelocityMode::Live),
+            None if s == "scaled" => Ok(VelocityMode::Scaled(DEFAULT_LIVE_WEIGHT)),
+            Some(("scaled", weight)) => weight
+                .parse::<f64>()
+                .ok()
+                .filter(|weight| (0.0..=1.0).contains(weight))
+                .map(VelocityMode::Scaled)
+                .ok_or_else(invalid),
+            Some(("fixed", velocity)) => velocity
+                .parse::<u8>()
+                .ok()
+                .filter(|velocity| *velocity > 0)
+                .and_then(u7::try_from)
+                .map(VelocityMode::Fixed)
+                .ok_or_else(invalid),
+            _ => Err(format!("unknown velocity mode '{}'", s)),
+        }
+    }
+}
+
+impl VelocityMode {
+    /// Chooses the velocity of a note played back
+    ///
+    /// # Arguments
+    ///
+    /// * score - The velocity of the note in the playback score
+    /// * live - The velocity of the latest matched live note, or `None` before the
+    ///   first match, in which case the score velocity is kept
+    ///
+    /// # Return value
+    ///
+    /// The velocity to play the note with, between 1 and 127
+    pub fn velocity(&self, score: u7, live: Option<u7>) -> u7 {
+        let (score, live) = match (*self, live) {
+            (VelocityMode::Fixed(velocity), _) => return velocity,
+            (_, None) | (VelocityMode::Score, _) => return score,
+            (_, Some(live)) => (score.as_int() as f64, live.as_int() as f64),
+        };
+        let weight = match *self {
+            VelocityMode::Scaled(weight) => weight,
+            _ => 1.0,
+        };
+        let blended = score + weight * (live - score);
+        u7::from(blended.round().clamp(1.0, 127.0) as u8)
+    }
+}
+
+/// Sets the velocities of the note-ons in playback events by a velocity mode, see
+/// [`VelocityMode::velocity`]
+pub fn apply_velocity_mode(events: &mut [ScoreEvent], mode: VelocityMode, live: Option<u7>) {
+    for event in events {
+        if let NoteOn { vel, .. } = &mut event.message {
+            if *vel > 0 {
+                *vel = mode.velocity(*vel, live);
+            }
+        }
+    }
+}
+
+/// Maps velocities in place
+fn remap(mut velocities: Vec<&mut u7>, curve: VelocityCurve) {
+    let values = velocities.iter().map(|vel| **vel).collect::<Vec<_>>();
+    for (vel, mapped) in velocities.iter_mut().zip(curve.apply(&values)) {
+        **vel = mapped;
+    }
+}
+
+/// Maps the velocities of the note-ons in a playback score
+pub fn remap_velocities(events: &mut [ScoreEvent], curve: VelocityCurve) {
+    let velocities = events
+        .iter_mut()
+        .filter_map(|event| match &mut event.message {
+            NoteOn { vel, .. } if *vel > 0 => Some(vel),
+            _ => None,
+        })
+        .collect();
+    remap(velocities, curve);
+}
+
+/// Maps the velocities of the note-ons in all tracks of a MIDI file, e.g. before saving
+/// it for playback
+pub fn remap_smf_velocities(smf: &mut Smf, curve: VelocityCurve) {
+    let velocities = smf
+        .tracks
+        .iter_mut()
+        .flatten()
+        .filter_map(|event| match &mut event.kind {
+            Midi {
+                message: NoteOn { vel, .. },
+                ..
+            } if *vel > 0 => Some(vel),
+            _ => None,
+        })
+        .collect();
+    remap(velocities, curve);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use midly::num::u4;
+    use rstest::rstest;
+
+    fn velocities(values: &[u8]) -> Vec<u7> {
+        values.iter().map(|&value| u7::from(value)).collect()
+    }
+
+    #[rstest(curve, expected,
+        case(VelocityCurve::Normalize, vec![40, 64, 127]),
+        case(VelocityCurve::Range(u7::from(50), u7::from(90)), vec![50, 61, 90]),
+        case(VelocityCurve::Compress(2.0), vec![29, 35, 51]),
+        case(VelocityCurve::Gamma(0.5), vec![50, 64, 90]),
+    )]
+    fn apply_curves(curve: VelocityCurve, expected: Vec<u8>) {
+        assert_eq!(
+            curve.apply(&velocities(&[20, 32, 64])),
+            velocities(&expected)
+        );
+    }
+
+    #[test]
+    fn flat_velocities_fill_range_midpoint() {
+        let curve = VelocityCurve::Range(u7::from(40), u7::from(80));
+        assert_eq!(curve.apply(&velocities(&[64, 64])), velocities(&[60, 60]));
+    }
+
+    #[test]
+    fn remap_only_note_ons() {
+        let event = |message| ScoreEvent {
+            time: 0,
+            channel: u4::from(0),
+            message,
+        };
+        let key = u7::from(60);
+        let mut events = [
+            event(NoteOn {
+                key,
+                vel: u7::from(32),
+            }),
+            event(NoteOn {
+                key,
+                vel: u7::from(0),
+            }),
+        ];
+        remap_velocities(&mut events, VelocityCurve::Normalize);
+        assert_eq!(
+            events.map(|event| event.message),
+            [
+                NoteOn {
+                    key,
+                    vel: u7::from(127)
+                },
+                NoteOn {
+                    key,
+                    vel: u7::from(0)
+                }
+            ]
+        );
+    }
+
+    #[rstest(
+        spec,
+        expected,
+        case("normalize", VelocityCurve::Normalize),
+        case("range:40-100", VelocityCurve::Range(u7::from(40), u7::from(100))),
+        case("compress:2", VelocityCurve::Compress(2.0)),
+        case("gamma:0.8", VelocityCurve::Gamma(0.8))
+    )]
+    fn parse_velocity_curve(spec: &str, expected: VelocityCurve) {
+        assert_eq!(spec.parse(), Ok(expected));
+    }
+
+    #[rstest(
+        mode,
+        live,
+        expected,
+        case(VelocityMode::Score, Some(100), 40),
+        case(VelocityMode::Live, Some(100), 100),
+        case(VelocityMode::Live, None, 40),
+        case(VelocityMode::Scaled(0.25), Some(100), 55),
+        case(VelocityMode::Fixed(u7::from(90)), None, 90)
+    )]
+    fn velocity_modes(mode: VelocityMode, live: Option<u8>, expected: u8) {
+        assert_eq!(
+            mode.velocity(u7::from(40), live.map(u7::from)),
+            u7::from(expected)
+        );
+    }
+
+    #[test]
+    fn apply_live_velocity() {
+        let key = u7::from(60);
+        let mut events = [
+            NoteOn {
+                key,
+                vel: u7::from(40),
+            },
+            NoteOn {
+                key,
+                vel: u7::from(0),
+            },
+        ]
+        .map(|message| ScoreEvent {
+            time: 0,
+            channel: u4::from(0),
+            message,
+        });
+        apply_velocity_mode(&mut events, VelocityMode::Live, Some(u7::from(70)));
+        assert_eq!(
+            events.map(|event| event.message),
+            [
+                NoteOn {
+                    key,
+                    vel: u7::from(70)
+                },
+                NoteOn {
+                    key,
+                    vel: u7::from(0)
+                }
+            ]
+        );
+    }
+
+    #[rstest(
+        spec,
+        expected,
+        case("score", VelocityMode::Score),
+        case("live", VelocityMode::Live),
+        case("scaled", VelocityMode::Scaled(0.5)),
+        case("scaled:0.8", VelocityMode::Scaled(0.8)),
+        case("fixed:64", VelocityMode::Fixed(u7::from(64)))
+    )]
+    fn parse_velocity_mode(spec: &str, expected: VelocityMode) {
+        assert_eq!(spec.parse(), Ok(expected));
+    }
+
+    #[rstest(
+        spec,
+        case("soloist"),
+        case("scaled:1.5"),
+        case("fixed:0"),
+        case("fixed:128")
+    )]
+    fn parse_invalid_velocity_mode(spec: &str) {
+        assert!(spec.parse::<VelocityMode>().is_err());
+    }
+
+    #[rstest(
+        spec,
+        case("loud"),
+        case("range:100-40"),
+        case("range:0-100"),
+        case("gamma:0"),
+        case("compress:x")
+    )]
+    fn parse_invalid_velocity_curve(spec: &str) {
+        assert!(spec.parse::<VelocityCurve>().is_err());
+    }
+}
@@ -0,0 +1,128 @@
+use crate::score::ScoreNote;
+use itertools::Itertools;
+use std::cmp::Reverse;
+
+/// How strongly the pitch center of a voice follows the notes assigned to it, from 0 for
+/// not at all to 1 for jumping to the latest note
+const CENTER_ADAPTATION: f64 = 0.3;
+
+/// Assigns each note of a score to a voice by pitch, e.g. to separate the right and the
+/// left hand of a single-track piano score
+///
+/// Each voice has a pitch center which starts at an evenly spaced quantile of the
+/// pitches of the score and drifts towards the notes assigned to it. Every note goes to
+/// the voice with the nearest center, except that the notes of a chord, i.e. notes
+/// starting at the same time, always keep their pitch order across the voices.
+///
+/// # Arguments
+///
+/// * score - The notes to assign
+/// * voices - The number of voices
+///
+/// # Return value
+///
+/// The voice of each note of the score, 0 being the highest voice
+pub fn assign_voices(score: &[ScoreNote], voices: usize) -> Vec<usize> {
+    let mut assigned = vec![0; score.len()];
+    if voices < 2 {
+        return assigned;
+    }
+    let mut pitches = score
+        .iter()
+        .map(|note| note.pitch.as_int())
+        .collect::<Vec<_>>();
+    pitches.sort_unstable();
+    let mut centers = (0..voices)
+        .map(|voice| {
+            let quantile = ((voices - voice) as f64 - 0.5) / voices as f64;
+            let index = (quantile * pitches.len() as f64) as usize;
+            pitches[index.min(pitches.len() - 1)] as f64
+        })
+        .collect::<Vec<_>>();
+    for (_, chord) in &score.iter().enumerate().chunk_by(|(_, note)| note.time) {
+        let mut chord = chord.collect::<Vec<_>>();
+        chord.sort_by_key(|(_, note)| Reverse(note.pitch));
+        // lower notes of the chord can't go to a higher voice than the notes above them
+        let mut highest_voice = 0;
+        for (index, note) in chord {
+            let pitch = note.pitch.as_int() as f64;
+            let voice = (highest_voice..voices)
+                .min_by(|&a, &b| {
+                    (centers[a] - pitch)
+                        .abs()
+                        .total_cmp(&(centers[b] - pitch).abs())
+                })
+                .unwrap();
+            centers[voice] += CENTER_ADAPTATION * (pitch - centers[voice]);
+            assigned[index] = voice;
+            highest_voice = voice;
+        }
+    }
+    assigned
+}
+
+/// Splits a score into voices by pitch, see [`assign_voices`]
+///
+/// # Return value
+///
+/// The notes of each voice from the highest voice to the lowest, in score order
+pub fn split_voices(score: &[ScoreNote], voices: usize) -> Vec<Vec<ScoreNote>> {
+    let mut split = vec![vec![]; voices.max(1)];
+    for (note, voice) in score.iter().zip(assign_voices(score, voices)) {
+        split[voice].push(*note);
+    }
+    split
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_piano_hands() {
+        // a melody over an Alberti bass
+        let score = notes![
+            (0, 72),
+            (0, 48),
+            (250, 55),
+            (500, 76),
+            (500, 52),
+            (750, 55),
+            (1000, 79),
+            (1000, 74),
+            (1000, 48),
+            (1250, 55),
+            (1500, 72),
+            (1500, 48)
+        ];
+        let [right, left]: [Vec<ScoreNote>; 2] = split_voices(&score, 2).try_into().unwrap();
+        assert_eq!(
+            right,
+            notes![(0, 72), (500, 76), (1000, 79), (1000, 74), (1500, 72)]
+        );
+        assert_eq!(
+            left,
+            notes![
+                (0, 48),
+                (250, 55),
+                (500, 52),
+                (750, 55),
+                (1000, 48),
+                (1250, 55),
+                (1500, 48)
+            ]
+        );
+    }
+
+    #[test]
+    fn chord_in_one_hand() {
+        let score = notes![(0, 72), (0, 48), (500, 52), (500, 48), (500, 43)];
+        assert_eq!(assign_voices(&score, 2), [0, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn single_voice() {
+        let score = notes![(0, 60), (0, 48)];
+        assert_eq!(split_voices(&score, 1), [score.to_vec()]);
+    }
+}